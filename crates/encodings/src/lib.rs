@@ -306,3 +306,277 @@ pub mod prefix_varint {
         }
     }
 }
+
+pub mod gorilla {
+    /// Compression for `(timestamp, value)` pairs based on the scheme
+    /// described in [Gorilla](https://www.vldb.org/pvldb/vol8/p1816-teller.pdf).
+    /// Timestamps are stored as the delta of their deltas, which is usually
+    /// 0 for regularly sampled data, and values are XORed with the previous
+    /// value, which for slowly changing data leaves only a few meaningful bits.
+    /// The first point is stored uncompressed. Each subsequent timestamp is
+    /// encoded as one of (`x` is a bit of the delta-of-delta):
+    /// ```python,ignore,no_run
+    /// 0                     delta-of-delta is 0
+    /// 10   xxxxxxx          7 bit delta-of-delta
+    /// 110  xxxxxxxxx        9 bit delta-of-delta
+    /// 1110 xxxxxxxxxxxx     12 bit delta-of-delta
+    /// 1111 x{64}            64 bit delta-of-delta
+    /// ```
+    /// and is followed by its value encoded as one of:
+    /// ```python,ignore,no_run
+    /// 0                     value is identical to the previous value
+    /// 10 x{n}               the meaningful bits of the XOR fit in the
+    ///                       previous window of n bits
+    /// 11 lllll mmmmmm x{m}  5 bit leading zeros count, 6 bit meaningful
+    ///                       length - 1, and the meaningful bits
+    /// ```
+    /// The number of points is not stored, and must be passed to the
+    /// decompressor.
+
+    const TIMESTAMP_BUCKETS: [(u64, u32, u32); 3] = [
+        // (prefix, prefix length, value bits)
+        (0b10, 2, 7),
+        (0b110, 3, 9),
+        (0b1110, 4, 12),
+    ];
+
+    pub fn compress_to_vec<I: Iterator<Item=(i64, f64)>>(bytes: &mut Vec<u8>, points: I) {
+        let mut writer = BitWriter { bytes, free: 0 };
+        let mut prev: Option<(i64, u64)> = None;
+        let mut prev_delta = 0i64;
+        let mut window: Option<(u32, u32)> = None;
+        for (ts, val) in points {
+            let val = val.to_bits();
+            let (prev_ts, prev_val) = match prev {
+                None => {
+                    writer.write(ts as u64, 64);
+                    writer.write(val, 64);
+                    prev = Some((ts, val));
+                    continue
+                },
+                Some(prev) => prev,
+            };
+
+            let delta = ts.wrapping_sub(prev_ts);
+            let delta_of_delta = delta.wrapping_sub(prev_delta);
+            if delta_of_delta == 0 {
+                writer.write(0, 1);
+            } else {
+                let bucket = TIMESTAMP_BUCKETS.iter()
+                    .find(|(_, _, bits)| fits_in(delta_of_delta, *bits));
+                match bucket {
+                    Some(&(prefix, prefix_bits, bits)) => {
+                        writer.write(prefix, prefix_bits);
+                        writer.write(delta_of_delta as u64 & mask(bits), bits);
+                    },
+                    None => {
+                        writer.write(0b1111, 4);
+                        writer.write(delta_of_delta as u64, 64);
+                    },
+                }
+            }
+
+            let xor = val ^ prev_val;
+            if xor == 0 {
+                writer.write(0, 1);
+            } else {
+                let leading = xor.leading_zeros().min(31);
+                let trailing = xor.trailing_zeros();
+                match window {
+                    Some((prev_leading, prev_trailing))
+                    if leading >= prev_leading && trailing >= prev_trailing => {
+                        let meaningful = 64 - prev_leading - prev_trailing;
+                        writer.write(0b10, 2);
+                        writer.write(xor >> prev_trailing, meaningful);
+                    },
+                    _ => {
+                        let meaningful = 64 - leading - trailing;
+                        writer.write(0b11, 2);
+                        writer.write(leading as u64, 5);
+                        writer.write((meaningful - 1) as u64, 6);
+                        writer.write(xor >> trailing, meaningful);
+                        window = Some((leading, trailing));
+                    },
+                }
+            }
+
+            prev = Some((ts, val));
+            prev_delta = delta;
+        }
+    }
+
+    pub fn decompressor(bytes: &[u8], num_points: usize) -> Decompressor<'_> {
+        Decompressor {
+            reader: BitReader { bytes, pos: 0 },
+            remaining: num_points,
+            prev: None,
+            prev_delta: 0,
+            window: (0, 0),
+        }
+    }
+
+    pub struct Decompressor<'a> {
+        reader: BitReader<'a>,
+        remaining: usize,
+        prev: Option<(i64, u64)>,
+        prev_delta: i64,
+        window: (u32, u32),
+    }
+
+    impl<'a> Iterator for Decompressor<'a> {
+        type Item = (i64, f64);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.remaining == 0 {
+                return None
+            }
+            self.remaining -= 1;
+
+            let reader = &mut self.reader;
+            let (prev_ts, prev_val) = match self.prev {
+                None => {
+                    let ts = reader.read(64) as i64;
+                    let val = reader.read(64);
+                    self.prev = Some((ts, val));
+                    return Some((ts, f64::from_bits(val)))
+                },
+                Some(prev) => prev,
+            };
+
+            let mut prefix_bits = 0;
+            while prefix_bits < 4 && reader.read(1) == 1 {
+                prefix_bits += 1;
+            }
+            let delta_of_delta = match prefix_bits {
+                0 => 0,
+                4 => reader.read(64) as i64,
+                n => {
+                    let bits = TIMESTAMP_BUCKETS[n - 1].2;
+                    sign_extend(reader.read(bits), bits)
+                },
+            };
+            let delta = self.prev_delta.wrapping_add(delta_of_delta);
+            let ts = prev_ts.wrapping_add(delta);
+
+            let val = if reader.read(1) == 0 {
+                prev_val
+            } else {
+                if reader.read(1) == 1 {
+                    let leading = reader.read(5) as u32;
+                    let meaningful = reader.read(6) as u32 + 1;
+                    self.window = (leading, 64 - leading - meaningful);
+                }
+                let (leading, trailing) = self.window;
+                let xor = reader.read(64 - leading - trailing) << trailing;
+                prev_val ^ xor
+            };
+
+            self.prev = Some((ts, val));
+            self.prev_delta = delta;
+            Some((ts, f64::from_bits(val)))
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.remaining, Some(self.remaining))
+        }
+    }
+
+    fn fits_in(value: i64, bits: u32) -> bool {
+        let min = -(1i64 << (bits - 1));
+        let max = (1i64 << (bits - 1)) - 1;
+        value >= min && value <= max
+    }
+
+    fn mask(bits: u32) -> u64 {
+        if bits == 64 {
+            !0
+        } else {
+            (1 << bits) - 1
+        }
+    }
+
+    fn sign_extend(value: u64, bits: u32) -> i64 {
+        ((value << (64 - bits)) as i64) >> (64 - bits)
+    }
+
+    struct BitWriter<'a> {
+        bytes: &'a mut Vec<u8>,
+        // number of unused bits in the last byte
+        free: u32,
+    }
+
+    impl<'a> BitWriter<'a> {
+        // writes the low `bits` bits of `value`, most significant bit first
+        fn write(&mut self, value: u64, mut bits: u32) {
+            while bits > 0 {
+                if self.free == 0 {
+                    self.bytes.push(0);
+                    self.free = 8;
+                }
+                let n = bits.min(self.free);
+                let chunk = (value >> (bits - n)) & mask(n);
+                *self.bytes.last_mut().unwrap() |= (chunk as u8) << (self.free - n);
+                self.free -= n;
+                bits -= n;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        // position in bits
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn read(&mut self, mut bits: u32) -> u64 {
+            let mut value = 0;
+            while bits > 0 {
+                let byte = self.bytes[self.pos / 8];
+                let available = 8 - (self.pos % 8) as u32;
+                let n = bits.min(available);
+                let chunk = (byte >> (available - n)) as u64 & mask(n);
+                value = (value << n) | chunk;
+                self.pos += n as usize;
+                bits -= n;
+            }
+            value
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use quickcheck_macros::quickcheck;
+
+        use super::*;
+
+        fn roundtrip(points: &[(i64, f64)]) -> Vec<(i64, f64)> {
+            let mut bytes = vec![];
+            compress_to_vec(&mut bytes, points.iter().cloned());
+            decompressor(&bytes, points.len()).collect()
+        }
+
+        #[quickcheck]
+        fn quick_test_roundtrip(points: Vec<(i64, f64)>) -> bool {
+            let output = roundtrip(&points);
+            assert_eq!(points.len(), output.len());
+            for (expected, actual) in points.iter().zip(output.iter()) {
+                assert_eq!(expected.0, actual.0);
+                assert_eq!(expected.1.to_bits(), actual.1.to_bits());
+            }
+            true
+        }
+
+        #[test]
+        fn test_regular_series_compresses() {
+            let points: Vec<_> = (0..1000)
+                .map(|i| (1_000_000 * i, (i / 10) as f64))
+                .collect();
+            let mut bytes = vec![];
+            compress_to_vec(&mut bytes, points.iter().cloned());
+            // 16 bytes uncompressed per point
+            assert!(bytes.len() * 10 < points.len() * 16);
+            assert_eq!(roundtrip(&points), points);
+        }
+    }
+}
@@ -0,0 +1,769 @@
+//! KLL quantile sketch implementation in rust.
+//! Based on the paper: https://arxiv.org/abs/1603.05346
+//! The compaction scheme follows the Apache DataSketches implementation, and
+//! sketches can be converted to and from the DataSketches C++/Python
+//! `kll_doubles_sketch` serialization format.
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_K: u16 = 200;
+pub const DEFAULT_M: u8 = 8;
+
+const FAMILY: u8 = 15;
+const PREAMBLE_INTS_SHORT: u8 = 2;
+const PREAMBLE_INTS_FULL: u8 = 5;
+const SERIAL_VERSION_1: u8 = 1;
+const SERIAL_VERSION_2: u8 = 2;
+
+const FLAG_EMPTY: u8 = 1 << 0;
+const FLAG_LEVEL_ZERO_SORTED: u8 = 1 << 1;
+const FLAG_SINGLE_ITEM: u8 = 1 << 2;
+
+const POWERS_OF_THREE: [u64; 31] = [
+    1, 3, 9, 27, 81, 243, 729, 2187, 6561, 19683, 59049, 177147, 531441,
+    1594323, 4782969, 14348907, 43046721, 129140163, 387420489, 1162261467,
+    3486784401, 10460353203, 31381059609, 94143178827, 282429536481,
+    847288609443, 2541865828329, 7625597484987, 22876792454961, 68630377364883,
+    205891132094649,
+];
+
+// level capacities are computed from POWERS_OF_THREE in at most two steps,
+// which can't reach past a depth of 60
+const MAX_NUM_LEVELS: usize = 61;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KllSketch {
+    k: u16,
+    m: u8,
+    // the smallest k of any sketch merged into this one, which determines the error
+    min_k: u16,
+    n: u64,
+    level_zero_sorted: bool,
+    min: f64,
+    max: f64,
+    // `items[levels[h]..levels[h + 1]]` are the items retained at level h, each
+    // standing in for 2^h of the original values. Level 0 grows downwards into
+    // the free space at `items[..levels[0]]`.
+    levels: Vec<u32>,
+    items: Vec<f64>,
+}
+
+impl KllSketch {
+    pub fn new(k: u16) -> Self {
+        Self::with_m(k, DEFAULT_M)
+    }
+
+    fn with_m(k: u16, m: u8) -> Self {
+        assert!(k >= m as u16, "KLL k must be at least {}", m);
+        KllSketch {
+            k,
+            m,
+            min_k: k,
+            n: 0,
+            level_zero_sorted: false,
+            min: f64::NAN,
+            max: f64::NAN,
+            levels: vec![k as u32, k as u32],
+            items: vec![0.0; k as usize],
+        }
+    }
+
+    pub fn k(&self) -> u16 {
+        self.k
+    }
+
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn num_retained(&self) -> usize {
+        (self.levels[self.num_levels()] - self.levels[0]) as usize
+    }
+
+    pub fn is_estimation_mode(&self) -> bool {
+        self.num_levels() > 1
+    }
+
+    fn num_levels(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    fn level(&self, level: usize) -> &[f64] {
+        if level >= self.num_levels() {
+            return &[]
+        }
+        &self.items[self.levels[level] as usize..self.levels[level + 1] as usize]
+    }
+
+    // NaNs are ignored, as in DataSketches
+    pub fn add_value(&mut self, value: f64) {
+        if value.is_nan() {
+            return
+        }
+        if self.is_empty() {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.internal_update(value);
+    }
+
+    fn internal_update(&mut self, value: f64) {
+        if self.levels[0] == 0 {
+            self.compress_while_updating();
+        }
+        self.n += 1;
+        self.level_zero_sorted = false;
+        self.levels[0] -= 1;
+        self.items[self.levels[0] as usize] = value;
+    }
+
+    fn find_level_to_compact(&self) -> usize {
+        let num_levels = self.num_levels();
+        (0..num_levels)
+            .find(|&level| {
+                let pop = self.levels[level + 1] - self.levels[level];
+                pop >= level_capacity(self.k, num_levels, level, self.m)
+            })
+            .expect("KLL sketch is full but has no level to compact")
+    }
+
+    fn add_empty_top_level(&mut self) {
+        let num_levels = self.num_levels();
+        let cur_total_cap = self.levels[num_levels];
+        let delta_cap = level_capacity(self.k, num_levels + 1, 0, self.m);
+        let new_total_cap = cur_total_cap + delta_cap;
+
+        let mut items = vec![0.0; new_total_cap as usize];
+        items[delta_cap as usize..].copy_from_slice(&self.items);
+        self.items = items;
+        self.levels.iter_mut().for_each(|level| *level += delta_cap);
+        self.levels.push(new_total_cap);
+    }
+
+    // frees up space for level 0 by compacting the lowest level that is at
+    // capacity into the level above it
+    fn compress_while_updating(&mut self) {
+        let level = self.find_level_to_compact();
+        if level == self.num_levels() - 1 {
+            self.add_empty_top_level();
+        }
+
+        let raw_beg = self.levels[level] as usize;
+        let raw_lim = self.levels[level + 1] as usize;
+        let pop_above = self.levels[level + 2] as usize - raw_lim;
+        let raw_pop = raw_lim - raw_beg;
+        let odd_pop = raw_pop % 2 == 1;
+        let adj_beg = if odd_pop { raw_beg + 1 } else { raw_beg };
+        let adj_pop = if odd_pop { raw_pop - 1 } else { raw_pop };
+        let half_adj_pop = adj_pop / 2;
+
+        if level == 0 && !self.level_zero_sorted {
+            sort(&mut self.items[adj_beg..adj_beg + adj_pop]);
+        }
+        let bit = random_bit(self.n, level);
+        if pop_above == 0 {
+            randomly_halve_up(&mut self.items, adj_beg, adj_pop, bit);
+        } else {
+            randomly_halve_down(&mut self.items, adj_beg, adj_pop, bit);
+            merge_sorted_arrays(&mut self.items, adj_beg, half_adj_pop, raw_lim, pop_above, adj_beg + half_adj_pop);
+        }
+
+        self.levels[level + 1] -= half_adj_pop as u32;
+        if odd_pop {
+            // the current level keeps the one leftover item
+            self.levels[level] = self.levels[level + 1] - 1;
+            self.items[self.levels[level] as usize] = self.items[raw_beg];
+        } else {
+            self.levels[level] = self.levels[level + 1];
+        }
+
+        // shift the levels below up into the space freed by the compaction
+        if level > 0 {
+            let bottom = self.levels[0] as usize;
+            self.items.copy_within(bottom..raw_beg, bottom + half_adj_pop);
+            for lvl in 0..level {
+                self.levels[lvl] += half_adj_pop as u32;
+            }
+        }
+    }
+
+    pub fn merge_sketch(&mut self, other: &KllSketch) {
+        if other.is_empty() {
+            return
+        }
+        assert_eq!(self.m, other.m, "cannot merge KLL sketches with different m");
+
+        if self.is_empty() {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+
+        let final_n = self.n + other.n;
+        for &value in other.level(0) {
+            self.internal_update(value);
+        }
+        if other.num_levels() >= 2 {
+            self.merge_higher_levels(other, final_n);
+        }
+        self.n = final_n;
+        if other.is_estimation_mode() {
+            self.min_k = self.min_k.min(other.min_k);
+        }
+    }
+
+    fn merge_higher_levels(&mut self, other: &KllSketch, final_n: u64) {
+        let num_levels = self.num_levels().max(other.num_levels());
+
+        // lay the levels of both sketches out contiguously, merging each
+        // level above 0, other's level 0 was already added to ours
+        let mut work_items = Vec::with_capacity(self.num_retained() + other.num_retained());
+        let mut in_levels = vec![0u32; num_levels + 2];
+        work_items.extend_from_slice(self.level(0));
+        in_levels[1] = work_items.len() as u32;
+        for lvl in 1..num_levels {
+            let merged = merge_sorted(self.level(lvl), other.level(lvl));
+            work_items.extend_from_slice(&merged);
+            in_levels[lvl + 1] = work_items.len() as u32;
+        }
+
+        let ub = ub_on_num_levels(final_n).max(num_levels);
+        in_levels.resize(ub + 2, 0);
+        let mut out_levels = vec![0u32; ub + 2];
+        let result = general_compress(
+            self.k,
+            self.m,
+            num_levels,
+            &mut work_items,
+            &mut in_levels,
+            &mut out_levels,
+            self.level_zero_sorted,
+            final_n,
+        );
+
+        let free_space_at_bottom = result.final_capacity - result.final_num_items;
+        let mut items = vec![0.0; result.final_capacity];
+        items[free_space_at_bottom..].copy_from_slice(
+            &work_items[out_levels[0] as usize..out_levels[0] as usize + result.final_num_items]
+        );
+        let shift = free_space_at_bottom as u32 - out_levels[0];
+        self.items = items;
+        self.levels = out_levels[..result.final_num_levels + 1].iter()
+            .map(|level| level + shift)
+            .collect();
+    }
+
+    // every retained item with its weight, ordered by item
+    fn sorted_view(&self) -> Vec<(f64, u64)> {
+        let mut view: Vec<(f64, u64)> = (0..self.num_levels())
+            .flat_map(|level| self.level(level).iter().map(move |&item| (item, 1u64 << level)))
+            .collect();
+        view.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        view
+    }
+
+    // Approximate the value at the given quantile (0.0-1.0)
+    pub fn estimate_quantile(&self, quantile: f64) -> f64 {
+        assert!((0.0..=1.0).contains(&quantile));
+        if self.is_empty() {
+            return f64::NAN
+        }
+        let remaining = (self.n as f64 * quantile) as u64 + 1;
+        if remaining >= self.n {
+            return self.max
+        }
+        let mut seen = 0;
+        for (item, weight) in self.sorted_view() {
+            seen += weight;
+            if seen >= remaining {
+                return item
+            }
+        }
+        self.max
+    }
+
+    // Approximate the fraction of values less than the given value
+    pub fn estimate_quantile_at_value(&self, value: f64) -> f64 {
+        if self.is_empty() {
+            return f64::NAN
+        }
+        let below: u64 = self.sorted_view().iter()
+            .take_while(|(item, _)| *item < value)
+            .map(|(_, weight)| weight)
+            .sum();
+        below as f64 / self.n as f64
+    }
+
+    pub fn to_datasketches_bytes(&self) -> Vec<u8> {
+        let single_item = self.n == 1;
+        let mut bytes = vec![];
+        bytes.push(if self.is_empty() || single_item { PREAMBLE_INTS_SHORT } else { PREAMBLE_INTS_FULL });
+        bytes.push(if single_item { SERIAL_VERSION_2 } else { SERIAL_VERSION_1 });
+        bytes.push(FAMILY);
+        let mut flags = 0;
+        if self.is_empty() {
+            flags |= FLAG_EMPTY;
+        }
+        if self.level_zero_sorted {
+            flags |= FLAG_LEVEL_ZERO_SORTED;
+        }
+        if single_item {
+            flags |= FLAG_SINGLE_ITEM;
+        }
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.k.to_le_bytes());
+        bytes.push(self.m);
+        bytes.push(0);
+        if self.is_empty() {
+            return bytes
+        }
+        if single_item {
+            bytes.extend_from_slice(&self.level(0)[0].to_le_bytes());
+            return bytes
+        }
+
+        let num_levels = self.num_levels();
+        bytes.extend_from_slice(&self.n.to_le_bytes());
+        bytes.extend_from_slice(&self.min_k.to_le_bytes());
+        bytes.push(num_levels as u8);
+        bytes.push(0);
+        // the end of the top level is implied by the capacity
+        for level in &self.levels[..num_levels] {
+            bytes.extend_from_slice(&level.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.min.to_le_bytes());
+        bytes.extend_from_slice(&self.max.to_le_bytes());
+        for item in &self.items[self.levels[0] as usize..] {
+            bytes.extend_from_slice(&item.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_datasketches_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        let preamble_ints = reader.u8()?;
+        let serial_version = reader.u8()?;
+        let family = reader.u8()?;
+        let flags = reader.u8()?;
+        let k = reader.u16()?;
+        let m = reader.u8()?;
+        reader.u8()?;
+
+        if family != FAMILY {
+            return Err(format!("not a KLL sketch, family {}", family))
+        }
+        if m != DEFAULT_M {
+            return Err(format!("unsupported KLL m {}", m))
+        }
+        if k < m as u16 {
+            return Err(format!("invalid KLL k {}", k))
+        }
+
+        let mut sketch = KllSketch::with_m(k, m);
+        if flags & FLAG_EMPTY != 0 {
+            if preamble_ints != PREAMBLE_INTS_SHORT || serial_version != SERIAL_VERSION_1 {
+                return Err("invalid preamble for an empty KLL sketch".to_string())
+            }
+            return Ok(sketch)
+        }
+        if flags & FLAG_SINGLE_ITEM != 0 {
+            if preamble_ints != PREAMBLE_INTS_SHORT || serial_version != SERIAL_VERSION_2 {
+                return Err("invalid preamble for a single item KLL sketch".to_string())
+            }
+            sketch.add_value(reader.f64()?);
+            return Ok(sketch)
+        }
+        if preamble_ints != PREAMBLE_INTS_FULL || serial_version != SERIAL_VERSION_1 {
+            return Err("invalid preamble for a KLL sketch".to_string())
+        }
+
+        sketch.n = reader.u64()?;
+        sketch.min_k = reader.u16()?;
+        let num_levels = reader.u8()? as usize;
+        reader.u8()?;
+        if num_levels == 0 || num_levels > MAX_NUM_LEVELS {
+            return Err(format!("invalid number of KLL levels {}", num_levels))
+        }
+        let capacity = compute_total_capacity(k, m, num_levels);
+        let mut levels = Vec::with_capacity(num_levels + 1);
+        for _ in 0..num_levels {
+            levels.push(reader.u32()?);
+        }
+        levels.push(capacity);
+        if levels.windows(2).any(|w| w[0] > w[1]) {
+            return Err("invalid KLL levels".to_string())
+        }
+        sketch.min = reader.f64()?;
+        sketch.max = reader.f64()?;
+        let mut items = vec![0.0; capacity as usize];
+        for item in &mut items[levels[0] as usize..] {
+            *item = reader.f64()?;
+        }
+        if !reader.bytes.is_empty() {
+            return Err("trailing bytes after KLL sketch".to_string())
+        }
+        sketch.level_zero_sorted = flags & FLAG_LEVEL_ZERO_SORTED != 0;
+        sketch.levels = levels;
+        sketch.items = items;
+        Ok(sketch)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.bytes.len() < N {
+            return Err("KLL sketch is truncated".to_string())
+        }
+        let (val, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        let mut out = [0; N];
+        out.copy_from_slice(val);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+}
+
+struct CompressResult {
+    final_num_levels: usize,
+    final_capacity: usize,
+    final_num_items: usize,
+}
+
+// Compacts the levels laid out at `in_levels` in `items` until they fit in
+// the capacity of a sketch, writing the resulting level boundaries to
+// `out_levels`. May add levels.
+#[allow(clippy::too_many_arguments)]
+fn general_compress(
+    k: u16,
+    m: u8,
+    num_levels_in: usize,
+    items: &mut [f64],
+    in_levels: &mut Vec<u32>,
+    out_levels: &mut Vec<u32>,
+    level_zero_sorted: bool,
+    seed: u64,
+) -> CompressResult {
+    let starting_item_count = (in_levels[num_levels_in] - in_levels[0]) as usize;
+    let mut current_num_levels = num_levels_in;
+    let mut current_item_count = starting_item_count;
+    let mut target_item_count = compute_total_capacity(k, m, current_num_levels) as usize;
+    out_levels[0] = 0;
+    let mut current_level = 0;
+    loop {
+        if in_levels.len() < current_level + 3 {
+            in_levels.resize(current_level + 3, 0);
+            out_levels.resize(current_level + 3, 0);
+        }
+        // create a size-zero level at the top
+        if current_level == current_num_levels - 1 {
+            in_levels[current_level + 2] = in_levels[current_level + 1];
+        }
+        let raw_beg = in_levels[current_level] as usize;
+        let raw_lim = in_levels[current_level + 1] as usize;
+        let raw_pop = raw_lim - raw_beg;
+        let out_beg = out_levels[current_level] as usize;
+
+        if current_item_count < target_item_count
+            || raw_pop < level_capacity(k, current_num_levels, current_level, m) as usize {
+            // move the level over as is
+            items.copy_within(raw_beg..raw_lim, out_beg);
+            out_levels[current_level + 1] = (out_beg + raw_pop) as u32;
+        } else {
+            // the sketch and this level are too full, so compact it
+            let pop_above = in_levels[current_level + 2] as usize - raw_lim;
+            let odd_pop = raw_pop % 2 == 1;
+            let adj_beg = if odd_pop { raw_beg + 1 } else { raw_beg };
+            let adj_pop = if odd_pop { raw_pop - 1 } else { raw_pop };
+            let half_adj_pop = adj_pop / 2;
+
+            if odd_pop {
+                items[out_beg] = items[raw_beg];
+                out_levels[current_level + 1] = (out_beg + 1) as u32;
+            } else {
+                out_levels[current_level + 1] = out_beg as u32;
+            }
+
+            if current_level == 0 && !level_zero_sorted {
+                sort(&mut items[adj_beg..adj_beg + adj_pop]);
+            }
+            let bit = random_bit(seed, current_level);
+            if pop_above == 0 {
+                randomly_halve_up(items, adj_beg, adj_pop, bit);
+            } else {
+                randomly_halve_down(items, adj_beg, adj_pop, bit);
+                merge_sorted_arrays(items, adj_beg, half_adj_pop, raw_lim, pop_above, adj_beg + half_adj_pop);
+            }
+
+            current_item_count -= half_adj_pop;
+            in_levels[current_level + 1] -= half_adj_pop as u32;
+
+            // compacting the old top level adds a level, and its capacity
+            if current_level == current_num_levels - 1 {
+                current_num_levels += 1;
+                target_item_count += level_capacity(k, current_num_levels, 0, m) as usize;
+            }
+        }
+
+        if current_level == current_num_levels - 1 {
+            break
+        }
+        current_level += 1;
+    }
+    debug_assert_eq!((out_levels[current_num_levels] - out_levels[0]) as usize, current_item_count);
+
+    CompressResult {
+        final_num_levels: current_num_levels,
+        final_capacity: compute_total_capacity(k, m, current_num_levels) as usize,
+        final_num_items: current_item_count,
+    }
+}
+
+fn sort(items: &mut [f64]) {
+    items.sort_by(|a, b| a.partial_cmp(b).unwrap());
+}
+
+fn merge_sorted(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] <= b[j] {
+            merged.push(a[i]);
+            i += 1;
+        } else {
+            merged.push(b[j]);
+            j += 1;
+        }
+    }
+    merged.extend_from_slice(&a[i..]);
+    merged.extend_from_slice(&b[j..]);
+    merged
+}
+
+fn merge_sorted_arrays(buf: &mut [f64], start_a: usize, len_a: usize, start_b: usize, len_b: usize, start_c: usize) {
+    let merged = merge_sorted(&buf[start_a..start_a + len_a], &buf[start_b..start_b + len_b]);
+    buf[start_c..start_c + merged.len()].copy_from_slice(&merged);
+}
+
+// keeps every other item, starting from the first or second, in the lower half
+fn randomly_halve_down(buf: &mut [f64], start: usize, length: usize, bit: usize) {
+    let half_length = length / 2;
+    let mut j = start + bit;
+    for i in start..start + half_length {
+        buf[i] = buf[j];
+        j += 2;
+    }
+}
+
+// keeps every other item, starting from the last or second to last, in the upper half
+fn randomly_halve_up(buf: &mut [f64], start: usize, length: usize, bit: usize) {
+    let half_length = length / 2;
+    let mut j = start + length - 1 - bit;
+    for i in (start + half_length..start + length).rev() {
+        buf[i] = buf[j];
+        j = j.saturating_sub(2);
+    }
+}
+
+// Which half of a compacted level survives is decided by a hash of the
+// number of values and the level instead of a random number generator, so
+// that building the same sketch twice gives the same result.
+fn random_bit(seed: u64, level: usize) -> usize {
+    let mut z = seed ^ ((level as u64) << 56);
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z & 1) as usize
+}
+
+fn int_cap_aux_aux(k: u16, depth: usize) -> u32 {
+    let twok = (k as u64) << 1;
+    let tmp = (twok << depth) / POWERS_OF_THREE[depth];
+    ((tmp + 1) >> 1) as u32
+}
+
+fn int_cap_aux(k: u16, depth: usize) -> u32 {
+    if depth <= 30 {
+        return int_cap_aux_aux(k, depth)
+    }
+    let half = depth / 2;
+    let rest = depth - half;
+    let tmp = int_cap_aux_aux(k, half);
+    int_cap_aux_aux(tmp as u16, rest)
+}
+
+// the capacity of level `height` decreases geometrically with its distance from the top
+fn level_capacity(k: u16, num_levels: usize, height: usize, m: u8) -> u32 {
+    let depth = num_levels - height - 1;
+    (m as u32).max(int_cap_aux(k, depth))
+}
+
+fn compute_total_capacity(k: u16, m: u8, num_levels: usize) -> u32 {
+    (0..num_levels).map(|height| level_capacity(k, num_levels, height, m)).sum()
+}
+
+fn ub_on_num_levels(n: u64) -> usize {
+    if n == 0 {
+        return 1
+    }
+    (64 - n.leading_zeros()) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn exact_below_capacity() {
+        let mut sketch = KllSketch::new(DEFAULT_K);
+        for i in 1..=100 {
+            sketch.add_value(i as f64);
+        }
+        assert!(!sketch.is_estimation_mode());
+        assert_eq!(sketch.count(), 100);
+        assert_eq!(sketch.min(), 1.0);
+        assert_eq!(sketch.max(), 100.0);
+        assert_eq!(sketch.estimate_quantile(0.0), 1.0);
+        assert_eq!(sketch.estimate_quantile(0.5), 51.0);
+        assert_eq!(sketch.estimate_quantile(1.0), 100.0);
+        assert_eq!(sketch.estimate_quantile_at_value(51.0), 0.5);
+    }
+
+    #[test]
+    fn nan_is_ignored() {
+        let mut sketch = KllSketch::new(DEFAULT_K);
+        sketch.add_value(f64::NAN);
+        assert!(sketch.is_empty());
+        sketch.add_value(1.0);
+        assert_eq!(sketch.count(), 1);
+    }
+
+    #[test]
+    fn rank_error_within_bounds() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut values: Vec<f64> = (0..100_000).map(|_| rng.gen()).collect();
+        let mut sketch = KllSketch::new(DEFAULT_K);
+        values.iter().for_each(|&v| sketch.add_value(v));
+        assert!(sketch.is_estimation_mode());
+        assert!(sketch.num_retained() < 1000);
+        assert_eq!(sketch.count(), values.len() as u64);
+
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for &q in &[0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let estimate = sketch.estimate_quantile(q);
+            let rank = rank_of(&values, estimate) as f64 / values.len() as f64;
+            // the normalized rank error for k = 200 is about 1.33%
+            assert!((rank - q).abs() < 0.02, "quantile {} estimated at rank {}", q, rank);
+        }
+    }
+
+    // the number of values less than `value` in the sorted `values`
+    fn rank_of(values: &[f64], value: f64) -> usize {
+        values
+            .binary_search_by(|v| if *v < value { Ordering::Less } else { Ordering::Greater })
+            .unwrap_err()
+    }
+
+    #[test]
+    fn merge_matches_single_sketch() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut values: Vec<f64> = (0..50_000).map(|_| rng.gen::<f64>() * 1000.0).collect();
+
+        let mut merged = KllSketch::new(DEFAULT_K);
+        for chunk in values.chunks(3_000) {
+            let mut part = KllSketch::new(DEFAULT_K);
+            chunk.iter().for_each(|&v| part.add_value(v));
+            merged.merge_sketch(&part);
+        }
+        assert_eq!(merged.count(), values.len() as u64);
+        assert!(merged.num_retained() < 1000);
+
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(merged.min(), values[0]);
+        assert_eq!(merged.max(), values[values.len() - 1]);
+        for &q in &[0.05, 0.5, 0.95] {
+            let estimate = merged.estimate_quantile(q);
+            let rank = rank_of(&values, estimate) as f64 / values.len() as f64;
+            assert!((rank - q).abs() < 0.02, "quantile {} estimated at rank {}", q, rank);
+        }
+    }
+
+    #[test]
+    fn datasketches_round_trip() {
+        let empty = KllSketch::new(DEFAULT_K);
+        let bytes = empty.to_datasketches_bytes();
+        assert_eq!(bytes, vec![2, 1, 15, 1, 200, 0, 8, 0]);
+        let decoded = KllSketch::from_datasketches_bytes(&bytes).unwrap();
+        assert!(decoded.is_empty());
+        assert_eq!(decoded.k(), DEFAULT_K);
+
+        let mut single = KllSketch::new(DEFAULT_K);
+        single.add_value(1.0);
+        let bytes = single.to_datasketches_bytes();
+        assert_eq!(&bytes[..8], &[2, 2, 15, 4, 200, 0, 8, 0]);
+        assert_eq!(&bytes[8..], &1.0f64.to_le_bytes());
+        assert_eq!(KllSketch::from_datasketches_bytes(&bytes).unwrap(), single);
+
+        let mut full = KllSketch::new(DEFAULT_K);
+        (0..10_000).for_each(|i| full.add_value(i as f64));
+        let bytes = full.to_datasketches_bytes();
+        assert_eq!(&bytes[..4], &[5, 1, 15, 0]);
+        let decoded = KllSketch::from_datasketches_bytes(&bytes).unwrap();
+        assert_eq!(decoded.count(), full.count());
+        assert_eq!(decoded.estimate_quantile(0.5), full.estimate_quantile(0.5));
+        assert_eq!(decoded.to_datasketches_bytes(), bytes);
+
+        assert!(KllSketch::from_datasketches_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // the number of levels follows the preamble, n and min_k
+        for &num_levels in &[0, 61, 62, 255] {
+            let mut corrupted = bytes.clone();
+            corrupted[18] = num_levels;
+            assert!(KllSketch::from_datasketches_bytes(&corrupted).is_err());
+        }
+    }
+}
@@ -33,6 +33,7 @@ pub struct XYPair {
 const INV_FLOATING_ERROR_THRESHOLD : f64 = 0.99;
 pub mod stats2d;
 pub mod stats1d;
+pub mod distributions;
 
 // This will wrap the logic for incrementing the sum for the third moment of a series of floats (i.e. Sum (i=1..N) of (i-avg)^3)
 // Math is sourced from https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Higher-order_statistics
@@ -2,6 +2,7 @@
 // https://github.com/postgres/postgres/blob/472e518a44eacd9caac7d618f1b6451672ca4481/src/backend/utils/adt/float.c#L3260
 use serde::{Deserialize, Serialize};
 use crate::{StatsError, XYPair, INV_FLOATING_ERROR_THRESHOLD, M3, M4};
+use crate::distributions::student_t_quantile;
 use flat_serialize_macro::FlatSerializable;
 
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, FlatSerializable)]
@@ -589,8 +590,49 @@ impl StatsSummary2D {
         }
         Some(self.sxy / self.n64())
     }
-}
 
+    /// returns the y value the least squares fit line predicts at x
+    pub fn predict(&self, x: f64) -> Option<f64> {
+        Some(self.intercept()? + self.slope()? * x)
+    }
+
+    /// returns the standard deviation of the residuals around the least squares fit line,
+    /// using n - 2 degrees of freedom
+    pub fn residual_stddev(&self) -> Option<f64> {
+        if self.n <= 2 || self.sx2 == 0.0 {
+            return None;
+        }
+        // rounding can push the residual sum of squares of a perfect fit slightly negative
+        let residual_sum_squares = (self.sy2 - self.sxy * self.sxy / self.sx2).max(0.0);
+        Some((residual_sum_squares / (self.n64() - 2.0)).sqrt())
+    }
+
+    /// returns the prediction at x along with the lower and upper bounds of the interval around it
+    /// at the given confidence level. When `new_observation` is set, the interval is a prediction
+    /// interval for a single new y value at x, otherwise it is a confidence interval for the fit
+    /// line itself.
+    ///```
+    /// use stats_agg::stats2d::StatsSummary2D;
+    /// use stats_agg::XYPair;
+    /// let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:1.0,}, XYPair{y:4.0, x:2.0,}, XYPair{y:6.0, x:3.0,}]).unwrap();
+    /// // a perfect fit has no uncertainty
+    /// assert_eq!(p.predict_interval(4.0, 0.95, true).unwrap(), (8.0, 8.0, 8.0));
+    /// // at least 3 points are needed to estimate the spread around the line
+    /// let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:1.0,}, XYPair{y:4.0, x:2.0,}]).unwrap();
+    /// assert!(p.predict_interval(4.0, 0.95, true).is_none());
+    /// ```
+    pub fn predict_interval(&self, x: f64, confidence: f64, new_observation: bool) -> Option<(f64, f64, f64)> {
+        let predicted = self.predict(x)?;
+        let residual_stddev = self.residual_stddev()?;
+        let n = self.n64();
+        let dx = x - self.sx / n;
+        let observation = if new_observation { 1.0 } else { 0.0 };
+        let stderr = residual_stddev * (observation + 1.0 / n + dx * dx / self.sx2).sqrt();
+        let t = student_t_quantile(0.5 + confidence / 2.0, n - 2.0);
+        let width = t * stderr;
+        Some((predicted, predicted - width, predicted + width))
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -628,4 +670,23 @@ mod tests {
         assert_eq!(p.intercept().unwrap(), 2.0);
         assert_eq!(p.x_intercept(), None);
     }
+
+    #[test]
+    fn test_predict_interval(){
+        // y = 2x + 1 with residuals -1, 1, 1, -1
+        let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:1.0,}, XYPair{y:6.0, x:2.0,}, XYPair{y:8.0, x:3.0,}, XYPair{y:8.0, x:4.0,}]).unwrap();
+        let (predicted, lower, upper) = p.predict_interval(5.0, 0.95, false).unwrap();
+        assert!((predicted - 11.0).abs() < 1e-12);
+        // slope 2, intercept 1, residual sum of squares 4 over 2 degrees of freedom
+        let stderr = 2f64.sqrt() * (0.25f64 + 6.25 / 5.0).sqrt();
+        // t quantile for 2 degrees of freedom at 0.975
+        let t = 4.302652729911275;
+        assert!((upper - (11.0 + t * stderr)).abs() < 1e-9);
+        assert!((lower - (11.0 - t * stderr)).abs() < 1e-9);
+
+        let (_, lower, upper) = p.predict_interval(5.0, 0.95, true).unwrap();
+        let stderr = 2f64.sqrt() * (1.25f64 + 6.25 / 5.0).sqrt();
+        assert!((upper - (11.0 + t * stderr)).abs() < 1e-9);
+        assert!((lower - (11.0 - t * stderr)).abs() < 1e-9);
+    }
 }
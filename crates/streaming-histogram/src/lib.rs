@@ -0,0 +1,271 @@
+//! Streaming histogram implementation in rust.
+//! Based on the paper: https://www.jmlr.org/papers/volume11/ben-haim10a/ben-haim10a.pdf
+//! The histogram keeps at most `max_bins` centroids, each a value with the
+//! number of points merged into it, and can estimate the boundaries of
+//! buckets holding equal numbers of points.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StreamingHistogram {
+    max_bins: u32,
+    count: u64,
+    min: f64,
+    max: f64,
+    // sorted by value, with distinct values
+    centroids: Vec<(f64, u64)>,
+}
+
+impl StreamingHistogram {
+    pub fn new(max_bins: u32) -> Self {
+        assert!(max_bins >= 2, "streaming histogram needs at least 2 bins");
+        StreamingHistogram {
+            max_bins,
+            count: 0,
+            min: f64::NAN,
+            max: f64::NAN,
+            centroids: Vec::with_capacity(max_bins as usize + 1),
+        }
+    }
+
+    pub fn from_parts(max_bins: u32, min: f64, max: f64, centroids: Vec<(f64, u64)>) -> Self {
+        let count = centroids.iter().map(|(_, count)| count).sum();
+        StreamingHistogram {
+            max_bins,
+            count,
+            min,
+            max,
+            centroids,
+        }
+    }
+
+    pub fn max_bins(&self) -> u32 {
+        self.max_bins
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn centroids(&self) -> &[(f64, u64)] {
+        &self.centroids
+    }
+
+    // NaNs are ignored
+    pub fn add_value(&mut self, value: f64) {
+        if value.is_nan() {
+            return
+        }
+        self.update_bounds(value, value);
+        self.count += 1;
+        self.insert(value, 1);
+        self.shrink();
+    }
+
+    pub fn merge(&mut self, other: &StreamingHistogram) {
+        if other.count == 0 {
+            return
+        }
+        self.update_bounds(other.min, other.max);
+        self.count += other.count;
+        for &(value, count) in &other.centroids {
+            self.insert(value, count);
+        }
+        self.shrink();
+    }
+
+    fn update_bounds(&mut self, min: f64, max: f64) {
+        if self.count == 0 {
+            self.min = min;
+            self.max = max;
+        } else {
+            self.min = self.min.min(min);
+            self.max = self.max.max(max);
+        }
+    }
+
+    fn insert(&mut self, value: f64, count: u64) {
+        match self.centroids.binary_search_by(|(v, _)| v.partial_cmp(&value).unwrap()) {
+            Ok(i) => self.centroids[i].1 += count,
+            Err(i) => self.centroids.insert(i, (value, count)),
+        }
+    }
+
+    // merges the closest pair of centroids until there are at most max_bins
+    fn shrink(&mut self) {
+        while self.centroids.len() > self.max_bins as usize {
+            let i = (0..self.centroids.len() - 1)
+                .min_by(|&a, &b| {
+                    let gap = |i: usize| self.centroids[i + 1].0 - self.centroids[i].0;
+                    gap(a).partial_cmp(&gap(b)).unwrap()
+                })
+                .unwrap();
+            let (v1, c1) = self.centroids[i];
+            let (v2, c2) = self.centroids.remove(i + 1);
+            let count = c1 + c2;
+            self.centroids[i] = ((v1 * c1 as f64 + v2 * c2 as f64) / count as f64, count);
+        }
+    }
+
+    // The centroids with zero weight points added at the min and max, so that
+    // the interpolation covers the whole range of values.
+    fn bounded_centroids(&self) -> Vec<(f64, f64)> {
+        let mut points = Vec::with_capacity(self.centroids.len() + 2);
+        if self.centroids.first().map(|(v, _)| *v > self.min).unwrap_or(true) {
+            points.push((self.min, 0.0));
+        }
+        points.extend(self.centroids.iter().map(|&(v, c)| (v, c as f64)));
+        if self.centroids.last().map(|(v, _)| *v < self.max).unwrap_or(true) {
+            points.push((self.max, 0.0));
+        }
+        points
+    }
+
+    // Estimated number of points less than or equal to `value`, each centroid
+    // is treated as half of its points lying on either side of its value, with
+    // the density between centroids interpolated linearly.
+    pub fn estimate_count_below(&self, value: f64) -> f64 {
+        if self.count == 0 || value < self.min {
+            return 0.0
+        }
+        if value >= self.max {
+            return self.count as f64
+        }
+        let points = self.bounded_centroids();
+        let mut before = 0.0;
+        for pair in points.windows(2) {
+            let ((p1, m1), (p2, m2)) = (pair[0], pair[1]);
+            if value < p2 {
+                let z = (value - p1) / (p2 - p1);
+                let mb = m1 + (m2 - m1) * z;
+                return before + m1 / 2.0 + (m1 + mb) / 2.0 * z
+            }
+            before += m1;
+        }
+        self.count as f64
+    }
+
+    // Splits the range of values into `num_buckets` buckets that are each
+    // estimated to contain the same number of points, returned as
+    // (start, end, count). If all the values are equal there is a single bucket.
+    pub fn equi_depth_buckets(&self, num_buckets: u32) -> Vec<(f64, f64, f64)> {
+        assert!(num_buckets > 0);
+        if self.count == 0 {
+            return vec![]
+        }
+        if self.min == self.max {
+            return vec![(self.min, self.max, self.count as f64)]
+        }
+
+        let points = self.bounded_centroids();
+        let depth = self.count as f64 / num_buckets as f64;
+        let mut boundaries = vec![self.min];
+        let mut pair = 0;
+        // the number of points in the centroids before points[pair]
+        let mut before = 0.0;
+        for j in 1..num_buckets {
+            let target = depth * j as f64;
+            loop {
+                let ((_, m1), (_, m2)) = (points[pair], points[pair + 1]);
+                if target < before + m1 + m2 / 2.0 || pair + 2 == points.len() {
+                    break
+                }
+                before += m1;
+                pair += 1;
+            }
+            let ((p1, m1), (p2, m2)) = (points[pair], points[pair + 1]);
+            // solve (m2 - m1) z^2 + 2 m1 z - 2 d = 0 for the fraction of the
+            // way between the centroids that holds the remaining d points
+            let d = target - (before + m1 / 2.0);
+            let (a, b, c) = (m2 - m1, 2.0 * m1, -2.0 * d);
+            let z = if a == 0.0 {
+                -c / b
+            } else {
+                (-b + (b * b - 4.0 * a * c).max(0.0).sqrt()) / (2.0 * a)
+            };
+            boundaries.push(p1 + (p2 - p1) * z.clamp(0.0, 1.0));
+        }
+        boundaries.push(self.max);
+
+        boundaries.windows(2)
+            .map(|w| (w[0], w[1], depth))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_below_max_bins() {
+        let mut hist = StreamingHistogram::new(10);
+        for v in &[1.0, 2.0, 2.0, 3.0] {
+            hist.add_value(*v);
+        }
+        assert_eq!(hist.count(), 4);
+        assert_eq!(hist.centroids(), &[(1.0, 1), (2.0, 2), (3.0, 1)]);
+        assert_eq!(hist.estimate_count_below(0.5), 0.0);
+        assert_eq!(hist.estimate_count_below(2.0), 2.0);
+        assert_eq!(hist.estimate_count_below(3.0), 4.0);
+    }
+
+    #[test]
+    fn shrinks_closest_centroids() {
+        let mut hist = StreamingHistogram::new(3);
+        for v in &[1.0, 10.0, 11.0, 20.0] {
+            hist.add_value(*v);
+        }
+        assert_eq!(hist.centroids(), &[(1.0, 1), (10.5, 2), (20.0, 1)]);
+        assert_eq!(hist.min(), 1.0);
+        assert_eq!(hist.max(), 20.0);
+    }
+
+    #[test]
+    fn uniform_buckets() {
+        let mut hist = StreamingHistogram::new(50);
+        for i in 0..=10000 {
+            hist.add_value(i as f64);
+        }
+        let buckets = hist.equi_depth_buckets(4);
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(buckets[0].0, 0.0);
+        assert_eq!(buckets[3].1, 10000.0);
+        for (i, &(_, end, count)) in buckets.iter().enumerate().take(3) {
+            assert!((end - 2500.0 * (i + 1) as f64).abs() < 100.0, "bucket {} ends at {}", i, end);
+            assert!((count - 10001.0 / 4.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn merge_combines_points() {
+        let mut a = StreamingHistogram::new(20);
+        let mut b = StreamingHistogram::new(20);
+        (0..500).for_each(|i| a.add_value(i as f64));
+        (500..1000).for_each(|i| b.add_value(i as f64));
+        a.merge(&b);
+        assert_eq!(a.count(), 1000);
+        assert_eq!(a.centroids().len(), 20);
+        assert_eq!(a.max(), 999.0);
+        let median = a.equi_depth_buckets(2)[0].1;
+        assert!((median - 500.0).abs() < 25.0, "median estimated at {}", median);
+    }
+
+    #[test]
+    fn single_value() {
+        let mut hist = StreamingHistogram::new(5);
+        hist.add_value(f64::NAN);
+        assert!(hist.equi_depth_buckets(3).is_empty());
+        hist.add_value(7.0);
+        hist.add_value(7.0);
+        assert_eq!(hist.equi_depth_buckets(3), vec![(7.0, 7.0, 2.0)]);
+    }
+}
@@ -147,6 +147,36 @@ impl SketchHashMap {
             .or_insert(SketchHashEntry { count: 0, next })
     }
 
+    // Add the counts from an iterator over another map's buckets.
+    // The iterator yields keys in increasing order, so the search for where a
+    // missing key belongs can resume from the previously merged key instead
+    // of walking from the head each time, making the whole merge linear.
+    fn merge_counts(&mut self, other: SketchHashIterator) {
+        // Invalid here stands for the position before the head of the list
+        let mut prev = SketchHashKey::Invalid;
+        for (key, count) in other {
+            if let Some(entry) = self.map.get_mut(&key) {
+                entry.count += count;
+                prev = key;
+                continue;
+            }
+            let mut next = match prev {
+                SketchHashKey::Invalid => self.head,
+                prev => self.map[&prev].next,
+            };
+            while key > next {
+                prev = next;
+                next = self.map[&next].next;
+            }
+            match prev {
+                SketchHashKey::Invalid => self.head = key,
+                prev => self.map.get_mut(&prev).expect("Invalid key found").next = key,
+            }
+            self.map.insert(key, SketchHashEntry { count, next });
+            prev = key;
+        }
+    }
+
     fn len(&self) -> usize {
         self.map.len()
     }
@@ -304,19 +334,23 @@ impl UDDSketch {
             return;
         }
 
-        let mut other = other.clone();
-
-        while self.compactions > other.compactions {
-            other.compact_buckets();
-        }
+        // only copy other if it needs to be compacted to match self
+        let compacted;
+        let other = if self.compactions > other.compactions {
+            let mut copy = other.clone();
+            while self.compactions > copy.compactions {
+                copy.compact_buckets();
+            }
+            compacted = copy;
+            &compacted
+        } else {
+            other
+        };
         while other.compactions > self.compactions {
             self.compact_buckets();
         }
 
-        for entry in other.buckets.iter() {
-            let (key, value) = entry;
-            self.buckets.entry(key).count += value;
-        }
+        self.buckets.merge_counts(other.buckets.iter());
 
         while self.buckets.len() > self.max_buckets as usize {
             self.compact_buckets();
@@ -577,6 +611,32 @@ mod tests {
         assert_eq!(sketch1.max_error(), a5); // Note that each compaction doesn't always result in half the numbers of buckets, hence a5 here instead of a4
     }
 
+    #[test]
+    fn merge_interleaved_sketches() {
+        // sketch2's buckets fall before, between, and after sketch1's
+        let mut sketch1 = UDDSketch::new(100, 0.1);
+        let mut sketch2 = UDDSketch::new(100, 0.1);
+        let mut combined = UDDSketch::new(100, 0.1);
+        for i in -20..20 {
+            let value = (1.5 as f64).powi(i);
+            if i % 3 == 0 {
+                sketch1.add_value(value);
+            } else {
+                sketch2.add_value(value);
+            }
+            combined.add_value(value);
+        }
+        sketch2.add_value(0.0);
+        combined.add_value(0.0);
+
+        sketch1.merge_sketch(&sketch2);
+        assert_eq!(sketch1.count(), combined.count());
+        assert_eq!(
+            sketch1.bucket_iter().collect::<Vec<_>>(),
+            combined.bucket_iter().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_quantile_and_value_estimates() {
         let mut sketch = UDDSketch::new(50, 0.1);
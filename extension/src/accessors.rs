@@ -64,6 +64,24 @@ pub mod toolkit_experimental {
     varlena_type!(AccessorExtrapolatedDelta);
     varlena_type!(AccessorExtrapolatedRate);
     varlena_type!(AccessorWithBounds);
+
+    varlena_type!(AccessorFirstTime);
+    varlena_type!(AccessorFirstVal);
+    varlena_type!(AccessorLastTime);
+    varlena_type!(AccessorLastVal);
+    varlena_type!(AccessorCovers);
+
+    varlena_type!(AccessorOpen);
+    varlena_type!(AccessorOpenTime);
+    varlena_type!(AccessorHigh);
+    varlena_type!(AccessorHighTime);
+    varlena_type!(AccessorLow);
+    varlena_type!(AccessorLowTime);
+    varlena_type!(AccessorClose);
+    varlena_type!(AccessorCloseTime);
+    varlena_type!(AccessorVolume);
+    varlena_type!(AccessorVwap);
+    varlena_type!(AccessorTypicalPrice);
 }
 
 pg_type! {
@@ -945,6 +963,20 @@ pub fn accessor_extrapolated_delta(
     }
 }
 
+// the method is left empty, so the default is used when it's applied
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="extrapolated_delta")]
+pub fn accessor_extrapolated_delta_default_method(
+) -> toolkit_experimental::AccessorExtrapolatedDelta<'static> {
+    unsafe {
+        flatten!{
+            AccessorExtrapolatedDelta {
+                len: 0,
+                bytes: "".as_bytes().into(),
+            }
+        }
+    }
+}
+
 
 pg_type! {
     #[derive(Debug)]
@@ -972,6 +1004,20 @@ pub fn accessor_extrapolated_rate(
     }
 }
 
+// the method is left empty, so the default is used when it's applied
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="extrapolated_rate")]
+pub fn accessor_extrapolated_rate_default_method(
+) -> toolkit_experimental::AccessorExtrapolatedRate<'static> {
+    unsafe {
+        flatten!{
+            AccessorExtrapolatedRate {
+                len: 0,
+                bytes: "".as_bytes().into(),
+            }
+        }
+    }
+}
+
 
 pg_type! {
     #[derive(Debug)]
@@ -1031,3 +1077,276 @@ impl<'i> AccessorWithBounds<'i> {
         }.into()
     }
 }
+
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorFirstTime {
+    }
+}
+
+ron_inout_funcs!(AccessorFirstTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="first_time")]
+pub fn accessor_first_time(
+) -> toolkit_experimental::AccessorFirstTime<'static> {
+    build!{
+        AccessorFirstTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorFirstVal {
+    }
+}
+
+ron_inout_funcs!(AccessorFirstVal);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="first_val")]
+pub fn accessor_first_val(
+) -> toolkit_experimental::AccessorFirstVal<'static> {
+    build!{
+        AccessorFirstVal {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorLastTime {
+    }
+}
+
+ron_inout_funcs!(AccessorLastTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="last_time")]
+pub fn accessor_last_time(
+) -> toolkit_experimental::AccessorLastTime<'static> {
+    build!{
+        AccessorLastTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorLastVal {
+    }
+}
+
+ron_inout_funcs!(AccessorLastVal);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="last_val")]
+pub fn accessor_last_val(
+) -> toolkit_experimental::AccessorLastVal<'static> {
+    build!{
+        AccessorLastVal {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorCovers {
+    }
+}
+
+ron_inout_funcs!(AccessorCovers);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="covers")]
+pub fn accessor_covers(
+) -> toolkit_experimental::AccessorCovers<'static> {
+    build!{
+        AccessorCovers {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorOpen {
+    }
+}
+
+ron_inout_funcs!(AccessorOpen);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="open")]
+pub fn accessor_open(
+) -> toolkit_experimental::AccessorOpen<'static> {
+    build!{
+        AccessorOpen {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorOpenTime {
+    }
+}
+
+ron_inout_funcs!(AccessorOpenTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="open_time")]
+pub fn accessor_open_time(
+) -> toolkit_experimental::AccessorOpenTime<'static> {
+    build!{
+        AccessorOpenTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorHigh {
+    }
+}
+
+ron_inout_funcs!(AccessorHigh);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="high")]
+pub fn accessor_high(
+) -> toolkit_experimental::AccessorHigh<'static> {
+    build!{
+        AccessorHigh {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorHighTime {
+    }
+}
+
+ron_inout_funcs!(AccessorHighTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="high_time")]
+pub fn accessor_high_time(
+) -> toolkit_experimental::AccessorHighTime<'static> {
+    build!{
+        AccessorHighTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorLow {
+    }
+}
+
+ron_inout_funcs!(AccessorLow);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="low")]
+pub fn accessor_low(
+) -> toolkit_experimental::AccessorLow<'static> {
+    build!{
+        AccessorLow {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorLowTime {
+    }
+}
+
+ron_inout_funcs!(AccessorLowTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="low_time")]
+pub fn accessor_low_time(
+) -> toolkit_experimental::AccessorLowTime<'static> {
+    build!{
+        AccessorLowTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorClose {
+    }
+}
+
+ron_inout_funcs!(AccessorClose);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="close")]
+pub fn accessor_close(
+) -> toolkit_experimental::AccessorClose<'static> {
+    build!{
+        AccessorClose {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorCloseTime {
+    }
+}
+
+ron_inout_funcs!(AccessorCloseTime);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="close_time")]
+pub fn accessor_close_time(
+) -> toolkit_experimental::AccessorCloseTime<'static> {
+    build!{
+        AccessorCloseTime {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorVolume {
+    }
+}
+
+ron_inout_funcs!(AccessorVolume);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="volume")]
+pub fn accessor_volume(
+) -> toolkit_experimental::AccessorVolume<'static> {
+    build!{
+        AccessorVolume {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorVwap {
+    }
+}
+
+ron_inout_funcs!(AccessorVwap);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="vwap")]
+pub fn accessor_vwap(
+) -> toolkit_experimental::AccessorVwap<'static> {
+    build!{
+        AccessorVwap {
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct AccessorTypicalPrice {
+    }
+}
+
+ron_inout_funcs!(AccessorTypicalPrice);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental" name="typical_price")]
+pub fn accessor_typical_price(
+) -> toolkit_experimental::AccessorTypicalPrice<'static> {
+    build!{
+        AccessorTypicalPrice {
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use serde::{Deserialize, Serialize};
+
+use uddsketch::{SketchHashKey, UDDSketch as UddSketchInternal};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    uddsketch::{
+        compress_buckets, decompress_counts, decompress_keys, CompressedBuckets,
+        PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE,
+    },
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// PG object for a percentile summary that stores its values exactly while
+// there are at most `threshold` of them, and is a uddsketch past that.
+pg_type! {
+    #[derive(Debug)]
+    struct AdaptivePercentile<'input> {
+        threshold: u64,
+        summary: enum AdaptiveSummary<'input> {
+            mode: u64,
+            // the values in sorted order
+            Exact: 1 {
+                num_values: u64,
+                values: [f64; self.num_values],
+            },
+            Sketch: 2 {
+                alpha: f64,
+                max_buckets: u32,
+                num_buckets: u32,
+                compactions: u64,
+                count: u64,
+                sum: f64,
+                zero_bucket_count: u64,
+                neg_indexes_bytes: u32,
+                neg_buckets_bytes: u32,
+                pos_indexes_bytes: u32,
+                pos_buckets_bytes: u32,
+                negative_indexes: [u8; self.neg_indexes_bytes],
+                negative_counts: [u8; self.neg_buckets_bytes],
+                positive_indexes: [u8; self.pos_indexes_bytes],
+                positive_counts: [u8; self.pos_buckets_bytes],
+            },
+        },
+    }
+}
+
+ron_inout_funcs!(AdaptivePercentile);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(AdaptivePercentile);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AdaptiveSummaryState {
+    Exact(Vec<f64>),
+    Sketch(UddSketchInternal),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdaptivePercentileTransState {
+    threshold: u64,
+    summary: AdaptiveSummaryState,
+}
+
+impl AdaptivePercentileTransState {
+    fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            summary: AdaptiveSummaryState::Exact(vec![]),
+        }
+    }
+
+    fn add_value(&mut self, value: f64) {
+        match &mut self.summary {
+            AdaptiveSummaryState::Exact(values) => values.push(value),
+            AdaptiveSummaryState::Sketch(sketch) => sketch.add_value(value),
+        }
+        self.promote_if_needed();
+    }
+
+    // when the thresholds differ the smaller one is kept, so the result is
+    // only exact if it would have been for both inputs
+    fn combine(&mut self, other: &AdaptivePercentileTransState) {
+        self.threshold = self.threshold.min(other.threshold);
+        match (&mut self.summary, &other.summary) {
+            (AdaptiveSummaryState::Exact(values), AdaptiveSummaryState::Exact(other)) =>
+                values.extend_from_slice(other),
+            (AdaptiveSummaryState::Sketch(sketch), AdaptiveSummaryState::Exact(other)) =>
+                other.iter().for_each(|&value| sketch.add_value(value)),
+            (AdaptiveSummaryState::Exact(values), AdaptiveSummaryState::Sketch(other)) => {
+                let mut sketch = other.clone();
+                values.iter().for_each(|&value| sketch.add_value(value));
+                self.summary = AdaptiveSummaryState::Sketch(sketch);
+            },
+            (AdaptiveSummaryState::Sketch(sketch), AdaptiveSummaryState::Sketch(other)) =>
+                sketch.merge_sketch(other),
+        }
+        self.promote_if_needed();
+    }
+
+    fn promote_if_needed(&mut self) {
+        if let AdaptiveSummaryState::Exact(values) = &self.summary {
+            if values.len() as u64 > self.threshold {
+                let mut sketch = UddSketchInternal::new(
+                    PERCENTILE_AGG_DEFAULT_SIZE as u64,
+                    PERCENTILE_AGG_DEFAULT_ERROR,
+                );
+                values.iter().for_each(|&value| sketch.add_value(value));
+                self.summary = AdaptiveSummaryState::Sketch(sketch);
+            }
+        }
+    }
+}
+
+impl<'input> AdaptivePercentile<'input> {
+    fn to_internal(&self) -> AdaptivePercentileTransState {
+        let summary = match &self.summary {
+            AdaptiveSummary::Exact { values, .. } =>
+                AdaptiveSummaryState::Exact(values.iter().collect()),
+            AdaptiveSummary::Sketch {
+                alpha, max_buckets, compactions, count, sum, zero_bucket_count,
+                negative_indexes, negative_counts, positive_indexes, positive_counts, ..
+            } => {
+                let keys = decompress_keys(negative_indexes.as_slice(), *zero_bucket_count != 0, positive_indexes.as_slice());
+                let counts = decompress_counts(negative_counts.as_slice(), *zero_bucket_count, positive_counts.as_slice());
+                AdaptiveSummaryState::Sketch(UddSketchInternal::new_from_data(
+                    *max_buckets as u64, *alpha, *compactions, *count, *sum, keys, counts,
+                ))
+            },
+        };
+        AdaptivePercentileTransState {
+            threshold: self.threshold,
+            summary,
+        }
+    }
+
+    fn from_internal(state: &AdaptivePercentileTransState) -> AdaptivePercentile<'static> {
+        let summary = match &state.summary {
+            AdaptiveSummaryState::Exact(values) => {
+                let mut values = values.clone();
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                AdaptiveSummary::Exact {
+                    num_values: values.len() as u64,
+                    values: values.into(),
+                }
+            },
+            AdaptiveSummaryState::Sketch(sketch) => {
+                let CompressedBuckets {
+                    negative_indexes,
+                    negative_counts,
+                    zero_bucket_count,
+                    positive_indexes,
+                    positive_counts,
+                } = compress_buckets(sketch.bucket_iter());
+                AdaptiveSummary::Sketch {
+                    alpha: sketch.max_error(),
+                    max_buckets: sketch.max_allowed_buckets() as u32,
+                    num_buckets: sketch.current_buckets_count() as u32,
+                    compactions: sketch.times_compacted() as u64,
+                    count: sketch.count(),
+                    sum: sketch.sum(),
+                    zero_bucket_count,
+                    neg_indexes_bytes: negative_indexes.len() as u32,
+                    neg_buckets_bytes: negative_counts.len() as u32,
+                    pos_indexes_bytes: positive_indexes.len() as u32,
+                    pos_buckets_bytes: positive_counts.len() as u32,
+                    negative_indexes: negative_indexes.into(),
+                    negative_counts: negative_counts.into(),
+                    positive_indexes: positive_indexes.into(),
+                    positive_counts: positive_counts.into(),
+                }
+            },
+        };
+        build!(
+            AdaptivePercentile {
+                threshold: state.threshold,
+                summary: summary,
+            }
+        )
+    }
+
+    fn sketch_buckets(&self) -> Option<(f64, u64, impl Iterator<Item=(SketchHashKey, u64)> + '_)> {
+        match &self.summary {
+            AdaptiveSummary::Exact { .. } => None,
+            AdaptiveSummary::Sketch {
+                alpha, count, zero_bucket_count,
+                negative_indexes, negative_counts, positive_indexes, positive_counts, ..
+            } => {
+                let keys = decompress_keys(negative_indexes.as_slice(), *zero_bucket_count != 0, positive_indexes.as_slice());
+                let counts = decompress_counts(negative_counts.as_slice(), *zero_bucket_count, positive_counts.as_slice());
+                Some((*alpha, *count, keys.zip(counts)))
+            },
+        }
+    }
+}
+
+// PG function for adding values to a summary.
+// Null and NaN values are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn adaptive_percentile_trans(
+    state: Option<Internal<AdaptivePercentileTransState>>,
+    threshold: i32,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AdaptivePercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                Some(value) if !value.is_nan() => value,
+                _ => return state,
+            };
+            let mut state = match state {
+                None => {
+                    if threshold < 0 {
+                        error!("adaptive_percentile_agg threshold must not be negative")
+                    }
+                    AdaptivePercentileTransState::new(threshold as u64).into()
+                },
+                Some(state) => state,
+            };
+            state.add_value(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn adaptive_percentile_summary_trans(
+    state: Option<Internal<AdaptivePercentileTransState>>,
+    value: Option<toolkit_experimental::AdaptivePercentile<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AdaptivePercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal(),
+            };
+            match state {
+                None => Some(value.into()),
+                Some(mut state) => {
+                    state.combine(&value);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn adaptive_percentile_combine(
+    state1: Option<Internal<AdaptivePercentileTransState>>,
+    state2: Option<Internal<AdaptivePercentileTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AdaptivePercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.combine(&state2);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn adaptive_percentile_serialize(
+    state: Internal<AdaptivePercentileTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn adaptive_percentile_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<AdaptivePercentileTransState> {
+    crate::do_deserialize!(bytes, AdaptivePercentileTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn adaptive_percentile_final(
+    state: Option<Internal<AdaptivePercentileTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::AdaptivePercentile<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            AdaptivePercentile::from_internal(&state).into()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.adaptive_percentile_agg(
+    threshold int, value DOUBLE PRECISION
+) (
+    sfunc = toolkit_experimental.adaptive_percentile_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.adaptive_percentile_final,
+    combinefunc = toolkit_experimental.adaptive_percentile_combine,
+    serialfunc = toolkit_experimental.adaptive_percentile_serialize,
+    deserialfunc = toolkit_experimental.adaptive_percentile_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    summary toolkit_experimental.AdaptivePercentile
+) (
+    sfunc = toolkit_experimental.adaptive_percentile_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.adaptive_percentile_final,
+    combinefunc = toolkit_experimental.adaptive_percentile_combine,
+    serialfunc = toolkit_experimental.adaptive_percentile_serialize,
+    deserialfunc = toolkit_experimental.adaptive_percentile_deserialize,
+    parallel = restricted
+);
+"#);
+
+//---- Available PG operations on the summary
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_adaptive_percentile_approx_percentile(
+    summary: toolkit_experimental::AdaptivePercentile,
+    accessor: toolkit_experimental::AccessorApproxPercentile,
+) -> f64 {
+    adaptive_percentile_approx_percentile(accessor.percentile, summary)
+}
+
+// The value at the given percentile (0.0-1.0). While the summary is exact this
+// interpolates between the closest values, like percentile_cont.
+#[pg_extern(immutable, parallel_safe, name="approx_percentile", schema = "toolkit_experimental")]
+pub fn adaptive_percentile_approx_percentile(
+    percentile: f64,
+    summary: toolkit_experimental::AdaptivePercentile,
+) -> f64 {
+    if !(0.0..=1.0).contains(&percentile) {
+        error!("percentile must be between 0.0 and 1.0")
+    }
+    match &summary.summary {
+        AdaptiveSummary::Exact { values, .. } => {
+            let values: Vec<f64> = values.iter().collect();
+            let position = percentile * (values.len() - 1) as f64;
+            let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+            let fraction = position - lower as f64;
+            values[lower] + (values[upper] - values[lower]) * fraction
+        },
+        AdaptiveSummary::Sketch { .. } => {
+            let (alpha, count, buckets) = summary.sketch_buckets().unwrap();
+            uddsketch::estimate_quantile(percentile, alpha, uddsketch::gamma(alpha), count, buckets)
+        },
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_adaptive_percentile_approx_rank(
+    summary: toolkit_experimental::AdaptivePercentile,
+    accessor: toolkit_experimental::AccessorApproxRank,
+) -> f64 {
+    adaptive_percentile_approx_percentile_rank(accessor.value, summary)
+}
+
+// The fraction of values below the given value, values equal to it count as
+// half below, matching uddsketch's estimate.
+#[pg_extern(immutable, parallel_safe, name="approx_percentile_rank", schema = "toolkit_experimental")]
+pub fn adaptive_percentile_approx_percentile_rank(
+    value: f64,
+    summary: toolkit_experimental::AdaptivePercentile,
+) -> f64 {
+    match &summary.summary {
+        AdaptiveSummary::Exact { values, .. } => {
+            let below = values.iter().filter(|&v| v < value).count();
+            let equal = values.iter().filter(|&v| v == value).count();
+            (below as f64 + equal as f64 / 2.0) / values.len() as f64
+        },
+        AdaptiveSummary::Sketch { .. } => {
+            let (alpha, count, buckets) = summary.sketch_buckets().unwrap();
+            uddsketch::estimate_quantile_at_value(value, uddsketch::gamma(alpha), count, buckets)
+        },
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_adaptive_percentile_num_vals(
+    summary: toolkit_experimental::AdaptivePercentile,
+    accessor: toolkit_experimental::AccessorNumVals,
+) -> f64 {
+    let _ = accessor;
+    adaptive_percentile_num_vals(summary)
+}
+
+// Number of values from which the summary was built.
+#[pg_extern(immutable, parallel_safe, name="num_vals", schema = "toolkit_experimental")]
+pub fn adaptive_percentile_num_vals(
+    summary: toolkit_experimental::AdaptivePercentile,
+) -> f64 {
+    match &summary.summary {
+        AdaptiveSummary::Exact { num_values, .. } => *num_values as f64,
+        AdaptiveSummary::Sketch { count, .. } => *count as f64,
+    }
+}
+
+// Whether the summary still stores its values exactly, and so answers
+// percentile queries exactly.
+#[pg_extern(immutable, parallel_safe, name="is_exact", schema = "toolkit_experimental")]
+pub fn adaptive_percentile_is_exact(
+    summary: toolkit_experimental::AdaptivePercentile,
+) -> bool {
+    matches!(summary.summary, AdaptiveSummary::Exact { .. })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_adaptive_percentile() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test (device INTEGER, value DOUBLE PRECISION)", None, None);
+            client.select("INSERT INTO test SELECT i % 2, i FROM generate_series(1, 10) i", None, None);
+
+            // below the threshold the answers match percentile_cont
+            let (exact, median, rank) = client
+                .select("SELECT \
+                        is_exact(summary), \
+                        approx_percentile(0.5, summary), \
+                        summary -> approx_percentile_rank(3) \
+                    FROM (SELECT adaptive_percentile_agg(10, value) AS summary FROM test) s", None, None)
+                .first()
+                .get_three::<bool, f64, f64>();
+            let expected = client
+                .select("SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY value) FROM test", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(exact, Some(true));
+            assert_eq!(median, Some(5.5));
+            assert_eq!(median, expected);
+            assert_eq!(rank, Some(0.25));
+
+            // rolling up two exact summaries past the threshold promotes them
+            let (exact, num_vals, median) = client
+                .select("SELECT \
+                        is_exact(summary), \
+                        num_vals(summary), \
+                        summary -> approx_percentile(0.5) \
+                    FROM (SELECT rollup(summary) AS summary FROM \
+                        (SELECT adaptive_percentile_agg(6, value) AS summary FROM test GROUP BY device) s) r", None, None)
+                .first()
+                .get_three::<bool, f64, f64>();
+            assert_eq!(exact, Some(false));
+            assert_eq!(num_vals, Some(10.0));
+            assert!((median.unwrap() - 6.0).abs() < 0.1);
+
+            let (exact, num_vals) = client
+                .select("SELECT is_exact(summary), num_vals(summary) \
+                    FROM (SELECT adaptive_percentile_agg(5, value) AS summary FROM test) s", None, None)
+                .first()
+                .get_two::<bool, f64>();
+            assert_eq!(exact, Some(false));
+            assert_eq!(num_vals, Some(10.0));
+
+            // NaN is ignored like NULL
+            let (num_vals, median) = client
+                .select("SELECT num_vals(summary), approx_percentile(0.5, summary) \
+                    FROM (SELECT adaptive_percentile_agg(10, value) AS summary FROM \
+                        (SELECT value FROM test UNION ALL SELECT 'NaN') v) s", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(num_vals, Some(10.0));
+            assert_eq!(median, Some(5.5));
+        });
+    }
+}
@@ -1,6 +1,6 @@
 use std::ptr::null_mut;
 
-use pgx::pg_sys;
+use pgx::{pg_sys, PgList};
 
 // TODO move to func_utils once there are enough function to warrant one
 pub unsafe fn get_collation(fcinfo: pg_sys::FunctionCallInfo) -> Option<pg_sys::Oid> {
@@ -30,3 +30,92 @@ pub fn aggregate_mctx(fcinfo: pg_sys::FunctionCallInfo) -> Option<pg_sys::Memory
         return Some(mctx);
     }
 }
+
+// Whether the aggregate is called with its input ordered ascending by its first
+// argument, e.g. `counter_agg(ts, val ORDER BY ts)`, in which case transition
+// functions see the values in that order.
+pub unsafe fn input_sorted_by_first_arg(fcinfo: pg_sys::FunctionCallInfo) -> bool {
+    let aggref = pg_sys::AggGetAggref(fcinfo);
+    if aggref.is_null() {
+        return false;
+    }
+    let order = PgList::<pg_sys::SortGroupClause>::from_pg((*aggref).aggorder);
+    let first_sort = match order.get_ptr(0) {
+        Some(sort) => sort,
+        None => return false,
+    };
+    let args = PgList::<pg_sys::TargetEntry>::from_pg((*aggref).args);
+    let first_arg = match args.get_ptr(0) {
+        Some(arg) => arg,
+        None => return false,
+    };
+    if (*first_arg).ressortgroupref != (*first_sort).tleSortGroupRef {
+        return false;
+    }
+    let mut reverse = false;
+    pg_sys::get_equality_op_for_ordering_op((*first_sort).sortop, &mut reverse) != pg_sys::InvalidOid
+        && !reverse
+}
+
+// What an aggregate called with a `null_policy` does with rows whose inputs
+// are NULL: skip them as the aggregates always have, raise an error, or
+// "poison" the group so that the aggregate returns NULL.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NullPolicy {
+    Ignore,
+    Error,
+    Poison,
+}
+
+pub fn null_policy(policy: Option<&str>) -> NullPolicy {
+    // a NULL policy is the default
+    let policy = match policy {
+        None => return NullPolicy::Ignore,
+        Some(policy) => policy,
+    };
+    match policy.trim().to_lowercase().as_str() {
+        "ignore" => NullPolicy::Ignore,
+        "error" => NullPolicy::Error,
+        "poison" => NullPolicy::Poison,
+        _ => pgx::error!("unknown null_policy. Valid policies are 'ignore', 'error' and 'poison'"),
+    }
+}
+
+// The aggregates taking DOUBLE PRECISION values also have REAL and NUMERIC
+// versions, so those columns don't need a cast, which would keep continuous
+// aggregates from matching expression indexes. All of them summarize f64s.
+pub fn numeric_to_f64(numeric: pg_sys::Datum) -> f64 {
+    use pgx::FromDatum;
+    // pgx's bindings wrap the functions, so we need the C one to call it
+    extern "C" {
+        fn numeric_float8(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe {
+        let float = pg_sys::DirectFunctionCall1Coll(
+            Some(numeric_float8),
+            pg_sys::InvalidOid,
+            numeric,
+        );
+        f64::from_datum(float, false, pg_sys::FLOAT8OID).unwrap()
+    }
+}
+
+// The aggregates taking TIMESTAMPTZ times also have TIMESTAMP and BIGINT
+// versions, so legacy schemas don't need casts either. TIMESTAMPs are read as
+// UTC, which unlike casting them doesn't depend on the session's timezone.
+pub fn timestamp_to_timestamptz(timestamp: pg_sys::Datum) -> pg_sys::TimestampTz {
+    timestamp as i64
+}
+
+// BIGINT times are microseconds since the unix epoch.
+pub fn epoch_micros_to_timestamptz(micros: i64) -> pg_sys::TimestampTz {
+    // microseconds between the unix and Postgres epochs, 1970-01-01 and 2000-01-01
+    const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+    // the range of valid timestamps, Postgres' MIN_TIMESTAMP and END_TIMESTAMP
+    const MIN_TIMESTAMP: i64 = -211_813_488_000_000_000;
+    const END_TIMESTAMP: i64 = 9_223_371_331_200_000_000;
+    match micros.checked_sub(POSTGRES_EPOCH_MICROS) {
+        Some(ts) if (MIN_TIMESTAMP..END_TIMESTAMP).contains(&ts) => ts,
+        _ => pgx::error!("timestamp out of range"),
+    }
+}
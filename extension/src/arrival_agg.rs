@@ -0,0 +1,460 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use serde::{Deserialize, Serialize};
+
+use stats_agg::stats1d::StatsSummary1D as InternalStatsSummary1D;
+
+use uddsketch::UDDSketch as UddSketchInternal;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    uddsketch::{
+        compress_buckets, decompress_counts, decompress_keys, CompressedBuckets,
+        PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE,
+    },
+    utilities::micros_to_interval,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// PG object summarizing the gaps between consecutive events, the gaps are
+// measured in microseconds. `n` through `sx4` are the StatsSummary1D of the
+// gaps, and the remaining fields a uddsketch of them, as stored by
+// UddSketch.
+pg_type! {
+    #[derive(Debug)]
+    struct ArrivalAgg<'input> {
+        first: i64,
+        last: i64,
+        max_gap: i64,
+        n: u64,
+        sx: f64,
+        sx2: f64,
+        sx3: f64,
+        sx4: f64,
+        alpha: f64,
+        max_buckets: u32,
+        num_buckets: u32,
+        compactions: u64,
+        count: u64,
+        sum: f64,
+        zero_bucket_count: u64,
+        neg_indexes_bytes: u32,
+        neg_buckets_bytes: u32,
+        pos_indexes_bytes: u32,
+        pos_buckets_bytes: u32,
+        negative_indexes: [u8; self.neg_indexes_bytes],
+        negative_counts: [u8; self.neg_buckets_bytes],
+        positive_indexes: [u8; self.pos_indexes_bytes],
+        positive_counts: [u8; self.pos_buckets_bytes],
+    }
+}
+
+ron_inout_funcs!(ArrivalAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(ArrivalAgg);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArrivalSummary {
+    first: i64,
+    last: i64,
+    max_gap: i64,
+    gaps: InternalStatsSummary1D,
+    sketch: UddSketchInternal,
+}
+
+impl ArrivalSummary {
+    fn new(ts: i64) -> Self {
+        Self {
+            first: ts,
+            last: ts,
+            max_gap: 0,
+            gaps: InternalStatsSummary1D::new(),
+            sketch: UddSketchInternal::new(PERCENTILE_AGG_DEFAULT_SIZE as u64, PERCENTILE_AGG_DEFAULT_ERROR),
+        }
+    }
+
+    fn add_gap(&mut self, gap: i64) {
+        self.gaps.accum(gap as f64)
+            .unwrap_or_else(|e| error!("error accumulating arrival gap: {:?}", e));
+        self.sketch.add_value(gap as f64);
+        self.max_gap = self.max_gap.max(gap);
+    }
+
+    // the timestamps must be sorted
+    fn from_sorted(timestamps: &[i64]) -> Self {
+        let mut summary = Self::new(timestamps[0]);
+        for pair in timestamps.windows(2) {
+            summary.add_gap(pair[1] - pair[0]);
+        }
+        summary.last = timestamps[timestamps.len() - 1];
+        summary
+    }
+
+    // adds the events of a summary starting after this one ends, along with
+    // the gap between the two
+    fn append(&mut self, other: &ArrivalSummary) {
+        if other.first < self.last {
+            panic!("arrival_agg rollup requires non-overlapping time ranges")
+        }
+        self.add_gap(other.first - self.last);
+        self.gaps = self.gaps.combine(other.gaps)
+            .unwrap_or_else(|e| error!("error combining arrival gaps: {:?}", e));
+        self.sketch.merge_sketch(&other.sketch);
+        self.max_gap = self.max_gap.max(other.max_gap);
+        self.last = other.last;
+    }
+}
+
+// Raw timestamps are buffered until the final function since parallel workers
+// may see interleaved events. Summaries from rollup are assumed to cover
+// disjoint time ranges.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ArrivalTransState {
+    timestamps: Vec<i64>,
+    summaries: Vec<ArrivalSummary>,
+}
+
+impl ArrivalTransState {
+    fn combine(&mut self, other: &ArrivalTransState) {
+        self.timestamps.extend_from_slice(&other.timestamps);
+        self.summaries.extend(other.summaries.iter().cloned());
+    }
+
+    fn summarize(&self) -> Option<ArrivalSummary> {
+        let mut summaries = self.summaries.clone();
+        if !self.timestamps.is_empty() {
+            let mut timestamps = self.timestamps.clone();
+            timestamps.sort_unstable();
+            summaries.push(ArrivalSummary::from_sorted(&timestamps));
+        }
+        summaries.sort_by_key(|summary| summary.first);
+        let mut iter = summaries.into_iter();
+        let mut summary = iter.next()?;
+        for next in iter {
+            summary.append(&next);
+        }
+        Some(summary)
+    }
+}
+
+impl<'input> ArrivalAgg<'input> {
+    fn to_internal(&self) -> ArrivalSummary {
+        let keys = decompress_keys(self.negative_indexes.as_slice(), self.zero_bucket_count != 0, self.positive_indexes.as_slice());
+        let counts = decompress_counts(self.negative_counts.as_slice(), self.zero_bucket_count, self.positive_counts.as_slice());
+        ArrivalSummary {
+            first: self.first,
+            last: self.last,
+            max_gap: self.max_gap,
+            gaps: self.gaps(),
+            sketch: UddSketchInternal::new_from_data(
+                self.max_buckets as u64, self.alpha, self.compactions, self.count, self.sum, keys, counts,
+            ),
+        }
+    }
+
+    fn from_internal(summary: &ArrivalSummary) -> ArrivalAgg<'static> {
+        let CompressedBuckets {
+            negative_indexes,
+            negative_counts,
+            zero_bucket_count,
+            positive_indexes,
+            positive_counts,
+        } = compress_buckets(summary.sketch.bucket_iter());
+        build!(
+            ArrivalAgg {
+                first: summary.first,
+                last: summary.last,
+                max_gap: summary.max_gap,
+                n: summary.gaps.n,
+                sx: summary.gaps.sx,
+                sx2: summary.gaps.sx2,
+                sx3: summary.gaps.sx3,
+                sx4: summary.gaps.sx4,
+                alpha: summary.sketch.max_error(),
+                max_buckets: summary.sketch.max_allowed_buckets() as u32,
+                num_buckets: summary.sketch.current_buckets_count() as u32,
+                compactions: summary.sketch.times_compacted() as u64,
+                count: summary.sketch.count(),
+                sum: summary.sketch.sum(),
+                zero_bucket_count: zero_bucket_count,
+                neg_indexes_bytes: negative_indexes.len() as u32,
+                neg_buckets_bytes: negative_counts.len() as u32,
+                pos_indexes_bytes: positive_indexes.len() as u32,
+                pos_buckets_bytes: positive_counts.len() as u32,
+                negative_indexes: negative_indexes.into(),
+                negative_counts: negative_counts.into(),
+                positive_indexes: positive_indexes.into(),
+                positive_counts: positive_counts.into(),
+            }
+        )
+    }
+
+    fn gaps(&self) -> InternalStatsSummary1D {
+        InternalStatsSummary1D {
+            n: self.n,
+            sx: self.sx,
+            sx2: self.sx2,
+            sx3: self.sx3,
+            sx4: self.sx4,
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn arrival_agg_trans(
+    state: Option<Internal<ArrivalTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ArrivalTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let ts = match ts {
+                None => return state,
+                Some(ts) => ts,
+            };
+            let mut state = state.unwrap_or_else(|| ArrivalTransState::default().into());
+            state.timestamps.push(ts);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn arrival_agg_summary_trans(
+    state: Option<Internal<ArrivalTransState>>,
+    value: Option<toolkit_experimental::ArrivalAgg<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ArrivalTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal(),
+            };
+            let mut state = state.unwrap_or_else(|| ArrivalTransState::default().into());
+            state.summaries.push(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn arrival_agg_combine(
+    state1: Option<Internal<ArrivalTransState>>,
+    state2: Option<Internal<ArrivalTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ArrivalTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.combine(&state2);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn arrival_agg_serialize(
+    state: Internal<ArrivalTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn arrival_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<ArrivalTransState> {
+    crate::do_deserialize!(bytes, ArrivalTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn arrival_agg_final(
+    state: Option<Internal<ArrivalTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::ArrivalAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let summary = state?.summarize()?;
+            ArrivalAgg::from_internal(&summary).into()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.arrival_agg(
+    ts timestamptz
+) (
+    sfunc = toolkit_experimental.arrival_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.arrival_agg_final,
+    combinefunc = toolkit_experimental.arrival_agg_combine,
+    serialfunc = toolkit_experimental.arrival_agg_serialize,
+    deserialfunc = toolkit_experimental.arrival_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    agg toolkit_experimental.ArrivalAgg
+) (
+    sfunc = toolkit_experimental.arrival_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.arrival_agg_final,
+    combinefunc = toolkit_experimental.arrival_agg_combine,
+    serialfunc = toolkit_experimental.arrival_agg_serialize,
+    deserialfunc = toolkit_experimental.arrival_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+// number of events, one more than the number of gaps
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_arrivals(
+    agg: toolkit_experimental::ArrivalAgg<'_>,
+) -> i64 {
+    agg.n as i64 + 1
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn mean_gap(
+    agg: toolkit_experimental::ArrivalAgg<'_>,
+) -> Option<Interval> {
+    agg.gaps().avg().map(|mean| micros_to_interval(mean.round() as i64))
+}
+
+// sample standard deviation of the gaps, NULL with fewer than two gaps
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stddev_gap(
+    agg: toolkit_experimental::ArrivalAgg<'_>,
+) -> Option<Interval> {
+    if agg.n < 2 {
+        return None
+    }
+    agg.gaps().stddev_samp().map(|stddev| micros_to_interval(stddev.round() as i64))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn max_gap(
+    agg: toolkit_experimental::ArrivalAgg<'_>,
+) -> Option<Interval> {
+    (agg.n > 0).then(|| micros_to_interval(agg.max_gap))
+}
+
+// the approximate gap at the given percentile (0.0-1.0), with uddsketch's
+// relative error
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn approx_gap_percentile(
+    percentile: f64,
+    agg: toolkit_experimental::ArrivalAgg<'_>,
+) -> Option<Interval> {
+    if !(0.0..=1.0).contains(&percentile) {
+        error!("percentile must be between 0.0 and 1.0")
+    }
+    if agg.n == 0 {
+        return None
+    }
+    let gap = agg.to_internal().sketch.estimate_quantile(percentile);
+    Some(micros_to_interval(gap.round() as i64))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_arrival_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE scrapes(time timestamptz)", None, None);
+            client.select(
+                "INSERT INTO scrapes VALUES \
+                    ('2020-01-01 00:00:30 UTC'), \
+                    ('2020-01-01 00:00:00 UTC'), \
+                    ('2020-01-01 00:00:10 UTC'), \
+                    ('2020-01-01 00:00:20 UTC'), \
+                    ('2020-01-01 00:01:30 UTC'), \
+                    ('2020-01-01 00:01:40 UTC')",
+                None,
+                None
+            );
+
+            let query = |agg: &str| {
+                client.select(
+                    &format!("SELECT \
+                            num_arrivals(agg), \
+                            mean_gap(agg)::TEXT, \
+                            max_gap(agg)::TEXT, \
+                            stddev_gap(agg)::TEXT, \
+                            (approx_gap_percentile(0.5, agg) BETWEEN '9.9 seconds' AND '10.1 seconds')::TEXT \
+                        FROM ({}) a", agg),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value::<i64>().unwrap(),
+                        row.by_ordinal(2).unwrap().value::<String>().unwrap(),
+                        row.by_ordinal(3).unwrap().value::<String>().unwrap(),
+                        row.by_ordinal(4).unwrap().value::<String>().unwrap(),
+                        row.by_ordinal(5).unwrap().value::<String>().unwrap(),
+                    ))
+                    .next()
+                    .unwrap()
+            };
+
+            // gaps of 10, 10, 10, 60 and 10 seconds
+            let direct = query("SELECT arrival_agg(time) AS agg FROM scrapes");
+            assert_eq!(direct.0, 6);
+            assert_eq!(direct.1, "00:00:20");
+            assert_eq!(direct.2, "00:01:00");
+            assert_eq!(direct.3, "00:00:22.36068");
+            // the median gap is only accurate to uddsketch's error
+            assert_eq!(direct.4, "true");
+
+            // the gap across the rolled up buckets is counted
+            let rolled_up = query("SELECT rollup(agg) AS agg FROM \
+                (SELECT arrival_agg(time) AS agg FROM scrapes \
+                    GROUP BY date_trunc('minute', time)) s");
+            assert_eq!(rolled_up, direct);
+
+            let single = client.select(
+                "SELECT num_arrivals(agg), mean_gap(agg) IS NULL, max_gap(agg) IS NULL \
+                FROM (SELECT arrival_agg(time) AS agg FROM scrapes WHERE time < '2020-01-01 00:00:05') a",
+                None,
+                None
+            )
+                .first()
+                .get_three::<i64, bool, bool>();
+            assert_eq!(single, (Some(1), Some(true), Some(true)));
+        });
+    }
+}
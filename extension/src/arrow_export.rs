@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use pgx::*;
+
+use arrow::{
+    array::{ArrayRef, Float64Array, TimestampMicrosecondArray, UInt64Array},
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+
+use crate::{
+    counter_agg::POSTGRES_EPOCH_IN_UNIX_MICROS,
+    stats_agg::StatsSummary1D,
+};
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+}
+
+// Arrow timestamps count from the unix epoch
+pub(crate) fn timestamp_array(timestamps: impl Iterator<Item = pg_sys::TimestampTz>) -> ArrayRef {
+    let micros: Vec<i64> = timestamps
+        .map(|ts| ts.checked_add(POSTGRES_EPOCH_IN_UNIX_MICROS)
+            .unwrap_or_else(|| error!("timestamp out of range for arrow export")))
+        .collect();
+    Arc::new(TimestampMicrosecondArray::from_vec(micros, Some("UTC".to_string())))
+}
+
+pub(crate) fn timestamp_field(name: &str) -> Field {
+    Field::new(name, DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".to_string())), false)
+}
+
+// A single record batch of the columns in the Arrow IPC streaming format.
+pub(crate) fn to_ipc_stream(fields: Vec<Field>, columns: Vec<ArrayRef>) -> Vec<u8> {
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns)
+        .unwrap_or_else(|e| error!("could not build arrow record batch: {}", e));
+    let mut bytes = vec![];
+    {
+        let mut writer = StreamWriter::try_new(&mut bytes, &schema)
+            .unwrap_or_else(|e| error!("could not write arrow stream: {}", e));
+        writer.write(&batch)
+            .and_then(|_| writer.finish())
+            .unwrap_or_else(|e| error!("could not write arrow stream: {}", e));
+    }
+    bytes
+}
+
+// The points of the series as an Arrow IPC stream with a `time` column of
+// UTC microsecond timestamps and a `value` column of doubles, in time order.
+// Points whose value is NULL are kept, marked in the value column's validity
+// bitmap.
+#[pg_extern(name="to_arrow", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timeseries_to_arrow(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'_>,
+) -> Vec<u8> {
+    let mut points: Vec<_> = series.iter_with_nulls().collect();
+    if !series.is_sorted() {
+        points.sort_by_key(|&(ts, _)| ts);
+    }
+    let values: Vec<Option<f64>> = points.iter().map(|&(_, val)| val).collect();
+    to_ipc_stream(
+        vec![
+            timestamp_field("time"),
+            Field::new("value", DataType::Float64, true),
+        ],
+        vec![
+            timestamp_array(points.iter().map(|&(ts, _)| ts)),
+            Arc::new(Float64Array::from(values)),
+        ],
+    )
+}
+
+// The summary as a single row Arrow IPC stream, with a column for each of
+// its stored sums, so exports can rebuild or combine summaries downstream.
+#[pg_extern(name="to_arrow", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn stats1d_to_arrow(
+    summary: toolkit_experimental::StatsSummary1D,
+)-> Vec<u8> {
+    let summary = summary.to_internal();
+    let sums = [("sx", summary.sx), ("sx2", summary.sx2), ("sx3", summary.sx3), ("sx4", summary.sx4)];
+    let mut fields = vec![Field::new("n", DataType::UInt64, false)];
+    fields.extend(sums.iter().map(|(name, _)| Field::new(name, DataType::Float64, false)));
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(UInt64Array::from(vec![summary.n]))];
+    columns.extend(sums.iter().map(|&(_, sum)| Arc::new(Float64Array::from(vec![sum])) as ArrayRef));
+    to_ipc_stream(fields, columns)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_to_arrow() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            let (empty, three) = client.select(
+                "SELECT \
+                    length(to_arrow(timeseries(time, value) FILTER (WHERE value > 10))), \
+                    length(to_arrow(timeseries(time, value))) \
+                FROM (VALUES \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0::DOUBLE PRECISION), \
+                    ('2020-01-01 UTC', 1), \
+                    ('2020-01-03 UTC', 3)) v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i32, i32>();
+            // no timeseries is built from no points
+            assert_eq!(empty, None);
+            assert!(three.unwrap() > 0);
+
+            // streams start with the continuation marker, and the time column
+            // holds the unix microseconds in order
+            let bytes = client.select(
+                "SELECT to_arrow(timeseries(time, value)) FROM (VALUES \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0::DOUBLE PRECISION), \
+                    ('2020-01-01 UTC', 1)) v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<Vec<u8>>()
+                .unwrap();
+            assert_eq!(&bytes[..4], &[0xff, 0xff, 0xff, 0xff]);
+            let micros = |ts: i64| (ts * 1_000_000).to_le_bytes();
+            let first = micros(1577836800);
+            let second = micros(1577923200);
+            let position = bytes.windows(8).position(|w| w == first).expect("first timestamp missing");
+            assert_eq!(&bytes[position + 8..position + 16], &second);
+            let position = bytes.windows(8).position(|w| w == 1.0f64.to_le_bytes()).expect("first value missing");
+            assert_eq!(&bytes[position + 8..position + 16], &2.0f64.to_le_bytes());
+
+            // points with NULL values keep their slot, the value column's
+            // validity bitmap marks them
+            let bytes = client.select(
+                "SELECT to_arrow(timeseries_with_nulls(time, value)) FROM (VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0::DOUBLE PRECISION), \
+                    ('2020-01-02 UTC', NULL), \
+                    ('2020-01-03 UTC', 3)) v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<Vec<u8>>()
+                .unwrap();
+            let position = bytes.windows(8).position(|w| w == first).expect("first timestamp missing");
+            assert_eq!(&bytes[position + 8..position + 16], &second);
+            assert_eq!(&bytes[position + 16..position + 24], &micros(1578009600));
+            let position = bytes.windows(8).position(|w| w == 1.0f64.to_le_bytes()).expect("first value missing");
+            assert_eq!(&bytes[position + 16..position + 24], &3.0f64.to_le_bytes());
+        });
+    }
+
+    #[pg_test]
+    fn test_stats1d_to_arrow() {
+        Spi::execute(|client| {
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            let bytes = client.select(
+                "SELECT to_arrow(stats_agg(v)) FROM (VALUES (1.5::DOUBLE PRECISION), (2.5)) v(v)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<Vec<u8>>()
+                .unwrap();
+            assert_eq!(&bytes[..4], &[0xff, 0xff, 0xff, 0xff]);
+            // the sum is stored in the body of the record batch
+            assert!(bytes.windows(8).any(|w| w == 4.0f64.to_le_bytes()));
+        });
+    }
+}
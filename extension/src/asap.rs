@@ -132,6 +132,8 @@ fn asap_final(
 
             Some(crate::build! {
                 TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
                     series: SeriesType::NormalSeries {
                         start_ts: start_ts,
                         // Set the step interval for the asap result so that it covers the same interval
@@ -152,6 +154,7 @@ pub fn asap_on_timeseries(
     resolution: i32
 ) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
     // TODO: implement this using zero copy (requires sort, find_downsample_interval, and downsample_and_gapfill on TimeSeries)
+    series = series.decompress().without_nulls();
     let needs_sort = matches!(&series.series, SeriesType::ExplicitSeries{..});
     let start_ts;
     let downsample_interval;
@@ -179,6 +182,8 @@ pub fn asap_on_timeseries(
         },
         SeriesType::GappyNormalSeries { .. } =>
             panic!("Series must be gapfilled before running asap smoothing"),
+        SeriesType::NullableSeries { .. } | SeriesType::CompressedSeries { .. } =>
+            unreachable!(),
     };
 
     // Drop the last value to match the reference implementation
@@ -188,6 +193,8 @@ pub fn asap_on_timeseries(
 
     Some(crate::build! {
         TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: SeriesType::NormalSeries {
                 start_ts: start_ts,
                 // Set the step interval for the asap result so that it covers the same interval
@@ -355,4 +362,37 @@ mod tests {
             assert_eq!(delta.unwrap(), 0);
         });
     }
+
+    #[pg_test]
+    fn test_asap_with_nulls() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select("CREATE TABLE asap_test (date timestamptz, value DOUBLE PRECISION)", None, None);
+            client.select(
+                "insert into asap_test \
+                select '2020-1-1 UTC'::timestamptz + make_interval(days=>foo), \
+                    CASE WHEN foo % 7 = 0 THEN NULL ELSE 10 + 5 * cos(foo) END \
+                from generate_series(0,1000) foo",
+                None,
+                None
+            );
+
+            // points with NULL values are skipped, the same as the asap aggregate does
+            let delta = client
+                .select(
+                    "SELECT count(*) \
+                    FROM toolkit_experimental.unnest( \
+                        (SELECT toolkit_experimental.asap_smooth(date, value, 100) FROM asap_test) \
+                    ) r1 FULL OUTER JOIN toolkit_experimental.unnest( \
+                        toolkit_experimental.asap_smooth((SELECT toolkit_experimental.timeseries_with_nulls(date, value) FROM asap_test), 100) \
+                    ) r2 ON r1 = r2 \
+                    WHERE r1 IS NULL OR r2 IS NULL",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(delta.unwrap(), 0);
+        });
+    }
 }
@@ -0,0 +1,1138 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use time_series::TSPoint;
+
+use crate::time_series::drawdown::max_drawdown;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// PG object summarizing the prices of a bucket of trades as a candlestick,
+// each of open, high, low and close remembers when it occurred
+pg_type! {
+    #[derive(Debug, PartialEq)]
+    struct Candlestick {
+        open: TSPoint,
+        high: TSPoint,
+        low: TSPoint,
+        close: TSPoint,
+        volume: f64,
+        // sum of price * volume over all trades
+        price_volume: f64,
+    }
+}
+
+ron_inout_funcs!(Candlestick);
+
+// a single candlestick within CandlestickBars
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct Bar {
+    open: TSPoint,
+    high: TSPoint,
+    low: TSPoint,
+    close: TSPoint,
+    volume: f64,
+    price_volume: f64,
+}
+
+// PG object holding the candlesticks of a series of trades cut whenever the
+// trades accumulate a given number of ticks, volume, or notional value
+pg_type! {
+    #[derive(Debug)]
+    struct CandlestickBars<'input> {
+        num_bars: u64,
+        bars: [Bar; self.num_bars],
+    }
+}
+
+ron_inout_funcs!(CandlestickBars);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(Candlestick);
+    varlena_type!(CandlestickBars);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CandlestickTransState {
+    open: TSPoint,
+    high: TSPoint,
+    low: TSPoint,
+    close: TSPoint,
+    volume: f64,
+    price_volume: f64,
+}
+
+impl CandlestickTransState {
+    fn new(point: TSPoint, volume: f64) -> Self {
+        Self {
+            open: point,
+            high: point,
+            low: point,
+            close: point,
+            volume,
+            price_volume: point.val * volume,
+        }
+    }
+
+    // on ties the open and the extremes keep the earliest time, the close the latest
+    fn combine(&mut self, other: &CandlestickTransState) {
+        if other.open.ts < self.open.ts {
+            self.open = other.open;
+        }
+        if other.high.val > self.high.val
+            || (other.high.val == self.high.val && other.high.ts < self.high.ts) {
+            self.high = other.high;
+        }
+        if other.low.val < self.low.val
+            || (other.low.val == self.low.val && other.low.ts < self.low.ts) {
+            self.low = other.low;
+        }
+        if other.close.ts >= self.close.ts {
+            self.close = other.close;
+        }
+        self.volume += other.volume;
+        self.price_volume += other.price_volume;
+    }
+}
+
+impl From<&CandlestickTransState> for Bar {
+    fn from(state: &CandlestickTransState) -> Self {
+        Self {
+            open: state.open,
+            high: state.high,
+            low: state.low,
+            close: state.close,
+            volume: state.volume,
+            price_volume: state.price_volume,
+        }
+    }
+}
+
+impl<'input> From<&Candlestick<'input>> for CandlestickTransState {
+    fn from(candlestick: &Candlestick<'input>) -> Self {
+        Self {
+            open: candlestick.open,
+            high: candlestick.high,
+            low: candlestick.low,
+            close: candlestick.close,
+            volume: candlestick.volume,
+            price_volume: candlestick.price_volume,
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_serialize(
+    state: Internal<CandlestickTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn candlestick_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<CandlestickTransState> {
+    crate::do_deserialize!(bytes, CandlestickTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_trans(
+    state: Option<Internal<CandlestickTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CandlestickTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let point = match (ts, price) {
+                (Some(ts), Some(val)) => TSPoint { ts, val },
+                _ => return state,
+            };
+            let next = CandlestickTransState::new(point, volume.unwrap_or(0.0));
+            match state {
+                None => Some(next.into()),
+                Some(mut state) => {
+                    state.combine(&next);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_summary_trans(
+    state: Option<Internal<CandlestickTransState>>,
+    next: Option<toolkit_experimental::Candlestick<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CandlestickTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => CandlestickTransState::from(&next),
+            };
+            match state {
+                None => Some(next.into()),
+                Some(mut state) => {
+                    state.combine(&next);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_combine(
+    state1: Option<Internal<CandlestickTransState>>,
+    state2: Option<Internal<CandlestickTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CandlestickTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.combine(&state2);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn candlestick_final(
+    state: Option<Internal<CandlestickTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::Candlestick<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            Some(build!(
+                Candlestick {
+                    open: state.open,
+                    high: state.high,
+                    low: state.low,
+                    close: state.close,
+                    volume: state.volume,
+                    price_volume: state.price_volume,
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.candlestick_agg(
+    ts timestamptz,
+    price double precision,
+    volume double precision
+) (
+    sfunc = toolkit_experimental.candlestick_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_final,
+    combinefunc = toolkit_experimental.candlestick_combine,
+    serialfunc = toolkit_experimental.candlestick_serialize,
+    deserialfunc = toolkit_experimental.candlestick_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    candlestick toolkit_experimental.Candlestick
+) (
+    sfunc = toolkit_experimental.candlestick_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_final,
+    combinefunc = toolkit_experimental.candlestick_combine,
+    serialfunc = toolkit_experimental.candlestick_serialize,
+    deserialfunc = toolkit_experimental.candlestick_deserialize,
+    parallel = restricted
+);
+"#);
+
+// Builds a candlestick from an already aggregated bar starting at `ts`, so
+// that it can be rolled up with candlesticks from candlestick_agg. The time
+// at which the high and low occurred within the bar is unknown, so the bar's
+// start is used throughout, and its typical price stands in for the average
+// traded price.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick(
+    ts: Option<pg_sys::TimestampTz>,
+    open: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    close: Option<f64>,
+    volume: Option<f64>,
+) -> Option<toolkit_experimental::Candlestick<'static>> {
+    let (ts, open, high, low, close) = match (ts, open, high, low, close) {
+        (Some(ts), Some(open), Some(high), Some(low), Some(close)) =>
+            (ts, open, high, low, close),
+        _ => return None,
+    };
+    if high < open.max(low).max(close) || low > open.min(close) {
+        error!("candlestick high must be at least, and low at most, each of its prices")
+    }
+    let volume = volume.unwrap_or(0.0);
+    let typical_price = (high + low + close) / 3.0;
+    Some(build!(
+        Candlestick {
+            open: TSPoint { ts, val: open },
+            high: TSPoint { ts, val: high },
+            low: TSPoint { ts, val: low },
+            close: TSPoint { ts, val: close },
+            volume: volume,
+            price_volume: typical_price * volume,
+        }
+    ))
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_open(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorOpen,
+) -> f64 {
+    let _ = accessor;
+    candlestick_open(candlestick)
+}
+
+#[pg_extern(name="open", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_open(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    candlestick.open.val
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_open_time(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorOpenTime,
+) -> pg_sys::TimestampTz {
+    let _ = accessor;
+    candlestick_open_time(candlestick)
+}
+
+#[pg_extern(name="open_time", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_open_time(
+    candlestick: toolkit_experimental::Candlestick,
+) -> pg_sys::TimestampTz {
+    candlestick.open.ts
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_high(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorHigh,
+) -> f64 {
+    let _ = accessor;
+    candlestick_high(candlestick)
+}
+
+#[pg_extern(name="high", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_high(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    candlestick.high.val
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_high_time(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorHighTime,
+) -> pg_sys::TimestampTz {
+    let _ = accessor;
+    candlestick_high_time(candlestick)
+}
+
+#[pg_extern(name="high_time", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_high_time(
+    candlestick: toolkit_experimental::Candlestick,
+) -> pg_sys::TimestampTz {
+    candlestick.high.ts
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_low(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorLow,
+) -> f64 {
+    let _ = accessor;
+    candlestick_low(candlestick)
+}
+
+#[pg_extern(name="low", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_low(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    candlestick.low.val
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_low_time(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorLowTime,
+) -> pg_sys::TimestampTz {
+    let _ = accessor;
+    candlestick_low_time(candlestick)
+}
+
+#[pg_extern(name="low_time", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_low_time(
+    candlestick: toolkit_experimental::Candlestick,
+) -> pg_sys::TimestampTz {
+    candlestick.low.ts
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_close(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorClose,
+) -> f64 {
+    let _ = accessor;
+    candlestick_close(candlestick)
+}
+
+#[pg_extern(name="close", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_close(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    candlestick.close.val
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_close_time(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorCloseTime,
+) -> pg_sys::TimestampTz {
+    let _ = accessor;
+    candlestick_close_time(candlestick)
+}
+
+#[pg_extern(name="close_time", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_close_time(
+    candlestick: toolkit_experimental::Candlestick,
+) -> pg_sys::TimestampTz {
+    candlestick.close.ts
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_volume(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorVolume,
+) -> f64 {
+    let _ = accessor;
+    candlestick_volume(candlestick)
+}
+
+#[pg_extern(name="volume", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_volume(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    candlestick.volume
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_vwap(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorVwap,
+) -> Option<f64> {
+    let _ = accessor;
+    candlestick_vwap(candlestick)
+}
+
+// the volume-weighted average price, NULL if no volume was traded
+#[pg_extern(name="vwap", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_vwap(
+    candlestick: toolkit_experimental::Candlestick,
+) -> Option<f64> {
+    if candlestick.volume == 0.0 {
+        return None
+    }
+    Some(candlestick.price_volume / candlestick.volume)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_typical_price(
+    candlestick: toolkit_experimental::Candlestick,
+    accessor: toolkit_experimental::AccessorTypicalPrice,
+) -> f64 {
+    let _ = accessor;
+    candlestick_typical_price(candlestick)
+}
+
+// the average of the high, low and close
+#[pg_extern(name="typical_price", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_typical_price(
+    candlestick: toolkit_experimental::Candlestick,
+) -> f64 {
+    (candlestick.high.val + candlestick.low.val + candlestick.close.val) / 3.0
+}
+
+// the open, high, low and close, in that order, each at the time it occurred
+#[pg_extern(name="into_values", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn candlestick_into_values(
+    candlestick: toolkit_experimental::Candlestick,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(value,f64),name!(point,String))> {
+    [
+        (candlestick.open, "open"),
+        (candlestick.high, "high"),
+        (candlestick.low, "low"),
+        (candlestick.close, "close"),
+    ]
+        .iter()
+        .map(|(point, name)| (point.ts, point.val, name.to_string()))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+// always the four points, so the planner doesn't need to assume 1000
+extension_sql!(r#"
+ALTER FUNCTION toolkit_experimental.into_values(toolkit_experimental.Candlestick) ROWS 4;
+"#);
+
+// The path of prices across a series of candlesticks, each contributes its
+// open, high, low and close at the times they occurred.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DrawdownTransState {
+    points: Vec<TSPoint>,
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_drawdown_serialize(
+    state: Internal<DrawdownTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn candlestick_drawdown_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<DrawdownTransState> {
+    crate::do_deserialize!(bytes, DrawdownTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_drawdown_trans(
+    state: Option<Internal<DrawdownTransState>>,
+    candlestick: Option<toolkit_experimental::Candlestick<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<DrawdownTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let candlestick = match candlestick {
+                None => return state,
+                Some(candlestick) => candlestick,
+            };
+            let mut state = match state {
+                None => DrawdownTransState { points: vec![] }.into(),
+                Some(state) => state,
+            };
+            state.points.extend_from_slice(&[
+                candlestick.open,
+                candlestick.high,
+                candlestick.low,
+                candlestick.close,
+            ]);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_drawdown_combine(
+    state1: Option<Internal<DrawdownTransState>>,
+    state2: Option<Internal<DrawdownTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<DrawdownTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.points.extend_from_slice(&state2.points);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+// the largest peak-to-trough decline across the candlesticks, use
+// max_drawdown() on a timeseries to also get when it happened
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn candlestick_drawdown_final(
+    state: Option<Internal<DrawdownTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut points = state?.points.clone();
+            // stable, so a candlestick's prices at the same time keep their order
+            points.sort_by_key(|point| point.ts);
+            max_drawdown(points.into_iter()).map(|drawdown| drawdown.drawdown)
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.max_drawdown(
+    candlestick toolkit_experimental.Candlestick
+) (
+    sfunc = toolkit_experimental.candlestick_drawdown_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_drawdown_final,
+    combinefunc = toolkit_experimental.candlestick_drawdown_combine,
+    serialfunc = toolkit_experimental.candlestick_drawdown_serialize,
+    deserialfunc = toolkit_experimental.candlestick_drawdown_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BarKind {
+    Tick,
+    Volume,
+    Dollar,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BarsTransState {
+    kind: BarKind,
+    threshold: f64,
+    // (trade, volume)
+    trades: Vec<(TSPoint, f64)>,
+}
+
+impl BarsTransState {
+    // Cuts the trades, in time order, into bars: a bar is closed by the trade
+    // that brings its ticks, volume, or notional value up to the threshold.
+    // The last bar may not have reached the threshold.
+    fn bars(&self) -> Vec<Bar> {
+        let mut trades = self.trades.clone();
+        trades.sort_by_key(|(trade, _)| trade.ts);
+
+        let mut bars = vec![];
+        let mut current: Option<(CandlestickTransState, f64)> = None;
+        for (trade, volume) in trades {
+            let size = match self.kind {
+                BarKind::Tick => 1.0,
+                BarKind::Volume => volume,
+                BarKind::Dollar => trade.val * volume,
+            };
+            let next = CandlestickTransState::new(trade, volume);
+            let (bar, accumulated) = match current.take() {
+                None => (next, size),
+                Some((mut bar, accumulated)) => {
+                    bar.combine(&next);
+                    (bar, accumulated + size)
+                },
+            };
+            if accumulated >= self.threshold {
+                bars.push(Bar::from(&bar));
+            } else {
+                current = Some((bar, accumulated));
+            }
+        }
+        if let Some((bar, _)) = current {
+            bars.push(Bar::from(&bar));
+        }
+        bars
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_bars_serialize(
+    state: Internal<BarsTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn candlestick_bars_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<BarsTransState> {
+    crate::do_deserialize!(bytes, BarsTransState)
+}
+
+fn bars_trans(
+    state: Option<Internal<BarsTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    kind: BarKind,
+    threshold: f64,
+) -> Option<Internal<BarsTransState>> {
+    let trade = match (ts, price) {
+        (Some(ts), Some(val)) => TSPoint { ts, val },
+        _ => return state,
+    };
+    let mut state = match state {
+        None => {
+            if !(threshold > 0.0) {
+                error!("bar threshold must be positive")
+            }
+            BarsTransState { kind, threshold, trades: vec![] }.into()
+        },
+        Some(state) => state,
+    };
+    state.trades.push((trade, volume.unwrap_or(0.0)));
+    Some(state)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tick_bars_trans(
+    state: Option<Internal<BarsTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    ticks: i64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<BarsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            bars_trans(state, ts, price, volume, BarKind::Tick, ticks as f64)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn volume_bars_trans(
+    state: Option<Internal<BarsTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    bar_volume: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<BarsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            bars_trans(state, ts, price, volume, BarKind::Volume, bar_volume)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn dollar_bars_trans(
+    state: Option<Internal<BarsTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    bar_value: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<BarsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            bars_trans(state, ts, price, volume, BarKind::Dollar, bar_value)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_bars_combine(
+    state1: Option<Internal<BarsTransState>>,
+    state2: Option<Internal<BarsTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<BarsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.trades.extend_from_slice(&state2.trades);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn candlestick_bars_final(
+    state: Option<Internal<BarsTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::CandlestickBars<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let bars = state?.bars();
+            Some(build!(
+                CandlestickBars {
+                    num_bars: bars.len() as _,
+                    bars: bars.into(),
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.tick_bars(
+    ts timestamptz,
+    price double precision,
+    volume double precision,
+    ticks bigint
+) (
+    sfunc = toolkit_experimental.tick_bars_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_bars_final,
+    combinefunc = toolkit_experimental.candlestick_bars_combine,
+    serialfunc = toolkit_experimental.candlestick_bars_serialize,
+    deserialfunc = toolkit_experimental.candlestick_bars_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.volume_bars(
+    ts timestamptz,
+    price double precision,
+    volume double precision,
+    bar_volume double precision
+) (
+    sfunc = toolkit_experimental.volume_bars_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_bars_final,
+    combinefunc = toolkit_experimental.candlestick_bars_combine,
+    serialfunc = toolkit_experimental.candlestick_bars_serialize,
+    deserialfunc = toolkit_experimental.candlestick_bars_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.dollar_bars(
+    ts timestamptz,
+    price double precision,
+    volume double precision,
+    bar_value double precision
+) (
+    sfunc = toolkit_experimental.dollar_bars_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.candlestick_bars_final,
+    combinefunc = toolkit_experimental.candlestick_bars_combine,
+    serialfunc = toolkit_experimental.candlestick_bars_serialize,
+    deserialfunc = toolkit_experimental.candlestick_bars_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_bars(
+    bars: toolkit_experimental::CandlestickBars<'_>,
+) -> i64 {
+    bars.num_bars as _
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bars(
+    bars: toolkit_experimental::CandlestickBars<'_>,
+) -> impl std::iter::Iterator<Item = (name!(bar_start,pg_sys::TimestampTz),name!(bar_end,pg_sys::TimestampTz),name!(candlestick,toolkit_experimental::Candlestick<'static>))> + '_ {
+    bars.bars.iter()
+        .map(|bar| {
+            let candlestick = build!(
+                Candlestick {
+                    open: bar.open,
+                    high: bar.high,
+                    low: bar.low,
+                    close: bar.close,
+                    volume: bar.volume,
+                    price_volume: bar.price_volume,
+                }
+            );
+            (bar.open.ts, bar.close.ts, candlestick)
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_candlestick_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE trades(ts timestamptz, price double precision, volume double precision)", None, None);
+            client.select(
+                "INSERT INTO trades VALUES \
+                    ('2020-01-01 00:00:00+00', 10.0, 100), \
+                    ('2020-01-01 00:20:00+00', 14.0, 50), \
+                    ('2020-01-01 00:40:00+00', 9.0, 10), \
+                    ('2020-01-01 01:10:00+00', 15.0, 20), \
+                    ('2020-01-01 01:30:00+00', 8.0, 30), \
+                    ('2020-01-01 01:50:00+00', 12.0, NULL)",
+                None,
+                None
+            );
+
+            let ohlc = |agg: &str| -> (f64, f64, f64, f64, f64) {
+                client.select(
+                    &format!("SELECT open(c), high(c), low(c), close(c), volume(c) FROM ({}) a(c)", agg),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                        row.by_ordinal(3).unwrap().value().unwrap(),
+                        row.by_ordinal(4).unwrap().value().unwrap(),
+                        row.by_ordinal(5).unwrap().value().unwrap(),
+                    ))
+                    .next()
+                    .unwrap()
+            };
+
+            let agg = "SELECT candlestick_agg(ts, price, volume) FROM trades";
+            assert_eq!(ohlc(agg), (10.0, 15.0, 8.0, 12.0, 210.0));
+
+            let rollup = "SELECT rollup(c) FROM ( \
+                SELECT candlestick_agg(ts, price, volume) AS c \
+                FROM trades GROUP BY date_trunc('hour', ts)) h";
+            assert_eq!(ohlc(rollup), (10.0, 15.0, 8.0, 12.0, 210.0));
+
+            let val = client.select(
+                &format!("SELECT high_time(c)::TEXT FROM ({}) a(c)", rollup),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "2020-01-01 01:10:00+00");
+
+            let (open_time, low_time) = client.select(
+                &format!("SELECT (c->open_time())::TEXT, (c->low_time())::TEXT FROM ({}) a(c)", agg),
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(open_time.unwrap(), "2020-01-01 00:00:00+00");
+            assert_eq!(low_time.unwrap(), "2020-01-01 01:30:00+00");
+
+            let (close, close_time) = client.select(
+                &format!("SELECT c->close(), (c->close_time())::TEXT FROM ({}) a(c)", rollup),
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, String>();
+            assert_eq!(close.unwrap(), 12.0);
+            assert_eq!(close_time.unwrap(), "2020-01-01 01:50:00+00");
+
+            let (vwap, typical_price) = client.select(
+                &format!("SELECT vwap(c), c->typical_price() FROM ({}) a(c)", rollup),
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(vwap.unwrap(), 2330.0 / 210.0);
+            assert_eq!(typical_price.unwrap(), 35.0 / 3.0);
+
+            let points: Vec<(String, f64, String)> = client.select(
+                &format!("SELECT v.time::TEXT, v.value, v.point FROM ({}) a(c), into_values(c) v", rollup),
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(points, vec![
+                ("2020-01-01 00:00:00+00".to_string(), 10.0, "open".to_string()),
+                ("2020-01-01 01:10:00+00".to_string(), 15.0, "high".to_string()),
+                ("2020-01-01 01:30:00+00".to_string(), 8.0, "low".to_string()),
+                ("2020-01-01 01:50:00+00".to_string(), 12.0, "close".to_string()),
+            ]);
+
+            let val = client.select(
+                "SELECT vwap(candlestick_agg(ts, price, 0)) FROM trades",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_candlestick_bars() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE trades(ts timestamptz, price double precision, volume double precision)", None, None);
+            client.select(
+                "INSERT INTO trades VALUES \
+                    ('2020-01-01 00:00:00+00', 10.0, 100), \
+                    ('2020-01-01 00:20:00+00', 14.0, 50), \
+                    ('2020-01-01 00:40:00+00', 9.0, 10), \
+                    ('2020-01-01 01:10:00+00', 15.0, 20), \
+                    ('2020-01-01 01:30:00+00', 8.0, 30), \
+                    ('2020-01-01 01:50:00+00', 12.0, NULL)",
+                None,
+                None
+            );
+
+            let bars = |agg: &str| -> Vec<(String, f64, f64, f64, f64, f64)> {
+                client.select(
+                    &format!("SELECT bar_start::TEXT, open(candlestick), high(candlestick), \
+                        low(candlestick), close(candlestick), volume(candlestick) \
+                        FROM bars((SELECT {} FROM trades))", agg),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                        row.by_ordinal(3).unwrap().value().unwrap(),
+                        row.by_ordinal(4).unwrap().value().unwrap(),
+                        row.by_ordinal(5).unwrap().value().unwrap(),
+                        row.by_ordinal(6).unwrap().value().unwrap(),
+                    ))
+                    .collect()
+            };
+
+            assert_eq!(bars("tick_bars(ts, price, volume, 4)"), vec![
+                ("2020-01-01 00:00:00+00".to_string(), 10.0, 15.0, 9.0, 15.0, 180.0),
+                ("2020-01-01 01:30:00+00".to_string(), 8.0, 12.0, 8.0, 12.0, 30.0),
+            ]);
+
+            assert_eq!(bars("volume_bars(ts, price, volume, 120)"), vec![
+                ("2020-01-01 00:00:00+00".to_string(), 10.0, 14.0, 10.0, 14.0, 150.0),
+                ("2020-01-01 00:40:00+00".to_string(), 9.0, 15.0, 8.0, 12.0, 60.0),
+            ]);
+
+            assert_eq!(bars("dollar_bars(ts, price, volume, 1000)"), vec![
+                ("2020-01-01 00:00:00+00".to_string(), 10.0, 10.0, 10.0, 10.0, 100.0),
+                ("2020-01-01 00:20:00+00".to_string(), 14.0, 15.0, 9.0, 15.0, 80.0),
+                ("2020-01-01 01:30:00+00".to_string(), 8.0, 12.0, 8.0, 12.0, 30.0),
+            ]);
+
+            let val = client.select(
+                "SELECT num_bars(volume_bars(ts, price, volume, 1000)) FROM trades",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val.unwrap(), 1);
+        });
+    }
+
+    #[pg_test]
+    fn test_candlestick_from_bars() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE bars(ts timestamptz, open float, high float, low float, close float, volume float)", None, None);
+            client.select(
+                "INSERT INTO bars VALUES \
+                    ('2020-01-01 00:00:00+00', 10.0, 12.0, 9.0, 11.0, 100), \
+                    ('2020-01-01 00:01:00+00', 11.0, 16.0, 11.0, 15.0, 200), \
+                    ('2020-01-01 00:02:00+00', 15.0, 15.0, 7.0, 8.0, 100)",
+                None,
+                None
+            );
+
+            let hourly = "SELECT rollup(candlestick(ts, open, high, low, close, volume)) FROM bars";
+            let (open, close) = client.select(
+                &format!("SELECT open(c), close(c) FROM ({}) a(c)", hourly),
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(open.unwrap(), 10.0);
+            assert_eq!(close.unwrap(), 8.0);
+
+            let (high_time, low_time) = client.select(
+                &format!("SELECT high_time(c)::TEXT, low_time(c)::TEXT FROM ({}) a(c)", hourly),
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(high_time.unwrap(), "2020-01-01 00:01:00+00");
+            assert_eq!(low_time.unwrap(), "2020-01-01 00:02:00+00");
+
+            let (volume, vwap) = client.select(
+                &format!("SELECT volume(c), vwap(c) FROM ({}) a(c)", hourly),
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(volume.unwrap(), 400.0);
+            let expected = (32.0 / 3.0 * 100.0 + 14.0 * 200.0 + 10.0 * 100.0) / 400.0;
+            assert!((vwap.unwrap() - expected).abs() < 1e-9);
+
+            // 16 at 00:01 down to 7 at 00:02
+            let val = client.select(
+                "SELECT max_drawdown(candlestick(ts, open, high, low, close, volume)) FROM bars",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 9.0);
+        });
+    }
+}
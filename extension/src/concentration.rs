@@ -0,0 +1,277 @@
+use pgx::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    palloc::Internal,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// Measures of how unevenly a total is shared out, e.g. the load of each tenant
+// or shard within a time bucket. Both take non-negative shares, and are NULL
+// when there are no values or they sum to 0.
+
+// The Gini coefficient needs the shares in order, so every value is kept.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GiniTransState {
+    values: Vec<f64>,
+}
+
+impl GiniTransState {
+    // 0 when every share is equal, approaching 1 as a single value takes
+    // everything
+    fn gini(&mut self) -> Option<f64> {
+        let n = self.values.len() as f64;
+        let sum: f64 = self.values.iter().sum();
+        if !(sum > 0.0) {
+            return None
+        }
+        self.values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let weighted: f64 = self.values.iter().enumerate()
+            .map(|(i, value)| (2.0 * (i + 1) as f64 - n - 1.0) * value)
+            .sum();
+        Some(weighted / (n * sum))
+    }
+}
+
+// The Herfindahl index only needs the sum of the shares and of their squares.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HerfindahlTransState {
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl HerfindahlTransState {
+    // the sum of the squared fractions of the total, 1/n when every share is
+    // equal and 1 when a single value takes everything
+    fn herfindahl(&self) -> Option<f64> {
+        if !(self.sum > 0.0) {
+            return None
+        }
+        Some(self.sum_squares / (self.sum * self.sum))
+    }
+}
+
+fn check_share(value: f64) -> f64 {
+    if !(value >= 0.0 && value.is_finite()) {
+        error!("concentration aggregates require finite, non-negative values")
+    }
+    value
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn gini_agg_serialize(
+    state: Internal<GiniTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn gini_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<GiniTransState> {
+    crate::do_deserialize!(bytes, GiniTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn gini_agg_trans(
+    state: Option<Internal<GiniTransState>>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<GiniTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => check_share(value),
+            };
+            let mut state = state.unwrap_or_else(|| GiniTransState::default().into());
+            state.values.push(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn gini_agg_combine(
+    state1: Option<Internal<GiniTransState>>,
+    state2: Option<Internal<GiniTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<GiniTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.values.extend_from_slice(&state2.values);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn gini_agg_final(
+    state: Option<Internal<GiniTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state?.clone().gini()
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn herfindahl_agg_serialize(
+    state: Internal<HerfindahlTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn herfindahl_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<HerfindahlTransState> {
+    crate::do_deserialize!(bytes, HerfindahlTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn herfindahl_agg_trans(
+    state: Option<Internal<HerfindahlTransState>>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<HerfindahlTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => check_share(value),
+            };
+            let mut state = state.unwrap_or_else(|| HerfindahlTransState::default().into());
+            state.sum += value;
+            state.sum_squares += value * value;
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn herfindahl_agg_combine(
+    state1: Option<Internal<HerfindahlTransState>>,
+    state2: Option<Internal<HerfindahlTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<HerfindahlTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.sum += state2.sum;
+                    state.sum_squares += state2.sum_squares;
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn herfindahl_agg_final(
+    state: Option<Internal<HerfindahlTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state?.herfindahl()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.gini_agg(value double precision) (
+    sfunc = toolkit_experimental.gini_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.gini_agg_final,
+    combinefunc = toolkit_experimental.gini_agg_combine,
+    serialfunc = toolkit_experimental.gini_agg_serialize,
+    deserialfunc = toolkit_experimental.gini_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.herfindahl_agg(value double precision) (
+    sfunc = toolkit_experimental.herfindahl_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.herfindahl_agg_final,
+    combinefunc = toolkit_experimental.herfindahl_agg_combine,
+    serialfunc = toolkit_experimental.herfindahl_agg_serialize,
+    deserialfunc = toolkit_experimental.herfindahl_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_concentration_aggs() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select("CREATE TABLE load(bucket INTEGER, tenant INTEGER, requests DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO load VALUES \
+                    (1, 1, 10), (1, 2, 10), (1, 3, 10), (1, 4, 10), \
+                    (2, 1, 0), (2, 2, 0), (2, 3, 0), (2, 4, 40), \
+                    (3, 1, 1), (3, 2, 2), (3, 3, 3), (3, 4, NULL), \
+                    (4, 1, 0), (4, 2, 0)",
+                None,
+                None
+            );
+
+            let results: Vec<(Option<f64>, Option<f64>)> = client.select(
+                "SELECT gini_agg(requests), herfindahl_agg(requests) FROM load GROUP BY bucket ORDER BY bucket",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value(),
+                    row.by_ordinal(2).unwrap().value(),
+                ))
+                .collect();
+            // evenly shared
+            assert!((results[0].0.unwrap() - 0.0).abs() < 1e-12);
+            assert!((results[0].1.unwrap() - 0.25).abs() < 1e-12);
+            // a single tenant takes everything
+            assert!((results[1].0.unwrap() - 0.75).abs() < 1e-12);
+            assert!((results[1].1.unwrap() - 1.0).abs() < 1e-12);
+            // NULLs are ignored
+            assert!((results[2].0.unwrap() - 2.0 / 9.0).abs() < 1e-12);
+            assert!((results[2].1.unwrap() - 14.0 / 36.0).abs() < 1e-12);
+            // nothing to share
+            assert_eq!(results[3], (None, None));
+        });
+    }
+
+    #[pg_test(error = "concentration aggregates require finite, non-negative values")]
+    fn test_gini_agg_negative() {
+        Spi::execute(|client| {
+            client.select("SELECT toolkit_experimental.gini_agg(v) FROM (VALUES (1.0), (-1.0)) t(v)", None, None);
+        });
+    }
+}
@@ -1,6 +1,8 @@
 use serde::{Serialize, Deserialize};
 
 use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
     slice,
 };
 
@@ -10,18 +12,22 @@ use pg_sys::Datum;
 use flat_serialize::*;
 
 use crate::{
-    aggregate_utils::in_aggregate_context,
+    aggregate_utils::{in_aggregate_context, input_sorted_by_first_arg, null_policy, numeric_to_f64, NullPolicy,
+        timestamp_to_timestamptz, epoch_micros_to_timestamptz},
     ron_inout_funcs,
     flatten,
     palloc::Internal,
     pg_type,
     range::*,
+    spill::SpilledRuns,
+    utilities::interval_to_micros,
 };
 
 use time_series::{
     TSPoint,
 };
 
+
 use counter_agg::{
     CounterSummary as InternalCounterSummary,
     range::I64Range,
@@ -36,6 +42,17 @@ type tstzrange = Datum;
 #[allow(non_camel_case_types)]
 type bytea = pg_sys::Datum;
 
+#[allow(non_camel_case_types)]
+type numeric = pg_sys::Datum;
+
+#[allow(non_camel_case_types)]
+type timestamp = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// microseconds between the unix epoch and the postgres epoch (2000-01-01)
+pub(crate) const POSTGRES_EPOCH_IN_UNIX_MICROS: i64 = 946_684_800_000_000;
+
 pg_type! {
     #[derive(Debug, PartialEq)]
     struct CounterSummary {
@@ -98,21 +115,60 @@ impl<'input> CounterSummary<'input> {
     // }
 }
 
+// The most points counter_agg buffers per group before spilling them, 0 for no
+// limit. The points can arrive in any order, so they can't be summarized until
+// all of them have been seen; instead a full buffer is sorted and spilled as a
+// run to a tuplestore, which writes to disk past work_mem, and the runs are
+// merged when the group is summarized.
+pub(crate) static MAX_BUFFERED_POINTS: GucSetting<i32> = GucSetting::new(0);
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CounterSummaryTransState {
     #[serde(skip)]
     point_buffer: Vec<TSPoint>,
     #[serde(skip)]
     bounds: Option<I64Range>, // stores bounds until we combine points, after which, the bounds are stored in each summary
+    #[serde(skip)]
+    spilled: Option<SpilledRuns>, // the sorted points of each time the buffer filled up
+    #[serde(skip)]
+    sorted_input: bool, // points arrive in time order, so they're summarized as they come
     // We have a summary buffer here in order to deal with the fact that when the cmobine function gets called it
     // must first build up a buffer of InternalMetricSummaries, then sort them, then call the combine function in
     // the correct order.
     summary_buffer: Vec<InternalCounterSummary>,
+    // a NULL input was seen with the 'poison' null_policy, the result is NULL
+    poisoned: bool,
 }
 
 impl CounterSummaryTransState {
     fn push_point(&mut self, value: TSPoint) {
+        // Sorted input is folded straight into a running summary, without
+        // buffering or sorting anything. Unsorted input can't be, a later
+        // point could land inside the range a summary already covers.
+        if self.sorted_input {
+            match self.summary_buffer.last_mut() {
+                Some(summary) => add_point(summary, &value),
+                None => self.summary_buffer.push(InternalCounterSummary::new(&value, self.bounds)),
+            }
+            return
+        }
         self.point_buffer.push(value);
+        let max_buffered = MAX_BUFFERED_POINTS.get();
+        if max_buffered > 0 && self.point_buffer.len() >= max_buffered as usize {
+            self.spill_points();
+        }
+    }
+
+    // must be called in the aggregate context, which owns the tuplestore
+    fn spill_points(&mut self) {
+        self.point_buffer.sort_unstable_by_key(|p| p.ts);
+        self.spilled.get_or_insert_with(|| unsafe { SpilledRuns::new() })
+            .push_run(&self.point_buffer);
+        self.point_buffer.clear();
+    }
+
+    fn has_spilled_points(&self) -> bool {
+        self.spilled.as_ref().map_or(false, |s| !s.is_empty())
     }
 
     // fn set_bounds(&mut self, bounds: Option<I64Range>){
@@ -120,16 +176,35 @@ impl CounterSummaryTransState {
     // }
 
     fn combine_points(&mut self) {
-        if self.point_buffer.is_empty() {
+        if self.point_buffer.is_empty() && !self.has_spilled_points() {
             return
         }
         self.point_buffer.sort_unstable_by_key(|p| p.ts);
-        let mut iter = self.point_buffer.iter();
-        let mut summary = InternalCounterSummary::new( iter.next().unwrap(), self.bounds);
-        for p in iter {
-            summary.add_point(p).unwrap();
+        let buffered = std::mem::take(&mut self.point_buffer);
+        let spilled = self.spilled.as_mut().map_or_else(Vec::new, |s| s.take_runs());
+        let mut runs: Vec<Box<dyn Iterator<Item=TSPoint>>> = spilled.into_iter()
+            .map(|run| Box::new(run) as Box<dyn Iterator<Item=TSPoint>>)
+            .collect();
+        runs.push(Box::new(buffered.into_iter()));
+
+        // merge the sorted runs, on equal times taking the earlier run's point first
+        let mut heads: Vec<Option<TSPoint>> = runs.iter_mut().map(|run| run.next()).collect();
+        let mut next: BinaryHeap<_> = heads.iter().enumerate()
+            .filter_map(|(run, head)| head.as_ref().map(|p| Reverse((p.ts, run))))
+            .collect();
+        let mut summary: Option<InternalCounterSummary> = None;
+        while let Some(Reverse((_, run))) = next.pop() {
+            let point = heads[run].take().unwrap();
+            heads[run] = runs[run].next();
+            if let Some(p) = heads[run] {
+                next.push(Reverse((p.ts, run)));
+            }
+            match &mut summary {
+                None => summary = Some(InternalCounterSummary::new(&point, self.bounds)),
+                Some(summary) => add_point(summary, &point),
+            }
         }
-        self.point_buffer.clear();
+        let summary = summary.unwrap();
         // check bounds only after we've combined all the points, so we aren't doing it all the time.
         if !summary.bounds_valid() {
             panic!("counter bounds invalid")
@@ -160,6 +235,12 @@ impl CounterSummaryTransState {
     }
 }
 
+// the points are added in time order, so this can only fail on a bug
+fn add_point(summary: &mut InternalCounterSummary, point: &TSPoint) {
+    summary.add_point(point)
+        .unwrap_or_else(|e| error!("could not add point to counter_agg: {:?}", e))
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn counter_summary_trans_serialize(
     mut state: Internal<CounterSummaryTransState>,
@@ -183,25 +264,66 @@ pub fn counter_agg_trans(
     val: Option<f64>,
     bounds: Option<tstzrange>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans_inner(state, ts, val, bounds, NullPolicy::Ignore, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_null_policy_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    policy: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    let policy = null_policy(policy.as_deref());
+    counter_agg_trans_inner(state, ts, val, bounds, policy, fcinfo)
+}
+
+fn counter_agg_trans_inner(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    policy: NullPolicy,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<CounterSummaryTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
             let p = match (ts, val) {
-                (_, None) => return state,
-                (None, _) => return state,
-                (Some(ts), Some(val)) => TSPoint{ts, val},
+                (Some(ts), Some(val)) => Some(TSPoint{ts, val}),
+                _ => match policy {
+                    NullPolicy::Ignore => return state,
+                    NullPolicy::Error => error!("counter_agg input contains NULLs, which null_policy 'error' disallows"),
+                    NullPolicy::Poison => None,
+                },
             };
-            match state {
+            let mut state: Internal<CounterSummaryTransState> = match state {
                 None => {
-                    let mut s = CounterSummaryTransState{point_buffer: vec![], bounds: None, summary_buffer: vec![]};
+                    let mut s = CounterSummaryTransState{point_buffer: vec![], bounds: None, spilled: None, sorted_input: false, summary_buffer: vec![], poisoned: false};
                     if let Some(r) = bounds {
                         s.bounds = get_range(r as *mut pg_sys::varlena);
                     }
-                    s.push_point(p);
-                    Some(s.into())
+                    s.sorted_input = input_sorted_by_first_arg(fcinfo);
+                    s.into()
+                },
+                Some(s) => s,
+            };
+            match p {
+                // the result will be NULL, there's no need to keep anything
+                _ if state.poisoned => (),
+                Some(p) => state.push_point(p),
+                None => {
+                    state.poisoned = true;
+                    state.point_buffer.clear();
+                    if let Some(spilled) = &mut state.spilled {
+                        spilled.clear();
+                    }
+                    state.summary_buffer.clear();
                 },
-                Some(mut s) => {s.push_point(p); Some(s)},
             }
+            Some(state)
         })
     }
 }
@@ -216,6 +338,92 @@ pub fn counter_agg_trans_no_bounds(
     counter_agg_trans(state, ts, val, None, fcinfo)
 }
 
+// REAL and NUMERIC values, see numeric_to_f64()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_real_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f32>,
+    bounds: Option<tstzrange>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts, val.map(|v| v as f64), bounds, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_real_trans_no_bounds(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts, val.map(|v| v as f64), None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_numeric_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<numeric>,
+    bounds: Option<tstzrange>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts, val.map(numeric_to_f64), bounds, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_numeric_trans_no_bounds(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts, val.map(numeric_to_f64), None, fcinfo)
+}
+
+// TIMESTAMP and epoch BIGINT times, see timestamp_to_timestamptz()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_timestamp_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<timestamp>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts.map(timestamp_to_timestamptz), val, bounds, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_timestamp_trans_no_bounds(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<timestamp>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts.map(timestamp_to_timestamptz), val, None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_epoch_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<i64>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts.map(epoch_micros_to_timestamptz), val, bounds, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_epoch_trans_no_bounds(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<i64>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans(state, ts.map(epoch_micros_to_timestamptz), val, None, fcinfo)
+}
+
 
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn counter_agg_summary_trans(
@@ -228,7 +436,7 @@ pub fn counter_agg_summary_trans(
             match (state, value) {
                 (state, None) => state,
                 (None, Some(value)) => Some(
-                    CounterSummaryTransState{point_buffer: vec![], bounds: None, summary_buffer: vec![value.to_internal_counter_summary()]}.into()),
+                    CounterSummaryTransState{point_buffer: vec![], bounds: None, spilled: None, sorted_input: false, summary_buffer: vec![value.to_internal_counter_summary()], poisoned: false}.into()),
                 (Some(mut state), Some(value)) => {
                     state.summary_buffer.push(value.to_internal_counter_summary());
                     Some(state)
@@ -248,15 +456,22 @@ pub fn counter_agg_combine(
         in_aggregate_context(fcinfo, || {
             match (state1, state2) {
                 (None, None) => None,
+                // state2 may not live in the aggregate context, so it's copied
+                // there if it becomes our state, and otherwise only read
                 (None, Some(state2)) => {let mut s = state2.clone(); s.combine_points(); Some(s.into())},
-                (Some(state1), None) => {let mut s = state1.clone(); s.combine_points(); Some(s.into())}, //should I make these return themselves?
-                (Some(state1), Some(state2)) => {
-                    let mut s1 = state1.clone(); // is there a way to avoid if it doesn't need it?
-                    s1.combine_points();
-                    let mut s2 = state2.clone();
-                    s2.combine_points();
-                    s2.push_summary(&s1);
-                    Some(s2.into())
+                (Some(mut state1), None) => {state1.combine_points(); Some(state1)},
+                (Some(mut state1), Some(state2)) => {
+                    state1.combine_points();
+                    state1.poisoned |= state2.poisoned;
+                    // deserialized states never have points
+                    if state2.point_buffer.is_empty() && !state2.has_spilled_points() {
+                        state1.push_summary(&state2);
+                    } else {
+                        let mut s2 = state2.clone();
+                        s2.combine_points();
+                        state1.push_summary(&s2);
+                    }
+                    Some(state1)
                 }
             }
         })
@@ -264,64 +479,380 @@ pub fn counter_agg_combine(
 }
 
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
-fn counter_agg_final(
+fn counter_agg_final(
+    state: Option<Internal<CounterSummaryTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::CounterSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) if state.poisoned => return None,
+                Some(state) => state.clone(),
+            };
+            state.combine_summaries();
+            debug_assert!(state.summary_buffer.len() <= 1);
+            match state.summary_buffer.pop() {
+                None => None,
+                Some(st) => {
+                    // there are some edge cases that this should prevent, but I'm not sure it's necessary, we do check the bounds in the functions that use them.
+                    if !st.bounds_valid() {
+                        panic!("counter bounds invalid")
+                    }
+                    Some(CounterSummary::from_internal_counter_summary(st).into())
+                }
+            }
+        })
+    }
+}
+
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange )
+(
+    sfunc = toolkit_experimental.counter_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// `null_policy` is one of 'ignore', 'error' or 'poison', see aggregate_utils.rs.
+// Aggregates can't have defaults, and a three argument version would be
+// ambiguous with the bounds one, so the policy needs the bounds too.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange, null_policy text )
+(
+    sfunc = toolkit_experimental.counter_agg_null_policy_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// allow calling counter agg without bounds provided.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.counter_agg_trans_no_bounds,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// counter_agg of REAL and NUMERIC values
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value REAL, bounds tstzrange )
+(
+    sfunc = toolkit_experimental.counter_agg_real_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value REAL )
+(
+    sfunc = toolkit_experimental.counter_agg_real_trans_no_bounds,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value NUMERIC, bounds tstzrange )
+(
+    sfunc = toolkit_experimental.counter_agg_numeric_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value NUMERIC )
+(
+    sfunc = toolkit_experimental.counter_agg_numeric_trans_no_bounds,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// counter_agg of TIMESTAMP and epoch BIGINT times
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts TIMESTAMP, value DOUBLE PRECISION, bounds tstzrange )
+(
+    sfunc = toolkit_experimental.counter_agg_timestamp_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts TIMESTAMP, value DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.counter_agg_timestamp_trans_no_bounds,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts BIGINT, value DOUBLE PRECISION, bounds tstzrange )
+(
+    sfunc = toolkit_experimental.counter_agg_epoch_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts BIGINT, value DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.counter_agg_epoch_trans_no_bounds,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(cs toolkit_experimental.CounterSummary)
+(
+    sfunc = toolkit_experimental.counter_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// delta_agg() builds a CounterSummary from the increases of a counter rather
+// than its values, for data already stored as deltas upstream. Each delta is
+// the increase since the previous row, the counter's value at a row is the sum
+// of the deltas up to it, so the first delta only sets the starting value.
+// The running sum needs the rows in time order, so the deltas are kept until
+// the final function.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeltaAggTransState {
+    deltas: Vec<TSPoint>,
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn delta_agg_trans_serialize(
+    state: Internal<DeltaAggTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn delta_agg_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<DeltaAggTransState> {
+    crate::do_deserialize!(bytes, DeltaAggTransState)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn delta_agg_trans(
+    state: Option<Internal<DeltaAggTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    delta: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<DeltaAggTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let p = match (ts, delta) {
+                (Some(ts), Some(val)) => TSPoint{ts, val},
+                _ => return state,
+            };
+            if !(p.val >= 0.0) {
+                error!("delta_agg deltas must be non-negative")
+            }
+            let mut state = state.unwrap_or_else(|| DeltaAggTransState{deltas: vec![]}.into());
+            state.deltas.push(p);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn delta_agg_combine(
+    state1: Option<Internal<DeltaAggTransState>>,
+    state2: Option<Internal<DeltaAggTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<DeltaAggTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1),
+                (Some(mut state1), Some(state2)) => {
+                    state1.deltas.extend_from_slice(&state2.deltas);
+                    Some(state1)
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn delta_agg_final(
+    state: Option<Internal<DeltaAggTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::CounterSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut deltas = state?.deltas.clone();
+            deltas.sort_by_key(|p| p.ts);
+            let mut total = 0.0;
+            let mut summary: Option<InternalCounterSummary> = None;
+            let mut i = 0;
+            while i < deltas.len() {
+                // deltas at the same time are one increase
+                let ts = deltas[i].ts;
+                while i < deltas.len() && deltas[i].ts == ts {
+                    total += deltas[i].val;
+                    i += 1;
+                }
+                let p = TSPoint{ts, val: total};
+                match &mut summary {
+                    None => summary = Some(InternalCounterSummary::new(&p, None)),
+                    Some(summary) => summary.add_point(&p).unwrap(),
+                }
+            }
+            summary.map(|s| CounterSummary::from_internal_counter_summary(s).into())
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.delta_agg( ts timestamptz, delta DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.delta_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.delta_agg_final,
+    combinefunc = toolkit_experimental.delta_agg_combine,
+    serialfunc = toolkit_experimental.delta_agg_trans_serialize,
+    deserialfunc = toolkit_experimental.delta_agg_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// `a + b` combines two summaries the same way rollup() does, so they can't
+// overlap in time
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn counter_summary_add(
+    a: toolkit_experimental::CounterSummary,
+    b: toolkit_experimental::CounterSummary,
+) -> toolkit_experimental::CounterSummary<'static> {
+    let (mut first, second) = if a.first.ts <= b.first.ts {
+        (a.to_internal_counter_summary(), b.to_internal_counter_summary())
+    } else {
+        (b.to_internal_counter_summary(), a.to_internal_counter_summary())
+    };
+    if first.combine(&second).is_err() {
+        error!("cannot add CounterSummaries that overlap in time")
+    }
+    CounterSummary::from_internal_counter_summary(first)
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+extension_sql!(r#"
+CREATE OPERATOR toolkit_experimental.+ (
+    PROCEDURE=toolkit_experimental.counter_summary_add,
+    LEFTARG=toolkit_experimental.CounterSummary,
+    RIGHTARG=toolkit_experimental.CounterSummary
+);
+"#);
+
+// Partial aggregation for merging counter_agg states computed elsewhere, e.g.
+// on other nodes or by batch jobs: counter_agg_partial() returns the
+// transition state serialized the same way as for parallel workers, and
+// counter_agg_finalize() combines any number of those into a CounterSummary.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn counter_agg_partial_final(
     state: Option<Internal<CounterSummaryTransState>>,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<toolkit_experimental::CounterSummary<'static>> {
+) -> Option<bytea> {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let mut state = match state {
-                None => return None,
-                Some(state) => state.clone(),
-            };
-            state.combine_summaries();
-            debug_assert!(state.summary_buffer.len() <= 1);
-            match state.summary_buffer.pop() {
-                None => None,
-                Some(st) => {
-                    // there are some edge cases that this should prevent, but I'm not sure it's necessary, we do check the bounds in the functions that use them.
-                    if !st.bounds_valid() {
-                        panic!("counter bounds invalid")
-                    }
-                    Some(CounterSummary::from_internal_counter_summary(st).into())
-                }
-            }
+            let state: Internal<CounterSummaryTransState> = state?.clone().into();
+            Some(counter_summary_trans_serialize(state))
         })
     }
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn counter_agg_finalize_trans(
+    state: Option<Internal<CounterSummaryTransState>>,
+    partial: Option<bytea>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    let partial = match partial {
+        None => return state,
+        Some(partial) => counter_summary_trans_deserialize(partial, None),
+    };
+    counter_agg_combine(state, Some(partial), fcinfo)
+}
 
 extension_sql!(r#"
-CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange )
+CREATE AGGREGATE toolkit_experimental.counter_agg_partial( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange )
 (
     sfunc = toolkit_experimental.counter_agg_trans,
     stype = internal,
-    finalfunc = toolkit_experimental.counter_agg_final,
+    finalfunc = toolkit_experimental.counter_agg_partial_final,
     combinefunc = toolkit_experimental.counter_agg_combine,
     serialfunc = toolkit_experimental.counter_summary_trans_serialize,
     deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
     parallel = restricted
 );
-"#);
 
-// allow calling counter agg without bounds provided.
-extension_sql!(r#"
-CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION )
+CREATE AGGREGATE toolkit_experimental.counter_agg_partial( ts timestamptz, value DOUBLE PRECISION )
 (
     sfunc = toolkit_experimental.counter_agg_trans_no_bounds,
     stype = internal,
-    finalfunc = toolkit_experimental.counter_agg_final,
+    finalfunc = toolkit_experimental.counter_agg_partial_final,
     combinefunc = toolkit_experimental.counter_agg_combine,
     serialfunc = toolkit_experimental.counter_summary_trans_serialize,
     deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
     parallel = restricted
 );
-"#);
 
-extension_sql!(r#"
-CREATE AGGREGATE toolkit_experimental.rollup(cs toolkit_experimental.CounterSummary)
+CREATE AGGREGATE toolkit_experimental.counter_agg_finalize(partial bytea)
 (
-    sfunc = toolkit_experimental.counter_agg_summary_trans,
+    sfunc = toolkit_experimental.counter_agg_finalize_trans,
     stype = internal,
     finalfunc = toolkit_experimental.counter_agg_final,
     combinefunc = toolkit_experimental.counter_agg_combine,
@@ -483,15 +1014,19 @@ fn counter_agg_with_bounds(
 }
 
 
-#[pg_operator(immutable, parallel_safe)]
+// accessors created without a method use the default when they're applied
+#[pg_operator(stable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_counter_agg_extrapolated_delta(
     sketch: toolkit_experimental::CounterSummary,
     accessor: toolkit_experimental::AccessorExtrapolatedDelta,
 ) -> Option<f64> {
-    let _ = accessor;
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    counter_agg_extrapolated_delta(sketch, &*method)
+    let method = match &*method {
+        "" => default_method(),
+        method => method_kind(method),
+    };
+    extrapolated_delta(sketch, method)
 }
 
 #[pg_extern(name="extrapolated_delta", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
@@ -499,7 +1034,21 @@ fn counter_agg_extrapolated_delta(
     summary: toolkit_experimental::CounterSummary,
     method: &str,
 )-> Option<f64> {
-    match method_kind(method) {
+    extrapolated_delta(summary, method_kind(method))
+}
+
+#[pg_extern(name="extrapolated_delta", schema = "toolkit_experimental", strict, stable, parallel_safe)]
+fn counter_agg_extrapolated_delta_default_method(
+    summary: toolkit_experimental::CounterSummary,
+)-> Option<f64> {
+    extrapolated_delta(summary, default_method())
+}
+
+fn extrapolated_delta(
+    summary: toolkit_experimental::CounterSummary,
+    method: Method,
+)-> Option<f64> {
+    match method {
         Prometheus => {
             summary.to_internal_counter_summary().prometheus_delta().unwrap()
         },
@@ -507,15 +1056,18 @@ fn counter_agg_extrapolated_delta(
 }
 
 
-#[pg_operator(immutable, parallel_safe)]
+#[pg_operator(stable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_counter_agg_extrapolated_rate(
     sketch: toolkit_experimental::CounterSummary,
     accessor: toolkit_experimental::AccessorExtrapolatedRate,
 ) -> Option<f64> {
-    let _ = accessor;
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    counter_agg_extrapolated_rate(sketch, &*method)
+    let method = match &*method {
+        "" => default_method(),
+        method => method_kind(method),
+    };
+    extrapolated_rate(sketch, method)
 }
 
 #[pg_extern(name="extrapolated_rate", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
@@ -523,7 +1075,21 @@ fn counter_agg_extrapolated_rate(
     summary: toolkit_experimental::CounterSummary,
     method: &str,
 )-> Option<f64> {
-    match method_kind(method) {
+    extrapolated_rate(summary, method_kind(method))
+}
+
+#[pg_extern(name="extrapolated_rate", schema = "toolkit_experimental", strict, stable, parallel_safe)]
+fn counter_agg_extrapolated_rate_default_method(
+    summary: toolkit_experimental::CounterSummary,
+)-> Option<f64> {
+    extrapolated_rate(summary, default_method())
+}
+
+fn extrapolated_rate(
+    summary: toolkit_experimental::CounterSummary,
+    method: Method,
+)-> Option<f64> {
+    match method {
         Prometheus => {
             summary.to_internal_counter_summary().prometheus_rate().unwrap()
         },
@@ -655,6 +1221,153 @@ fn counter_agg_counter_zero_time(
     Some((summary.to_internal_counter_summary().stats.x_intercept()? * 1_000_000.0) as i64)
 }
 
+// The accessor functions the argument-less `->` operators are equivalent to.
+const ARROW_ACCESSORS: &[(&str, &str)] = &[
+    ("arrow_counter_agg_delta", "delta"),
+    ("arrow_counter_agg_rate", "rate"),
+    ("arrow_counter_agg_time_delta", "time_delta"),
+    ("arrow_counter_agg_irate_left", "irate_left"),
+    ("arrow_counter_agg_irate_right", "irate_right"),
+    ("arrow_counter_agg_idelta_left", "idelta_left"),
+    ("arrow_counter_agg_idelta_right", "idelta_right"),
+    ("arrow_counter_agg_num_elements", "num_elements"),
+    ("arrow_counter_agg_num_changes", "num_changes"),
+    ("arrow_counter_agg_num_resets", "num_resets"),
+    ("arrow_counter_agg_slope", "slope"),
+    ("arrow_counter_agg_intercept", "intercept"),
+    ("arrow_counter_agg_corr", "corr"),
+    ("arrow_counter_agg_zero_time", "counter_zero_time"),
+];
+
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// Planner support for the `->` operators of accessors without arguments:
+// `summary -> delta()` is rewritten into `delta(summary)`, so the accessor is
+// never built or read, and both spellings plan to the same expression, which
+// lets e.g. an `ORDER BY` reuse the value computed for the select list.
+// Returns a pointer to the replacement expression, or 0 if the expression
+// should be left unchanged.
+//
+// SupportRequestSimplify only exists on PostgreSQL 12 and later, on older
+// versions this is a no-op.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_accessor_support(
+    input: internal,
+) -> internal {
+    unsafe { simplify_arrow_accessor(input) }
+}
+
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+unsafe fn simplify_arrow_accessor(input: pg_sys::Datum) -> pg_sys::Datum {
+    let node = input as *mut pg_sys::Node;
+    if !is_a(node, pg_sys::NodeTag_T_SupportRequestSimplify) {
+        return 0
+    }
+
+    let request = node as *mut pg_sys::SupportRequestSimplify;
+    let fcall = (*request).fcall;
+    let args = PgList::<pg_sys::Node>::from_pg((*fcall).args);
+    if args.len() != 2 {
+        return 0
+    }
+    // only a constant accessor is known to not have side effects we'd drop
+    let accessor = args.get_ptr(1).unwrap();
+    if !is_a(accessor, pg_sys::NodeTag_T_Const) || (*(accessor as *mut pg_sys::Const)).constisnull {
+        return 0
+    }
+
+    let name = pg_sys::get_func_name((*fcall).funcid);
+    if name.is_null() {
+        return 0
+    }
+    let name = std::ffi::CStr::from_ptr(name).to_string_lossy();
+    let accessor_fn = match ARROW_ACCESSORS.iter().find(|(arrow, _)| *arrow == name) {
+        Some((_, accessor_fn)) => accessor_fn,
+        None => return 0,
+    };
+    let signature = std::ffi::CString::new(format!(
+        "toolkit_experimental.{}(toolkit_experimental.countersummary)",
+        accessor_fn
+    )).unwrap();
+    let accessor_fn: pg_sys::Oid = direct_function_call(
+        pg_sys::regprocedurein,
+        vec![signature.as_c_str().into_datum()],
+    ).unwrap();
+
+    let mut new_args = PgList::<pg_sys::Node>::new();
+    new_args.push(args.get_ptr(0).unwrap());
+    pg_sys::makeFuncExpr(
+        accessor_fn,
+        (*fcall).funcresulttype,
+        new_args.into_pg(),
+        (*fcall).funccollid,
+        (*fcall).inputcollid,
+        pg_sys::CoercionForm_COERCE_EXPLICIT_CALL,
+    ) as pg_sys::Datum
+}
+
+#[cfg(not(any(feature = "pg12", feature = "pg13")))]
+unsafe fn simplify_arrow_accessor(_input: pg_sys::Datum) -> pg_sys::Datum {
+    0
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+DECLARE
+    arrow regprocedure;
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        FOR arrow IN
+            SELECT p.oid::regprocedure FROM pg_catalog.pg_proc p
+            WHERE p.proname LIKE 'arrow\_counter\_agg\_%' AND p.pronargs = 2
+        LOOP
+            EXECUTE format('ALTER FUNCTION %s SUPPORT toolkit_experimental.counter_agg_accessor_support', arrow);
+        END LOOP;
+    END IF;
+END
+$$;
+"#);
+
+// The samples PromQL's `rate()` would emit for the summary's range: one at
+// every multiple of `step` since the unix epoch, as Grafana aligns its
+// queries, within the summary's bounds `(lower, upper]`. A summary only knows
+// the rate over its whole range, so every sample carries that extrapolated
+// rate; summaries bucketed by `step`, e.g. from
+// `counter_agg(ts, val, time_bucket_range(step, ts))`, each produce the single
+// sample at the end of their bucket, the same as `rate(metric[step])`
+// evaluated every `step`. Summaries too short for a rate produce no samples.
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn to_prom_samples(
+    summary: toolkit_experimental::CounterSummary,
+    step: Interval,
+) -> impl std::iter::Iterator<Item = (name!(ts,pg_sys::TimestampTz),name!(rate,f64))> {
+    let step = interval_to_micros(step, "steps");
+    if step <= 0 {
+        error!("step must be positive")
+    }
+    let summary = summary.to_internal_counter_summary();
+    let rate = summary.prometheus_rate()
+        .unwrap_or_else(|_| error!("to_prom_samples requires a counter summary with finite bounds"));
+    let bounds = summary.bounds.unwrap();
+    let (lower, upper) = (
+        bounds.left.unwrap() + POSTGRES_EPOCH_IN_UNIX_MICROS,
+        bounds.right.unwrap() + POSTGRES_EPOCH_IN_UNIX_MICROS,
+    );
+    // the first multiple of step after lower
+    let first = (lower.div_euclid(step) + 1) * step;
+    let samples = match rate {
+        Some(rate) => (0..)
+            .map(|i| first + i * step)
+            .take_while(|ts| *ts <= upper)
+            .map(|ts| (ts - POSTGRES_EPOCH_IN_UNIX_MICROS, rate))
+            .collect(),
+        None => vec![],
+    };
+    samples.into_iter()
+}
+
 #[derive(Clone, Copy)]
 pub enum Method {
     Prometheus,
@@ -668,6 +1381,18 @@ pub fn method_kind(method: &str)  -> Method {
     }
 }
 
+// The method extrapolated_delta() and extrapolated_rate() use when they're
+// called without one.
+pub(crate) static DEFAULT_EXTRAPOLATION_METHOD: GucSetting<Option<&'static str>> =
+    GucSetting::new(Some("prometheus"));
+
+pub fn default_method() -> Method {
+    match DEFAULT_EXTRAPOLATION_METHOD.get() {
+        Some(method) => method_kind(&method),
+        None => Prometheus,
+    }
+}
+
 pub fn as_method(method: &str) -> Option<Method> {
     match method.trim().to_lowercase().as_str() {
         "prometheus" => Some(Method::Prometheus),
@@ -676,6 +1401,79 @@ pub fn as_method(method: &str) -> Option<Method> {
 }
 
 
+// The CounterSummary as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_to_msgpack(
+    summary: toolkit_experimental::CounterSummary<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*summary)
+}
+
+// inverse of to_msgpack(CounterSummary)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_from_msgpack(
+    bytes: &[u8],
+) -> toolkit_experimental::CounterSummary<'static> {
+    unsafe { CounterSummary(crate::msgpack::decode("CounterSummary", bytes, 1), None).flatten() }
+}
+
+// The CounterSummary as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_to_jsonb(
+    summary: toolkit_experimental::CounterSummary<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*summary)
+}
+
+// inverse of to_jsonb(CounterSummary), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_from_jsonb(
+    json: JsonB,
+) -> toolkit_experimental::CounterSummary<'static> {
+    unsafe { CounterSummary(crate::jsonb::decode("CounterSummary", &json.0, 1), None).flatten() }
+}
+
+// The size of the CounterSummary as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_size(
+    summary: toolkit_experimental::CounterSummary<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, summary.0.num_bytes(), None)
+}
+
+// Checks the CounterSummary's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_summary_validate(
+    summary: toolkit_experimental::CounterSummary<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    let summary = summary.to_internal_counter_summary();
+    let points = [summary.first, summary.second, summary.penultimate, summary.last];
+    if points.windows(2).any(|pair| pair[0].ts > pair[1].ts) {
+        diagnostics.error("the first, second, penultimate and last points are not in time order".to_string())
+    }
+    if summary.stats.n == 0 {
+        diagnostics.error("the summary has no points".to_string())
+    }
+    if let Some(bounds) = &summary.bounds {
+        match (bounds.left, bounds.right) {
+            (Some(left), Some(right)) if left >= right =>
+                diagnostics.error("the bounds are empty".to_string()),
+            (left, right) => {
+                let outside = |ts: i64| left.map_or(false, |left| ts < left) || right.map_or(false, |right| ts >= right);
+                if outside(summary.first.ts) || outside(summary.last.ts) {
+                    diagnostics.error("points fall outside the bounds".to_string())
+                }
+            },
+        }
+    }
+    if points.iter().any(|point| !point.val.is_finite()) {
+        diagnostics.warning("the summary contains non-finite values".to_string())
+    }
+    diagnostics.into_rows()
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
 
@@ -909,6 +1707,483 @@ mod tests {
     }
 
 
+    #[pg_test]
+    fn test_counter_rollup_order() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 100) i",
+                None,
+                None
+            );
+
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            // the summaries can arrive in any order, including ones falling
+            // between summaries that have already arrived
+            for order in &["bucket ASC", "bucket DESC", "extract(minute FROM bucket)::int % 3, bucket"] {
+                let stmt = format!("WITH t as (SELECT date_trunc('minute', ts) AS bucket, counter_agg(ts, val) as agg FROM test group by 1) \
+                    SELECT rollup(agg ORDER BY {}) FROM t", order);
+                let rolled_up = select_one!(client, &stmt, toolkit_experimental::CounterSummary);
+                assert_close_enough(&expected.to_internal_counter_summary(), &rolled_up.to_internal_counter_summary());
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_max_buffered_points() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 100) i",
+                None,
+                None
+            );
+
+            // the table is scanned in insertion order
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            client.select("SET LOCAL timescaledb_toolkit_counter_agg_max_buffered_points TO 7", None, None);
+            let summarized = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&expected.to_internal_counter_summary(), &summarized.to_internal_counter_summary());
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_sorted_input() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 100) i ORDER BY (i * 37) % 101",
+                None,
+                None
+            );
+
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            // points ordered by time are summarized as they arrive
+            let stmt = "SELECT counter_agg(ts, val ORDER BY ts) FROM test";
+            let sorted = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&expected.to_internal_counter_summary(), &sorted.to_internal_counter_summary());
+
+            let stmt = "SELECT counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)') FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            let stmt = "SELECT counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)' ORDER BY ts) FROM test";
+            let sorted = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_eq!(sorted.to_internal_counter_summary().bounds, expected.to_internal_counter_summary().bounds);
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_max_buffered_points_out_of_order() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            // out of order, with every point repeated
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + (i / 2) * '10 seconds'::interval, ((i / 2) * 7) % 50 \
+                FROM generate_series(0, 100) i ORDER BY (i * 37) % 101",
+                None,
+                None
+            );
+
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            // the setting only changes how the points are held, not the result
+            for max_buffered in &[1, 2, 7, 100] {
+                client.select(&format!("SET LOCAL timescaledb_toolkit_counter_agg_max_buffered_points TO {}", max_buffered), None, None);
+                let spilled = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+                assert_close_enough(&expected.to_internal_counter_summary(), &spilled.to_internal_counter_summary());
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_max_buffered_points_past_work_mem() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 20000) i ORDER BY (i * 37) % 20001",
+                None,
+                None
+            );
+
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            // the spilled runs don't fit in work_mem, so they're written to disk
+            client.select("SET LOCAL work_mem TO '64kB'", None, None);
+            client.select("SET LOCAL timescaledb_toolkit_counter_agg_max_buffered_points TO 100", None, None);
+            let spilled = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&expected.to_internal_counter_summary(), &spilled.to_internal_counter_summary());
+
+            // a poisoned group drops its spilled points
+            let stmt = "SELECT counter_agg(ts, CASE WHEN ts = '2020-01-01 12:00:00+00' THEN NULL ELSE val END, NULL, 'poison') IS NULL FROM test";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_null_policy() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval AS ts, \
+                    CASE WHEN i = 2 THEN NULL ELSE i::DOUBLE PRECISION END AS val \
+                    FROM generate_series(0, 4) i",
+                None,
+                None
+            );
+            let bounds = "'[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)'";
+
+            let stmt = format!("SELECT toolkit_experimental.num_changes(toolkit_experimental.counter_agg(ts, val, {}, 'ignore')) FROM test", bounds);
+            assert_eq!(select_one!(client, &stmt, i64), 3);
+            // a NULL policy is the same as 'ignore'
+            let stmt = format!("SELECT toolkit_experimental.num_changes(toolkit_experimental.counter_agg(ts, val, {}, NULL)) FROM test", bounds);
+            assert_eq!(select_one!(client, &stmt, i64), 3);
+
+            let stmt = format!("SELECT toolkit_experimental.counter_agg(ts, val, {}, 'poison') IS NULL FROM test", bounds);
+            assert!(select_one!(client, &stmt, bool));
+            let stmt = format!("SELECT toolkit_experimental.counter_agg(ts, val, {}, 'poison') IS NULL FROM test WHERE val IS NOT NULL", bounds);
+            assert!(!select_one!(client, &stmt, bool));
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_real_and_numeric() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval AS ts, \
+                    (i % 4)::REAL AS r, (i % 4)::NUMERIC AS n \
+                    FROM generate_series(0, 9) i",
+                None,
+                None
+            );
+            for col in &["r", "n"] {
+                let stmt = format!("SELECT toolkit_experimental.delta(toolkit_experimental.counter_agg(ts, {})) FROM test", col);
+                assert_eq!(select_one!(client, &stmt, f64), 7.0);
+                let stmt = format!(
+                    "SELECT toolkit_experimental.num_resets(toolkit_experimental.counter_agg(ts, {}, '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)')) FROM test",
+                    col
+                );
+                assert_eq!(select_one!(client, &stmt, i64), 2);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_timestamp_and_epoch() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval AS ts, \
+                    (i % 4)::DOUBLE PRECISION AS val \
+                    FROM generate_series(0, 9) i",
+                None,
+                None
+            );
+            let stmt = "SELECT toolkit_experimental.counter_agg(ts, val)::TEXT = \
+                    toolkit_experimental.counter_agg((ts AT TIME ZONE 'UTC')::TIMESTAMP, val)::TEXT \
+                FROM test";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT toolkit_experimental.counter_agg(ts, val)::TEXT = \
+                    toolkit_experimental.counter_agg((extract(epoch FROM ts) * 1000000)::BIGINT, val)::TEXT \
+                FROM test";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_summary_add() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval AS ts, \
+                    (i % 4)::DOUBLE PRECISION AS val \
+                    FROM generate_series(0, 9) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE summaries AS SELECT ts < '2020-01-01 00:00:50+00' AS early, toolkit_experimental.counter_agg(ts, val) AS cs \
+                    FROM test GROUP BY 1",
+                None,
+                None
+            );
+            // either order gives the same result
+            for (left, right) in &[("e", "l"), ("l", "e")] {
+                let stmt = format!(
+                    "SELECT toolkit_experimental.delta({0}.cs OPERATOR(toolkit_experimental.+) {1}.cs), \
+                        toolkit_experimental.num_resets({0}.cs OPERATOR(toolkit_experimental.+) {1}.cs) \
+                    FROM summaries e, summaries l WHERE e.early AND NOT l.early",
+                    left, right
+                );
+                let (delta, resets) = client.select(&stmt, None, None)
+                    .first()
+                    .get_two::<f64, i64>();
+                assert_eq!(delta, Some(7.0));
+                assert_eq!(resets, Some(2));
+            }
+        });
+    }
+
+    #[pg_test(error = "cannot add CounterSummaries that overlap in time")]
+    fn test_counter_summary_add_overlapping() {
+        Spi::execute(|client| {
+            client.select(
+                "WITH s AS (SELECT toolkit_experimental.counter_agg(ts, 1.0) AS cs \
+                    FROM generate_series('2020-01-01 UTC'::timestamptz, '2020-01-02 UTC', '1 hour') ts) \
+                SELECT a.cs OPERATOR(toolkit_experimental.+) b.cs FROM s a, s b",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_default_extrapolation_method() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval AS ts, \
+                    i::DOUBLE PRECISION AS val \
+                    FROM generate_series(1, 5) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE summary AS SELECT toolkit_experimental.counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:01:00+00)') AS cs FROM test",
+                None,
+                None
+            );
+            let stmt = "SELECT \
+                    toolkit_experimental.extrapolated_delta(cs) = toolkit_experimental.extrapolated_delta(cs, 'prometheus') \
+                    AND toolkit_experimental.extrapolated_rate(cs) = toolkit_experimental.extrapolated_rate(cs, 'prometheus') \
+                    AND (cs -> toolkit_experimental.extrapolated_delta()) = toolkit_experimental.extrapolated_delta(cs, 'prometheus') \
+                FROM summary";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+
+    #[pg_test(error = "unknown analysis method. Valid methods are 'prometheus'")]
+    fn test_counter_invalid_default_extrapolation_method() {
+        Spi::execute(|client| {
+            client.select("SET LOCAL timescaledb_toolkit.default_extrapolation_method TO 'nonsense'", None, None);
+            client.select(
+                "SELECT toolkit_experimental.extrapolated_rate(toolkit_experimental.counter_agg(ts, 1.0, tstzrange(ts, ts + '1 minute'))) \
+                FROM (VALUES ('2020-01-01 00:00:00+00'::timestamptz)) t(ts)",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test(error = "counter_agg input contains NULLs, which null_policy 'error' disallows")]
+    fn test_counter_null_policy_error() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)', 'error') FROM \
+                    (VALUES ('2020-01-01 00:00:00+00'::timestamptz, 1.0::DOUBLE PRECISION), ('2020-01-01 00:01:00+00', NULL)) t(ts, val)",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_arrow_simplified() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select("INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0)", None, None);
+
+            // the operator is planned as a call to the accessor function
+            let plan: Vec<String> = client.select(
+                "EXPLAIN (VERBOSE) SELECT counter_agg(ts, val)->delta(), counter_agg(ts, val)->num_resets() FROM test",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            let plan = plan.join("\n");
+            assert!(plan.contains("delta(counter_agg("), "{}", plan);
+            assert!(plan.contains("num_resets(counter_agg("), "{}", plan);
+            assert!(!plan.contains("->"), "{}", plan);
+
+            let stmt = "SELECT \
+                counter_agg(ts, val)->delta(), \
+                delta(counter_agg(ts, val)) \
+            FROM test";
+            assert_relative_eq!(select_and_check_one!(client, stmt, f64), 10.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_partial_finalize() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 100) i",
+                None,
+                None
+            );
+
+            let stmt = "SELECT counter_agg(ts, val) FROM test";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+
+            // partials computed elsewhere are shipped as bytea and merged here
+            client.select(
+                "CREATE TABLE partials AS \
+                SELECT counter_agg_partial(ts, val) AS partial FROM test GROUP BY date_trunc('minute', ts)",
+                None,
+                None
+            );
+            let stmt = "SELECT counter_agg_finalize(partial) FROM partials";
+            let finalized = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&expected.to_internal_counter_summary(), &finalized.to_internal_counter_summary());
+        });
+    }
+
+    #[pg_test]
+    fn test_delta_agg() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE deltas(ts timestamptz, delta DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select(
+                "INSERT INTO deltas VALUES \
+                    ('2020-01-01 00:03:00+00', 20.0), \
+                    ('2020-01-01 00:00:00+00', 5.0), \
+                    ('2020-01-01 00:02:00+00', 0.0), \
+                    ('2020-01-01 00:01:00+00', 4.0), \
+                    ('2020-01-01 00:01:00+00', 6.0), \
+                    ('2020-01-01 00:02:00+00', NULL)",
+                None,
+                None
+            );
+
+            // the same as the counter's running total
+            let stmt = "SELECT counter_agg(ts, val) FROM (VALUES \
+                    ('2020-01-01 00:00:00+00'::timestamptz, 5.0::float8), \
+                    ('2020-01-01 00:01:00+00', 15.0), \
+                    ('2020-01-01 00:02:00+00', 15.0), \
+                    ('2020-01-01 00:03:00+00', 35.0)) v(ts, val)";
+            let expected = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            let stmt = "SELECT delta_agg(ts, delta) FROM deltas";
+            let summary = select_one!(client, stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&expected.to_internal_counter_summary(), &summary.to_internal_counter_summary());
+
+            // the first delta only sets the starting value
+            let stmt = "SELECT delta(delta_agg(ts, delta)) FROM deltas";
+            assert_relative_eq!(select_one!(client, stmt, f64), 30.0);
+            let stmt = "SELECT num_resets(delta_agg(ts, delta)) FROM deltas";
+            assert_eq!(select_one!(client, stmt, i64), 0);
+        });
+    }
+
+    #[pg_test(error = "delta_agg deltas must be non-negative")]
+    fn test_delta_agg_negative() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.delta_agg(ts, delta) FROM (VALUES \
+                    ('2020-01-01 00:00:00+00'::timestamptz, 5.0::float8), \
+                    ('2020-01-01 00:01:00+00', -1.0)) v(ts, delta)",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_to_prom_samples() {
+        Spi::execute(|client| {
+            client.select("SET TIME ZONE 'UTC'", None, None);
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            let stmt = "INSERT INTO test VALUES\
+                ('2020-01-01 00:00:00+00', 0.0),\
+                ('2020-01-01 00:01:00+00', 60.0),\
+                ('2020-01-01 00:02:00+00', 120.0),\
+                ('2020-01-01 00:03:00+00', 60.0),\
+                ('2020-01-01 00:04:00+00', 120.0)";
+            client.select(stmt, None, None);
+
+            // a summary per step gives the sample at the end of each step
+            let samples: Vec<(String, f64)> = client.select(
+                "SELECT (s).ts::TEXT, (s).rate FROM ( \
+                    SELECT to_prom_samples( \
+                        counter_agg(ts, val, tstzrange(bucket, bucket + '2 minutes 0.001 seconds')), \
+                        '2 minutes') AS s \
+                    FROM (SELECT to_timestamp(floor(extract(epoch FROM ts) / 120) * 120) AS bucket, ts, val FROM test) b \
+                    GROUP BY bucket \
+                ) t ORDER BY 1",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                ))
+                .collect();
+            // the last step has a single point and so no rate
+            assert_eq!(samples.len(), 2);
+            assert_eq!(samples[0].0, "2020-01-01 00:02:00+00");
+            assert_eq!(samples[1].0, "2020-01-01 00:04:00+00");
+            assert_relative_eq!(samples[0].1, 1.0);
+            assert_relative_eq!(samples[1].1, 1.0);
+
+            // a longer summary gives its rate at every step it covers
+            let (count, rate) = client.select(
+                "SELECT count(*), min(rate) FROM to_prom_samples( \
+                    (SELECT counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:04:00.001+00)') FROM test), \
+                    '1 minute')",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i64, f64>();
+            assert_eq!(count, Some(4));
+            let expected = select_one!(client,
+                "SELECT extrapolated_rate(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:04:00.001+00)'), 'prometheus') FROM test",
+                f64);
+            assert_relative_eq!(rate.unwrap(), expected);
+        });
+    }
+
+
     // #[pg_test]
     // fn test_combine_aggregate(){
     //     Spi::execute(|client| {
@@ -0,0 +1,299 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use streaming_histogram::StreamingHistogram;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct Centroid {
+    value: f64,
+    count: u64,
+}
+
+// PG object for the streaming histogram, the centroids are sorted by value
+pg_type! {
+    #[derive(Debug)]
+    struct EquiDepthHistogram<'input> {
+        max_bins: u32,
+        num_centroids: u32,
+        min: f64,
+        max: f64,
+        centroids: [Centroid; self.num_centroids],
+    }
+}
+
+ron_inout_funcs!(EquiDepthHistogram);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(EquiDepthHistogram);
+}
+
+impl<'input> EquiDepthHistogram<'input> {
+    fn to_internal(&self) -> StreamingHistogram {
+        StreamingHistogram::from_parts(
+            self.max_bins,
+            self.min,
+            self.max,
+            self.centroids.iter().map(|c| (c.value, c.count)).collect(),
+        )
+    }
+
+    fn from_internal(histogram: &StreamingHistogram) -> EquiDepthHistogram<'static> {
+        let centroids: Vec<Centroid> = histogram.centroids().iter()
+            .map(|&(value, count)| Centroid { value, count })
+            .collect();
+        build!(
+            EquiDepthHistogram {
+                max_bins: histogram.max_bins(),
+                num_centroids: centroids.len() as u32,
+                min: histogram.min(),
+                max: histogram.max(),
+                centroids: centroids.into(),
+            }
+        )
+    }
+}
+
+// PG function for adding values to a histogram.
+// Null and NaN values are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_trans(
+    state: Option<Internal<StreamingHistogram>>,
+    max_bins: i32,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StreamingHistogram>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => {
+                    if max_bins < 2 {
+                        error!("equi_depth_histogram needs at least 2 bins")
+                    }
+                    StreamingHistogram::new(max_bins as u32).into()
+                },
+                Some(state) => state,
+            };
+            state.add_value(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_summary_trans(
+    state: Option<Internal<StreamingHistogram>>,
+    value: Option<toolkit_experimental::EquiDepthHistogram<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StreamingHistogram>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal(),
+            };
+            match state {
+                None => Some(value.into()),
+                Some(mut state) => {
+                    state.merge(&value);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+// PG function for merging histograms.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_combine(
+    state1: Option<Internal<StreamingHistogram>>,
+    state2: Option<Internal<StreamingHistogram>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StreamingHistogram>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut histogram = state1.clone();
+                    histogram.merge(&state2);
+                    Some(histogram.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_serialize(
+    state: Internal<StreamingHistogram>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StreamingHistogram> {
+    crate::do_deserialize!(bytes, StreamingHistogram)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn equi_depth_histogram_final(
+    state: Option<Internal<StreamingHistogram>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::EquiDepthHistogram<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            EquiDepthHistogram::from_internal(&state).into()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.equi_depth_histogram(
+    max_bins int, value DOUBLE PRECISION
+) (
+    sfunc = toolkit_experimental.equi_depth_histogram_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.equi_depth_histogram_final,
+    combinefunc = toolkit_experimental.equi_depth_histogram_combine,
+    serialfunc = toolkit_experimental.equi_depth_histogram_serialize,
+    deserialfunc = toolkit_experimental.equi_depth_histogram_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    histogram toolkit_experimental.EquiDepthHistogram
+) (
+    sfunc = toolkit_experimental.equi_depth_histogram_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.equi_depth_histogram_final,
+    combinefunc = toolkit_experimental.equi_depth_histogram_combine,
+    serialfunc = toolkit_experimental.equi_depth_histogram_serialize,
+    deserialfunc = toolkit_experimental.equi_depth_histogram_deserialize,
+    parallel = restricted
+);
+"#);
+
+// Splits the values into `num_buckets` buckets each estimated to hold the same
+// number of values. The first bucket starts at the smallest value and the last
+// ends at the largest.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn into_buckets(
+    histogram: toolkit_experimental::EquiDepthHistogram<'_>,
+    num_buckets: i32,
+) -> impl std::iter::Iterator<Item = (name!(bucket_start,f64),name!(bucket_end,f64),name!(count,f64))> {
+    if num_buckets < 1 {
+        error!("into_buckets needs at least 1 bucket")
+    }
+    histogram.to_internal()
+        .equi_depth_buckets(num_buckets as u32)
+        .into_iter()
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_equi_depth_histogram_num_vals(
+    histogram: toolkit_experimental::EquiDepthHistogram,
+    accessor: toolkit_experimental::AccessorNumVals,
+) -> f64 {
+    let _ = accessor;
+    equi_depth_histogram_num_vals(histogram)
+}
+
+// Number of values from which the histogram was built.
+#[pg_extern(immutable, parallel_safe, name="num_vals", schema = "toolkit_experimental")]
+pub fn equi_depth_histogram_num_vals(
+    histogram: toolkit_experimental::EquiDepthHistogram,
+) -> f64 {
+    histogram.centroids.iter().map(|c| c.count).sum::<u64>() as f64
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_equi_depth_histogram() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test (device INTEGER, value DOUBLE PRECISION)", None, None);
+            client.select("INSERT INTO test SELECT i % 4, i FROM generate_series(0, 10000) i", None, None);
+
+            let buckets: Vec<(f64, f64, f64)> = client
+                .select("SELECT bucket_start, bucket_end, count FROM \
+                    into_buckets((SELECT equi_depth_histogram(50, value) FROM test), 4)", None, None)
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(buckets.len(), 4);
+            assert_eq!(buckets[0].0, 0.0);
+            assert_eq!(buckets[3].1, 10000.0);
+            for (i, &(start, end, count)) in buckets.iter().enumerate() {
+                assert!((count - 10001.0 / 4.0).abs() < 1e-9);
+                if i > 0 {
+                    assert_eq!(start, buckets[i - 1].1);
+                }
+                if i < 3 {
+                    assert!((end - 2500.0 * (i + 1) as f64).abs() < 100.0, "bucket {} ends at {}", i, end);
+                }
+            }
+
+            let (num_vals, rolled_up_median) = client
+                .select("SELECT \
+                        num_vals(h), \
+                        (SELECT bucket_end FROM into_buckets(h, 2) LIMIT 1) \
+                    FROM (SELECT rollup(histogram) AS h FROM \
+                        (SELECT equi_depth_histogram(50, value) AS histogram FROM test GROUP BY device) s) r",
+                    None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(num_vals, Some(10001.0));
+            assert!((rolled_up_median.unwrap() - 5000.0).abs() < 200.0);
+
+            let count = client
+                .select("SELECT equi_depth_histogram(50, value) -> num_vals() FROM test WHERE value > 100000", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(count, None);
+        });
+    }
+}
@@ -0,0 +1,383 @@
+use pgx::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// PG object holding the sums the forecast error metrics are computed from.
+// Percentage errors are undefined when the actual value is 0, so MAPE only
+// sums the points with a nonzero actual value.
+pg_type! {
+    #[derive(Debug, PartialEq)]
+    struct ForecastError {
+        n: u64,
+        sum_abs_error: f64,
+        sum_squared_error: f64,
+        num_nonzero_actuals: u64,
+        sum_abs_pct_error: f64,
+        sum_sym_abs_pct_error: f64,
+    }
+}
+
+ron_inout_funcs!(ForecastError);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(ForecastError);
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ForecastErrorTransState {
+    n: u64,
+    sum_abs_error: f64,
+    sum_squared_error: f64,
+    num_nonzero_actuals: u64,
+    sum_abs_pct_error: f64,
+    sum_sym_abs_pct_error: f64,
+}
+
+impl ForecastErrorTransState {
+    fn add(&mut self, forecast: f64, actual: f64) {
+        let error = forecast - actual;
+        self.n += 1;
+        self.sum_abs_error += error.abs();
+        self.sum_squared_error += error * error;
+        if actual != 0.0 {
+            self.num_nonzero_actuals += 1;
+            self.sum_abs_pct_error += (error / actual).abs();
+        }
+        // a forecast of 0 for an actual 0 is exact, so it adds no error
+        let scale = (forecast.abs() + actual.abs()) / 2.0;
+        if scale != 0.0 {
+            self.sum_sym_abs_pct_error += error.abs() / scale;
+        }
+    }
+
+    fn combine(&mut self, other: &ForecastErrorTransState) {
+        self.n += other.n;
+        self.sum_abs_error += other.sum_abs_error;
+        self.sum_squared_error += other.sum_squared_error;
+        self.num_nonzero_actuals += other.num_nonzero_actuals;
+        self.sum_abs_pct_error += other.sum_abs_pct_error;
+        self.sum_sym_abs_pct_error += other.sum_sym_abs_pct_error;
+    }
+
+    fn to_summary(&self) -> ForecastError<'static> {
+        build!(
+            ForecastError {
+                n: self.n,
+                sum_abs_error: self.sum_abs_error,
+                sum_squared_error: self.sum_squared_error,
+                num_nonzero_actuals: self.num_nonzero_actuals,
+                sum_abs_pct_error: self.sum_abs_pct_error,
+                sum_sym_abs_pct_error: self.sum_sym_abs_pct_error,
+            }
+        )
+    }
+}
+
+impl<'input> From<&ForecastError<'input>> for ForecastErrorTransState {
+    fn from(summary: &ForecastError<'input>) -> Self {
+        Self {
+            n: summary.n,
+            sum_abs_error: summary.sum_abs_error,
+            sum_squared_error: summary.sum_squared_error,
+            num_nonzero_actuals: summary.num_nonzero_actuals,
+            sum_abs_pct_error: summary.sum_abs_pct_error,
+            sum_sym_abs_pct_error: summary.sum_sym_abs_pct_error,
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast_error_serialize(
+    state: Internal<ForecastErrorTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn forecast_error_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<ForecastErrorTransState> {
+    crate::do_deserialize!(bytes, ForecastErrorTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast_error_trans(
+    state: Option<Internal<ForecastErrorTransState>>,
+    forecast: Option<f64>,
+    actual: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ForecastErrorTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (forecast, actual) = match (forecast, actual) {
+                (Some(forecast), Some(actual)) => (forecast, actual),
+                _ => return state,
+            };
+            let mut state = state.unwrap_or_else(|| ForecastErrorTransState::default().into());
+            state.add(forecast, actual);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast_error_summary_trans(
+    state: Option<Internal<ForecastErrorTransState>>,
+    next: Option<toolkit_experimental::ForecastError<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ForecastErrorTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => ForecastErrorTransState::from(&next),
+            };
+            match state {
+                None => Some(next.into()),
+                Some(mut state) => {
+                    state.combine(&next);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast_error_combine(
+    state1: Option<Internal<ForecastErrorTransState>>,
+    state2: Option<Internal<ForecastErrorTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ForecastErrorTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.combine(&state2);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn forecast_error_final(
+    state: Option<Internal<ForecastErrorTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::ForecastError<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            Some(state?.to_summary())
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.forecast_error_agg(
+    forecast double precision,
+    actual double precision
+) (
+    sfunc = toolkit_experimental.forecast_error_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.forecast_error_final,
+    combinefunc = toolkit_experimental.forecast_error_combine,
+    serialfunc = toolkit_experimental.forecast_error_serialize,
+    deserialfunc = toolkit_experimental.forecast_error_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    forecast_error toolkit_experimental.ForecastError
+) (
+    sfunc = toolkit_experimental.forecast_error_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.forecast_error_final,
+    combinefunc = toolkit_experimental.forecast_error_combine,
+    serialfunc = toolkit_experimental.forecast_error_serialize,
+    deserialfunc = toolkit_experimental.forecast_error_deserialize,
+    parallel = restricted
+);
+"#);
+
+// Compares the points of the two series that share a timestamp, points
+// present in only one of the series are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast_error(
+    forecast: crate::time_series::toolkit_experimental::TimeSeries<'_>,
+    actual: crate::time_series::toolkit_experimental::TimeSeries<'_>,
+) -> toolkit_experimental::ForecastError<'static> {
+    let forecast = forecast.sorted_points();
+    let actual = actual.sorted_points();
+    let mut state = ForecastErrorTransState::default();
+    let (mut f, mut a) = (0, 0);
+    while f < forecast.len() && a < actual.len() {
+        match forecast[f].ts.cmp(&actual[a].ts) {
+            std::cmp::Ordering::Less => f += 1,
+            std::cmp::Ordering::Greater => a += 1,
+            std::cmp::Ordering::Equal => {
+                state.add(forecast[f].val, actual[a].val);
+                f += 1;
+                a += 1;
+            },
+        }
+    }
+    state.to_summary()
+}
+
+// Number of forecast, actual pairs compared.
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn forecast_error_num_vals(
+    summary: toolkit_experimental::ForecastError,
+) -> i64 {
+    summary.n as i64
+}
+
+// Mean absolute error, NULL if there were no values.
+#[pg_extern(name="mae", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn forecast_error_mae(
+    summary: toolkit_experimental::ForecastError,
+) -> Option<f64> {
+    if summary.n == 0 {
+        return None
+    }
+    Some(summary.sum_abs_error / summary.n as f64)
+}
+
+// Root mean squared error, NULL if there were no values.
+#[pg_extern(name="rmse", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn forecast_error_rmse(
+    summary: toolkit_experimental::ForecastError,
+) -> Option<f64> {
+    if summary.n == 0 {
+        return None
+    }
+    Some((summary.sum_squared_error / summary.n as f64).sqrt())
+}
+
+// Mean absolute percentage error as a percentage, over the values whose
+// actual value is not 0. NULL if there are no such values.
+#[pg_extern(name="mape", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn forecast_error_mape(
+    summary: toolkit_experimental::ForecastError,
+) -> Option<f64> {
+    if summary.num_nonzero_actuals == 0 {
+        return None
+    }
+    Some(100.0 * summary.sum_abs_pct_error / summary.num_nonzero_actuals as f64)
+}
+
+// Symmetric mean absolute percentage error as a percentage between 0 and 200,
+// NULL if there were no values.
+#[pg_extern(name="smape", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn forecast_error_smape(
+    summary: toolkit_experimental::ForecastError,
+) -> Option<f64> {
+    if summary.n == 0 {
+        return None
+    }
+    Some(100.0 * summary.sum_sym_abs_pct_error / summary.n as f64)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_forecast_error() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE backtest(model TEXT, time TIMESTAMPTZ, forecast DOUBLE PRECISION, actual DOUBLE PRECISION)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO backtest VALUES \
+                    ('a', '2020-01-01 UTC', 110, 100), \
+                    ('a', '2020-01-02 UTC', 90, 100), \
+                    ('a', '2020-01-03 UTC', 0, 0), \
+                    ('b', '2020-01-01 UTC', 50, 100), \
+                    ('b', '2020-01-02 UTC', 100, 100), \
+                    ('b', '2020-01-03 UTC', 40, 0)",
+                None,
+                None
+            );
+
+            let (mae, rmse, mape) = client.select(
+                "SELECT mae(e), rmse(e), mape(e) FROM \
+                    (SELECT forecast_error_agg(forecast, actual) AS e FROM backtest WHERE model = 'a') s",
+                None,
+                None
+            )
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert!((mae.unwrap() - 20.0 / 3.0).abs() < 1e-12);
+            assert!((rmse.unwrap() - (200.0f64 / 3.0).sqrt()).abs() < 1e-12);
+            // the point with an actual value of 0 is skipped
+            assert!((mape.unwrap() - 10.0).abs() < 1e-12);
+
+            let (smape, num_vals) = client.select(
+                "SELECT smape(e), num_vals(e) FROM \
+                    (SELECT forecast_error_agg(forecast, actual) AS e FROM backtest WHERE model = 'a') s",
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, i64>();
+            let expected = 100.0 * (10.0 / 105.0 + 10.0 / 95.0) / 3.0;
+            assert!((smape.unwrap() - expected).abs() < 1e-12);
+            assert_eq!(num_vals, Some(3));
+
+            // the timeseries form matches the aggregate, even with a point
+            // missing from the forecast and the series out of order
+            let (series_mae, rolled_up_mae) = client.select(
+                "SELECT \
+                    (SELECT mae(forecast_error(\
+                        (SELECT timeseries(time, forecast) FROM backtest WHERE model = 'b' AND time > '2020-01-01 UTC'), \
+                        (SELECT timeseries(time, actual) FROM (SELECT * FROM backtest ORDER BY time DESC) s WHERE model = 'b')))), \
+                    (SELECT mae(rollup(e)) FROM \
+                        (SELECT forecast_error_agg(forecast, actual) AS e FROM backtest GROUP BY model) s)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, f64>();
+            assert!((series_mae.unwrap() - 20.0).abs() < 1e-12);
+            assert!((rolled_up_mae.unwrap() - (20.0 + 90.0) / 6.0).abs() < 1e-12);
+
+            let empty = client.select(
+                "SELECT mae(forecast_error_agg(forecast, actual)) FROM backtest WHERE model = 'c'",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(empty, None);
+        });
+    }
+}
@@ -0,0 +1,310 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    utilities::micros_to_interval,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// PG object recording how far through an ordered funnel of steps a group of
+// events got, and when each step was reached. Steps are numbered from 1 as in
+// the SQL array they were given by.
+pg_type! {
+    #[derive(Debug)]
+    struct FunnelAgg<'input> {
+        num_steps: u64,
+        steps_reached: u64,
+        names_len: u64,
+        step_times: [i64; self.steps_reached],
+        // the step names, each followed by a NUL byte
+        step_names: [u8; self.names_len],
+    }
+}
+
+ron_inout_funcs!(FunnelAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(FunnelAgg);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunnelTransState {
+    steps: Vec<String>,
+    // (time, step index) of each event that is one of the steps
+    events: Vec<(i64, usize)>,
+}
+
+impl FunnelTransState {
+    // Each step is reached by the earliest matching event at or after the
+    // previous step was reached, which also reaches as many steps as possible.
+    fn step_times(&mut self) -> Vec<i64> {
+        self.events.sort_unstable();
+        let mut times = vec![];
+        for &(time, step) in &self.events {
+            if step == times.len() {
+                times.push(time);
+            }
+        }
+        times
+    }
+}
+
+impl<'input> FunnelAgg<'input> {
+    fn step_time(&self, step: i32) -> Option<i64> {
+        if step < 1 || step as u64 > self.num_steps {
+            error!("funnel step {} does not exist, steps are numbered from 1 to {}", step, self.num_steps)
+        }
+        self.step_times.as_slice().get(step as usize - 1).copied()
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn funnel_agg_serialize(
+    state: Internal<FunnelTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn funnel_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<FunnelTransState> {
+    crate::do_deserialize!(bytes, FunnelTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn funnel_agg_trans(
+    state: Option<Internal<FunnelTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    event: Option<&str>,
+    steps: Array<&str>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<FunnelTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => FunnelTransState {
+                    steps: steps.iter()
+                        .map(|step| step.unwrap_or_else(|| error!("funnel steps must not be NULL")).to_string())
+                        .collect(),
+                    events: vec![],
+                }.into(),
+                Some(state) => state,
+            };
+            let (ts, event) = match (ts, event) {
+                (Some(ts), Some(event)) => (ts, event),
+                _ => return Some(state),
+            };
+            // an event may appear as more than one step of the funnel
+            let steps: Vec<usize> = state.steps.iter().enumerate()
+                .filter(|(_, step)| *step == event)
+                .map(|(i, _)| i)
+                .collect();
+            state.events.extend(steps.into_iter().map(|step| (ts, step)));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn funnel_agg_combine(
+    state1: Option<Internal<FunnelTransState>>,
+    state2: Option<Internal<FunnelTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<FunnelTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.events.extend_from_slice(&state2.events);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn funnel_agg_final(
+    state: Option<Internal<FunnelTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::FunnelAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?.clone();
+            let times = state.step_times();
+            let mut names = vec![];
+            for step in &state.steps {
+                names.extend_from_slice(step.as_bytes());
+                names.push(0);
+            }
+            Some(build!(
+                FunnelAgg {
+                    num_steps: state.steps.len() as _,
+                    steps_reached: times.len() as _,
+                    names_len: names.len() as _,
+                    step_times: times.into(),
+                    step_names: names.into(),
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.funnel_agg(
+    ts timestamptz,
+    event text,
+    steps text[]
+) (
+    sfunc = toolkit_experimental.funnel_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.funnel_agg_final,
+    combinefunc = toolkit_experimental.funnel_agg_combine,
+    serialfunc = toolkit_experimental.funnel_agg_serialize,
+    deserialfunc = toolkit_experimental.funnel_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn steps_reached(
+    agg: toolkit_experimental::FunnelAgg<'_>,
+) -> i64 {
+    agg.steps_reached as _
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn reached_step(
+    agg: toolkit_experimental::FunnelAgg<'_>,
+    step: i32,
+) -> bool {
+    agg.step_time(step).is_some()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn step_reached_at(
+    agg: toolkit_experimental::FunnelAgg<'_>,
+    step: i32,
+) -> Option<pg_sys::TimestampTz> {
+    agg.step_time(step)
+}
+
+// the time taken to get from `from_step` to `to_step`, NULL if `to_step` was not reached
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_between_steps(
+    agg: toolkit_experimental::FunnelAgg<'_>,
+    from_step: i32,
+    to_step: i32,
+) -> Option<Interval> {
+    if from_step > to_step {
+        error!("funnel steps must be given in order")
+    }
+    let from = agg.step_time(from_step)?;
+    let to = agg.step_time(to_step)?;
+    Some(micros_to_interval(to - from))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_funnel_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE events(user_id int, ts timestamptz, event text)", None, None);
+            client.select(
+                "INSERT INTO events VALUES \
+                    (1, '2020-01-01 00:00:00+00', 'view'), \
+                    (1, '2020-01-01 00:05:00+00', 'view'), \
+                    (1, '2020-01-01 00:03:00+00', 'cart'), \
+                    (1, '2020-01-01 00:10:00+00', 'buy'), \
+                    (2, '2020-01-01 00:00:00+00', 'cart'), \
+                    (2, '2020-01-01 00:01:00+00', 'view'), \
+                    (3, '2020-01-01 00:00:00+00', 'buy')",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW funnels AS \
+                    SELECT user_id, funnel_agg(ts, event, ARRAY['view', 'cart', 'buy']) AS agg \
+                    FROM events GROUP BY user_id",
+                None,
+                None
+            );
+
+            // user 2 added to their cart before viewing, so only reached the first step
+            let reached: Vec<i64> = client.select(
+                "SELECT steps_reached(agg) FROM funnels ORDER BY user_id",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(reached, vec![3, 1, 0]);
+
+            let val = client.select(
+                "SELECT time_between_steps(agg, 1, 3)::TEXT FROM funnels WHERE user_id = 1",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:10:00");
+
+            let val = client.select(
+                "SELECT step_reached_at(agg, 2)::TEXT FROM funnels WHERE user_id = 1",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "2020-01-01 00:03:00+00");
+
+            let val = client.select(
+                "SELECT reached_step(agg, 2) FROM funnels WHERE user_id = 2",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val.unwrap(), false);
+
+            let val = client.select(
+                "SELECT time_between_steps(agg, 1, 2) FROM funnels WHERE user_id = 2",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val, None);
+        });
+    }
+}
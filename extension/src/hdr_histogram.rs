@@ -0,0 +1,453 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use hdrhistogram::{
+    Histogram,
+    serialization::{Deserializer, Serializer, V2DeflateSerializer, V2Serializer},
+};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// PG object for the histogram, stored in the HdrHistogram V2 encoding
+pg_type! {
+    #[derive(Debug)]
+    struct HdrHistogram<'input> {
+        num_bytes: u32,
+        bytes: [u8; self.num_bytes],
+    }
+}
+
+ron_inout_funcs!(HdrHistogram);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(HdrHistogram);
+}
+
+fn decode_histogram(mut bytes: &[u8]) -> Histogram<u64> {
+    Deserializer::new()
+        .deserialize(&mut bytes)
+        .unwrap_or_else(|e| error!("invalid hdr histogram: {:?}", e))
+}
+
+impl<'input> HdrHistogram<'input> {
+    fn to_histogram(&self) -> Histogram<u64> {
+        decode_histogram(self.bytes.as_slice())
+    }
+
+    fn from_histogram(histogram: &Histogram<u64>) -> HdrHistogram<'static> {
+        let mut bytes = vec![];
+        V2Serializer::new()
+            .serialize(histogram, &mut bytes)
+            .unwrap_or_else(|e| error!("hdr histogram serialization error: {:?}", e));
+        build!(
+            HdrHistogram {
+                num_bytes: bytes.len() as u32,
+                bytes: bytes.into(),
+            }
+        )
+    }
+}
+
+// Histogram doesn't implement serde, so the transition state is sent between
+// workers in its V2 encoding
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedHistogram {
+    bytes: Vec<u8>,
+}
+
+impl From<&Histogram<u64>> for SerializedHistogram {
+    fn from(histogram: &Histogram<u64>) -> Self {
+        let mut bytes = vec![];
+        V2Serializer::new()
+            .serialize(histogram, &mut bytes)
+            .unwrap_or_else(|e| error!("hdr histogram serialization error: {:?}", e));
+        SerializedHistogram { bytes }
+    }
+}
+
+impl From<SerializedHistogram> for Histogram<u64> {
+    fn from(serialized: SerializedHistogram) -> Self {
+        decode_histogram(&serialized.bytes)
+    }
+}
+
+// PG function for adding values to a histogram.
+// Null values are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hdr_histogram_trans(
+    state: Option<Internal<Histogram<u64>>>,
+    lowest_trackable: i64,
+    highest_trackable: i64,
+    significant_digits: i32,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<Histogram<u64>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => {
+                    if lowest_trackable < 1 || highest_trackable < 2 * lowest_trackable {
+                        error!("hdr_histogram range must have 1 <= lowest_trackable and 2 * lowest_trackable <= highest_trackable")
+                    }
+                    if !(0..=5).contains(&significant_digits) {
+                        error!("hdr_histogram significant_digits must be between 0 and 5")
+                    }
+                    Histogram::new_with_bounds(
+                        lowest_trackable as u64,
+                        highest_trackable as u64,
+                        significant_digits as u8,
+                    ).unwrap_or_else(|e| error!("invalid hdr_histogram parameters: {:?}", e)).into()
+                },
+                Some(state) => state,
+            };
+            if value < 0 {
+                error!("hdr_histogram cannot record negative value {}", value)
+            }
+            state.record(value as u64)
+                .unwrap_or_else(|_| error!("value {} is outside the trackable range of the hdr_histogram", value));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hdr_histogram_summary_trans(
+    state: Option<Internal<Histogram<u64>>>,
+    value: Option<toolkit_experimental::HdrHistogram<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<Histogram<u64>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_histogram(),
+            };
+            match state {
+                None => Some(value.into()),
+                Some(mut state) => {
+                    merge_histograms(&mut state, &value);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+// histograms with different ranges can be rolled up, the result grows to
+// cover the combined range
+fn merge_histograms(state: &mut Histogram<u64>, other: &Histogram<u64>) {
+    state.auto(true);
+    state.add(other)
+        .unwrap_or_else(|e| error!("cannot combine hdr histograms: {:?}", e));
+}
+
+// PG function for merging histograms.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hdr_histogram_combine(
+    state1: Option<Internal<Histogram<u64>>>,
+    state2: Option<Internal<Histogram<u64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<Histogram<u64>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut histogram = state1.clone();
+                    merge_histograms(&mut histogram, &state2);
+                    Some(histogram.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hdr_histogram_serialize(
+    state: Internal<Histogram<u64>>,
+) -> bytea {
+    let serializable = &SerializedHistogram::from(&*state);
+    crate::do_serialize!(serializable)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hdr_histogram_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<Histogram<u64>> {
+    let histogram: Histogram<u64> = crate::do_deserialize!(bytes, SerializedHistogram);
+    histogram.into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn hdr_histogram_final(
+    state: Option<Internal<Histogram<u64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::HdrHistogram<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            HdrHistogram::from_histogram(&state).into()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.hdr_histogram(
+    lowest_trackable bigint, highest_trackable bigint, significant_digits int, value bigint
+) (
+    sfunc = toolkit_experimental.hdr_histogram_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.hdr_histogram_final,
+    combinefunc = toolkit_experimental.hdr_histogram_combine,
+    serialfunc = toolkit_experimental.hdr_histogram_serialize,
+    deserialfunc = toolkit_experimental.hdr_histogram_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    histogram toolkit_experimental.HdrHistogram
+) (
+    sfunc = toolkit_experimental.hdr_histogram_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.hdr_histogram_final,
+    combinefunc = toolkit_experimental.hdr_histogram_combine,
+    serialfunc = toolkit_experimental.hdr_histogram_serialize,
+    deserialfunc = toolkit_experimental.hdr_histogram_deserialize,
+    parallel = restricted
+);
+"#);
+
+// The histogram as compressed Base64, as found in the last column of
+// HdrHistogram interval logs, e.g. those exported by JMeter or Gatling.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn to_base64(
+    histogram: toolkit_experimental::HdrHistogram<'_>,
+) -> String {
+    let mut bytes = vec![];
+    V2DeflateSerializer::new()
+        .serialize(&histogram.to_histogram(), &mut bytes)
+        .unwrap_or_else(|e| error!("hdr histogram serialization error: {:?}", e));
+    base64::encode(&bytes)
+}
+
+// Reads a Base64 encoded histogram, either on its own or as a line of an
+// HdrHistogram interval log, in which case the histogram is the last column.
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn hdr_histogram_from_base64(
+    text: &str,
+) -> toolkit_experimental::HdrHistogram<'static> {
+    let encoded = text.rsplit(',').next().unwrap_or(text).trim();
+    let bytes = base64::decode(encoded)
+        .unwrap_or_else(|e| error!("invalid base64 in hdr histogram: {}", e));
+    HdrHistogram::from_histogram(&decode_histogram(&bytes))
+}
+
+//---- Available PG operations on the histogram
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_approx_percentile(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorApproxPercentile,
+) -> Option<f64> {
+    hdr_histogram_approx_percentile(accessor.percentile, histogram)
+}
+
+// The value at the given percentile (0.0-1.0), accurate to the histogram's
+// significant digits.
+#[pg_extern(immutable, parallel_safe, name="approx_percentile", schema = "toolkit_experimental")]
+pub fn hdr_histogram_approx_percentile(
+    percentile: f64,
+    histogram: toolkit_experimental::HdrHistogram,
+) -> Option<f64> {
+    if !(0.0..=1.0).contains(&percentile) {
+        error!("percentile must be between 0.0 and 1.0")
+    }
+    let histogram = histogram.to_histogram();
+    if histogram.is_empty() {
+        return None
+    }
+    Some(histogram.value_at_quantile(percentile) as f64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_approx_rank(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorApproxRank,
+) -> Option<f64> {
+    hdr_histogram_approx_percentile_rank(accessor.value, histogram)
+}
+
+// The fraction of values at or below the given value
+#[pg_extern(immutable, parallel_safe, name="approx_percentile_rank", schema = "toolkit_experimental")]
+pub fn hdr_histogram_approx_percentile_rank(
+    value: f64,
+    histogram: toolkit_experimental::HdrHistogram,
+) -> Option<f64> {
+    let histogram = histogram.to_histogram();
+    if histogram.is_empty() {
+        return None
+    }
+    if value < 0.0 {
+        return Some(0.0)
+    }
+    Some(histogram.quantile_below(value as u64))
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_num_vals(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorNumVals,
+) -> f64 {
+    let _ = accessor;
+    hdr_histogram_num_vals(histogram)
+}
+
+// Number of values recorded in the histogram.
+#[pg_extern(immutable, parallel_safe, name="num_vals", schema = "toolkit_experimental")]
+pub fn hdr_histogram_num_vals(
+    histogram: toolkit_experimental::HdrHistogram,
+) -> f64 {
+    histogram.to_histogram().len() as f64
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_min(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorMin,
+) -> Option<f64> {
+    let _ = accessor;
+    hdr_histogram_min(histogram)
+}
+
+// Smallest value recorded, accurate to the histogram's significant digits.
+#[pg_extern(immutable, parallel_safe, name="min_val", schema = "toolkit_experimental")]
+pub fn hdr_histogram_min(
+    histogram: toolkit_experimental::HdrHistogram,
+) -> Option<f64> {
+    let histogram = histogram.to_histogram();
+    (!histogram.is_empty()).then(|| histogram.min() as f64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_max(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorMax,
+) -> Option<f64> {
+    let _ = accessor;
+    hdr_histogram_max(histogram)
+}
+
+// Largest value recorded, accurate to the histogram's significant digits.
+#[pg_extern(immutable, parallel_safe, name="max_val", schema = "toolkit_experimental")]
+pub fn hdr_histogram_max(
+    histogram: toolkit_experimental::HdrHistogram,
+) -> Option<f64> {
+    let histogram = histogram.to_histogram();
+    (!histogram.is_empty()).then(|| histogram.max() as f64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hdr_histogram_mean(
+    histogram: toolkit_experimental::HdrHistogram,
+    accessor: toolkit_experimental::AccessorMean,
+) -> Option<f64> {
+    let _ = accessor;
+    hdr_histogram_mean(histogram)
+}
+
+// Average of the values recorded, computed from the bucket midpoints.
+#[pg_extern(immutable, parallel_safe, name="mean", schema = "toolkit_experimental")]
+pub fn hdr_histogram_mean(
+    histogram: toolkit_experimental::HdrHistogram,
+) -> Option<f64> {
+    let histogram = histogram.to_histogram();
+    (!histogram.is_empty()).then(|| histogram.mean())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_hdr_histogram() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE latencies (host INTEGER, micros BIGINT)", None, None);
+            client.select("INSERT INTO latencies SELECT i % 4, i FROM generate_series(1, 10000) i", None, None);
+            client.select("CREATE VIEW histograms AS \
+                SELECT host, hdr_histogram(1, 3600000000, 3, micros) \
+                FROM latencies GROUP BY host", None, None);
+
+            let (num_vals, min, max) = client
+                .select("SELECT \
+                    num_vals(hdr_histogram), \
+                    hdr_histogram -> min_val(), \
+                    max_val(hdr_histogram) \
+                    FROM (SELECT rollup(hdr_histogram) AS hdr_histogram FROM histograms) h", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(num_vals, Some(10000.0));
+            assert_eq!(min, Some(1.0));
+            // values are stored with 3 significant digits
+            assert!((max.unwrap() - 10000.0).abs() <= 10.0);
+
+            let (median, median2, rank) = client
+                .select("SELECT \
+                    approx_percentile(0.5, hdr_histogram), \
+                    hdr_histogram -> approx_percentile(0.5), \
+                    approx_percentile_rank(2500, hdr_histogram) \
+                    FROM (SELECT rollup(hdr_histogram) AS hdr_histogram FROM histograms) h", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert!((median.unwrap() - 5000.0).abs() <= 5.0);
+            assert_eq!(median, median2);
+            assert!((rank.unwrap() - 0.25).abs() < 0.001);
+
+            // Base64 is the HdrHistogram interval log encoding
+            let (encoded, round_tripped, from_log_line) = client
+                .select("SELECT \
+                        to_base64(h), \
+                        num_vals(hdr_histogram_from_base64(to_base64(h))), \
+                        num_vals(hdr_histogram_from_base64('Tag=api,0.127,1.007,2.769,' || to_base64(h))) \
+                    FROM (SELECT hdr_histogram(1, 3600000000, 3, micros) AS h FROM latencies) s", None, None)
+                .first()
+                .get_three::<String, f64, f64>();
+            assert!(encoded.unwrap().starts_with("HISTFAAA"));
+            assert_eq!(round_tripped, Some(10000.0));
+            assert_eq!(from_log_line, Some(10000.0));
+        });
+    }
+}
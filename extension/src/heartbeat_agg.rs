@@ -0,0 +1,675 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use counter_agg::range::I64Range;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    utilities::{interval_to_micros, micros_to_interval},
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+#[allow(non_camel_case_types)]
+type tstzrange = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// [start, end)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct LiveRange {
+    start: i64,
+    end: i64,
+}
+
+// PG object recording when a system was alive over [start_time, end_time),
+// where a system is considered alive for `liveness` after each heartbeat
+pg_type! {
+    #[derive(Debug)]
+    struct HeartbeatAgg<'input> {
+        start_time: i64,
+        end_time: i64,
+        last_heartbeat: i64,
+        liveness: i64, // microseconds
+        num_ranges: u64,
+        live_ranges: [LiveRange; self.num_ranges],
+    }
+}
+
+ron_inout_funcs!(HeartbeatAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(HeartbeatAgg);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeartbeatTransState {
+    start: i64,
+    end: i64,
+    liveness: i64,
+    heartbeats: Vec<i64>,
+    // live ranges of rolled up aggregates
+    ranges: Vec<LiveRange>,
+}
+
+impl HeartbeatTransState {
+    fn live_ranges(&mut self) -> Vec<LiveRange> {
+        self.heartbeats.sort_unstable();
+        let mut live: Vec<LiveRange> = self.heartbeats.iter()
+            .map(|&heartbeat| LiveRange {
+                start: heartbeat,
+                end: heartbeat.saturating_add(self.liveness).min(self.end),
+            })
+            .chain(self.ranges.iter().copied())
+            .collect();
+        live.sort_by_key(|range| range.start);
+
+        let mut ranges: Vec<LiveRange> = vec![];
+        for range in live {
+            match ranges.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+        ranges
+    }
+
+    fn add_agg(&mut self, agg: &HeartbeatAgg<'_>) {
+        if agg.liveness != self.liveness {
+            error!("cannot rollup heartbeat aggregates with different liveness intervals")
+        }
+        self.start = self.start.min(agg.start_time);
+        self.end = self.end.max(agg.end_time);
+        // the last heartbeat may keep the system alive past the end of its aggregate
+        if agg.last_heartbeat != i64::MIN {
+            self.heartbeats.push(agg.last_heartbeat);
+        }
+        self.ranges.extend(agg.live_ranges.iter());
+    }
+}
+
+impl<'input> HeartbeatAgg<'input> {
+    fn uptime(&self) -> i64 {
+        self.live_ranges.iter().map(|range| range.end - range.start).sum()
+    }
+
+    // the live ranges, including the time at the start of this aggregate that
+    // `prev`, the aggregate of the preceding interval, was still alive for
+    fn interpolated_ranges(&self, prev: Option<&HeartbeatAgg<'_>>) -> Vec<LiveRange> {
+        let mut ranges: Vec<LiveRange> = self.live_ranges.iter().collect();
+        if let Some(prev) = prev {
+            if prev.liveness != self.liveness {
+                error!("cannot interpolate heartbeat aggregates with different liveness intervals")
+            }
+        }
+        let carried_until = match prev {
+            Some(prev) if prev.last_heartbeat != i64::MIN =>
+                prev.last_heartbeat.saturating_add(prev.liveness).min(self.end_time),
+            _ => return ranges,
+        };
+        if carried_until <= self.start_time {
+            return ranges
+        }
+        match ranges.first_mut() {
+            Some(first) if first.start <= carried_until => {
+                first.start = self.start_time;
+                first.end = first.end.max(carried_until);
+            },
+            _ => ranges.insert(0, LiveRange { start: self.start_time, end: carried_until }),
+        }
+        ranges
+    }
+
+    fn interpolated(&self, prev: Option<&HeartbeatAgg<'_>>) -> HeartbeatAgg<'static> {
+        let ranges = self.interpolated_ranges(prev);
+        build!(
+            HeartbeatAgg {
+                start_time: self.start_time,
+                end_time: self.end_time,
+                last_heartbeat: self.last_heartbeat,
+                liveness: self.liveness,
+                num_ranges: ranges.len() as _,
+                live_ranges: ranges.into(),
+            }
+        )
+    }
+
+    fn dead_ranges(&self) -> Vec<LiveRange> {
+        let mut dead = vec![];
+        let mut dead_since = self.start_time;
+        for range in self.live_ranges.iter() {
+            if range.start > dead_since {
+                dead.push(LiveRange { start: dead_since, end: range.start });
+            }
+            dead_since = range.end;
+        }
+        if dead_since < self.end_time {
+            dead.push(LiveRange { start: dead_since, end: self.end_time });
+        }
+        dead
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_serialize(
+    state: Internal<HeartbeatTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<HeartbeatTransState> {
+    crate::do_deserialize!(bytes, HeartbeatTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_trans(
+    state: Option<Internal<HeartbeatTransState>>,
+    heartbeat: Option<pg_sys::TimestampTz>,
+    agg_start: pg_sys::TimestampTz,
+    agg_duration: Interval,
+    liveness: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => HeartbeatTransState {
+                    start: agg_start,
+                    end: agg_start.saturating_add(interval_to_micros(agg_duration, "heartbeat intervals")),
+                    liveness: interval_to_micros(liveness, "heartbeat intervals"),
+                    heartbeats: vec![],
+                    ranges: vec![],
+                }.into(),
+                Some(state) => state,
+            };
+            match heartbeat {
+                Some(heartbeat) if heartbeat >= state.start && heartbeat < state.end =>
+                    state.heartbeats.push(heartbeat),
+                _ => (),
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_summary_trans(
+    state: Option<Internal<HeartbeatTransState>>,
+    next: Option<toolkit_experimental::HeartbeatAgg<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => next,
+            };
+            let mut state = match state {
+                None => HeartbeatTransState {
+                    start: next.start_time,
+                    end: next.end_time,
+                    liveness: next.liveness,
+                    heartbeats: vec![],
+                    ranges: vec![],
+                }.into(),
+                Some(state) => state,
+            };
+            state.add_agg(&next);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_combine(
+    state1: Option<Internal<HeartbeatTransState>>,
+    state2: Option<Internal<HeartbeatTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.start = state.start.min(state2.start);
+                    state.end = state.end.max(state2.end);
+                    state.heartbeats.extend_from_slice(&state2.heartbeats);
+                    state.ranges.extend_from_slice(&state2.ranges);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn heartbeat_agg_final(
+    state: Option<Internal<HeartbeatTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::HeartbeatAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?.clone();
+            let ranges = state.live_ranges();
+            // with no heartbeats the system was last seen before the aggregate started
+            let last_heartbeat = state.heartbeats.last().copied().unwrap_or(i64::MIN);
+            Some(build!(
+                HeartbeatAgg {
+                    start_time: state.start,
+                    end_time: state.end,
+                    last_heartbeat,
+                    liveness: state.liveness,
+                    num_ranges: ranges.len() as _,
+                    live_ranges: ranges.into(),
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.heartbeat_agg(
+    heartbeat timestamptz,
+    agg_start timestamptz,
+    agg_duration interval,
+    heartbeat_liveness interval
+) (
+    sfunc = toolkit_experimental.heartbeat_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.heartbeat_agg_final,
+    combinefunc = toolkit_experimental.heartbeat_agg_combine,
+    serialfunc = toolkit_experimental.heartbeat_agg_serialize,
+    deserialfunc = toolkit_experimental.heartbeat_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    agg toolkit_experimental.HeartbeatAgg
+) (
+    sfunc = toolkit_experimental.heartbeat_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.heartbeat_agg_final,
+    combinefunc = toolkit_experimental.heartbeat_agg_combine,
+    serialfunc = toolkit_experimental.heartbeat_agg_serialize,
+    deserialfunc = toolkit_experimental.heartbeat_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uptime(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+) -> Interval {
+    micros_to_interval(agg.uptime())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn downtime(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+) -> Interval {
+    micros_to_interval(agg.end_time - agg.start_time - agg.uptime())
+}
+
+// the aggregate with the liveness of the last heartbeat of `prev`, the
+// aggregate of the preceding interval, carried over into this one
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn interpolate(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+    prev: Option<toolkit_experimental::HeartbeatAgg<'_>>,
+) -> toolkit_experimental::HeartbeatAgg<'static> {
+    agg.interpolated(prev.as_ref())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn interpolated_uptime(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+    prev: Option<toolkit_experimental::HeartbeatAgg<'_>>,
+) -> Interval {
+    micros_to_interval(agg.interpolated(prev.as_ref()).uptime())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn interpolated_downtime(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+    prev: Option<toolkit_experimental::HeartbeatAgg<'_>>,
+) -> Interval {
+    let agg = agg.interpolated(prev.as_ref());
+    micros_to_interval(agg.end_time - agg.start_time - agg.uptime())
+}
+
+// Availability over the period covered by the aggregate compared against
+// `target`, the fraction of the period the system is required to be alive.
+// The remaining downtime is how much more could be tolerated without
+// breaching the target.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sla_report(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+    target: f64,
+) -> impl std::iter::Iterator<Item = (name!(availability,f64),name!(downtime_remaining,Interval),name!(breached,bool))> {
+    if !(0.0..=1.0).contains(&target) {
+        error!("SLA target must be between 0 and 1")
+    }
+    let period = agg.end_time - agg.start_time;
+    let uptime = agg.uptime();
+    let availability = if period == 0 { 1.0 } else { uptime as f64 / period as f64 };
+    let allowed_downtime = ((1.0 - target) * period as f64) as i64;
+    let remaining = (allowed_downtime - (period - uptime)).max(0);
+    std::iter::once((availability, micros_to_interval(remaining), availability < target))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_at(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+    ts: pg_sys::TimestampTz,
+) -> bool {
+    if ts < agg.start_time || ts >= agg.end_time {
+        error!("timestamp is outside the range covered by the heartbeat aggregate")
+    }
+    agg.live_ranges.iter().any(|range| range.start <= ts && ts < range.end)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_ranges(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+) -> impl std::iter::Iterator<Item = tstzrange> + '_ {
+    agg.live_ranges.iter().map(to_tstzrange)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn dead_ranges(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+) -> impl std::iter::Iterator<Item = tstzrange> + '_ {
+    agg.dead_ranges().into_iter().map(to_tstzrange)
+}
+
+// the whole aggregated range split into the spans where the system was live
+// and those where it was dead, in time order
+#[pg_extern(immutable, parallel_safe, name = "into_values", schema = "toolkit_experimental")]
+pub fn heartbeat_agg_into_values(
+    agg: toolkit_experimental::HeartbeatAgg<'_>,
+) -> impl std::iter::Iterator<Item = (name!(start_time,pg_sys::TimestampTz),name!(end_time,pg_sys::TimestampTz),name!(value,bool))> + '_ {
+    let mut spans: Vec<_> = agg.live_ranges.iter().map(|range| (range.start, range.end, true))
+        .chain(agg.dead_ranges().into_iter().map(|range| (range.start, range.end, false)))
+        .collect();
+    spans.sort_by_key(|&(start, _, _)| start);
+    spans.into_iter()
+}
+
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// The rows of the range functions above for the planner, see
+// estimate_srf_rows()
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_ranges_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: HeartbeatAgg| agg.num_ranges as usize)
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn dead_ranges_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: HeartbeatAgg| agg.dead_ranges().len())
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_into_values_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: HeartbeatAgg|
+            agg.num_ranges as usize + agg.dead_ranges().len())
+    }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        ALTER FUNCTION toolkit_experimental.live_ranges(toolkit_experimental.HeartbeatAgg)
+            SUPPORT toolkit_experimental.live_ranges_rows_support;
+        ALTER FUNCTION toolkit_experimental.dead_ranges(toolkit_experimental.HeartbeatAgg)
+            SUPPORT toolkit_experimental.dead_ranges_rows_support;
+        ALTER FUNCTION toolkit_experimental.into_values(toolkit_experimental.HeartbeatAgg)
+            SUPPORT toolkit_experimental.heartbeat_agg_into_values_rows_support;
+    END IF;
+END
+$$;
+"#);
+
+fn to_tstzrange(range: LiveRange) -> tstzrange {
+    let range = I64Range {
+        left: Some(range.start),
+        right: Some(range.end),
+    };
+    unsafe {
+        crate::range::i64range_to_tstzrange(range) as tstzrange
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_heartbeat_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE heartbeats(ts timestamptz)", None, None);
+            client.select(
+                "INSERT INTO heartbeats VALUES \
+                    ('2020-01-01 00:01:00+00'), \
+                    ('2020-01-01 00:02:00+00'), \
+                    ('2020-01-01 00:05:00+00'), \
+                    ('2020-01-01 00:09:00+00')",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW agg AS \
+                    SELECT heartbeat_agg(ts, '2020-01-01 00:00:00+00', '10 minutes', '2 minutes') AS agg \
+                    FROM heartbeats",
+                None,
+                None
+            );
+
+            // live during [00:01, 00:04), [00:05, 00:07), and [00:09, 00:10)
+            let val = client.select("SELECT uptime(agg)::TEXT FROM agg", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:06:00");
+
+            let val = client.select("SELECT downtime(agg)::TEXT FROM agg", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:04:00");
+
+            let val = client.select(
+                "SELECT live_at(agg, '2020-01-01 00:04:00+00') FROM agg",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val.unwrap(), false);
+
+            let val = client.select(
+                "SELECT live_at(agg, '2020-01-01 00:06:59+00') FROM agg",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val.unwrap(), true);
+
+            // 6 of 10 minutes up
+            let val = client.select(
+                "SELECT availability, downtime_remaining::TEXT FROM sla_report((SELECT agg FROM agg), 0.5)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, String>();
+            assert_eq!(val, (Some(0.6), Some("00:01:00".to_string())));
+
+            let val = client.select(
+                "SELECT breached, downtime_remaining::TEXT FROM sla_report((SELECT agg FROM agg), 0.9)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<bool, String>();
+            assert_eq!(val, (Some(true), Some("00:00:00".to_string())));
+
+            let ranges: Vec<String> = client.select(
+                "SELECT dead_ranges(agg)::TEXT FROM agg",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(ranges, vec![
+                "[\"2020-01-01 00:00:00+00\",\"2020-01-01 00:01:00+00\")",
+                "[\"2020-01-01 00:04:00+00\",\"2020-01-01 00:05:00+00\")",
+                "[\"2020-01-01 00:07:00+00\",\"2020-01-01 00:09:00+00\")",
+            ]);
+
+            let spans: Vec<(String, String, bool)> = client.select(
+                "SELECT start_time::TIME::TEXT, end_time::TIME::TEXT, value FROM into_values((SELECT agg FROM agg))",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(spans, vec![
+                ("00:00:00".to_string(), "00:01:00".to_string(), false),
+                ("00:01:00".to_string(), "00:04:00".to_string(), true),
+                ("00:04:00".to_string(), "00:05:00".to_string(), false),
+                ("00:05:00".to_string(), "00:07:00".to_string(), true),
+                ("00:07:00".to_string(), "00:09:00".to_string(), false),
+                ("00:09:00".to_string(), "00:10:00".to_string(), true),
+            ]);
+        });
+    }
+
+    #[pg_test]
+    fn test_heartbeat_interpolation_and_rollup() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE heartbeats(ts timestamptz)", None, None);
+            client.select(
+                "INSERT INTO heartbeats VALUES \
+                    ('2020-01-01 00:00:00+00'), \
+                    ('2020-01-01 00:04:00+00'), \
+                    ('2020-01-01 00:06:00+00'), \
+                    ('2020-01-01 00:09:00+00')",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW buckets AS \
+                    SELECT bucket, heartbeat_agg(ts, bucket, '5 minutes', '2 minutes') AS agg \
+                    FROM (SELECT ts, to_timestamp(floor(extract(epoch FROM ts) / 300) * 300) AS bucket FROM heartbeats) h \
+                    GROUP BY bucket",
+                None,
+                None
+            );
+
+            // the heartbeat at 00:04 keeps the system alive until 00:06
+            let uptimes: Vec<(String, String)> = client.select(
+                "SELECT uptime(agg)::TEXT, \
+                    interpolated_uptime(agg, LAG(agg) OVER (ORDER BY bucket))::TEXT \
+                FROM buckets ORDER BY bucket",
+                None,
+                None
+            )
+                .map(|row| (row.by_ordinal(1).unwrap().value().unwrap(), row.by_ordinal(2).unwrap().value().unwrap()))
+                .collect();
+            assert_eq!(uptimes, vec![
+                ("00:03:00".to_string(), "00:03:00".to_string()),
+                ("00:03:00".to_string(), "00:04:00".to_string()),
+            ]);
+
+            let val = client.select(
+                "SELECT interpolated_downtime(agg, LAG(agg) OVER (ORDER BY bucket))::TEXT \
+                FROM buckets ORDER BY bucket DESC LIMIT 1",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:01:00");
+
+            // rolling up doesn't introduce downtime at the bucket boundary
+            let val = client.select("SELECT uptime(rollup(agg))::TEXT FROM buckets", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:07:00");
+
+            let val = client.select(
+                "SELECT uptime(heartbeat_agg(ts, '2020-01-01 00:00:00+00', '10 minutes', '2 minutes'))::TEXT FROM heartbeats",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:07:00");
+        });
+    }
+
+    #[pg_test(error = "cannot interpolate heartbeat aggregates with different liveness intervals")]
+    fn test_heartbeat_interpolation_liveness_mismatch() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.interpolated_uptime(\
+                    toolkit_experimental.heartbeat_agg('2020-01-01 00:06:00+00', '2020-01-01 00:05:00+00', '5 minutes', '2 minutes'), \
+                    toolkit_experimental.heartbeat_agg('2020-01-01 00:04:00+00', '2020-01-01 00:00:00+00', '5 minutes', '3 minutes'))",
+                None,
+                None
+            );
+        });
+    }
+}
@@ -77,11 +77,10 @@ pub fn hyperloglog_combine(
         in_aggregate_context(fcinfo, || match (state1, state2) {
             (None, None) => None,
             (None, Some(state2)) => Some(state2.clone().into()),
-            (Some(state1), None) => Some(state1.clone().into()),
-            (Some(state1), Some(state2)) => {
-                let mut logger = state1.logger.clone();
-                logger.merge_in(&state2.logger);
-                Some(HyperLogLogTrans { logger }.into())
+            (Some(state1), None) => Some(state1),
+            (Some(mut state1), Some(state2)) => {
+                state1.logger.merge_in(&state2.logger);
+                Some(state1)
             }
         })
     }
@@ -480,6 +479,67 @@ impl<'de> Deserialize<'de> for DatumHashBuilder {
     }
 }
 
+// The Hyperloglog as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_to_msgpack(
+    hyperloglog: toolkit_experimental::HyperLogLog<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*hyperloglog)
+}
+
+// inverse of to_msgpack(Hyperloglog)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_from_msgpack(
+    bytes: &[u8],
+) -> toolkit_experimental::HyperLogLog<'static> {
+    unsafe { HyperLogLog(crate::msgpack::decode("HyperLogLog", bytes, 1), None).flatten() }
+}
+
+// The Hyperloglog as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_to_jsonb(
+    hyperloglog: toolkit_experimental::HyperLogLog<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*hyperloglog)
+}
+
+// inverse of to_jsonb(Hyperloglog), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_from_jsonb(
+    json: JsonB,
+) -> toolkit_experimental::HyperLogLog<'static> {
+    unsafe { HyperLogLog(crate::jsonb::decode("HyperLogLog", &json.0, 1), None).flatten() }
+}
+
+// The size of the HyperLogLog as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_size(
+    hyperloglog: toolkit_experimental::HyperLogLog<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    let entries = match &hyperloglog.log {
+        Storage::Sparse { num_compressed, .. } => *num_compressed as usize,
+        Storage::Dense { precision, .. } => 1 << *precision,
+    };
+    crate::summary_size::summary_size(fcinfo, hyperloglog.0.num_bytes(), Some(entries))
+}
+
+// Checks the HyperLogLog's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_validate(
+    hyperloglog: toolkit_experimental::HyperLogLog<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    let precision = match hyperloglog.log {
+        Storage::Sparse { precision, .. } => precision,
+        Storage::Dense { precision, .. } => precision,
+    };
+    if !(4..=18).contains(&precision) {
+        diagnostics.error(format!("the precision {} is not between 4 and 18", precision))
+    }
+    diagnostics.into_rows()
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -0,0 +1,142 @@
+use pgx::*;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::serialization::check_version;
+
+// Summaries are encoded as JSON objects keyed by field name, the same fields
+// as their text and MessagePack forms.
+pub(crate) fn encode<T: Serialize>(value: &T) -> JsonB {
+    JsonB(serde_json::to_value(value)
+        .unwrap_or_else(|e| error!("json serialization error: {}", e)))
+}
+
+// Decodes a summary written by `encode`, or built by hand in the same form.
+// Unlike the text input, fields we don't know are rejected rather than
+// ignored, so a misspelled field isn't silently replaced by its default, and
+// summaries from newer versions of the extension are rejected as usual.
+pub(crate) fn decode<'de, T: Deserialize<'de> + Serialize>(
+    type_name: &str,
+    json: &'de Value,
+    current_version: u8,
+) -> T {
+    let fields = match json {
+        Value::Object(fields) => fields,
+        _ => error!("{} JSON must be an object", type_name),
+    };
+    let version = match fields.get("version").and_then(Value::as_u64) {
+        Some(version) if version <= u8::MAX as u64 => version as u8,
+        _ => error!("{} JSON requires an integer \"version\" field", type_name),
+    };
+    check_version(
+        type_name,
+        version,
+        current_version,
+        || {
+            let value = T::deserialize(json)
+                .unwrap_or_else(|e| error!("invalid {} JSON: {}", type_name, e));
+            // every field read is written back, anything else is unknown
+            if let Value::Object(known) = encode(&value).0 {
+                if let Some(field) = fields.keys().find(|field| !known.contains_key(*field)) {
+                    error!("invalid {} JSON: unknown field \"{}\"", type_name, field)
+                }
+            }
+            value
+        },
+        |version| crate::serialization::unknown_version(type_name, version),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_jsonb_round_trip() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE data AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' minutes')::INTERVAL AS time, \
+                        (i * i % 17)::DOUBLE PRECISION AS value \
+                    FROM generate_series(1, 100) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW summaries AS SELECT \
+                    stats_agg(value) AS stats1d, \
+                    stats_agg(value, value * 2) AS stats2d, \
+                    tdigest(20, value) AS tdigest, \
+                    uddsketch(50, 0.01, value) AS uddsketch, \
+                    hyperloglog(32, value) AS hyperloglog, \
+                    counter_agg(time, value) AS counter, \
+                    time_weight('Linear', time, value) AS time_weight \
+                FROM data",
+                None,
+                None
+            );
+
+            let round_trips = [
+                ("stats1d", "stats1d_from_jsonb"),
+                ("stats2d", "stats2d_from_jsonb"),
+                ("tdigest", "tdigest_from_jsonb"),
+                ("uddsketch", "uddsketch_from_jsonb"),
+                ("hyperloglog", "hyperloglog_from_jsonb"),
+                ("counter", "counter_summary_from_jsonb"),
+                ("time_weight", "time_weight_summary_from_jsonb"),
+            ];
+            for (column, from_jsonb) in round_trips.iter() {
+                let (text, round_tripped) = client.select(
+                    &format!("SELECT {0}::TEXT, {1}(toolkit_experimental.to_jsonb({0}))::TEXT FROM summaries", column, from_jsonb),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_two::<String, String>();
+                assert_eq!(text, round_tripped, "{} did not round trip", column);
+            }
+
+            // adjusted summaries can be written back
+            let n = client.select(
+                "SELECT toolkit_experimental.num_vals(stats1d_from_jsonb( \
+                    jsonb_set(toolkit_experimental.to_jsonb(stats1d), '{n}', '1000'))) \
+                FROM summaries",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(n, Some(1000));
+        });
+    }
+
+    #[pg_test(error = "invalid StatsSummary1D JSON: unknown field \"count\"")]
+    fn test_jsonb_unknown_field() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.stats1d_from_jsonb( \
+                    toolkit_experimental.to_jsonb(toolkit_experimental.stats_agg(1.0::DOUBLE PRECISION)) || '{\"count\": 1}')::TEXT",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test(error = "StatsSummary1D version 2 is from a newer version of the extension, the newest version this one reads is 1")]
+    fn test_jsonb_newer_version() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.stats1d_from_jsonb('{\"version\": 2, \"n\": 0}')::TEXT",
+                None,
+                None
+            );
+        });
+    }
+}
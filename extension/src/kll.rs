@@ -0,0 +1,388 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use kll::KllSketch as KllSketchInternal;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// PG object for the sketch, stored in the Apache DataSketches serialization
+// format so it can be exchanged with other systems as is.
+pg_type! {
+    #[derive(Debug)]
+    struct KllSketch<'input> {
+        num_bytes: u32,
+        bytes: [u8; self.num_bytes],
+    }
+}
+
+ron_inout_funcs!(KllSketch);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    pub(crate) use crate::accessors::toolkit_experimental::*;
+
+    varlena_type!(KllSketch);
+}
+
+impl<'input> KllSketch<'input> {
+    fn to_internal(&self) -> KllSketchInternal {
+        KllSketchInternal::from_datasketches_bytes(self.bytes.as_slice())
+            .unwrap_or_else(|e| error!("invalid kll sketch: {}", e))
+    }
+
+    fn from_internal(sketch: &KllSketchInternal) -> KllSketch<'static> {
+        let bytes = sketch.to_datasketches_bytes();
+        build!(
+            KllSketch {
+                num_bytes: bytes.len() as u32,
+                bytes: bytes.into(),
+            }
+        )
+    }
+}
+
+// PG function for adding values to a sketch.
+// Null values are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_trans(
+    state: Option<Internal<KllSketchInternal>>,
+    k: i32,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<KllSketchInternal>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => {
+                    if k < kll::DEFAULT_M as i32 || k > u16::MAX as i32 {
+                        error!("kll_sketch k must be between {} and {}", kll::DEFAULT_M, u16::MAX)
+                    }
+                    KllSketchInternal::new(k as u16).into()
+                },
+                Some(state) => state,
+            };
+            state.add_value(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_summary_trans(
+    state: Option<Internal<KllSketchInternal>>,
+    value: Option<toolkit_experimental::KllSketch<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<KllSketchInternal>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal(),
+            };
+            let mut state = match state {
+                None => return Some(value.into()),
+                Some(state) => state,
+            };
+            state.merge_sketch(&value);
+            Some(state)
+        })
+    }
+}
+
+// PG function for merging sketches.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_combine(
+    state1: Option<Internal<KllSketchInternal>>,
+    state2: Option<Internal<KllSketchInternal>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<KllSketchInternal>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1),
+                (Some(mut state1), Some(state2)) => {
+                    state1.merge_sketch(&state2);
+                    Some(state1)
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_serialize(
+    state: Internal<KllSketchInternal>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<KllSketchInternal> {
+    crate::do_deserialize!(bytes, KllSketchInternal)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn kll_sketch_final(
+    state: Option<Internal<KllSketchInternal>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::KllSketch<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            KllSketch::from_internal(&state).into()
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.kll_sketch(
+    k int, value DOUBLE PRECISION
+) (
+    sfunc = toolkit_experimental.kll_sketch_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.kll_sketch_final,
+    combinefunc = toolkit_experimental.kll_sketch_combine,
+    serialfunc = toolkit_experimental.kll_sketch_serialize,
+    deserialfunc = toolkit_experimental.kll_sketch_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    sketch toolkit_experimental.KllSketch
+) (
+    sfunc = toolkit_experimental.kll_sketch_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.kll_sketch_final,
+    combinefunc = toolkit_experimental.kll_sketch_combine,
+    serialfunc = toolkit_experimental.kll_sketch_serialize,
+    deserialfunc = toolkit_experimental.kll_sketch_deserialize,
+    parallel = restricted
+);
+"#);
+
+// The sketch in the DataSketches `kll_doubles_sketch` binary format, which can
+// be read by e.g. `datasketches.kll_doubles_sketch.deserialize()` in Python.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn to_datasketches(
+    sketch: toolkit_experimental::KllSketch<'_>,
+) -> Vec<u8> {
+    sketch.bytes.as_slice().to_vec()
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn kll_from_datasketches(
+    bytes: &[u8],
+) -> toolkit_experimental::KllSketch<'static> {
+    // validate and normalize the bytes before storing them
+    let sketch = KllSketchInternal::from_datasketches_bytes(bytes)
+        .unwrap_or_else(|e| error!("invalid kll sketch: {}", e));
+    KllSketch::from_internal(&sketch)
+}
+
+//---- Available PG operations on the sketch
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_approx_percentile(
+    sketch: toolkit_experimental::KllSketch,
+    accessor: toolkit_experimental::AccessorApproxPercentile,
+) -> Option<f64> {
+    kll_sketch_approx_percentile(accessor.percentile, sketch)
+}
+
+// Approximate the value at the given approx_percentile (0.0-1.0)
+// The rank of the result is within the sketch's error of the requested rank.
+#[pg_extern(immutable, parallel_safe, name="approx_percentile", schema = "toolkit_experimental")]
+pub fn kll_sketch_approx_percentile(
+    percentile: f64,
+    sketch: toolkit_experimental::KllSketch,
+) -> Option<f64> {
+    if !(0.0..=1.0).contains(&percentile) {
+        error!("percentile must be between 0.0 and 1.0")
+    }
+    let sketch = sketch.to_internal();
+    if sketch.is_empty() {
+        return None
+    }
+    Some(sketch.estimate_quantile(percentile))
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_approx_rank(
+    sketch: toolkit_experimental::KllSketch,
+    accessor: toolkit_experimental::AccessorApproxRank,
+) -> Option<f64> {
+    kll_sketch_approx_percentile_rank(accessor.value, sketch)
+}
+
+// Approximate the fraction of values less than the given value
+#[pg_extern(immutable, parallel_safe, name="approx_percentile_rank", schema = "toolkit_experimental")]
+pub fn kll_sketch_approx_percentile_rank(
+    value: f64,
+    sketch: toolkit_experimental::KllSketch,
+) -> Option<f64> {
+    let sketch = sketch.to_internal();
+    if sketch.is_empty() {
+        return None
+    }
+    Some(sketch.estimate_quantile_at_value(value))
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_num_vals(
+    sketch: toolkit_experimental::KllSketch,
+    accessor: toolkit_experimental::AccessorNumVals,
+) -> f64 {
+    let _ = accessor;
+    kll_sketch_num_vals(sketch)
+}
+
+// Number of elements from which the sketch was built.
+#[pg_extern(immutable, parallel_safe, name="num_vals", schema = "toolkit_experimental")]
+pub fn kll_sketch_num_vals(
+    sketch: toolkit_experimental::KllSketch,
+) -> f64 {
+    sketch.to_internal().count() as f64
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_min(
+    sketch: toolkit_experimental::KllSketch,
+    accessor: toolkit_experimental::AccessorMin,
+) -> Option<f64> {
+    let _ = accessor;
+    kll_sketch_min(sketch)
+}
+
+// Minimum value entered in the sketch, this is exact.
+#[pg_extern(immutable, parallel_safe, name="min_val", schema = "toolkit_experimental")]
+pub fn kll_sketch_min(
+    sketch: toolkit_experimental::KllSketch,
+) -> Option<f64> {
+    let sketch = sketch.to_internal();
+    (!sketch.is_empty()).then(|| sketch.min())
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_max(
+    sketch: toolkit_experimental::KllSketch,
+    accessor: toolkit_experimental::AccessorMax,
+) -> Option<f64> {
+    let _ = accessor;
+    kll_sketch_max(sketch)
+}
+
+// Maximum value entered in the sketch, this is exact.
+#[pg_extern(immutable, parallel_safe, name="max_val", schema = "toolkit_experimental")]
+pub fn kll_sketch_max(
+    sketch: toolkit_experimental::KllSketch,
+) -> Option<f64> {
+    let sketch = sketch.to_internal();
+    (!sketch.is_empty()).then(|| sketch.max())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_kll_sketch() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test (device INTEGER, data DOUBLE PRECISION)", None, None);
+            client.select("INSERT INTO test SELECT i % 10, i FROM generate_series(1, 100000) i", None, None);
+
+            let (num_vals, min, max) = client
+                .select("SELECT \
+                    num_vals(kll_sketch(200, data)), \
+                    min_val(kll_sketch(200, data)), \
+                    max_val(kll_sketch(200, data)) \
+                    FROM test", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(num_vals, Some(100000.0));
+            assert_eq!(min, Some(1.0));
+            assert_eq!(max, Some(100000.0));
+
+            // the rank error for k = 200 is about 1.33%
+            for &percentile in &[0.01, 0.25, 0.5, 0.75, 0.99] {
+                let (value, rank) = client
+                    .select(&format!("SELECT \
+                            approx_percentile({0}, sketch), \
+                            approx_percentile_rank(approx_percentile({0}, sketch), sketch) \
+                        FROM (SELECT kll_sketch(200, data) AS sketch FROM test) s", percentile), None, None)
+                    .first()
+                    .get_two::<f64, f64>();
+                let value = value.unwrap();
+                assert!((value / 100000.0 - percentile).abs() < 0.02, "{} at {}", value, percentile);
+                assert!((rank.unwrap() - percentile).abs() < 0.02);
+            }
+
+            let (rolled_up, direct) = client
+                .select("SELECT \
+                        (SELECT rollup(sketch) -> num_vals() FROM \
+                            (SELECT kll_sketch(200, data) AS sketch FROM test GROUP BY device) s), \
+                        (SELECT kll_sketch(200, data) -> max_val() FROM test)", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(rolled_up, Some(100000.0));
+            assert_eq!(direct, Some(100000.0));
+
+            let rolled_up_median = client
+                .select("SELECT rollup(sketch) -> approx_percentile(0.5) FROM \
+                    (SELECT kll_sketch(200, data) AS sketch FROM test GROUP BY device) s", None, None)
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert!((rolled_up_median / 100000.0 - 0.5).abs() < 0.02);
+
+            // round trip through the DataSketches format
+            let (size, median, round_tripped) = client
+                .select("SELECT \
+                        length(to_datasketches(sketch))::float, \
+                        approx_percentile(0.5, sketch), \
+                        approx_percentile(0.5, kll_from_datasketches(to_datasketches(sketch))) \
+                    FROM (SELECT kll_sketch(200, data) AS sketch FROM test) s", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert!(size.unwrap() < 8000.0);
+            assert_eq!(median, round_tripped);
+
+            let single = client
+                .select("SELECT encode(to_datasketches(kll_sketch(200, 1.0)), 'hex')", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(single.unwrap(), "02020f04c8000800000000000000f03f");
+        });
+    }
+}
@@ -12,13 +12,41 @@ pub mod stats_agg;
 pub mod utilities;
 pub mod time_series;
 pub mod topn;
+pub mod state_aggregate;
+pub mod heartbeat_agg;
+pub mod sessionize;
+pub mod funnel;
+pub mod retention;
+pub mod candlestick;
+pub mod kll;
+pub mod hdr_histogram;
+pub mod equi_depth_histogram;
+pub mod adaptive_percentile;
+pub mod arrival_agg;
+pub mod forecast_error;
+pub mod concentration;
+#[cfg(feature = "arrow_export")]
+pub mod arrow_export;
+pub mod prometheus;
+pub mod msgpack;
+pub mod jsonb;
+pub mod summary_size;
+pub mod validate;
 
 mod palloc;
 mod aggregate_utils;
 mod type_builder;
 mod serialization;
+mod spill;
 mod schema_test;
 
+// This gives the types send and receive functions, so it must come after all
+// of them
+pub mod zz_binary_io;
+
+// This gives the types comparison operators, so it must come after all of them
+pub mod zz_comparison;
+
 // This should be last so we don't run our warning trigger on when
 // installing this extension
 pub mod zz_triggers;
@@ -40,6 +68,41 @@ pub extern "C" fn _PG_init() {
         //TODO should this be superuser?
         GucContext::Userset,
     );
+    GucRegistry::define_int_guc(
+        "timescaledb_toolkit_counter_agg_max_buffered_points",
+        "the most points counter_agg buffers per group before spilling them",
+        "bounds the memory counter_agg uses for large groups, 0 for no limit. \
+            Each time a group reaches the limit its buffered points are sorted \
+            and spilled to a tuplestore, which writes to disk past work_mem; \
+            this doesn't change the result",
+        &counter_agg::MAX_BUFFERED_POINTS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+    );
+    GucRegistry::define_bool_guc(
+        "timescaledb_toolkit_compress_transition_states",
+        "compress large aggregate states passed between parallel workers",
+        "trades CPU for less data shipped between parallel workers, \
+            for aggregates whose states are large such as sketches and \
+            buffered points",
+        &type_builder::COMPRESS_TRANSITION_STATES,
+        GucContext::Userset,
+    );
+    GucRegistry::define_string_guc(
+        "timescaledb_toolkit.default_extrapolation_method",
+        "the method extrapolated_delta() and extrapolated_rate() use when none is given",
+        "currently the only method is 'prometheus'",
+        &counter_agg::DEFAULT_EXTRAPOLATION_METHOD,
+        GucContext::Userset,
+    );
+    GucRegistry::define_string_guc(
+        "timescaledb_toolkit.default_time_weight_method",
+        "the method time_weight() uses when none is given",
+        "one of 'linear' or 'locf'",
+        &time_weighted_average::DEFAULT_TIME_WEIGHT_METHOD,
+        GucContext::Userset,
+    );
 }
 
 #[cfg(test)]
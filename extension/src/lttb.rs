@@ -72,6 +72,8 @@ pub fn lttb_final(
             let downsampled = lttb(&*series, state.resolution);
             flatten!(
                 TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
                     series: SeriesType::SortedSeries {
                         num_points: downsampled.len() as u64,
                         points: (&*downsampled).into(),
@@ -171,7 +173,7 @@ pub fn lttb_on_timeseries(
     series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
     threshold: i32,
 ) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
-    lttb_ts(series, threshold as usize).into()
+    lttb_ts(series.decompress(), threshold as usize).into()
 }
 
 // based on https://github.com/jeromefroe/lttb-rs version 0.2.0
@@ -181,6 +183,8 @@ pub fn lttb_ts<'s>(
 )
 -> crate::time_series::toolkit_experimental::TimeSeries<'s>
 {
+    // points with NULL values can't be selected
+    let data = data.without_nulls();
     if !data.is_sorted() {
         panic!("lttb requires sorted timeseries");
     }
@@ -258,6 +262,8 @@ pub fn lttb_ts<'s>(
 
     crate::build! {
         TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: SeriesType::SortedSeries {
                 num_points: sampled.len() as _,
                 points: sampled.into(),
@@ -304,4 +310,42 @@ mod tests {
             assert_eq!(delta.unwrap(), 0);
         })
     }
+
+    #[pg_test]
+    fn test_lttb_with_nulls() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(time TIMESTAMPTZ, value DOUBLE PRECISION);", None, None);
+            client.select(
+                "INSERT INTO test
+                SELECT time, CASE WHEN extract(minute FROM time) = 0 THEN NULL ELSE value END
+                FROM toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, NULL);", None, None);
+
+            // points with NULL values are skipped, the same as the lttb aggregate does
+            let delta = client
+                .select(
+                    "SELECT count(*) FROM toolkit_experimental.unnest(
+                        (SELECT toolkit_experimental.lttb(time, value, 100) FROM test)
+                    ) r1 FULL OUTER JOIN toolkit_experimental.unnest(
+                        toolkit_experimental.lttb((SELECT toolkit_experimental.timeseries_with_nulls(time, value) FROM test), 100)
+                    ) r2 ON r1 = r2 WHERE r1 IS NULL OR r2 IS NULL;",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i32>();
+            assert_eq!(delta.unwrap(), 0);
+
+            let num_points = client
+                .select(
+                    "SELECT toolkit_experimental.num_vals(
+                        (SELECT toolkit_experimental.timeseries_with_nulls(time, value) FROM test) -> toolkit_experimental.lttb(100)
+                    )",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(num_points.unwrap(), 100);
+        })
+    }
 }
@@ -0,0 +1,123 @@
+use pgx::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::serialization::check_version;
+
+#[derive(Deserialize)]
+struct Versioned {
+    version: u8,
+}
+
+// The to_msgpack() and *_from_msgpack() functions of the summary types give a
+// compact binary alternative to their text, for caching summaries outside
+// Postgres.
+//
+// Summaries are encoded as MessagePack maps keyed by field name, the same
+// fields as their text, so other languages can read them without knowing our
+// field order.
+pub(crate) fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    rmp_serde::to_vec_named(value)
+        .unwrap_or_else(|e| error!("msgpack serialization error: {}", e))
+}
+
+// Decodes a summary written by `encode`, rejecting those from newer versions
+// of the extension. Older versions would need to be upgraded here once a
+// summary's format changes.
+pub(crate) fn decode<'de, T: Deserialize<'de>>(
+    type_name: &str,
+    bytes: &'de [u8],
+    current_version: u8,
+) -> T {
+    let Versioned { version } = rmp_serde::from_read_ref(bytes)
+        .unwrap_or_else(|e| error!("invalid msgpack {}: {}", type_name, e));
+    check_version(
+        type_name,
+        version,
+        current_version,
+        || rmp_serde::from_read_ref(bytes)
+            .unwrap_or_else(|e| error!("invalid msgpack {}: {}", type_name, e)),
+        |version| crate::serialization::unknown_version(type_name, version),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_msgpack_round_trip() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE data AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' minutes')::INTERVAL AS time, \
+                        (i * i % 17)::DOUBLE PRECISION AS value \
+                    FROM generate_series(1, 100) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW summaries AS SELECT \
+                    stats_agg(value) AS stats1d, \
+                    stats_agg(value, value * 2) AS stats2d, \
+                    tdigest(20, value) AS tdigest, \
+                    uddsketch(50, 0.01, value) AS uddsketch, \
+                    hyperloglog(32, value) AS hyperloglog, \
+                    counter_agg(time, value) AS counter, \
+                    time_weight('Linear', time, value) AS time_weight \
+                FROM data",
+                None,
+                None
+            );
+
+            let round_trips = [
+                ("stats1d", "stats1d_from_msgpack"),
+                ("stats2d", "stats2d_from_msgpack"),
+                ("tdigest", "tdigest_from_msgpack"),
+                ("uddsketch", "uddsketch_from_msgpack"),
+                ("hyperloglog", "hyperloglog_from_msgpack"),
+                ("counter", "counter_summary_from_msgpack"),
+                ("time_weight", "time_weight_summary_from_msgpack"),
+            ];
+            for (column, from_msgpack) in round_trips.iter() {
+                let (text, round_tripped) = client.select(
+                    &format!("SELECT {0}::TEXT, {1}(to_msgpack({0}))::TEXT FROM summaries", column, from_msgpack),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_two::<String, String>();
+                assert_eq!(text, round_tripped, "{} did not round trip", column);
+            }
+
+            // the summaries are maps keyed by field name, starting with the version
+            let prefix = client.select(
+                "SELECT substring(to_msgpack(stats1d) from 1 for 10) FROM summaries",
+                None,
+                None
+            )
+                .first()
+                .get_one::<Vec<u8>>()
+                .unwrap();
+            assert_eq!(prefix, b"\x86\xa7version\x01");
+        });
+    }
+
+    #[pg_test(error = "StatsSummary1D version 2 is from a newer version of the extension, the newest version this one reads is 1")]
+    fn test_msgpack_newer_version() {
+        Spi::execute(|client| {
+            // {"version": 2, "n": 0}
+            client.select(
+                "SELECT toolkit_experimental.stats1d_from_msgpack('\\x82a776657273696f6e02a16e00'::BYTEA)::TEXT",
+                None,
+                None
+            );
+        });
+    }
+}
@@ -0,0 +1,251 @@
+use pgx::*;
+
+use serde_json::{Map, Value};
+
+use crate::counter_agg::POSTGRES_EPOCH_IN_UNIX_MICROS;
+
+const METRIC_NAME_LABEL: &str = "__name__";
+
+// The parts of a remote-write `prometheus.TimeSeries` message we use.
+struct RemoteSeries<'a> {
+    labels: Vec<(&'a str, &'a str)>,
+    // (unix milliseconds, value)
+    samples: Vec<(i64, f64)>,
+}
+
+#[derive(Clone, Copy)]
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+// Just enough of the protobuf wire format to walk the fields of a message.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.bytes.split_first().ok_or("truncated varint")?;
+            self.bytes = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        Err("varint too long")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if len > self.bytes.len() {
+            return Err("truncated field")
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, WireValue<'a>)>, &'static str> {
+        if self.bytes.is_empty() {
+            return Ok(None)
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(self.varint()?),
+            1 => {
+                let bytes = self.take(8)?;
+                WireValue::Fixed64(u64::from_le_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3],
+                    bytes[4], bytes[5], bytes[6], bytes[7],
+                ]))
+            },
+            2 => {
+                let len = self.varint()? as usize;
+                WireValue::Bytes(self.take(len)?)
+            },
+            5 => {
+                self.take(4)?;
+                WireValue::Fixed32
+            },
+            _ => return Err("unsupported wire type"),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn for_each(
+        bytes: &'a [u8],
+        mut f: impl FnMut(u64, WireValue<'a>) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        let mut fields = Fields { bytes };
+        while let Some((number, value)) = fields.next_field()? {
+            f(number, value)?
+        }
+        Ok(())
+    }
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, &'static str> {
+    std::str::from_utf8(bytes).map_err(|_| "label is not valid UTF-8")
+}
+
+// Label { string name = 1; string value = 2; }
+fn decode_label(bytes: &[u8]) -> Result<(&str, &str), &'static str> {
+    let (mut name, mut value) = ("", "");
+    Fields::for_each(bytes, |number, field| {
+        match (number, field) {
+            (1, WireValue::Bytes(bytes)) => name = utf8(bytes)?,
+            (2, WireValue::Bytes(bytes)) => value = utf8(bytes)?,
+            _ => (),
+        }
+        Ok(())
+    })?;
+    Ok((name, value))
+}
+
+// Sample { double value = 1; int64 timestamp = 2; }
+fn decode_sample(bytes: &[u8]) -> Result<(i64, f64), &'static str> {
+    let (mut timestamp, mut value) = (0, 0.0);
+    Fields::for_each(bytes, |number, field| {
+        match (number, field) {
+            (1, WireValue::Fixed64(bits)) => value = f64::from_bits(bits),
+            (2, WireValue::Varint(ts)) => timestamp = ts as i64,
+            _ => (),
+        }
+        Ok(())
+    })?;
+    Ok((timestamp, value))
+}
+
+// WriteRequest { repeated TimeSeries timeseries = 1; ... }
+// TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; ... }
+// Metadata, exemplars, and native histograms are skipped.
+fn decode_write_request(bytes: &[u8]) -> Result<Vec<RemoteSeries<'_>>, &'static str> {
+    let mut series = vec![];
+    Fields::for_each(bytes, |number, field| {
+        if let (1, WireValue::Bytes(bytes)) = (number, field) {
+            let mut labels = vec![];
+            let mut samples = vec![];
+            Fields::for_each(bytes, |number, field| {
+                match (number, field) {
+                    (1, WireValue::Bytes(bytes)) => labels.push(decode_label(bytes)?),
+                    (2, WireValue::Bytes(bytes)) => samples.push(decode_sample(bytes)?),
+                    _ => (),
+                }
+                Ok(())
+            })?;
+            series.push(RemoteSeries { labels, samples });
+        }
+        Ok(())
+    })?;
+    Ok(series)
+}
+
+// Decodes a Prometheus remote-write request into one row per sample: the
+// metric name from the `__name__` label, the remaining labels as a JSON object
+// of text values, the sample time, and its value. Payloads are snappy
+// block-compressed as sent by Prometheus unless `compressed` is false.
+// Metadata, exemplars, and native histograms are ignored.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn prometheus_remote_write(
+    payload: &[u8],
+    compressed: default!(bool, true),
+) -> impl std::iter::Iterator<Item = (name!(metric,Option<String>),name!(labels,JsonB),name!(ts,pg_sys::TimestampTz),name!(value,f64))> {
+    let decompressed;
+    let payload = if compressed {
+        decompressed = snap::raw::Decoder::new()
+            .decompress_vec(payload)
+            .unwrap_or_else(|e| error!("invalid snappy compressed payload: {}", e));
+        &decompressed[..]
+    } else {
+        payload
+    };
+    let series = decode_write_request(payload)
+        .unwrap_or_else(|e| error!("invalid remote-write payload: {}", e));
+
+    let mut rows = vec![];
+    for RemoteSeries { labels, samples } in series {
+        let metric = labels.iter()
+            .find(|(name, _)| *name == METRIC_NAME_LABEL)
+            .map(|(_, value)| value.to_string());
+        let labels: Map<String, Value> = labels.into_iter()
+            .filter(|(name, _)| *name != METRIC_NAME_LABEL)
+            .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+            .collect();
+        for (millis, value) in samples {
+            let ts = millis.checked_mul(1000)
+                .and_then(|micros| micros.checked_sub(POSTGRES_EPOCH_IN_UNIX_MICROS))
+                .unwrap_or_else(|| error!("sample timestamp {} is out of range", millis));
+            rows.push((metric.clone(), JsonB(Value::Object(labels.clone())), ts, value));
+        }
+    }
+    rows.into_iter()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_prometheus_remote_write() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // a request with one series `up{job="node"}` holding samples 1 at
+            // 2020-01-01 and 0 at 2020-01-01 00:00:15
+            let payload = "'\\x0a410a0e0a085f5f6e616d655f5f120275700a0b0a036a6f621204\
+                6e6f6465121009000000000000f03f1080d09bf3f52d1210090000000000000000\
+                1098c59cf3f52d'::BYTEA";
+            let rows: Vec<(String, String, String, f64)> = client.select(
+                &format!("SELECT metric, labels::TEXT, ts::TEXT, value FROM \
+                    prometheus_remote_write({}, compressed => false)", payload),
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                    row.by_ordinal(4).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(rows, vec![
+                ("up".to_string(), r#"{"job": "node"}"#.to_string(), "2020-01-01 00:00:00+00".to_string(), 1.0),
+                ("up".to_string(), r#"{"job": "node"}"#.to_string(), "2020-01-01 00:00:15+00".to_string(), 0.0),
+            ]);
+
+            // the same request snappy compressed by Prometheus
+            let count = client.select(
+                "SELECT count(*) FROM prometheus_remote_write(\
+                    '\\x43f0420a410a0e0a085f5f6e616d655f5f120275700a0b0a036a6f621204\
+                    6e6f6465121009000000000000f03f1080d09bf3f52d1210090000000000000000\
+                    1098c59cf3f52d'::BYTEA)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(count, Some(2));
+        });
+    }
+
+    #[pg_test(error = "sample timestamp 9223372036854775807 is out of range")]
+    fn test_prometheus_remote_write_timestamp_out_of_range() {
+        Spi::execute(|client| {
+            // one unlabeled series with a sample at the largest int64 millisecond
+            client.select(
+                "SELECT count(*) FROM toolkit_experimental.prometheus_remote_write(\
+                    '\\x0a0c120a10ffffffffffffffff7f'::BYTEA, compressed => false)",
+                None,
+                None
+            );
+        });
+    }
+}
@@ -52,6 +52,28 @@ pub unsafe fn get_range(range: tstzrange) -> Option<I64Range> {
 
 }
 
+// Inverse of `get_range()`, builds a `[)` tstzrange from an I64Range. Missing
+// bounds are treated as infinite.
+pub unsafe fn i64range_to_tstzrange(range: I64Range) -> tstzrange {
+    let typcache = pg_sys::lookup_type_cache(
+        pg_sys::TSTZRANGEOID,
+        pg_sys::TYPECACHE_RANGE_INFO as _,
+    );
+    let mut lower = pg_sys::RangeBound {
+        val: range.left.unwrap_or(0) as pg_sys::Datum,
+        infinite: range.left.is_none(),
+        inclusive: true,
+        lower: true,
+    };
+    let mut upper = pg_sys::RangeBound {
+        val: range.right.unwrap_or(0) as pg_sys::Datum,
+        infinite: range.right.is_none(),
+        inclusive: false,
+        lower: false,
+    };
+    pg_sys::make_range(typcache, &mut lower, &mut upper, false) as tstzrange
+}
+
 unsafe fn get_toasted_bytes(ptr: &pg_sys::varlena) -> &[u8] {
     let mut ptr = pg_sys::pg_detoast_datum_packed(ptr as *const _ as *mut _);
     if pgx::varatt_is_1b(ptr) {
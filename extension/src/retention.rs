@@ -0,0 +1,321 @@
+use std::{collections::BTreeSet, slice};
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    utilities::interval_to_micros,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// a user was active at least once during the `period`th period of the cohort
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct UserPeriod {
+    user: i64,
+    period: i64,
+}
+
+// PG object recording which periods after `cohort_start` each user was
+// active in. The cohort is the users active in the first period.
+pg_type! {
+    #[derive(Debug)]
+    struct RetentionAgg<'input> {
+        cohort_start: i64,
+        period: i64, // microseconds
+        num_activities: u64,
+        // ordered by user, then period
+        activities: [UserPeriod; self.num_activities],
+    }
+}
+
+ron_inout_funcs!(RetentionAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(RetentionAgg);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetentionTransState {
+    cohort_start: i64,
+    period: i64,
+    activities: BTreeSet<UserPeriod>,
+}
+
+impl RetentionTransState {
+    fn add_agg(&mut self, agg: &RetentionAgg<'_>) {
+        if agg.cohort_start != self.cohort_start || agg.period != self.period {
+            error!("cannot rollup retention aggregates with different cohorts")
+        }
+        self.activities.extend(agg.activities.iter());
+    }
+}
+
+impl<'input> RetentionAgg<'input> {
+    fn cohort_size(&self) -> usize {
+        self.activities.iter().filter(|activity| activity.period == 0).count()
+    }
+
+    // the number of users in the cohort active during the `period`th period
+    fn num_retained(&self, period: i64) -> usize {
+        let activities = self.activities.as_slice();
+        activities.iter()
+            .filter(|activity| activity.period == period)
+            .filter(|activity| activities
+                .binary_search(&UserPeriod { user: activity.user, period: 0 })
+                .is_ok())
+            .count()
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn retention_agg_serialize(
+    state: Internal<RetentionTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn retention_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<RetentionTransState> {
+    crate::do_deserialize!(bytes, RetentionTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn retention_agg_trans(
+    state: Option<Internal<RetentionTransState>>,
+    user_id: Option<i64>,
+    ts: Option<pg_sys::TimestampTz>,
+    cohort_start: pg_sys::TimestampTz,
+    period: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<RetentionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => {
+                    let period = interval_to_micros(period, "retention periods");
+                    if period <= 0 {
+                        error!("retention period must be positive")
+                    }
+                    RetentionTransState {
+                        cohort_start,
+                        period,
+                        activities: BTreeSet::new(),
+                    }.into()
+                },
+                Some(state) => state,
+            };
+            match (user_id, ts) {
+                (Some(user), Some(ts)) if ts >= state.cohort_start => {
+                    let period = (ts - state.cohort_start) / state.period;
+                    state.activities.insert(UserPeriod { user, period });
+                },
+                _ => (),
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn retention_agg_summary_trans(
+    state: Option<Internal<RetentionTransState>>,
+    next: Option<toolkit_experimental::RetentionAgg<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<RetentionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => next,
+            };
+            let mut state = match state {
+                None => RetentionTransState {
+                    cohort_start: next.cohort_start,
+                    period: next.period,
+                    activities: BTreeSet::new(),
+                }.into(),
+                Some(state) => state,
+            };
+            state.add_agg(&next);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn retention_agg_combine(
+    state1: Option<Internal<RetentionTransState>>,
+    state2: Option<Internal<RetentionTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<RetentionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.activities.extend(state2.activities.iter().copied());
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn retention_agg_final(
+    state: Option<Internal<RetentionTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::RetentionAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            let activities: Vec<_> = state.activities.iter().copied().collect();
+            Some(build!(
+                RetentionAgg {
+                    cohort_start: state.cohort_start,
+                    period: state.period,
+                    num_activities: activities.len() as _,
+                    activities: activities.into(),
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.retention_agg(
+    user_id bigint,
+    ts timestamptz,
+    cohort_start timestamptz,
+    period interval
+) (
+    sfunc = toolkit_experimental.retention_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.retention_agg_final,
+    combinefunc = toolkit_experimental.retention_agg_combine,
+    serialfunc = toolkit_experimental.retention_agg_serialize,
+    deserialfunc = toolkit_experimental.retention_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    agg toolkit_experimental.RetentionAgg
+) (
+    sfunc = toolkit_experimental.retention_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.retention_agg_final,
+    combinefunc = toolkit_experimental.retention_agg_combine,
+    serialfunc = toolkit_experimental.retention_agg_serialize,
+    deserialfunc = toolkit_experimental.retention_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn cohort_size(
+    agg: toolkit_experimental::RetentionAgg<'_>,
+) -> i64 {
+    agg.cohort_size() as _
+}
+
+// the fraction of the cohort active during the `period_n`th period after the
+// cohort started, NULL for an empty cohort
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn retained(
+    agg: toolkit_experimental::RetentionAgg<'_>,
+    period_n: i32,
+) -> Option<f64> {
+    if period_n < 0 {
+        error!("retention periods are numbered from 0")
+    }
+    match agg.cohort_size() {
+        0 => None,
+        size => Some(agg.num_retained(period_n as i64) as f64 / size as f64),
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_retention_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE activity(user_id bigint, ts timestamptz)", None, None);
+            client.select(
+                "INSERT INTO activity VALUES \
+                    (1, '2020-01-01 10:00:00+00'), \
+                    (2, '2020-01-01 11:00:00+00'), \
+                    (3, '2020-01-01 12:00:00+00'), \
+                    (4, '2020-01-01 13:00:00+00'), \
+                    (1, '2020-01-02 10:00:00+00'), \
+                    (1, '2020-01-02 11:00:00+00'), \
+                    (2, '2020-01-02 10:00:00+00'), \
+                    (5, '2020-01-02 10:00:00+00'), \
+                    (1, '2020-01-08 10:00:00+00')",
+                None,
+                None
+            );
+
+            let retained = |query: &str, period: i32| {
+                client.select(
+                    &format!("SELECT retained(({}), {})", query, period),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<f64>()
+            };
+            let agg = "SELECT retention_agg(user_id, ts, '2020-01-01 00:00:00+00', '1 day') FROM activity";
+
+            let val = client.select(&format!("SELECT cohort_size(({}))", agg), None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val.unwrap(), 4);
+
+            // user 5 isn't part of the cohort
+            assert_eq!(retained(agg, 0), Some(1.0));
+            assert_eq!(retained(agg, 1), Some(0.5));
+            assert_eq!(retained(agg, 7), Some(0.25));
+            assert_eq!(retained(agg, 3), Some(0.0));
+
+            let rollup = "SELECT rollup(agg) FROM ( \
+                SELECT retention_agg(user_id, ts, '2020-01-01 00:00:00+00', '1 day') AS agg \
+                FROM activity GROUP BY date_trunc('day', ts)) d";
+            assert_eq!(retained(rollup, 1), Some(0.5));
+            assert_eq!(retained(rollup, 7), Some(0.25));
+
+            let empty = "SELECT retention_agg(user_id, ts, '2021-01-01 00:00:00+00', '1 day') FROM activity";
+            assert_eq!(retained(empty, 1), None);
+        });
+    }
+}
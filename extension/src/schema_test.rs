@@ -25,8 +25,10 @@ mod tests {
             let released_features: HashSet<_> = RELEASED_FEATURES.iter().cloned().collect();
             let unexpected_features: Vec<_> = client
                 .select(
-                    "SELECT pg_catalog.pg_describe_object(classid, objid, 0) \
+                    "SELECT pg_catalog.pg_describe_object(classid, objid, 0), p.prosrc \
                     FROM pg_catalog.pg_extension e, pg_catalog.pg_depend d \
+                    LEFT JOIN pg_catalog.pg_proc p \
+                        ON d.classid = 'pg_catalog.pg_proc'::pg_catalog.regclass AND p.oid = d.objid \
                     WHERE e.extname='timescaledb_toolkit' \
                     AND refclassid = 'pg_catalog.pg_extension'::pg_catalog.regclass \
                     AND d.refobjid = e.oid \
@@ -36,6 +38,7 @@ mod tests {
                     None,
                 ).filter_map(|row| {
                     let val: String = row.by_ordinal(1).unwrap().value().unwrap();
+                    let source: Option<String> = row.by_ordinal(2).unwrap().value();
 
                     if released_features.contains(&*val) {
                         return None
@@ -69,6 +72,12 @@ mod tests {
                             return None
                     }
 
+                    // The operator classes are in the experimental schema too
+                    if val.starts_with("operator class toolkit_experimental.")
+                        || val.starts_with("operator family toolkit_experimental.") {
+                            return None
+                    }
+
                     // Allow all `->` operators for now; it's the accessor that
                     // will be unstable.
                     if val.starts_with("operator ->(") {
@@ -78,6 +87,12 @@ mod tests {
                         return None
                     }
 
+                    // The binary send and receive functions zz_binary_io gives
+                    // every type, they're as stable as the type itself
+                    if matches!(source.as_deref(), Some("toolkit_binary_send") | Some("toolkit_binary_recv")) {
+                        return None
+                    }
+
                     // Allow all casts now, it's the types that'll be unstable
                     if val.starts_with("cast from toolkit_experimental.") {
                         return None
@@ -101,6 +116,31 @@ mod tests {
         });
     }
 
+    // Our summaries can grow to megabytes, so every type needs to allow
+    // Postgres to compress them and move them out of line.
+    #[pg_test]
+    fn test_types_are_toastable() {
+        Spi::execute(|client| {
+            let untoastable: Vec<String> = client
+                .select(
+                    "SELECT t.oid::regtype::text \
+                    FROM pg_catalog.pg_type t \
+                    JOIN pg_catalog.pg_depend d \
+                        ON d.classid = 'pg_catalog.pg_type'::regclass AND d.objid = t.oid AND d.deptype = 'e' \
+                    JOIN pg_catalog.pg_extension e \
+                        ON d.refclassid = 'pg_catalog.pg_extension'::regclass AND d.refobjid = e.oid \
+                    WHERE e.extname = 'timescaledb_toolkit' AND t.typtype = 'b' \
+                        AND t.typlen = -1 AND t.typstorage <> 'x' \
+                    ORDER BY 1",
+                    None,
+                    None,
+                )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert!(untoastable.is_empty(), "types without extended storage: {:#?}", untoastable);
+        });
+    }
+
     // list of features that are released and can be in places other than the
     // experimental schema
     // TODO it may pay to auto-discover this list based on the previous version of
@@ -168,6 +208,7 @@ mod tests {
         "operator ->>(regproc,regproc)",
         "operator ->>(regproc,toolkit_experimental.unstabletimeseriespipeline)",
         "operator ->>(toolkit_experimental.timeseries,regproc)",
+        "operator ->>(toolkit_experimental.timeseriesset,toolkit_experimental.unstabletimeseriespipeline)",
         "operator ->>(toolkit_experimental.unstabletimeseriespipeline,regproc)",
         "operator ->(toolkit_experimental.timeseries,toolkit_experimental.pipelinethenstatsagg)",
         "operator ->(toolkit_experimental.unstabletimeseriespipeline,toolkit_experimental.pipelinethenstatsagg)",
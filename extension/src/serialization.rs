@@ -1,10 +1,10 @@
 
-use std::{convert::TryInto, ffi::CStr, os::raw::{c_char, c_int}};
+use std::{cmp::Ordering, convert::TryInto, ffi::CStr, os::raw::{c_char, c_int}};
 pub use self::types::{PgTypId, ShortTypeId};
 pub use self::collations::PgCollationId;
 pub use self::functions::PgProcId;
 
-use pgx::pg_sys;
+use pgx::{error, pg_sys};
 
 mod functions;
 mod types;
@@ -122,6 +122,54 @@ pub fn str_from_db_encoding(s: &CStr) -> &str {
     return unsafe { CStr::from_ptr(encoded).to_str().unwrap() }
 }
 
+// The version in the text of a serialized type. Every type's text starts with
+// its `version` field, so we can tell which format we were given before
+// trying to parse it.
+pub fn text_version(input: &str) -> Option<u8> {
+    let rest = input.trim_start().strip_prefix('(')?.trim_start();
+    let rest = rest.strip_prefix("version")?.trim_start().strip_prefix(':')?.trim_start();
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..digits].parse().ok()
+}
+
+// Reads text that may have been written by a different version of the
+// extension, e.g. restored from a backup. Text of the current version is
+// `read` directly, older text is passed to `upgrade` to be converted to the
+// current format, and newer text is rejected as we can't know what it holds.
+pub fn read_versioned<'s, T>(
+    type_name: &str,
+    input: &'s str,
+    current_version: u8,
+    read: impl FnOnce(&'s str) -> T,
+    upgrade: impl FnOnce(u8, &'s str) -> T,
+) -> T {
+    let version = text_version(input)
+        .unwrap_or_else(|| error!("invalid {}, missing version", type_name));
+    check_version(type_name, version, current_version, || read(input), |version| upgrade(version, input))
+}
+
+pub fn check_version<T>(
+    type_name: &str,
+    version: u8,
+    current_version: u8,
+    read: impl FnOnce() -> T,
+    upgrade: impl FnOnce(u8) -> T,
+) -> T {
+    match version.cmp(&current_version) {
+        Ordering::Equal => read(),
+        Ordering::Less => upgrade(version),
+        Ordering::Greater => error!(
+            "{} version {} is from a newer version of the extension, the newest version this one reads is {}",
+            type_name, version, current_version
+        ),
+    }
+}
+
+// The upgrade for types that have only had one version.
+pub fn unknown_version(type_name: &str, version: u8) -> ! {
+    error!("invalid {} version {}", type_name, version)
+}
+
 pub(crate) mod serde_reference_adaptor {
     pub(crate) fn default_padding() -> [u8; 3] {
         [0; 3]
@@ -131,3 +179,67 @@ pub(crate) mod serde_reference_adaptor {
         0
     }
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    use serde::Deserialize;
+
+    use super::{read_versioned, text_version, unknown_version};
+
+    #[derive(Deserialize)]
+    struct CountV1 {
+        count: u64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct CountV2 {
+        version: u8,
+        count: u64,
+        sum: f64,
+    }
+
+    fn read_count(input: &str) -> CountV2 {
+        read_versioned("Count", input, 2, |input| ron::from_str(input).unwrap(), |version, input| match version {
+            1 => {
+                let old: CountV1 = ron::from_str(input).unwrap();
+                CountV2 { version: 2, count: old.count, sum: 0.0 }
+            },
+            _ => unknown_version("Count", version),
+        })
+    }
+
+    #[pg_test]
+    fn test_text_version() {
+        assert_eq!(text_version("(version:1,n:1,sx:10)"), Some(1));
+        assert_eq!(text_version(" ( version : 12 , n:1)"), Some(12));
+        assert_eq!(text_version("(version:1)"), Some(1));
+        assert_eq!(text_version("(n:1,version:1)"), None);
+        assert_eq!(text_version("(version:300)"), None);
+        assert_eq!(text_version(""), None);
+    }
+
+    #[pg_test]
+    fn test_read_versioned_upgrades_older_text() {
+        assert_eq!(read_count("(version:1,count:3)"), CountV2 { version: 2, count: 3, sum: 0.0 });
+        assert_eq!(read_count("(version:2,count:3,sum:4.5)"), CountV2 { version: 2, count: 3, sum: 4.5 });
+    }
+
+    #[pg_test(error = "Count version 3 is from a newer version of the extension, the newest version this one reads is 2")]
+    fn test_read_versioned_rejects_newer_text() {
+        read_count("(version:3,count:3,sum:4.5,extra:1)");
+    }
+
+    #[pg_test(error = "invalid Count version 0")]
+    fn test_read_versioned_rejects_unknown_text() {
+        read_count("(version:0,count:3)");
+    }
+
+    #[pg_test(error = "StatsSummary1D version 2 is from a newer version of the extension, the newest version this one reads is 1")]
+    fn test_newer_summary_text() {
+        Spi::execute(|client| {
+            client.select("SELECT '(version:2,n:1,sx:10,sx2:0,sx3:0,sx4:0)'::toolkit_experimental.StatsSummary1D::TEXT", None, None);
+        });
+    }
+}
@@ -0,0 +1,303 @@
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    utilities::interval_to_micros,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+type Interval = pg_sys::Datum;
+
+// the events from `start` through `end`, inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct Session {
+    start: i64,
+    end: i64,
+    num_events: u64,
+}
+
+// PG object grouping events into sessions, a new session starts whenever
+// there is more than `max_gap` between consecutive events
+pg_type! {
+    #[derive(Debug)]
+    struct Sessions<'input> {
+        max_gap: i64, // microseconds
+        num_sessions: u64,
+        sessions: [Session; self.num_sessions],
+    }
+}
+
+ron_inout_funcs!(Sessions);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(Sessions);
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionTransState {
+    max_gap: i64,
+    events: Vec<i64>,
+    // sessions of rolled up aggregates
+    sessions: Vec<Session>,
+}
+
+impl SessionTransState {
+    fn sessionize(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.events.iter()
+            .map(|&ts| Session { start: ts, end: ts, num_events: 1 })
+            .chain(self.sessions.iter().copied())
+            .collect();
+        sessions.sort_by_key(|session| session.start);
+
+        let mut merged: Vec<Session> = vec![];
+        for session in sessions {
+            match merged.last_mut() {
+                Some(last) if session.start - last.end <= self.max_gap => {
+                    last.end = last.end.max(session.end);
+                    last.num_events += session.num_events;
+                },
+                _ => merged.push(session),
+            }
+        }
+        merged
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sessionize_serialize(
+    state: Internal<SessionTransState>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn sessionize_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<SessionTransState> {
+    crate::do_deserialize!(bytes, SessionTransState)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sessionize_trans(
+    state: Option<Internal<SessionTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    max_gap: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<SessionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let ts = match ts {
+                None => return state,
+                Some(ts) => ts,
+            };
+            let mut state = match state {
+                None => SessionTransState {
+                    max_gap: interval_to_micros(max_gap, "session gaps"),
+                    events: vec![],
+                    sessions: vec![],
+                }.into(),
+                Some(state) => state,
+            };
+            state.events.push(ts);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sessionize_summary_trans(
+    state: Option<Internal<SessionTransState>>,
+    next: Option<toolkit_experimental::Sessions<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<SessionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => next,
+            };
+            let mut state = match state {
+                None => SessionTransState {
+                    max_gap: next.max_gap,
+                    events: vec![],
+                    sessions: vec![],
+                }.into(),
+                Some(state) => state,
+            };
+            if state.max_gap != next.max_gap {
+                error!("cannot rollup sessions with different gaps")
+            }
+            state.sessions.extend(next.sessions.iter());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sessionize_combine(
+    state1: Option<Internal<SessionTransState>>,
+    state2: Option<Internal<SessionTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<SessionTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let mut state = state1.clone();
+                    state.events.extend_from_slice(&state2.events);
+                    state.sessions.extend_from_slice(&state2.sessions);
+                    Some(state.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn sessionize_final(
+    state: Option<Internal<SessionTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::Sessions<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            let sessions = state.sessionize();
+            Some(build!(
+                Sessions {
+                    max_gap: state.max_gap,
+                    num_sessions: sessions.len() as _,
+                    sessions: sessions.into(),
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.sessionize(
+    ts timestamptz,
+    max_gap interval
+) (
+    sfunc = toolkit_experimental.sessionize_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.sessionize_final,
+    combinefunc = toolkit_experimental.sessionize_combine,
+    serialfunc = toolkit_experimental.sessionize_serialize,
+    deserialfunc = toolkit_experimental.sessionize_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    sessions toolkit_experimental.Sessions
+) (
+    sfunc = toolkit_experimental.sessionize_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.sessionize_final,
+    combinefunc = toolkit_experimental.sessionize_combine,
+    serialfunc = toolkit_experimental.sessionize_serialize,
+    deserialfunc = toolkit_experimental.sessionize_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_sessions(
+    sessions: toolkit_experimental::Sessions<'_>,
+) -> i64 {
+    sessions.num_sessions as _
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn sessions(
+    sessions: toolkit_experimental::Sessions<'_>,
+) -> impl std::iter::Iterator<Item = (name!(session_start,pg_sys::TimestampTz),name!(session_end,pg_sys::TimestampTz),name!(num_events,i64))> + '_ {
+    sessions.sessions.iter()
+        .map(|session| (session.start, session.end, session.num_events as i64))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_sessionize() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE events(ts timestamptz)", None, None);
+            client.select(
+                "INSERT INTO events VALUES \
+                    ('2020-01-01 00:00:00+00'), \
+                    ('2020-01-01 00:10:00+00'), \
+                    ('2020-01-01 00:55:00+00'), \
+                    ('2020-01-01 01:05:00+00'), \
+                    ('2020-01-01 01:20:00+00'), \
+                    ('2020-01-01 03:00:00+00')",
+                None,
+                None
+            );
+
+            let expected = vec![
+                ("2020-01-01 00:00:00+00".to_string(), "2020-01-01 00:10:00+00".to_string(), 2),
+                ("2020-01-01 00:55:00+00".to_string(), "2020-01-01 01:20:00+00".to_string(), 3),
+                ("2020-01-01 03:00:00+00".to_string(), "2020-01-01 03:00:00+00".to_string(), 1),
+            ];
+            let sessions = |agg: &str| -> Vec<(String, String, i64)> {
+                client.select(
+                    &format!("SELECT session_start::TEXT, session_end::TEXT, num_events FROM sessions(({}))", agg),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                        row.by_ordinal(3).unwrap().value().unwrap(),
+                    ))
+                    .collect()
+            };
+
+            assert_eq!(sessions("SELECT sessionize(ts, '30 minutes') FROM events"), expected);
+
+            // sessions spanning the hour boundary are merged back together
+            assert_eq!(
+                sessions("SELECT rollup(s) FROM \
+                    (SELECT sessionize(ts, '30 minutes') AS s FROM events GROUP BY date_trunc('hour', ts)) h"),
+                expected,
+            );
+
+            let val = client.select(
+                "SELECT num_sessions(sessionize(ts, '2 hours')) FROM events",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val.unwrap(), 1);
+        });
+    }
+}
@@ -0,0 +1,165 @@
+
+// Points stored in a tuplestore, which keeps them in memory up to `work_mem`
+// and writes them to a temporary file past that, so that aggregates which
+// must see every point of a group before summarizing it don't need unbounded
+// memory. The points are stored as sorted runs which are read back in
+// parallel to be merged.
+
+use std::os::raw::{c_int, c_void};
+
+use pgx::*;
+
+use time_series::TSPoint;
+
+#[derive(Debug, Clone)]
+pub struct SpilledRuns {
+    store: *mut pg_sys::Tuplestorestate,
+    desc: pg_sys::TupleDesc,
+    num_stored: usize,
+    // the start and length of each run in `store`
+    runs: Vec<(usize, usize)>,
+    // the store's read pointers, one for each run being read
+    read_pointers: Vec<c_int>,
+}
+
+impl SpilledRuns {
+    // The store is freed when the current memory context is, which must be
+    // the one holding this.
+    pub unsafe fn new() -> Self {
+        let desc = point_desc();
+        let store = pg_sys::tuplestore_begin_heap(true, false, pg_sys::work_mem);
+        // the store's temporary file isn't closed when its memory is freed
+        let callback = pg_sys::palloc0(std::mem::size_of::<pg_sys::MemoryContextCallback>())
+            as *mut pg_sys::MemoryContextCallback;
+        (*callback).func = Some(end_tuplestore);
+        (*callback).arg = store as *mut c_void;
+        pg_sys::MemoryContextRegisterResetCallback(pg_sys::CurrentMemoryContext, callback);
+        Self {
+            store,
+            desc,
+            num_stored: 0,
+            runs: vec![],
+            read_pointers: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    // the points must be sorted by time
+    pub fn push_run(&mut self, points: &[TSPoint]) {
+        for point in points {
+            let mut values = [point.ts as pg_sys::Datum, point.val.to_bits() as pg_sys::Datum];
+            let mut nulls = [false; 2];
+            unsafe {
+                pg_sys::tuplestore_putvalues(self.store, self.desc, values.as_mut_ptr(), nulls.as_mut_ptr());
+            }
+        }
+        self.runs.push((self.num_stored, points.len()));
+        self.num_stored += points.len();
+    }
+
+    // Forgets the runs, their points are left in the store until it's freed
+    // as copies of this may still read them.
+    pub fn clear(&mut self) {
+        self.runs.clear();
+    }
+
+    // Reads every run in time order, the runs are forgotten afterwards.
+    pub fn take_runs(&mut self) -> Vec<RunReader> {
+        let runs = std::mem::take(&mut self.runs);
+        runs.iter().enumerate().map(|(i, &(start, len))| unsafe {
+            if i == self.read_pointers.len() {
+                self.read_pointers.push(pg_sys::tuplestore_alloc_read_pointer(self.store, pg_sys::EXEC_FLAG_REWIND as c_int));
+            }
+            let read_pointer = self.read_pointers[i];
+            pg_sys::tuplestore_select_read_pointer(self.store, read_pointer);
+            pg_sys::tuplestore_rescan(self.store);
+            if start > 0 {
+                pg_sys::tuplestore_skiptuples(self.store, start as i64, true);
+            }
+            RunReader {
+                store: self.store,
+                read_pointer,
+                slot: make_slot(self.desc),
+                remaining: len,
+            }
+        }).collect()
+    }
+}
+
+pub struct RunReader {
+    store: *mut pg_sys::Tuplestorestate,
+    read_pointer: c_int,
+    slot: *mut pg_sys::TupleTableSlot,
+    remaining: usize,
+}
+
+impl Iterator for RunReader {
+    type Item = TSPoint;
+
+    fn next(&mut self) -> Option<TSPoint> {
+        if self.remaining == 0 {
+            return None
+        }
+        self.remaining -= 1;
+        unsafe {
+            pg_sys::tuplestore_select_read_pointer(self.store, self.read_pointer);
+            if !pg_sys::tuplestore_gettupleslot(self.store, true, false, self.slot) {
+                error!("spilled points are missing")
+            }
+            let (ts, val) = slot_values(self.slot);
+            Some(TSPoint{ ts: ts as i64, val: f64::from_bits(val as u64) })
+        }
+    }
+}
+
+impl Drop for RunReader {
+    fn drop(&mut self) {
+        unsafe { pg_sys::ExecDropSingleTupleTableSlot(self.slot) }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn end_tuplestore(store: *mut c_void) {
+    pg_sys::tuplestore_end(store as *mut pg_sys::Tuplestorestate)
+}
+
+// points are stored as (ts int8, val float8)
+unsafe fn point_desc() -> pg_sys::TupleDesc {
+    #[cfg(any(feature = "pg10", feature = "pg11"))]
+    let desc = pg_sys::CreateTemplateTupleDesc(2, false);
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    let desc = pg_sys::CreateTemplateTupleDesc(2);
+    pg_sys::TupleDescInitEntry(desc, 1, "ts\0".as_ptr() as *const _, pg_sys::INT8OID, -1, 0);
+    pg_sys::TupleDescInitEntry(desc, 2, "val\0".as_ptr() as *const _, pg_sys::FLOAT8OID, -1, 0);
+    desc
+}
+
+#[cfg(any(feature = "pg10", feature = "pg11"))]
+unsafe fn make_slot(desc: pg_sys::TupleDesc) -> *mut pg_sys::TupleTableSlot {
+    pg_sys::MakeSingleTupleTableSlot(desc)
+}
+
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+unsafe fn make_slot(desc: pg_sys::TupleDesc) -> *mut pg_sys::TupleTableSlot {
+    pg_sys::MakeSingleTupleTableSlot(desc, &pg_sys::TTSOpsMinimalTuple)
+}
+
+#[cfg(any(feature = "pg10", feature = "pg11"))]
+unsafe fn slot_values(slot: *mut pg_sys::TupleTableSlot) -> (pg_sys::Datum, pg_sys::Datum) {
+    let mut is_null = false;
+    let ts = pg_sys::slot_getattr(slot, 1, &mut is_null);
+    let val = pg_sys::slot_getattr(slot, 2, &mut is_null);
+    (ts, val)
+}
+
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+unsafe fn slot_values(slot: *mut pg_sys::TupleTableSlot) -> (pg_sys::Datum, pg_sys::Datum) {
+    // slot_getallattrs() is inline, so call the function it wraps
+    if (*slot).tts_nvalid < 2 {
+        pg_sys::slot_getsomeattrs_int(slot, 2);
+    }
+    (*(*slot).tts_values, *(*slot).tts_values.add(1))
+}
@@ -0,0 +1,1145 @@
+use std::{collections::BTreeMap, slice};
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    ron_inout_funcs,
+    build,
+    palloc::Internal, pg_type,
+    utilities::{interval_to_micros, micros_to_interval},
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct DurationInState {
+    duration: i64, // microseconds
+    // byte range of the state's name within `states`
+    state_beg: u32,
+    state_end: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct IntDurationInState {
+    duration: i64, // microseconds
+    state: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct StateChange {
+    time: i64,
+    state: u64, // index into durations
+}
+
+// PG object recording how long each state was held, along with the times at
+// which the state changed
+pg_type! {
+    #[derive(Debug)]
+    struct StateAgg<'input> {
+        last_time: i64,
+        states_len: u64,
+        durations_len: u64,
+        transitions_len: u64,
+        durations: [DurationInState; self.durations_len],
+        transitions: [StateChange; self.transitions_len],
+        states: [u8; self.states_len],
+    }
+}
+
+ron_inout_funcs!(StateAgg);
+
+// StateAgg for states encoded as integers, which don't need to store names
+pg_type! {
+    #[derive(Debug)]
+    struct IntStateAgg<'input> {
+        last_time: i64,
+        durations_len: u64,
+        transitions_len: u64,
+        durations: [IntDurationInState; self.durations_len],
+        transitions: [StateChange; self.transitions_len],
+    }
+}
+
+ron_inout_funcs!(IntStateAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(StateAgg);
+    varlena_type!(IntStateAgg);
+}
+
+pub trait State: Clone + Ord + Serialize + DeserializeOwned {}
+impl State for String {}
+impl State for i64 {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record<S> {
+    time: i64,
+    state: S,
+}
+
+// A state is held from the time it is recorded until the next record, so the
+// summary covers the span from the first record to the last one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateSummary<S: Ord> {
+    // only the records where the state differs from the previous one
+    transitions: Vec<Record<S>>,
+    last_time: i64,
+    durations: BTreeMap<S, i64>,
+}
+
+impl<S: State> StateSummary<S> {
+    fn from_sorted_records(records: &[Record<S>]) -> Option<Self> {
+        let last_time = records.last()?.time;
+        let mut transitions: Vec<Record<S>> = vec![];
+        let mut durations = BTreeMap::new();
+        for (i, record) in records.iter().enumerate() {
+            let held_until = records.get(i + 1).map_or(record.time, |next| next.time);
+            *durations.entry(record.state.clone()).or_insert(0) += held_until - record.time;
+            if transitions.last().map_or(true, |prev| prev.state != record.state) {
+                transitions.push(record.clone());
+            }
+        }
+        Some(Self { transitions, last_time, durations })
+    }
+
+    fn first_time(&self) -> i64 {
+        self.transitions[0].time
+    }
+
+    fn last_state(&self) -> &S {
+        &self.transitions.last().unwrap().state
+    }
+
+    // the last state of `self` is held until `next` starts
+    fn append(&mut self, next: &StateSummary<S>) {
+        if next.first_time() < self.last_time {
+            error!("state_agg summaries must not overlap")
+        }
+        let last_state = self.last_state().clone();
+        *self.durations.entry(last_state.clone()).or_insert(0) +=
+            next.first_time() - self.last_time;
+        for (state, duration) in &next.durations {
+            *self.durations.entry(state.clone()).or_insert(0) += duration;
+        }
+        let skip = if next.transitions[0].state == last_state { 1 } else { 0 };
+        self.transitions.extend(next.transitions[skip..].iter().cloned());
+        self.last_time = next.last_time;
+    }
+
+    // transitions as indexes into the states in order
+    fn state_changes(&self) -> Vec<StateChange> {
+        self.transitions.iter()
+            .map(|record| StateChange {
+                time: record.time,
+                state: self.durations.keys().position(|s| *s == record.state).unwrap() as u64,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateAggTransState<S: Ord> {
+    records: Vec<Record<S>>,
+    summaries: Vec<StateSummary<S>>,
+}
+
+impl<S: State> StateAggTransState<S> {
+    fn new() -> Self {
+        Self { records: vec![], summaries: vec![] }
+    }
+
+    fn summarize(&mut self) -> Option<StateSummary<S>> {
+        // stable sort so ties are resolved in input order
+        self.records.sort_by_key(|record| record.time);
+        if let Some(summary) = StateSummary::from_sorted_records(&self.records) {
+            self.summaries.push(summary);
+        }
+        self.records.clear();
+
+        self.summaries.sort_by_key(|summary| summary.first_time());
+        let mut summaries = self.summaries.drain(..);
+        let mut combined = summaries.next()?;
+        for summary in summaries {
+            combined.append(&summary);
+        }
+        Some(combined)
+    }
+}
+
+// the time spent in the state at index `state` within [start, end), assuming
+// the last state is held until `last_held_until`
+fn duration_in_range(
+    transitions: &[StateChange],
+    state: u64,
+    start: i64,
+    end: i64,
+    last_held_until: i64,
+) -> i64 {
+    transitions.iter().enumerate()
+        .filter(|(_, change)| change.state == state)
+        .map(|(i, change)| {
+            let held_until = transitions.get(i + 1).map_or(last_held_until, |next| next.time);
+            (held_until.min(end) - change.time.max(start)).max(0)
+        })
+        .sum()
+}
+
+// the index of the state in effect at `ts`, if `ts` is within the aggregated range
+fn state_index_at(transitions: &[StateChange], last_time: i64, ts: i64) -> Option<u64> {
+    if ts > last_time {
+        return None
+    }
+    // the number of transitions at or before `ts`, binary_search_by() never
+    // finds an exact match so it returns where one would be inserted
+    let held = transitions
+        .binary_search_by(|change| if change.time <= ts { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater })
+        .unwrap_err();
+    if held == 0 {
+        return None
+    }
+    Some(transitions[held - 1].state)
+}
+
+// the longest uninterrupted time spent in the state at index `state`
+fn longest_run(transitions: &[StateChange], state: u64, last_time: i64) -> i64 {
+    transitions.iter().enumerate()
+        .filter(|(_, change)| change.state == state)
+        .map(|(i, change)| transitions.get(i + 1).map_or(last_time, |next| next.time) - change.time)
+        .max()
+        .unwrap_or(0)
+}
+
+fn transition_index_counts(transitions: &[StateChange]) -> BTreeMap<(u64, u64), i64> {
+    let mut counts = BTreeMap::new();
+    for pair in transitions.windows(2) {
+        *counts.entry((pair[0].state, pair[1].state)).or_insert(0) += 1;
+    }
+    counts
+}
+
+// each state held, as (start, end, index of the state), in time order
+fn state_spans(transitions: &[StateChange], last_time: i64) -> impl Iterator<Item = (i64, i64, u64)> + '_ {
+    transitions.iter().enumerate()
+        .map(move |(i, change)| {
+            let held_until = transitions.get(i + 1).map_or(last_time, |next| next.time);
+            (change.time, held_until, change.state)
+        })
+}
+
+impl<'input> StateAgg<'input> {
+    fn state_name(&self, index: u64) -> &str {
+        let entry = self.durations.as_slice()[index as usize];
+        let bytes = &self.states.as_slice()[entry.state_beg as usize..entry.state_end as usize];
+        std::str::from_utf8(bytes).unwrap()
+    }
+
+    fn state_index(&self, state: &str) -> Option<u64> {
+        (0..self.durations_len).find(|&i| self.state_name(i) == state)
+    }
+
+    fn to_summary(&self) -> StateSummary<String> {
+        StateSummary {
+            transitions: self.transitions.iter()
+                .map(|change| Record {
+                    time: change.time,
+                    state: self.state_name(change.state).to_string(),
+                })
+                .collect(),
+            last_time: self.last_time,
+            durations: self.durations.iter().enumerate()
+                .map(|(i, entry)| (self.state_name(i as u64).to_string(), entry.duration))
+                .collect(),
+        }
+    }
+
+    fn from_summary(summary: &StateSummary<String>) -> StateAgg<'static> {
+        let mut states = vec![];
+        let mut durations = vec![];
+        for (state, duration) in &summary.durations {
+            let state_beg = states.len() as u32;
+            states.extend_from_slice(state.as_bytes());
+            durations.push(DurationInState {
+                duration: *duration,
+                state_beg,
+                state_end: states.len() as u32,
+            });
+        }
+        let transitions = summary.state_changes();
+
+        build!(
+            StateAgg {
+                last_time: summary.last_time,
+                states_len: states.len() as _,
+                durations_len: durations.len() as _,
+                transitions_len: transitions.len() as _,
+                durations: durations.into(),
+                transitions: transitions.into(),
+                states: states.into(),
+            }
+        )
+    }
+
+    fn first_time(&self) -> i64 {
+        self.transitions.as_slice()[0].time
+    }
+
+    fn last_state(&self) -> &str {
+        self.state_name(self.transitions.as_slice()[self.transitions_len as usize - 1].state)
+    }
+}
+
+impl<'input> IntStateAgg<'input> {
+    fn state_index(&self, state: i64) -> Option<u64> {
+        self.durations.iter().position(|entry| entry.state == state).map(|i| i as u64)
+    }
+
+    fn state_at_index(&self, index: u64) -> i64 {
+        self.durations.as_slice()[index as usize].state
+    }
+
+    fn to_summary(&self) -> StateSummary<i64> {
+        StateSummary {
+            transitions: self.transitions.iter()
+                .map(|change| Record {
+                    time: change.time,
+                    state: self.state_at_index(change.state),
+                })
+                .collect(),
+            last_time: self.last_time,
+            durations: self.durations.iter()
+                .map(|entry| (entry.state, entry.duration))
+                .collect(),
+        }
+    }
+
+    fn from_summary(summary: &StateSummary<i64>) -> IntStateAgg<'static> {
+        let durations: Vec<_> = summary.durations.iter()
+            .map(|(state, duration)| IntDurationInState { duration: *duration, state: *state })
+            .collect();
+        let transitions = summary.state_changes();
+
+        build!(
+            IntStateAgg {
+                last_time: summary.last_time,
+                durations_len: durations.len() as _,
+                transitions_len: transitions.len() as _,
+                durations: durations.into(),
+                transitions: transitions.into(),
+            }
+        )
+    }
+
+    fn first_time(&self) -> i64 {
+        self.transitions.as_slice()[0].time
+    }
+
+    fn last_state(&self) -> i64 {
+        self.state_at_index(self.transitions.as_slice()[self.transitions_len as usize - 1].state)
+    }
+}
+
+// the aggregate support functions are shared between the text and integer
+// variants, differing only in the type of state
+unsafe fn trans<S: State>(
+    state: Option<Internal<StateAggTransState<S>>>,
+    ts: Option<pg_sys::TimestampTz>,
+    value: Option<S>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<S>>> {
+    in_aggregate_context(fcinfo, || {
+        let record = match (ts, value) {
+            (Some(time), Some(state)) => Record { time, state },
+            _ => return state,
+        };
+        let mut state = match state {
+            None => StateAggTransState::new().into(),
+            Some(state) => state,
+        };
+        state.records.push(record);
+        Some(state)
+    })
+}
+
+unsafe fn summary_trans<S: State>(
+    state: Option<Internal<StateAggTransState<S>>>,
+    next: Option<StateSummary<S>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<S>>> {
+    in_aggregate_context(fcinfo, || {
+        let next = match next {
+            None => return state,
+            Some(next) => next,
+        };
+        let mut state = match state {
+            None => StateAggTransState::new().into(),
+            Some(state) => state,
+        };
+        state.summaries.push(next);
+        Some(state)
+    })
+}
+
+unsafe fn combine<S: State>(
+    state1: Option<Internal<StateAggTransState<S>>>,
+    state2: Option<Internal<StateAggTransState<S>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<S>>> {
+    in_aggregate_context(fcinfo, || {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(state2)) => Some(state2.clone().into()),
+            (Some(state1), None) => Some(state1.clone().into()),
+            (Some(state1), Some(state2)) => {
+                // the records of one state may fall between those of the
+                // other, so they can only be summarized together
+                let mut state = state1.clone();
+                state.records.extend(state2.records.iter().cloned());
+                state.summaries.extend(state2.summaries.iter().cloned());
+                Some(state.into())
+            }
+        }
+    })
+}
+
+unsafe fn finalize<S: State, T>(
+    state: Option<Internal<StateAggTransState<S>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+    from_summary: impl FnOnce(&StateSummary<S>) -> T,
+) -> Option<T> {
+    in_aggregate_context(fcinfo, || {
+        let mut state = state?.clone();
+        state.summarize().map(|summary| from_summary(&summary))
+    })
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_serialize(
+    state: Internal<StateAggTransState<String>>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn state_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StateAggTransState<String>> {
+    crate::do_deserialize!(bytes, StateAggTransState<String>)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_trans(
+    state: Option<Internal<StateAggTransState<String>>>,
+    ts: Option<pg_sys::TimestampTz>,
+    value: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<String>>> {
+    unsafe { trans(state, ts, value, fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_summary_trans(
+    state: Option<Internal<StateAggTransState<String>>>,
+    next: Option<toolkit_experimental::StateAgg<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<String>>> {
+    unsafe { summary_trans(state, next.map(|next| next.to_summary()), fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_combine(
+    state1: Option<Internal<StateAggTransState<String>>>,
+    state2: Option<Internal<StateAggTransState<String>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<String>>> {
+    unsafe { combine(state1, state2, fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn state_agg_final(
+    state: Option<Internal<StateAggTransState<String>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::StateAgg<'static>> {
+    unsafe { finalize(state, fcinfo, StateAgg::from_summary) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn int_state_agg_serialize(
+    state: Internal<StateAggTransState<i64>>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn int_state_agg_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StateAggTransState<i64>> {
+    crate::do_deserialize!(bytes, StateAggTransState<i64>)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn int_state_agg_trans(
+    state: Option<Internal<StateAggTransState<i64>>>,
+    ts: Option<pg_sys::TimestampTz>,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<i64>>> {
+    unsafe { trans(state, ts, value, fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn int_state_agg_summary_trans(
+    state: Option<Internal<StateAggTransState<i64>>>,
+    next: Option<toolkit_experimental::IntStateAgg<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<i64>>> {
+    unsafe { summary_trans(state, next.map(|next| next.to_summary()), fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn int_state_agg_combine(
+    state1: Option<Internal<StateAggTransState<i64>>>,
+    state2: Option<Internal<StateAggTransState<i64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState<i64>>> {
+    unsafe { combine(state1, state2, fcinfo) }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn int_state_agg_final(
+    state: Option<Internal<StateAggTransState<i64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::IntStateAgg<'static>> {
+    unsafe { finalize(state, fcinfo, IntStateAgg::from_summary) }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.state_agg(
+    ts timestamptz,
+    value text
+) (
+    sfunc = toolkit_experimental.state_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.state_agg_final,
+    combinefunc = toolkit_experimental.state_agg_combine,
+    serialfunc = toolkit_experimental.state_agg_serialize,
+    deserialfunc = toolkit_experimental.state_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    agg toolkit_experimental.StateAgg
+) (
+    sfunc = toolkit_experimental.state_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.state_agg_final,
+    combinefunc = toolkit_experimental.state_agg_combine,
+    serialfunc = toolkit_experimental.state_agg_serialize,
+    deserialfunc = toolkit_experimental.state_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.state_agg(
+    ts timestamptz,
+    value bigint
+) (
+    sfunc = toolkit_experimental.int_state_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.int_state_agg_final,
+    combinefunc = toolkit_experimental.int_state_agg_combine,
+    serialfunc = toolkit_experimental.int_state_agg_serialize,
+    deserialfunc = toolkit_experimental.int_state_agg_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.rollup(
+    agg toolkit_experimental.IntStateAgg
+) (
+    sfunc = toolkit_experimental.int_state_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.int_state_agg_final,
+    combinefunc = toolkit_experimental.int_state_agg_combine,
+    serialfunc = toolkit_experimental.int_state_agg_serialize,
+    deserialfunc = toolkit_experimental.int_state_agg_deserialize,
+    parallel = restricted
+);
+"#);
+
+type Interval = pg_sys::Datum;
+
+#[pg_extern(immutable, parallel_safe, name = "duration_in", schema = "toolkit_experimental")]
+pub fn duration_in(
+    agg: toolkit_experimental::StateAgg<'_>,
+    state: String,
+) -> Interval {
+    let duration = agg.state_index(&state)
+        .map_or(0, |i| agg.durations.as_slice()[i as usize].duration);
+    micros_to_interval(duration)
+}
+
+// the time spent in `state` between `start` and `start + interval`
+#[pg_extern(immutable, parallel_safe, name = "duration_in", schema = "toolkit_experimental")]
+pub fn duration_in_range(
+    agg: toolkit_experimental::StateAgg<'_>,
+    state: String,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+) -> Interval {
+    let end = start.saturating_add(interval_to_micros(interval, "duration_in intervals"));
+    let duration = agg.state_index(&state).map_or(0, |state|
+        duration_in_range(agg.transitions.as_slice(), state, start, end, agg.last_time));
+    micros_to_interval(duration)
+}
+
+// Like duration_in, but for an aggregate covering exactly the bucket
+// [start, start + interval): the state before the first record is taken to be
+// the last state of `prev`, the aggregate of the preceding bucket, and the
+// last state is held until the end of the bucket.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn interpolated_duration_in(
+    agg: toolkit_experimental::StateAgg<'_>,
+    state: String,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+    prev: Option<toolkit_experimental::StateAgg<'_>>,
+) -> Interval {
+    let end = start.saturating_add(interval_to_micros(interval, "duration_in intervals"));
+    let mut duration = agg.state_index(&state).map_or(0, |state|
+        duration_in_range(agg.transitions.as_slice(), state, start, end, end));
+    if let Some(prev) = prev {
+        if prev.last_state() == state {
+            duration += (agg.first_time().min(end) - start).max(0);
+        }
+    }
+    micros_to_interval(duration)
+}
+
+// the state in effect at `ts`, or NULL if `ts` is outside the aggregated range
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_at(
+    agg: toolkit_experimental::StateAgg<'_>,
+    ts: pg_sys::TimestampTz,
+) -> Option<String> {
+    state_index_at(agg.transitions.as_slice(), agg.last_time, ts)
+        .map(|state| agg.state_name(state).to_string())
+}
+
+// the number of times each state was followed by a different one
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn transition_counts(
+    agg: toolkit_experimental::StateAgg<'_>,
+) -> impl std::iter::Iterator<Item = (name!(from_state,String),name!(to_state,String),name!(count,i64))> + '_ {
+    transition_index_counts(agg.transitions.as_slice()).into_iter()
+        .map(move |((from, to), count)| (
+            agg.state_name(from).to_string(),
+            agg.state_name(to).to_string(),
+            count,
+        ))
+}
+
+// every span of time during which a single state was held
+#[pg_extern(immutable, parallel_safe, name = "into_values", schema = "toolkit_experimental")]
+pub fn state_agg_into_values(
+    agg: toolkit_experimental::StateAgg<'_>,
+) -> impl std::iter::Iterator<Item = (name!(start_time,pg_sys::TimestampTz),name!(end_time,pg_sys::TimestampTz),name!(value,String))> + '_ {
+    state_spans(agg.transitions.as_slice(), agg.last_time)
+        .map(move |(start, end, state)| (start, end, agg.state_name(state).to_string()))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn longest_duration_in(
+    agg: toolkit_experimental::StateAgg<'_>,
+    state: String,
+) -> Interval {
+    let duration = agg.state_index(&state)
+        .map_or(0, |state| longest_run(agg.transitions.as_slice(), state, agg.last_time));
+    micros_to_interval(duration)
+}
+
+// the number of times the state changed
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_transitions(
+    agg: toolkit_experimental::StateAgg<'_>,
+) -> i64 {
+    agg.transitions_len as i64 - 1
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_distinct_states(
+    agg: toolkit_experimental::StateAgg<'_>,
+) -> i64 {
+    agg.durations_len as _
+}
+
+#[pg_extern(immutable, parallel_safe, name = "duration_in", schema = "toolkit_experimental")]
+pub fn int_duration_in(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+    state: i64,
+) -> Interval {
+    let duration = agg.state_index(state)
+        .map_or(0, |i| agg.durations.as_slice()[i as usize].duration);
+    micros_to_interval(duration)
+}
+
+#[pg_extern(immutable, parallel_safe, name = "duration_in", schema = "toolkit_experimental")]
+pub fn int_duration_in_range(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+    state: i64,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+) -> Interval {
+    let end = start.saturating_add(interval_to_micros(interval, "duration_in intervals"));
+    let duration = agg.state_index(state).map_or(0, |state|
+        duration_in_range(agg.transitions.as_slice(), state, start, end, agg.last_time));
+    micros_to_interval(duration)
+}
+
+#[pg_extern(immutable, parallel_safe, name = "interpolated_duration_in", schema = "toolkit_experimental")]
+pub fn int_interpolated_duration_in(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+    state: i64,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+    prev: Option<toolkit_experimental::IntStateAgg<'_>>,
+) -> Interval {
+    let end = start.saturating_add(interval_to_micros(interval, "duration_in intervals"));
+    let mut duration = agg.state_index(state).map_or(0, |state|
+        duration_in_range(agg.transitions.as_slice(), state, start, end, end));
+    if let Some(prev) = prev {
+        if prev.last_state() == state {
+            duration += (agg.first_time().min(end) - start).max(0);
+        }
+    }
+    micros_to_interval(duration)
+}
+
+#[pg_extern(immutable, parallel_safe, name = "state_at", schema = "toolkit_experimental")]
+pub fn int_state_at(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+    ts: pg_sys::TimestampTz,
+) -> Option<i64> {
+    state_index_at(agg.transitions.as_slice(), agg.last_time, ts)
+        .map(|state| agg.state_at_index(state))
+}
+
+#[pg_extern(immutable, parallel_safe, name = "transition_counts", schema = "toolkit_experimental")]
+pub fn int_transition_counts(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+) -> impl std::iter::Iterator<Item = (name!(from_state,i64),name!(to_state,i64),name!(count,i64))> + '_ {
+    transition_index_counts(agg.transitions.as_slice()).into_iter()
+        .map(move |((from, to), count)| (
+            agg.state_at_index(from),
+            agg.state_at_index(to),
+            count,
+        ))
+}
+
+#[pg_extern(immutable, parallel_safe, name = "into_values", schema = "toolkit_experimental")]
+pub fn int_state_agg_into_values(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+) -> impl std::iter::Iterator<Item = (name!(start_time,pg_sys::TimestampTz),name!(end_time,pg_sys::TimestampTz),name!(value,i64))> + '_ {
+    state_spans(agg.transitions.as_slice(), agg.last_time)
+        .map(move |(start, end, state)| (start, end, agg.state_at_index(state)))
+}
+
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// The number of spans into_values() returns for the planner, one per
+// transition, see estimate_srf_rows()
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_into_values_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: StateAgg| agg.transitions_len as usize)
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn int_state_agg_into_values_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: IntStateAgg| agg.transitions_len as usize)
+    }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        ALTER FUNCTION toolkit_experimental.into_values(toolkit_experimental.StateAgg)
+            SUPPORT toolkit_experimental.state_agg_into_values_rows_support;
+        ALTER FUNCTION toolkit_experimental.into_values(toolkit_experimental.IntStateAgg)
+            SUPPORT toolkit_experimental.int_state_agg_into_values_rows_support;
+    END IF;
+END
+$$;
+"#);
+
+#[pg_extern(immutable, parallel_safe, name = "longest_duration_in", schema = "toolkit_experimental")]
+pub fn int_longest_duration_in(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+    state: i64,
+) -> Interval {
+    let duration = agg.state_index(state)
+        .map_or(0, |state| longest_run(agg.transitions.as_slice(), state, agg.last_time));
+    micros_to_interval(duration)
+}
+
+#[pg_extern(immutable, parallel_safe, name = "num_transitions", schema = "toolkit_experimental")]
+pub fn int_num_transitions(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+) -> i64 {
+    agg.transitions_len as i64 - 1
+}
+
+#[pg_extern(immutable, parallel_safe, name = "num_distinct_states", schema = "toolkit_experimental")]
+pub fn int_num_distinct_states(
+    agg: toolkit_experimental::IntStateAgg<'_>,
+) -> i64 {
+    agg.durations_len as _
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_state_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                    ('2020-01-01 00:00:00+00', 'ok'), \
+                    ('2020-01-01 00:01:00+00', 'err'), \
+                    ('2020-01-01 00:03:00+00', 'ok')",
+                None,
+                None
+            );
+
+            // ok is held for a minute, err for two
+            let expected = "(\
+                version:1,\
+                last_time:631152180000000,\
+                states_len:5,\
+                durations_len:2,\
+                transitions_len:3,\
+                durations:[\
+                    (duration:120000000,state_beg:0,state_end:3),\
+                    (duration:60000000,state_beg:3,state_end:5)\
+                ],\
+                transitions:[\
+                    (time:631152000000000,state:1),\
+                    (time:631152060000000,state:0),\
+                    (time:631152180000000,state:1)\
+                ],\
+                states:[101,114,114,111,107]\
+            )";
+            let val = client.select("SELECT state_agg(ts, state)::TEXT FROM test", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            let val = client.select(
+                "SELECT state_agg(ts, state ORDER BY random())::TEXT FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            // the time between summaries is credited to the earlier one's last state
+            let val = client.select(
+                "SELECT rollup(agg)::TEXT FROM \
+                    (SELECT state_agg(ts, state) AS agg FROM test GROUP BY date_trunc('minute', ts)) s",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+        });
+    }
+
+    #[pg_test]
+    fn test_duration_in() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                    ('2020-01-01 00:00:00+00', 'ok'), \
+                    ('2020-01-01 00:01:00+00', 'err'), \
+                    ('2020-01-01 00:03:00+00', 'ok')",
+                None,
+                None
+            );
+
+            let duration_in = |args: &str| {
+                client.select(
+                    &format!("SELECT duration_in(state_agg(ts, state), {})::TEXT FROM test", args),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+            assert_eq!(duration_in("'ok'"), "00:01:00");
+            assert_eq!(duration_in("'err'"), "00:02:00");
+            assert_eq!(duration_in("'unknown'"), "00:00:00");
+
+            assert_eq!(duration_in("'ok', '2020-01-01 00:00:30+00', '2 minutes'"), "00:00:30");
+            assert_eq!(duration_in("'err', '2020-01-01 00:00:30+00', '2 minutes'"), "00:01:30");
+            assert_eq!(duration_in("'err', '2020-01-01 00:05:00+00', '1 day'"), "00:00:00");
+        });
+    }
+
+    #[pg_test]
+    fn test_interpolated_duration_in() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                    ('2020-01-01 12:00:00+00', 'ok'), \
+                    ('2020-01-02 06:00:00+00', 'err'), \
+                    ('2020-01-02 18:00:00+00', 'ok')",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW daily AS \
+                    SELECT date_trunc('day', ts) AS bucket, state_agg(ts, state) AS agg \
+                    FROM test GROUP BY 1",
+                None,
+                None
+            );
+
+            // ok spans midnight, so it is held for the first six hours of the second day
+            let durations: Vec<(String, String)> = client.select(
+                "SELECT \
+                    interpolated_duration_in(agg, 'ok', bucket, '1 day', LAG(agg) OVER (ORDER BY bucket))::TEXT, \
+                    interpolated_duration_in(agg, 'err', bucket, '1 day', LAG(agg) OVER (ORDER BY bucket))::TEXT \
+                FROM daily ORDER BY bucket",
+                None,
+                None
+            )
+                .map(|row| (row.by_ordinal(1).unwrap().value().unwrap(), row.by_ordinal(2).unwrap().value().unwrap()))
+                .collect();
+            assert_eq!(durations, vec![
+                ("12:00:00".to_string(), "00:00:00".to_string()),
+                ("12:00:00".to_string(), "12:00:00".to_string()),
+            ]);
+        });
+    }
+
+    #[pg_test]
+    fn test_state_at_and_transitions() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                    ('2020-01-01 00:00:00+00', 'ok'), \
+                    ('2020-01-01 00:01:00+00', 'err'), \
+                    ('2020-01-01 00:02:00+00', 'err'), \
+                    ('2020-01-01 00:03:00+00', 'ok'), \
+                    ('2020-01-01 00:04:00+00', 'err'), \
+                    ('2020-01-01 00:05:00+00', 'off')",
+                None,
+                None
+            );
+
+            let state_at = |ts: &str| {
+                client.select(
+                    &format!("SELECT state_at(state_agg(ts, state), '{}') FROM test", ts),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<String>()
+            };
+            assert_eq!(state_at("2019-12-31 23:59:00+00"), None);
+            assert_eq!(state_at("2020-01-01 00:00:00+00").as_deref(), Some("ok"));
+            assert_eq!(state_at("2020-01-01 00:02:30+00").as_deref(), Some("err"));
+            assert_eq!(state_at("2020-01-01 00:03:00+00").as_deref(), Some("ok"));
+            assert_eq!(state_at("2020-01-01 00:05:00+00").as_deref(), Some("off"));
+            assert_eq!(state_at("2020-01-01 00:06:00+00"), None);
+
+            let counts: Vec<(String, String, i64)> = client.select(
+                "SELECT from_state, to_state, count FROM transition_counts((SELECT state_agg(ts, state) FROM test))",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            // states are indexed in name order
+            assert_eq!(counts, vec![
+                ("err".to_string(), "off".to_string(), 1),
+                ("err".to_string(), "ok".to_string(), 1),
+                ("ok".to_string(), "err".to_string(), 2),
+            ]);
+
+            let val = client.select(
+                "SELECT num_transitions(agg), num_distinct_states(agg) FROM (SELECT state_agg(ts, state) AS agg FROM test) s",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i64, i64>();
+            assert_eq!(val, (Some(4), Some(3)));
+
+            // the two err records at 00:01 and 00:02 are a single run
+            let val = client.select(
+                "SELECT longest_duration_in(state_agg(ts, state), 'err')::TEXT FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:02:00");
+
+            let spans: Vec<(String, String, String)> = client.select(
+                "SELECT start_time::TEXT, end_time::TEXT, value FROM into_values((SELECT state_agg(ts, state) FROM test))",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(spans, vec![
+                ("2020-01-01 00:00:00+00".to_string(), "2020-01-01 00:01:00+00".to_string(), "ok".to_string()),
+                ("2020-01-01 00:01:00+00".to_string(), "2020-01-01 00:03:00+00".to_string(), "err".to_string()),
+                ("2020-01-01 00:03:00+00".to_string(), "2020-01-01 00:04:00+00".to_string(), "ok".to_string()),
+                ("2020-01-01 00:04:00+00".to_string(), "2020-01-01 00:05:00+00".to_string(), "err".to_string()),
+                ("2020-01-01 00:05:00+00".to_string(), "2020-01-01 00:05:00+00".to_string(), "off".to_string()),
+            ]);
+        });
+    }
+
+    #[pg_test]
+    fn test_int_state_agg() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select("CREATE TABLE test(ts timestamptz, state BIGINT)", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                    ('2020-01-01 00:00:00+00', 1), \
+                    ('2020-01-01 00:01:00+00', 2), \
+                    ('2020-01-01 00:03:00+00', 1)",
+                None,
+                None
+            );
+
+            let expected = "(\
+                version:1,\
+                last_time:631152180000000,\
+                durations_len:2,\
+                transitions_len:3,\
+                durations:[\
+                    (duration:60000000,state:1),\
+                    (duration:120000000,state:2)\
+                ],\
+                transitions:[\
+                    (time:631152000000000,state:0),\
+                    (time:631152060000000,state:1),\
+                    (time:631152180000000,state:0)\
+                ]\
+            )";
+            let val = client.select("SELECT state_agg(ts, state)::TEXT FROM test", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            let val = client.select(
+                "SELECT rollup(agg)::TEXT FROM \
+                    (SELECT state_agg(ts, state) AS agg FROM test GROUP BY date_trunc('minute', ts)) s",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            let val = client.select(
+                "SELECT duration_in(state_agg(ts, state), 2)::TEXT FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:02:00");
+
+            let val = client.select(
+                "SELECT duration_in(state_agg(ts, state), 1, '2020-01-01 00:00:30+00', '2 minutes')::TEXT FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "00:00:30");
+
+            let val = client.select(
+                "SELECT state_at(state_agg(ts, state), '2020-01-01 00:02:00+00') FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val, Some(2));
+        });
+    }
+}
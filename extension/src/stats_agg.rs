@@ -7,19 +7,24 @@ use pgx::*;
 use flat_serialize::*;
 
 use crate::{
-    aggregate_utils::in_aggregate_context,
+    aggregate_utils::{in_aggregate_context, null_policy, numeric_to_f64, NullPolicy},
     ron_inout_funcs,
     build,
     palloc::Internal,
     pg_type,
+    time_series::{SeriesType, TimeSeries},
 };
 
 use stats_agg::XYPair;
+use time_series::TSPoint;
 pub use stats_agg::stats1d::StatsSummary1D as InternalStatsSummary1D;
 pub use stats_agg::stats2d::StatsSummary2D as InternalStatsSummary2D;
 
 use self::Method::*;
 
+#[allow(non_camel_case_types)]
+type numeric = pg_sys::Datum;
+
 #[allow(non_camel_case_types)]
 type bytea = pg_sys::Datum;
 
@@ -66,7 +71,7 @@ mod toolkit_experimental {
 }
 
 impl<'input> StatsSummary1D<'input> {
-    fn to_internal(&self) -> InternalStatsSummary1D {
+    pub(crate) fn to_internal(&self) -> InternalStatsSummary1D {
         InternalStatsSummary1D{
             n: self.n,
             sx: self.sx,
@@ -274,6 +279,84 @@ pub fn stats2d_inv_trans<'s>(
 }
 
 
+// stats_agg of REAL and NUMERIC values, see numeric_to_f64()
+#[pg_extern(schema = "toolkit_experimental",immutable, parallel_safe)]
+pub fn stats1d_real_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    stats1d_trans(state, val.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable, parallel_safe)]
+pub fn stats2d_real_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<f32>,
+    x: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    stats2d_trans(state, y.map(|v| v as f64), x.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable)]
+pub fn stats1d_real_inv_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    stats1d_inv_trans(state, val.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable)]
+pub fn stats2d_real_inv_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<f32>,
+    x: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    stats2d_inv_trans(state, y.map(|v| v as f64), x.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable, parallel_safe)]
+pub fn stats1d_numeric_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    stats1d_trans(state, val.map(numeric_to_f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable, parallel_safe)]
+pub fn stats2d_numeric_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<numeric>,
+    x: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    stats2d_trans(state, y.map(numeric_to_f64), x.map(numeric_to_f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable)]
+pub fn stats1d_numeric_inv_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    stats1d_inv_trans(state, val.map(numeric_to_f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental",immutable)]
+pub fn stats2d_numeric_inv_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<numeric>,
+    x: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    stats2d_inv_trans(state, y.map(numeric_to_f64), x.map(numeric_to_f64), fcinfo)
+}
+
+
 #[pg_extern(schema = "toolkit_experimental",immutable, parallel_safe)]
 pub fn stats1d_summary_trans<'s, 'v>(
     state: Option<Internal<StatsSummary1D<'s>>>,
@@ -525,6 +608,258 @@ CREATE AGGREGATE toolkit_experimental.stats_agg_no_inv( y DOUBLE PRECISION, x DO
 );
 "#);
 
+// stats_agg of REAL and NUMERIC values
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.stats_agg( value REAL )
+(
+    sfunc = toolkit_experimental.stats1d_real_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats1d_final,
+    combinefunc = toolkit_experimental.stats1d_combine,
+    serialfunc = toolkit_experimental.stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.stats1d_real_trans,
+    minvfunc = toolkit_experimental.stats1d_real_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats1d_final,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.stats_agg( y REAL, x REAL )
+(
+    sfunc = toolkit_experimental.stats2d_real_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats2d_final,
+    combinefunc = toolkit_experimental.stats2d_combine,
+    serialfunc = toolkit_experimental.stats2d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats2d_trans_deserialize,
+    msfunc = toolkit_experimental.stats2d_real_trans,
+    minvfunc = toolkit_experimental.stats2d_real_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats2d_final,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.stats_agg( value NUMERIC )
+(
+    sfunc = toolkit_experimental.stats1d_numeric_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats1d_final,
+    combinefunc = toolkit_experimental.stats1d_combine,
+    serialfunc = toolkit_experimental.stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.stats1d_numeric_trans,
+    minvfunc = toolkit_experimental.stats1d_numeric_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats1d_final,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.stats_agg( y NUMERIC, x NUMERIC )
+(
+    sfunc = toolkit_experimental.stats2d_numeric_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats2d_final,
+    combinefunc = toolkit_experimental.stats2d_combine,
+    serialfunc = toolkit_experimental.stats2d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats2d_trans_deserialize,
+    msfunc = toolkit_experimental.stats2d_numeric_trans,
+    minvfunc = toolkit_experimental.stats2d_numeric_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats2d_final,
+    parallel = safe
+);
+"#);
+
+// The stats_agg variants taking a `null_policy`, one of 'ignore', 'error' or
+// 'poison' (see aggregate_utils.rs). The summaries have nowhere to record that
+// a NULL poisoned the group, so these aggregates keep that next to them.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StatsPolicyState<S> {
+    summary: S,
+    poisoned: bool,
+}
+
+type StatsPolicyState1D = StatsPolicyState<InternalStatsSummary1D>;
+type StatsPolicyState2D = StatsPolicyState<InternalStatsSummary2D>;
+
+// Ok(None) to skip the row and Err(()) to poison the group
+fn apply_null_policy<T>(val: Option<T>, policy: NullPolicy) -> Result<Option<T>, ()> {
+    match (val, policy) {
+        (Some(val), _) => Ok(Some(val)),
+        (None, NullPolicy::Ignore) => Ok(None),
+        (None, NullPolicy::Error) => error!("stats_agg input contains NULLs, which null_policy 'error' disallows"),
+        (None, NullPolicy::Poison) => Err(()),
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn stats1d_null_policy_trans(
+    state: Option<Internal<StatsPolicyState1D>>,
+    val: Option<f64>,
+    policy: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsPolicyState1D>> {
+    let policy = null_policy(policy.as_deref());
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state.unwrap_or_else(||
+                StatsPolicyState{summary: InternalStatsSummary1D::new(), poisoned: false}.into());
+            if state.poisoned {
+                return Some(state)
+            }
+            match apply_null_policy(val, policy) {
+                Ok(Some(val)) => state.summary.accum(val).unwrap(),
+                Ok(None) => (),
+                Err(()) => state.poisoned = true,
+            }
+            Some(state)
+        })
+    }
+}
+
+// as with stats2d_trans a point is NULL if either value is
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn stats2d_null_policy_trans(
+    state: Option<Internal<StatsPolicyState2D>>,
+    y: Option<f64>,
+    x: Option<f64>,
+    policy: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsPolicyState2D>> {
+    let policy = null_policy(policy.as_deref());
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state.unwrap_or_else(||
+                StatsPolicyState{summary: InternalStatsSummary2D::new(), poisoned: false}.into());
+            if state.poisoned {
+                return Some(state)
+            }
+            let val = y.and_then(|y| x.map(|x| XYPair{y, x}));
+            match apply_null_policy(val, policy) {
+                Ok(Some(val)) => state.summary.accum(val).unwrap(),
+                Ok(None) => (),
+                Err(()) => state.poisoned = true,
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn stats1d_null_policy_combine(
+    state1: Option<Internal<StatsPolicyState1D>>,
+    state2: Option<Internal<StatsPolicyState1D>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsPolicyState1D>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state)) | (Some(state), None) => Some(state.clone().into()),
+                (Some(state1), Some(state2)) => Some(StatsPolicyState{
+                    summary: state1.summary.combine(state2.summary).unwrap(),
+                    poisoned: state1.poisoned || state2.poisoned,
+                }.into()),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn stats2d_null_policy_combine(
+    state1: Option<Internal<StatsPolicyState2D>>,
+    state2: Option<Internal<StatsPolicyState2D>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsPolicyState2D>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state)) | (Some(state), None) => Some(state.clone().into()),
+                (Some(state1), Some(state2)) => Some(StatsPolicyState{
+                    summary: state1.summary.combine(state2.summary).unwrap(),
+                    poisoned: state1.poisoned || state2.poisoned,
+                }.into()),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn stats1d_null_policy_serialize(
+    state: Internal<StatsPolicyState1D>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn stats1d_null_policy_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StatsPolicyState1D> {
+    crate::do_deserialize!(bytes, StatsPolicyState1D)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn stats2d_null_policy_serialize(
+    state: Internal<StatsPolicyState2D>,
+) -> bytea {
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn stats2d_null_policy_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StatsPolicyState2D> {
+    crate::do_deserialize!(bytes, StatsPolicyState2D)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn stats1d_null_policy_final(
+    state: Option<Internal<StatsPolicyState1D>>,
+) -> Option<toolkit_experimental::StatsSummary1D<'static>> {
+    match state {
+        Some(state) if !state.poisoned => Some(StatsSummary1D::from_internal(state.summary)),
+        _ => None,
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn stats2d_null_policy_final(
+    state: Option<Internal<StatsPolicyState2D>>,
+) -> Option<toolkit_experimental::StatsSummary2D<'static>> {
+    match state {
+        Some(state) if !state.poisoned => Some(StatsSummary2D::from_internal(state.summary)),
+        _ => None,
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.stats_agg( value DOUBLE PRECISION, null_policy text )
+(
+    sfunc = toolkit_experimental.stats1d_null_policy_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats1d_null_policy_final,
+    combinefunc = toolkit_experimental.stats1d_null_policy_combine,
+    serialfunc = toolkit_experimental.stats1d_null_policy_serialize,
+    deserialfunc = toolkit_experimental.stats1d_null_policy_deserialize,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.stats_agg( y DOUBLE PRECISION, x DOUBLE PRECISION, null_policy text )
+(
+    sfunc = toolkit_experimental.stats2d_null_policy_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats2d_null_policy_final,
+    combinefunc = toolkit_experimental.stats2d_null_policy_combine,
+    serialfunc = toolkit_experimental.stats2d_null_policy_serialize,
+    deserialfunc = toolkit_experimental.stats2d_null_policy_deserialize,
+    parallel = safe
+);
+"#);
+
 //  Currently, rollup does not have the inverse function so if you want the behavior where we don't use the inverse,
 // you can use it in your window functions (useful for our own perf testing as well)
 
@@ -730,6 +1065,43 @@ fn stats1d_num_vals(
     summary.to_internal().count()
 }
 
+// Scores each point of the series by how many standard deviations it lies
+// from the mean of the baseline, positive above the mean and negative below.
+// NULL if the baseline's standard deviation is undefined or 0.
+#[pg_extern(name="outlier_score", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn stats1d_outlier_score(
+    baseline: toolkit_experimental::StatsSummary1D,
+    series: crate::time_series::toolkit_experimental::TimeSeries,
+    method: default!(&str, "sample"),
+)-> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
+    let baseline = baseline.to_internal();
+    let mean = baseline.avg()?;
+    let stddev = match method_kind(method) {
+        Population => baseline.stddev_pop()?,
+        Sample => baseline.stddev_samp()?,
+    };
+    if !(stddev > 0.0) {
+        return None
+    }
+
+    let mut points: Vec<TSPoint> = series.iter()
+        .map(|TSPoint{ ts, val }| TSPoint{ ts, val: (val - mean) / stddev })
+        .collect();
+    if !series.is_sorted() {
+        points.sort_by_key(|point| point.ts);
+    }
+    Some(build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: points.len() as u64,
+                points: points.into(),
+            }
+        }
+    ))
+}
+
 
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
@@ -1097,6 +1469,36 @@ fn stats2d_covar(
     }
 }
 
+// seconds between the unix epoch and the postgres epoch (2000-01-01)
+const POSTGRES_EPOCH_IN_UNIX_SECONDS: f64 = 946_684_800.0;
+
+// Extrapolates the least squares fit line to `at`, for summaries built with
+// `stats_agg(value, to_epoch(time))`. Along with the prediction returns the
+// bounds of the interval around it at the given confidence: a 'prediction'
+// interval for a single new value at `at`, or a 'confidence' interval for the
+// fit line. Returns no rows when there are fewer than 3 points or the x
+// values are all equal.
+#[pg_extern(name="predict", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn stats2d_predict(
+    summary: toolkit_experimental::StatsSummary2D,
+    at: pg_sys::TimestampTz,
+    confidence: default!(f64, 0.95),
+    interval: default!(&str, "prediction"),
+) -> impl std::iter::Iterator<Item = (name!(prediction,f64),name!(lower_bound,f64),name!(upper_bound,f64))> {
+    if !(confidence > 0.0 && confidence < 1.0) {
+        error!("predict confidence must be between 0 and 1")
+    }
+    let new_observation = match interval.trim().to_lowercase().as_str() {
+        "prediction" => true,
+        "confidence" => false,
+        _ => error!("unknown interval type. Valid types are 'prediction' and 'confidence'"),
+    };
+    let x = at as f64 / 1_000_000.0 + POSTGRES_EPOCH_IN_UNIX_SECONDS;
+    summary.to_internal()
+        .predict_interval(x, confidence, new_observation)
+        .into_iter()
+}
+
 #[derive(Clone, Copy)]
 pub enum Method {
     Population,
@@ -1119,6 +1521,125 @@ pub fn as_method(method: &str) -> Option<Method> {
     }
 }
 
+// The StatsSummary1D as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_to_msgpack(
+    summary: toolkit_experimental::StatsSummary1D<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*summary)
+}
+
+// inverse of to_msgpack(StatsSummary1D)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_from_msgpack(
+    bytes: &[u8],
+) -> toolkit_experimental::StatsSummary1D<'static> {
+    unsafe { StatsSummary1D(crate::msgpack::decode("StatsSummary1D", bytes, 1), None).flatten() }
+}
+
+// The StatsSummary1D as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_to_jsonb(
+    summary: toolkit_experimental::StatsSummary1D<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*summary)
+}
+
+// inverse of to_jsonb(StatsSummary1D), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_from_jsonb(
+    json: JsonB,
+) -> toolkit_experimental::StatsSummary1D<'static> {
+    unsafe { StatsSummary1D(crate::jsonb::decode("StatsSummary1D", &json.0, 1), None).flatten() }
+}
+
+// The size of the StatsSummary1D as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_size(
+    summary: toolkit_experimental::StatsSummary1D<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, summary.0.num_bytes(), None)
+}
+
+// Checks the StatsSummary1D's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_validate(
+    summary: toolkit_experimental::StatsSummary1D<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    if summary.sx2 < 0.0 || summary.sx4 < 0.0 {
+        diagnostics.error("sums of even powers of the deviation are negative".to_string())
+    }
+    if summary.n == 0 && (summary.sx != 0.0 || summary.sx2 != 0.0 || summary.sx3 != 0.0 || summary.sx4 != 0.0) {
+        diagnostics.error("the summary has no values but non-zero sums".to_string())
+    }
+    if !summary.sx.is_finite() {
+        diagnostics.warning("the sum of values is not finite".to_string())
+    }
+    diagnostics.into_rows()
+}
+
+// The StatsSummary2D as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_to_msgpack(
+    summary: toolkit_experimental::StatsSummary2D<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*summary)
+}
+
+// inverse of to_msgpack(StatsSummary2D)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_from_msgpack(
+    bytes: &[u8],
+) -> toolkit_experimental::StatsSummary2D<'static> {
+    unsafe { StatsSummary2D(crate::msgpack::decode("StatsSummary2D", bytes, 1), None).flatten() }
+}
+
+// The StatsSummary2D as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_to_jsonb(
+    summary: toolkit_experimental::StatsSummary2D<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*summary)
+}
+
+// inverse of to_jsonb(StatsSummary2D), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_from_jsonb(
+    json: JsonB,
+) -> toolkit_experimental::StatsSummary2D<'static> {
+    unsafe { StatsSummary2D(crate::jsonb::decode("StatsSummary2D", &json.0, 1), None).flatten() }
+}
+
+// The size of the StatsSummary2D as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_size(
+    summary: toolkit_experimental::StatsSummary2D<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, summary.0.num_bytes(), None)
+}
+
+// Checks the StatsSummary2D's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats2d_validate(
+    summary: toolkit_experimental::StatsSummary2D<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    if summary.sx2 < 0.0 || summary.sx4 < 0.0 || summary.sy2 < 0.0 || summary.sy4 < 0.0 {
+        diagnostics.error("sums of even powers of the deviation are negative".to_string())
+    }
+    let sums = [summary.sx, summary.sx2, summary.sx3, summary.sx4, summary.sy, summary.sy2, summary.sy3, summary.sy4, summary.sxy];
+    if summary.n == 0 && sums.iter().any(|&sum| sum != 0.0) {
+        diagnostics.error("the summary has no values but non-zero sums".to_string())
+    }
+    if !summary.sx.is_finite() || !summary.sy.is_finite() {
+        diagnostics.warning("the sum of values is not finite".to_string())
+    }
+    diagnostics.into_rows()
+}
+
 // TODO: Add testing - probably want to do some fuzz testing against the Postgres implementations of the same. Possibly translate the Postgres tests as well?
 // #[cfg(any(test, feature = "pg_test"))]
 // mod tests {
@@ -1274,6 +1795,209 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_stats_agg_null_policy() {
+        Spi::execute(|client| {
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select("CREATE TABLE test(y DOUBLE PRECISION, x DOUBLE PRECISION)", None, None);
+            client.select("INSERT INTO test VALUES (1, 1), (2, NULL), (NULL, 3), (4, 4)", None, None);
+
+            let (n1, n2) = client.select(
+                "SELECT num_vals(stats_agg(y, 'ignore')), num_vals(stats_agg(y, x, 'ignore')) FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i64, i64>();
+            assert_eq!(n1, Some(3));
+            assert_eq!(n2, Some(2));
+
+            let (p1, p2) = client.select(
+                "SELECT stats_agg(y, 'poison') IS NULL, stats_agg(y, x, 'poison') IS NULL FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(p1, Some(true));
+            assert_eq!(p2, Some(true));
+
+            let poisoned = client.select(
+                "SELECT stats_agg(y, 'poison') IS NULL FROM test WHERE y IS NOT NULL",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(poisoned, Some(false));
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_agg_real_and_numeric() {
+        Spi::execute(|client| {
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE test AS SELECT i::REAL AS r, i::NUMERIC AS n, i::DOUBLE PRECISION AS d \
+                    FROM generate_series(1, 10) i",
+                None,
+                None
+            );
+
+            let (real, numeric) = client.select(
+                "SELECT sum(stats_agg(r)) = sum(stats_agg(d)) AND variance(stats_agg(r)) = variance(stats_agg(d)), \
+                    sum(stats_agg(n)) = sum(stats_agg(d)) AND variance(stats_agg(n)) = variance(stats_agg(d)) \
+                    FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(real, Some(true));
+            assert_eq!(numeric, Some(true));
+
+            let (real, numeric) = client.select(
+                "SELECT slope(stats_agg(r, r * 2)), slope(stats_agg(n, n * 2)) FROM test",
+                None,
+                None
+            )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(real, Some(0.5));
+            assert_eq!(numeric, Some(0.5));
+
+            // the moving aggregate versions
+            let sums: Vec<f64> = client.select(
+                "SELECT sum(stats_agg(r) OVER (ORDER BY r ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)) FROM test ORDER BY r",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(sums, vec![1.0, 3.0, 5.0, 7.0, 9.0, 11.0, 13.0, 15.0, 17.0, 19.0]);
+        });
+    }
+
+    #[pg_test(error = "stats_agg input contains NULLs, which null_policy 'error' disallows")]
+    fn test_stats_agg_null_policy_error() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.stats_agg(v, 'error') FROM (VALUES (1.0::DOUBLE PRECISION), (NULL)) v(v)",
+                None,
+                None
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_outlier_score() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // a baseline with mean 5 and population standard deviation 2
+            client.select(
+                "CREATE TABLE baseline AS SELECT stats_agg(v) AS s FROM \
+                    (VALUES (3.0::DOUBLE PRECISION), (3), (7), (7)) v(v)",
+                None,
+                None
+            );
+            let scores = client.select(
+                "SELECT outlier_score((SELECT s FROM baseline), timeseries(time, value), 'population')::TEXT FROM \
+                    (VALUES ('2020-01-02 UTC'::TIMESTAMPTZ, 9.0::DOUBLE PRECISION), \
+                        ('2020-01-01 UTC', 5), \
+                        ('2020-01-03 UTC', 3)) v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(scores.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:-1)\
+            ]");
+
+            let constant = client.select(
+                "SELECT outlier_score(stats_agg(1.0), timeseries('2020-01-01 UTC'::TIMESTAMPTZ, 1.0))::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(constant, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_predict() {
+        Spi::execute(|client| {
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // disk usage growing by 2 per day from 1, with residuals -1, 1, 1, -1
+            client.select(
+                "CREATE TABLE disk AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        (ARRAY[2, 6, 8, 8])[i]::DOUBLE PRECISION AS used \
+                    FROM generate_series(1, 4) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE summary AS \
+                    SELECT stats_agg(used, to_epoch(time) / 86400 - to_epoch('2020-01-01 UTC') / 86400) AS s FROM disk",
+                None,
+                None
+            );
+
+            // x is measured in days here, so predict at day 5 by passing its
+            // value as seconds since the unix epoch
+            let bounds = |interval: &str| {
+                client.select(
+                    &format!("SELECT prediction, lower_bound, upper_bound \
+                        FROM summary, predict(s, to_timestamp(5), interval => '{}')", interval),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_three::<f64, f64, f64>()
+            };
+
+            // t quantile for 2 degrees of freedom at 0.975
+            let t = 4.302652729911275;
+            let (prediction, lower, upper) = bounds("confidence");
+            let width = t * 2f64.sqrt() * (0.25f64 + 6.25 / 5.0).sqrt();
+            assert!((prediction.unwrap() - 11.0).abs() < 1e-9);
+            assert!((lower.unwrap() - (11.0 - width)).abs() < 1e-9);
+            assert!((upper.unwrap() - (11.0 + width)).abs() < 1e-9);
+
+            let (prediction, lower, upper) = bounds("prediction");
+            let width = t * 2f64.sqrt() * (1.25f64 + 6.25 / 5.0).sqrt();
+            assert!((prediction.unwrap() - 11.0).abs() < 1e-9);
+            assert!((lower.unwrap() - (11.0 - width)).abs() < 1e-9);
+            assert!((upper.unwrap() - (11.0 + width)).abs() < 1e-9);
+
+            let rows = client.select(
+                "SELECT count(*) FROM \
+                    (SELECT stats_agg(used, to_epoch(time)) AS s FROM disk WHERE used < 8) two_points, \
+                    predict(s, now())",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(rows, Some(0));
+        });
+    }
+
     #[pg_test]
     fn stats_agg_fuzz() {
         let mut state = TestState::new(RUNS, VALS, SEED);
@@ -1494,4 +2218,34 @@ mod tests {
             );
         });
     }
+
+    // A StatsSummary1D transition state of the values 1 and 2 as serialized by
+    // the 1.x releases, before states were versioned and compressed; these
+    // must still be readable when restored into a newer extension.
+    #[pg_test]
+    fn test_stats1d_trans_deserialize_golden_bytes() {
+        let bytes = vec![
+            1, 1, // type version, SerializationType::Default
+            1, // StatsSummary1DData version
+            2, 0, 0, 0, 0, 0, 0, 0, // n
+            0, 0, 0, 0, 0, 0, 0x08, 0x40, // sx = 3.0
+            0, 0, 0, 0, 0, 0, 0xe0, 0x3f, // sx2 = 0.5
+            0, 0, 0, 0, 0, 0, 0, 0, // sx3 = 0.0
+            0, 0, 0, 0, 0, 0, 0xc0, 0x3f, // sx4 = 0.125
+        ];
+        let state = super::stats1d_trans_deserialize(bytes.clone().into_datum().unwrap(), None);
+        assert_eq!(state.n, 2);
+        assert_eq!(state.sx, 3.0);
+        assert_eq!(state.sx2, 0.5);
+        assert_eq!(state.sx3, 0.0);
+        assert_eq!(state.sx4, 0.125);
+
+        // and serializing it again writes the same bytes
+        let reserialized = super::stats1d_trans_serialize(state);
+        let reserialized = unsafe {
+            let len = varsize_any_exhdr(reserialized as *const _);
+            std::slice::from_raw_parts(vardata_any(reserialized as *const _) as *const u8, len)
+        };
+        assert_eq!(reserialized, &bytes[..]);
+    }
 }
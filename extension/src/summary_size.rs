@@ -0,0 +1,77 @@
+use pgx::*;
+
+// The row returned by every toolkit_summary_size(): the bytes the summary
+// argument takes where it came from, which is compressed and/or out of line
+// if it was read from a table (the same as `pg_column_size()`), the bytes it
+// takes once expanded in memory, and for the variable-sized summaries the
+// number of buckets, centroids, or registers that make up most of that.
+pub(crate) fn summary_size(
+    fcinfo: pg_sys::FunctionCallInfo,
+    in_memory_bytes: usize,
+    entries: Option<usize>,
+) -> std::iter::Once<(i64, i64, Option<i64>)> {
+    let on_disk_bytes = unsafe {
+        pg_sys::toast_datum_size(pg_getarg_datum_raw(fcinfo, 0))
+    };
+    std::iter::once((
+        on_disk_bytes as i64,
+        in_memory_bytes as i64,
+        entries.map(|entries| entries as i64),
+    ))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_summary_size() {
+        Spi::execute(|client| {
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE sketches AS SELECT \
+                    tdigest(20, v) AS tdigest, \
+                    uddsketch(50, 0.01, v) AS uddsketch, \
+                    hyperloglog(64, v) AS hyperloglog, \
+                    stats_agg(v) AS stats \
+                FROM generate_series(1, 10000) i, LATERAL (SELECT i::DOUBLE PRECISION AS v) v",
+                None,
+                None
+            );
+
+            let (on_disk, in_memory, entries) = client
+                .select("SELECT on_disk_bytes, in_memory_bytes, entries FROM sketches, toolkit_summary_size(tdigest)", None, None)
+                .first()
+                .get_three::<i64, i64, i64>();
+            assert!(entries.unwrap() > 0 && entries.unwrap() <= 20);
+            assert!(on_disk.unwrap() <= in_memory.unwrap());
+
+            let entries = client
+                .select("SELECT entries FROM sketches, toolkit_summary_size(uddsketch)", None, None)
+                .first()
+                .get_one::<i64>();
+            assert!(entries.unwrap() > 0 && entries.unwrap() <= 50);
+
+            let entries = client
+                .select("SELECT entries FROM sketches, toolkit_summary_size(hyperloglog)", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(entries, Some(64));
+
+            let (on_disk, entries) = client
+                .select("SELECT on_disk_bytes, entries FROM sketches, toolkit_summary_size(stats)", None, None)
+                .first()
+                .get_two::<i64, i64>();
+            let expected = client
+                .select("SELECT pg_column_size(stats)::BIGINT FROM sketches", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(on_disk, expected);
+            assert_eq!(entries, None);
+        });
+    }
+}
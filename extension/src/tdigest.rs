@@ -93,7 +93,7 @@ pub fn tdigest_combine(
             match (state1, state2) {
                 (None, None) => None,
                 (None, Some(state2)) => Some(state2.clone().into()),
-                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), None) => Some(state1),
                 (Some(state1), Some(state2)) => {
                     assert_eq!(state1.digested.max_size(), state2.digested.max_size());
                     let digvec = vec![state1.digested.clone(), state2.digested.clone()];
@@ -252,7 +252,7 @@ pub fn tdigest_compound_combine(
             match (state1, state2) {
                 (None, None) => None,
                 (None, Some(state2)) => Some(state2.clone().into()),
-                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), None) => Some(state1),
                 (Some(state1), Some(state2)) => {
                     assert_eq!(state1.max_size(), state2.max_size());
                     Some(InternalTDigest::merge_digests(
@@ -305,6 +305,28 @@ CREATE AGGREGATE rollup(
 );
 "#);
 
+// `a + b` merges two digests the same way rollup() does
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn tdigest_add(
+    a: TDigest,
+    b: TDigest,
+) -> TDigest<'static> {
+    if a.max_buckets != b.max_buckets {
+        error!("cannot add TDigests with different numbers of buckets")
+    }
+    let merged = InternalTDigest::merge_digests(vec![a.to_internal_tdigest(), b.to_internal_tdigest()]);
+    TDigest::from_internal_tdigest(&merged)
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+extension_sql!(r#"
+CREATE OPERATOR toolkit_experimental.+ (
+    PROCEDURE=toolkit_experimental.tdigest_add,
+    LEFTARG=TDigest,
+    RIGHTARG=TDigest
+);
+"#);
+
 //---- Available PG operations on the digest
 
 
@@ -422,6 +444,143 @@ pub fn tdigest_mean(
     }
 }
 
+// The TDigest as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_to_msgpack(
+    digest: TDigest<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*digest)
+}
+
+// inverse of to_msgpack(TDigest)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_msgpack(
+    bytes: &[u8],
+) -> TDigest<'static> {
+    unsafe { TDigest(crate::msgpack::decode("TDigest", bytes, 1), None).flatten() }
+}
+
+// The TDigest as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_to_jsonb(
+    digest: TDigest<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*digest)
+}
+
+// inverse of to_jsonb(TDigest), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_jsonb(
+    json: JsonB,
+) -> TDigest<'static> {
+    unsafe { TDigest(crate::jsonb::decode("TDigest", &json.0, 1), None).flatten() }
+}
+
+// The size of the TDigest as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_size(
+    digest: TDigest<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, digest.0.num_bytes(), Some(digest.buckets as usize))
+}
+
+// Checks the TDigest's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_validate(
+    digest: TDigest<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    if digest.buckets > digest.max_buckets {
+        diagnostics.error(format!("the digest has {} centroids but at most {} are allowed", digest.buckets, digest.max_buckets))
+    }
+    let weights: u64 = digest.centroids.iter().map(|centroid| centroid.weight()).sum();
+    if weights != digest.count {
+        diagnostics.error(format!("centroid weights sum to {} but the count is {}", weights, digest.count))
+    }
+    let means: Vec<f64> = digest.centroids.iter().map(|centroid| centroid.mean()).collect();
+    if means.windows(2).any(|pair| !(pair[0] <= pair[1])) {
+        diagnostics.error("centroids are not ordered by mean".to_string())
+    }
+    if digest.count > 0 {
+        if !(digest.0.min <= digest.0.max) {
+            diagnostics.error(format!("the minimum {} is greater than the maximum {}", digest.0.min, digest.0.max))
+        } else if means.iter().any(|&mean| mean < digest.0.min || mean > digest.0.max) {
+            diagnostics.error("centroid means fall outside the minimum and maximum".to_string())
+        }
+    }
+    diagnostics.into_rows()
+}
+
+// Parses the TDigestState Elasticsearch writes for its percentiles
+// aggregations: the compression as a big-endian double, the number of
+// centroids as a variable length int, then each centroid's mean as a
+// big-endian double and count as a variable length long.
+fn parse_elasticsearch_tdigest(mut bytes: &[u8]) -> Result<Vec<Centroid>, &'static str> {
+    fn double(bytes: &mut &[u8]) -> Result<f64, &'static str> {
+        if bytes.len() < 8 {
+            return Err("truncated double")
+        }
+        let (double, rest) = bytes.split_at(8);
+        *bytes = rest;
+        Ok(f64::from_be_bytes(double.try_into().unwrap()))
+    }
+    // 7 bits at a time, least significant first
+    fn varint(bytes: &mut &[u8]) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().ok_or("truncated variable length int")?;
+            *bytes = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        Err("variable length int too long")
+    }
+
+    let _compression = double(&mut bytes)?;
+    let num_centroids = varint(&mut bytes)?;
+    let mut centroids = vec![];
+    for _ in 0..num_centroids {
+        let mean = double(&mut bytes)?;
+        let count = varint(&mut bytes)?;
+        centroids.push(Centroid::new(mean, count));
+    }
+    if !bytes.is_empty() {
+        return Err("trailing bytes")
+    }
+    Ok(centroids)
+}
+
+// Imports the serialized TDigestState of an Elasticsearch percentiles
+// aggregation so it can be rolled up with tdigests built here. `size` is the
+// number of buckets of the tdigests it will be combined with, the digest is
+// compressed to fit it. Elasticsearch doesn't record the minimum, maximum, or
+// sum of the values, these are estimated from the centroids.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_elasticsearch(
+    bytes: &[u8],
+    size: i32,
+) -> TDigest<'static> {
+    if size < 1 {
+        error!("tdigest size must be positive")
+    }
+    let mut centroids = parse_elasticsearch_tdigest(bytes)
+        .unwrap_or_else(|e| error!("invalid Elasticsearch tdigest: {}", e));
+    centroids.retain(|centroid| centroid.weight() > 0);
+    centroids.sort();
+
+    let count = centroids.iter().map(|centroid| centroid.weight()).sum();
+    let sum = centroids.iter().map(|centroid| centroid.mean() * centroid.weight() as f64).sum();
+    let (min, max) = match (centroids.first(), centroids.last()) {
+        (Some(first), Some(last)) => (first.mean(), last.mean()),
+        _ => (f64::NAN, f64::NAN),
+    };
+    let digest = InternalTDigest::new(centroids, sum, count, max, min, size as usize);
+    TDigest::from_internal_tdigest(&digest)
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -629,4 +788,62 @@ mod tests {
             apx_eql(test_value.unwrap(), 9.0, 0.1);
         });
     }
+
+    #[pg_test]
+    fn test_tdigest_add() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select("CREATE TABLE digests AS \
+                SELECT v > 50 AS upper, tdigest(20, v) \
+                FROM generate_series(1, 100) v \
+                GROUP BY 1", None, None);
+
+            let (sum, rollup) = client
+                .select("SELECT \
+                    (SELECT a.tdigest OPERATOR(toolkit_experimental.+) b.tdigest FROM digests a, digests b WHERE NOT a.upper AND b.upper)::TEXT, \
+                    (SELECT rollup(tdigest) FROM (SELECT tdigest FROM digests ORDER BY upper) d)::TEXT", None, None)
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(sum, rollup);
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_from_elasticsearch() {
+        Spi::execute(|client| {
+            // compression 100 with centroids of 1 at 1.0, 2 at 2.0, and 1 at 3.0
+            client.select("CREATE VIEW es AS \
+                SELECT toolkit_experimental.tdigest_from_elasticsearch('\\x4059000000000000\
+                    03\
+                    3ff0000000000000 01\
+                    4000000000000000 02\
+                    4008000000000000 01', 100) AS digest", None, None);
+
+            let (count, mean) = client
+                .select("SELECT num_vals(digest), mean(digest) FROM es", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(count.unwrap(), 4.0, 0.000001);
+            apx_eql(mean.unwrap(), 2.0, 0.000001);
+
+            let (min, max) = client
+                .select("SELECT min_val(digest), max_val(digest) FROM es", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(min.unwrap(), 1.0, 0.000001);
+            apx_eql(max.unwrap(), 3.0, 0.000001);
+
+            // merged with a digest of the same values built here
+            let (count, median) = client
+                .select("SELECT num_vals(rollup(digest)), approx_percentile(0.5, rollup(digest)) FROM ( \
+                    SELECT digest FROM es \
+                    UNION ALL \
+                    SELECT tdigest(100, v) FROM unnest(ARRAY[1.0, 2.0, 2.0, 3.0]) v \
+                ) digests", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(count.unwrap(), 8.0, 0.000001);
+            apx_eql(median.unwrap(), 2.0, 0.1);
+        });
+    }
 }
@@ -1,10 +1,12 @@
 
-use std::{slice};
+use std::{collections::BTreeMap, slice};
 
 use pgx::*;
 
 use crate::{
-    aggregate_utils::in_aggregate_context, pg_type, build, flatten, palloc::Internal,
+    aggregate_utils::{in_aggregate_context, timestamp_to_timestamptz, epoch_micros_to_timestamptz},
+    pg_type, build, flatten, palloc::Internal,
+    serialization::_ts_toolkit_decode_timestamptz,
 };
 
 use time_series::{
@@ -15,15 +17,36 @@ pub use iter::Iter;
 
 use flat_serialize::*;
 
+use encodings::gorilla;
+
 mod pipeline;
 mod iter;
+mod json;
+mod text;
+mod accessors;
+mod compression;
+mod labels;
+mod set;
+pub(crate) mod drawdown;
+mod rolling_corr;
+mod holt_winters;
+mod autocorrelation;
+mod matrix_profile;
+mod sax;
+mod trend_test;
+mod cusum;
+mod cross_correlation;
 
 #[allow(non_camel_case_types)]
 type bytea = pg_sys::Datum;
 
+#[allow(non_camel_case_types)]
+type timestamp = pg_sys::Datum;
+
 pg_type! {
     #[derive(Debug)]
     struct TimeSeries<'input> {
+        num_label_bytes: u64,
         series: enum SeriesType<'input> {
             type_id: u64,
             SortedSeries: 1 {
@@ -49,7 +72,27 @@ pg_type! {
                 values: [f64; self.num_vals],
                 present: [u64; (self.count + 63) / 64]
             },
+            // NullableSeries can contain points whose value is NULL, these are
+            // marked in the `nulls` bitmap and their `val` is meaningless
+            NullableSeries: 5 {
+                num_points: u64,  // required to be aligned
+                sorted: u64,  // padded bool
+                points: [TSPoint; self.num_points],
+                nulls: [u64; (self.num_points + 63) / 64]
+            },
+            // CompressedSeries stores its points using the encoding selected
+            // by `encoding_version`, currently only 1, encodings::gorilla.
+            // The points can only be read sequentially.
+            CompressedSeries: 6 {
+                num_points: u64,  // required to be aligned
+                sorted: u64,  // padded bool
+                encoding_version: u64,
+                num_bytes: u64,
+                bytes: [u8; self.num_bytes]
+            },
         },
+        // optional key/value pairs identifying the series, see labels.rs
+        label_bytes: [u8; self.num_label_bytes],
     }
 }
 
@@ -59,10 +102,17 @@ impl<'input> InOutFuncs for TimeSeries<'input> {
 
         // TODO remove extra allocation
         // FIXME print timestamps as times, not integers
-        let serializer: Vec<_> = self.iter().collect();
-
-        // Extra & in the to_string call due to ron not supporting ?Sized, shouldn't affect output
-        let stringified = ron::to_string(&&*serializer).unwrap();
+        let labels: BTreeMap<&str, &str> = self.labels().collect();
+        let stringified = if self.has_nulls() {
+            // written the same as other points, with `None` for the NULLs
+            let serializer: Vec<_> = self.iter_with_nulls()
+                .map(|(ts, val)| NullablePoint{ ts: json::encode_timestamptz(ts), val })
+                .collect();
+            write_text(labels, &serializer)
+        } else {
+            let serializer: Vec<_> = self.iter().collect();
+            write_text(labels, &*serializer)
+        };
         match str_to_db_encoding(&stringified) {
             Utf8(s) => buffer.push_str(s),
             Other(s) => buffer.push_bytes(s.to_bytes()),
@@ -79,16 +129,32 @@ impl<'input> InOutFuncs for TimeSeries<'input> {
         // the data, so the lifetimes of the borrows aren't actually
         // relevant to the output lifetime
         // TODO reduce allocation
-        let series: Vec<TSPoint> = unsafe {
+        let input = unsafe {
             unsafe fn extend_lifetime(s: &str) -> &'static str {
                 std::mem::transmute(s)
             }
-            let input = extend_lifetime(str_from_db_encoding(input));
-            ron::from_str(input).unwrap()
+            extend_lifetime(str_from_db_encoding(input))
+        };
+        let labelled = input.trim_start().starts_with('(');
+        let (labels, series) = match read_text::<TSPoint>(input, labelled) {
+            Ok(text) => text,
+            Err(err) => {
+                // series containing NULLs have `None` for those values
+                let input = format!("#![enable(implicit_some)]{}", input);
+                let (labels, points) = match read_text::<NullablePoint>(&input, labelled) {
+                    Ok(text) => text,
+                    Err(_) => panic!("{}", err),
+                };
+                let points = points.into_iter()
+                    .map(|point| (_ts_toolkit_decode_timestamptz(&point.ts), point.val));
+                return unsafe { nullable_series_from(points).with_label_bytes(labels).flatten() }
+            },
         };
         unsafe {
             flatten! {
                 TimeSeries {
+                    num_label_bytes: labels.len() as u64,
+                    label_bytes: labels.into(),
                     series: SeriesType::ExplicitSeries {
                         num_points: series.len() as u64,
                         points: series.into(),
@@ -99,10 +165,62 @@ impl<'input> InOutFuncs for TimeSeries<'input> {
     }
 }
 
+// The text of a series with labels, series without them are written as just
+// their list of points.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "TimeSeries")]
+struct LabelledText<L, P> {
+    labels: L,
+    points: P,
+}
+
+fn write_text<P: serde::Serialize>(labels: BTreeMap<&str, &str>, points: P) -> String {
+    if labels.is_empty() {
+        ron::to_string(&points).unwrap()
+    } else {
+        ron::to_string(&LabelledText{ labels, points }).unwrap()
+    }
+}
+
+// the encoded label bytes and the points of the text of a series
+fn read_text<'de, P: serde::Deserialize<'de>>(input: &'de str, labelled: bool) -> Result<(Vec<u8>, Vec<P>), ron::Error> {
+    if !labelled {
+        return Ok((vec![], ron::from_str(input)?))
+    }
+    let text: LabelledText<BTreeMap<String, String>, Vec<P>> = ron::from_str(input)?;
+    let labels = labels::encode_labels(text.labels.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+    Ok((labels, text.points))
+}
+
+// The text form of a point in a series containing NULLs, the same as a
+// TSPoint's except that NULL values are written as `None`.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "TSPoint")]
+struct NullablePoint {
+    ts: String,
+    #[serde(serialize_with = "serialize_nullable_val")]
+    val: Option<f64>,
+}
+
+fn serialize_nullable_val<S: serde::Serializer>(val: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match val {
+        Some(val) => serializer.serialize_f64(*val),
+        None => serializer.serialize_none(),
+    }
+}
+
 // hack to allow us to qualify names with "toolkit_experimental"
 // so that pgx generates the correct SQL
 pub mod toolkit_experimental {
     pub(crate) use super::*;
+    pub(crate) use crate::accessors::{
+        AccessorNumVals,
+        AccessorFirstTime,
+        AccessorFirstVal,
+        AccessorLastTime,
+        AccessorLastVal,
+        AccessorCovers,
+    };
     varlena_type!(TimeSeries);
 }
 
@@ -117,6 +235,10 @@ impl<'input> TimeSeries<'input> {
                 values.len(),
             SeriesType::GappyNormalSeries{values, ..} =>
                 values.len(),
+            SeriesType::NullableSeries{points, nulls, ..} =>
+                num_non_null(points.len(), nulls.as_slice()),
+            SeriesType::CompressedSeries{num_points, ..} =>
+                *num_points as _,
         }
     }
 
@@ -136,6 +258,11 @@ impl<'input> TimeSeries<'input> {
                 Some(TSPoint{ts: start_ts + index as i64 * step_interval, val: values.as_slice()[index]}),
             SeriesType::GappyNormalSeries{..} =>
                 panic!("Can not efficient index into the middle of a normalized timeseries with gaps"),
+            // points with NULL values are skipped, as with iter()
+            SeriesType::NullableSeries{..} =>
+                self.iter().nth(index),
+            SeriesType::CompressedSeries{..} =>
+                panic!("Can not index into a compressed timeseries, use decompress first"),
         }
     }
 
@@ -149,9 +276,95 @@ impl<'input> TimeSeries<'input> {
                 true,
             SeriesType::GappyNormalSeries{..} =>
                 true,
+            SeriesType::NullableSeries{sorted, ..} =>
+                sorted != 0,
+            SeriesType::CompressedSeries{sorted, ..} =>
+                sorted != 0,
         }
     }
 
+    // the points in time order, for consumers that don't need a series
+    pub(crate) fn sorted_points(&self) -> Vec<TSPoint> {
+        let mut points: Vec<TSPoint> = self.iter().collect();
+        if !self.is_sorted() {
+            points.sort_by_key(|point| point.ts);
+        }
+        points
+    }
+
+    pub fn has_nulls(&self) -> bool {
+        match &self.series {
+            SeriesType::NullableSeries{nulls, ..} =>
+                nulls.iter().any(|word| word != 0),
+            _ => false,
+        }
+    }
+
+    // Iterates over every stored point, including those with NULL values,
+    // which `iter()` skips.
+    pub fn iter_with_nulls(&self) -> Box<dyn Iterator<Item=(i64, Option<f64>)> + '_> {
+        match &self.series {
+            SeriesType::NullableSeries{points, nulls, ..} => {
+                let nulls = nulls.as_slice();
+                Box::new(points.iter().enumerate().map(move |(i, point)| {
+                    if is_null(nulls, i) {
+                        (point.ts, None)
+                    } else {
+                        (point.ts, Some(point.val))
+                    }
+                }))
+            },
+            _ => Box::new(self.iter().map(|point| (point.ts, Some(point.val)))),
+        }
+    }
+
+    // drops the points with NULL values, for consumers that need every point
+    // to have one, other series are returned unchanged
+    pub fn without_nulls(self) -> TimeSeries<'input> {
+        match self.series {
+            SeriesType::NullableSeries{..} => self.decompressed(),
+            _ => self,
+        }
+    }
+
+    // expands a compressed series into one that supports indexing and
+    // in-place modification, other series are returned unchanged
+    pub fn decompress(self) -> TimeSeries<'input> {
+        match self.series {
+            SeriesType::CompressedSeries{..} => self.decompressed(),
+            _ => self,
+        }
+    }
+
+    fn decompressed(&self) -> TimeSeries<'static> {
+        let points: Vec<TSPoint> = self.iter().collect();
+        let labels = self.label_bytes.as_slice().to_vec();
+        let series: TimeSeries<'static> = if self.is_sorted() {
+            build!{
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::SortedSeries {
+                        num_points: points.len() as _,
+                        points: points.into(),
+                    }
+                }
+            }
+        } else {
+            build!{
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::ExplicitSeries {
+                        num_points: points.len() as _,
+                        points: points.into(),
+                    }
+                }
+            }
+        };
+        series.with_label_bytes(labels)
+    }
+
     fn clone_owned(&self) -> TimeSeries<'static> {
         TimeSeriesData::clone(&*self).into_owned().into()
     }
@@ -168,6 +381,10 @@ impl<'a> TimeSeries<'a> {
                 Iter::Normal{idx: 0, start: *start_ts, step: *step_interval, vals: values.iter()},
             SeriesType::GappyNormalSeries{count, start_ts, step_interval, present, values, ..} =>
                 Iter::GappyNormal{idx: 0, count: *count, start: *start_ts, step: *step_interval, present: present.as_slice(), vals: values.iter()},
+            SeriesType::NullableSeries{points, nulls, ..} =>
+                Iter::Nullable{idx: 0, remaining: num_non_null(points.len(), nulls.as_slice()), nulls: nulls.as_slice(), iter: points.iter()},
+            SeriesType::CompressedSeries{num_points, encoding_version, bytes, ..} =>
+                Iter::Compressed{iter: decompressor(*encoding_version, bytes.as_slice(), *num_points as _)},
         }
     }
 
@@ -181,6 +398,10 @@ impl<'a> TimeSeries<'a> {
                 Iter::Normal{idx: 0, start: start_ts, step: step_interval, vals: values.into_iter()},
             SeriesType::GappyNormalSeries{count, start_ts, step_interval, present, values, ..} =>
                 Iter::GappyNormal{idx: 0, count: count, start: start_ts, step: step_interval, present: present.slice(), vals: values.into_iter()},
+            SeriesType::NullableSeries{points, nulls, ..} =>
+                Iter::Nullable{idx: 0, remaining: num_non_null(points.len(), nulls.slice()), nulls: nulls.slice(), iter: points.into_iter()},
+            SeriesType::CompressedSeries{num_points, encoding_version, bytes, ..} =>
+                Iter::Compressed{iter: decompressor(encoding_version, bytes.slice(), num_points as _)},
         }
     }
 
@@ -190,6 +411,57 @@ impl<'a> TimeSeries<'a> {
             SeriesType::NormalSeries { num_vals, .. } => *num_vals as _,
             SeriesType::ExplicitSeries { num_points, ..} => *num_points as _,
             SeriesType::GappyNormalSeries { num_vals, .. } => *num_vals as _,
+            SeriesType::NullableSeries { num_points, .. } => *num_points as _,
+            SeriesType::CompressedSeries { num_points, .. } => *num_points as _,
+        }
+    }
+}
+
+fn is_null(nulls: &[u64], idx: usize) -> bool {
+    nulls[idx / 64] & (1 << (idx % 64)) != 0
+}
+
+fn num_non_null(num_points: usize, nulls: &[u64]) -> usize {
+    num_points - nulls.iter().map(|word| word.count_ones() as usize).sum::<usize>()
+}
+
+fn decompressor(encoding_version: u64, bytes: &[u8], num_points: usize)
+-> gorilla::Decompressor<'_> {
+    match encoding_version {
+        1 => gorilla::decompressor(bytes, num_points),
+        _ => panic!("unknown timeseries compression version {}", encoding_version),
+    }
+}
+
+// build a NullableSeries from (time, value) pairs, NULL values are recorded in
+// the bitmap and stored as 0
+pub fn nullable_series_from(
+    points: impl Iterator<Item=(i64, Option<f64>)>,
+) -> TimeSeries<'static> {
+    let mut sorted = true;
+    let mut nulls = vec![];
+    let points: Vec<TSPoint> = points.enumerate().map(|(i, (ts, val))| {
+        if i % 64 == 0 {
+            nulls.push(0);
+        }
+        if val.is_none() {
+            nulls[i / 64] |= 1 << (i % 64);
+        }
+        TSPoint{ ts, val: val.unwrap_or(0.0) }
+    }).collect();
+    if points.windows(2).any(|w| w[0].ts > w[1].ts) {
+        sorted = false;
+    }
+    build!{
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::NullableSeries {
+                num_points: points.len() as _,
+                sorted: if sorted {1} else {0},
+                points: points.into(),
+                nulls: nulls.into(),
+            }
         }
     }
 }
@@ -222,12 +494,26 @@ pub fn timeseries_deserialize(
     data.into()
 }
 
+// points with NULL values are skipped, timeseries_with_nulls() keeps them
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn timeseries_trans(
     state: Option<Internal<TimeSeries<'_>>>,
     time: Option<pg_sys::TimestampTz>,
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeSeries<'_>>> {
+    if value.is_none() {
+        return state
+    }
+    timeseries_with_nulls_trans(state, time, value, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_with_nulls_trans(
+    state: Option<Internal<TimeSeries<'_>>>,
+    time: Option<pg_sys::TimestampTz>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<TimeSeries<'_>>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -235,13 +521,11 @@ pub fn timeseries_trans(
                 None => return state,
                 Some(time) => time,
             };
-            let value = match value {
-                None => return state,   // Should we support NULL values?
-                Some(value) => value,
-            };
             let mut state = match state {
                 None => Internal::from(build!{
                     TimeSeries {
+                        num_label_bytes: 0,
+                        label_bytes: vec![].into(),
                         series: SeriesType::SortedSeries{
                             num_points: 0,
                             points: vec![].into(),
@@ -250,12 +534,32 @@ pub fn timeseries_trans(
                 }),
                 Some(state) => state,
             };
+            if value.is_none() && !matches!(state.series, SeriesType::NullableSeries{..}) {
+                // first NULL value, switch to a representation that can track it
+                *state = nullable_series_from(state.iter_with_nulls());
+            }
             match &mut state.series {
+                SeriesType::NullableSeries { num_points, sorted, points, nulls } => {
+                    let idx = points.len();
+                    points.as_owned().push(TSPoint{ts: time, val: value.unwrap_or(0.0)});
+                    *num_points = points.len() as _;
+                    if idx % 64 == 0 {
+                        nulls.as_owned().push(0);
+                    }
+                    if value.is_none() {
+                        nulls.as_owned()[idx / 64] |= 1 << (idx % 64);
+                    }
+                    if idx > 0 && points.as_slice()[idx - 1].ts > time {
+                        *sorted = 0;
+                    }
+                },
                 SeriesType::ExplicitSeries { num_points, points } => {
+                    let value = value.unwrap();
                     points.as_owned().push(TSPoint{ts: time, val:value});
                     *num_points = points.len() as _;
                 },
                 SeriesType::SortedSeries { num_points, points } => {
+                    let value = value.unwrap();
                     points.as_owned().push(TSPoint{ts: time, val:value});
                     *num_points = points.len() as _;
                     if let Some(slice) = points.as_slice().windows(2).last() {
@@ -263,6 +567,8 @@ pub fn timeseries_trans(
                             let points = std::mem::replace(points, vec![].into());
                             *state = build!{
                                 TimeSeries {
+                                    num_label_bytes: 0,
+                                    label_bytes: vec![].into(),
                                     series: SeriesType::ExplicitSeries{
                                         num_points: points.len() as _,
                                         points: points,
@@ -279,6 +585,27 @@ pub fn timeseries_trans(
     }
 }
 
+// TIMESTAMP and epoch BIGINT times, see timestamp_to_timestamptz()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_timestamp_trans(
+    state: Option<Internal<TimeSeries<'_>>>,
+    time: Option<timestamp>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeSeries<'_>>> {
+    timeseries_trans(state, time.map(timestamp_to_timestamptz), value, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_epoch_trans(
+    state: Option<Internal<TimeSeries<'_>>>,
+    time: Option<i64>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeSeries<'_>>> {
+    timeseries_trans(state, time.map(epoch_micros_to_timestamptz), value, fcinfo)
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn timeseries_compound_trans<'b>(
     state: Option<Internal<TimeSeries<'static>>>,
@@ -291,7 +618,10 @@ pub fn timeseries_compound_trans<'b>(
             match (state, series) {
                 (None, None) => None,
                 (Some(state), None) => Some(state),
-                (None, Some(series)) => Some(series.clone_owned().into()),
+                // the state is modified in place, so it can't be compressed
+                (None, Some(series)) => Some(series.clone_owned().decompress().into()),
+                (Some(state), Some(series)) if state.has_nulls() || series.has_nulls() =>
+                    Some(combine(state.clone(), series).into()),
                 (Some(mut state), Some(series)) =>
                     match &mut state.series {
                         ExplicitSeries { num_points, points } => {
@@ -316,6 +646,8 @@ pub fn timeseries_compound_trans<'b>(
                             let points = std::mem::replace(points, vec![].into());
                             *state = build!{
                                 TimeSeries {
+                                    num_label_bytes: 0,
+                                    label_bytes: vec![].into(),
                                     series: SeriesType::ExplicitSeries{
                                         num_points: points.len() as _,
                                         points: points,
@@ -352,6 +684,16 @@ pub fn timeseries_combine<'a, 'b> (
 }
 
 pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'static> {
+    // the result is only the same series as the inputs if they agree on it
+    let labels = if first.label_bytes.as_slice() == second.label_bytes.as_slice() {
+        first.label_bytes.as_slice().to_vec()
+    } else {
+        vec![]
+    };
+    combine_points(first, second).with_label_bytes(labels)
+}
+
+fn combine_points(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'static> {
     use SeriesType::*;
     if first.num_vals() == 0 {
         return second.clone_owned();
@@ -360,6 +702,11 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
         return first.clone_owned();
     }
 
+    // NULLs can only be preserved in a NullableSeries
+    if first.has_nulls() || second.has_nulls() {
+        return nullable_series_from(first.iter_with_nulls().chain(second.iter_with_nulls()));
+    }
+
     // If two explicit series are sorted and disjoint, return a sorted explicit series
     if let (
         SortedSeries{ num_points: _, points: first_points },
@@ -368,6 +715,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
             let mut new_points = first_points.clone().into_owned();
             new_points.as_owned().extend(second_points.iter());
             return build! { TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
                 series: SortedSeries {
                     num_points: new_points.len() as _,
                     points: new_points.into(),
@@ -379,6 +728,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
             let mut new_points = second_points.clone().into_owned();
             new_points.as_owned().extend(first_points.iter());
             return build! { TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
                 series: SortedSeries {
                     num_points: new_points.len() as _,
                     points: new_points.into(),
@@ -408,6 +759,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
                 let mut new_values = values_1.clone().into_owned();
                 new_values.as_owned().extend(values_2.iter());
                 return build!{ TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
                     series: NormalSeries {
                         start_ts: *start_ts_1,
                         step_interval: *step_interval_1,
@@ -421,6 +774,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
                 let mut new_values = values_2.clone().into_owned();
                 new_values.as_owned().extend(values_1.iter());
                 return build!{ TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
                     series: NormalSeries {
                         start_ts: *start_ts_2,
                         step_interval: *step_interval_2,
@@ -438,6 +793,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
     let points: Vec<_> = first.iter().chain(second.iter()).collect();
     if ordered {
         build!{ TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: SortedSeries {
                 num_points: points.len() as _,
                 points: points.into(),
@@ -445,6 +802,8 @@ pub fn combine(first: TimeSeries<'_>, second: TimeSeries<'_>) -> TimeSeries<'sta
         }}
     } else {
         build!{ TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: ExplicitSeries {
                 num_points: points.len() as _,
                 points: points.into(),
@@ -481,6 +840,43 @@ CREATE AGGREGATE toolkit_experimental.timeseries(ts TIMESTAMPTZ, value DOUBLE PR
 );
 "#);
 
+// A timeseries that keeps the points whose value is NULL, so that they can be
+// filled in by the fill_nulls() element, where timeseries() drops them.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.timeseries_with_nulls(ts TIMESTAMPTZ, value DOUBLE PRECISION) (
+    sfunc = toolkit_experimental.timeseries_with_nulls_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.timeseries_final,
+    combinefunc = toolkit_experimental.timeseries_combine,
+    serialfunc = toolkit_experimental.timeseries_serialize,
+    deserialfunc = toolkit_experimental.timeseries_deserialize,
+    parallel = safe
+);
+"#);
+
+// timeseries of TIMESTAMP and epoch BIGINT times
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.timeseries(ts TIMESTAMP, value DOUBLE PRECISION) (
+    sfunc = toolkit_experimental.timeseries_timestamp_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.timeseries_final,
+    combinefunc = toolkit_experimental.timeseries_combine,
+    serialfunc = toolkit_experimental.timeseries_serialize,
+    deserialfunc = toolkit_experimental.timeseries_deserialize,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.timeseries(ts BIGINT, value DOUBLE PRECISION) (
+    sfunc = toolkit_experimental.timeseries_epoch_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.timeseries_final,
+    combinefunc = toolkit_experimental.timeseries_combine,
+    serialfunc = toolkit_experimental.timeseries_serialize,
+    deserialfunc = toolkit_experimental.timeseries_deserialize,
+    parallel = safe
+);
+"#);
+
 extension_sql!(r#"
 CREATE AGGREGATE toolkit_experimental.rollup(
     toolkit_experimental.timeseries
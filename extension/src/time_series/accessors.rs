@@ -0,0 +1,301 @@
+use pgx::*;
+
+use counter_agg::range::I64Range;
+
+use super::*;
+
+#[allow(non_camel_case_types)]
+type tstzrange = pg_sys::Datum;
+
+impl<'input> TimeSeries<'input> {
+    // the points with the earliest and latest timestamps, NULL points are ignored
+    fn first_point(&self) -> Option<TSPoint> {
+        if self.is_sorted() {
+            return self.iter().next()
+        }
+        self.iter().min_by_key(|point| point.ts)
+    }
+
+    fn last_point(&self) -> Option<TSPoint> {
+        // the series stored as plain arrays can be read from the end instead
+        // of walking what may be millions of points
+        match &self.series {
+            SeriesType::SortedSeries{points, ..} =>
+                return points.as_slice().last().copied(),
+            SeriesType::NormalSeries{start_ts, step_interval, values, ..} =>
+                return values.as_slice().last().map(|&val| TSPoint {
+                    ts: start_ts + (values.len() as i64 - 1) * step_interval,
+                    val,
+                }),
+            _ => (),
+        }
+        if self.is_sorted() {
+            return self.iter().last()
+        }
+        // max_by_key returns the last maximum, so ties go to the later point
+        self.iter().max_by_key(|point| point.ts)
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_num_vals(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorNumVals,
+) -> i64 {
+    let _ = accessor;
+    timeseries_num_vals(series)
+}
+
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_num_vals(
+    series: toolkit_experimental::TimeSeries,
+) -> i64 {
+    // NULL points aren't counted
+    series.num_points() as _
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_first_time(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorFirstTime,
+) -> Option<pg_sys::TimestampTz> {
+    let _ = accessor;
+    timeseries_first_time(series)
+}
+
+#[pg_extern(name="first_time", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_first_time(
+    series: toolkit_experimental::TimeSeries,
+) -> Option<pg_sys::TimestampTz> {
+    series.first_point().map(|point| point.ts)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_first_val(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorFirstVal,
+) -> Option<f64> {
+    let _ = accessor;
+    timeseries_first_val(series)
+}
+
+#[pg_extern(name="first_val", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_first_val(
+    series: toolkit_experimental::TimeSeries,
+) -> Option<f64> {
+    series.first_point().map(|point| point.val)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_last_time(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorLastTime,
+) -> Option<pg_sys::TimestampTz> {
+    let _ = accessor;
+    timeseries_last_time(series)
+}
+
+#[pg_extern(name="last_time", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_last_time(
+    series: toolkit_experimental::TimeSeries,
+) -> Option<pg_sys::TimestampTz> {
+    series.last_point().map(|point| point.ts)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_last_val(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorLastVal,
+) -> Option<f64> {
+    let _ = accessor;
+    timeseries_last_val(series)
+}
+
+#[pg_extern(name="last_val", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_last_val(
+    series: toolkit_experimental::TimeSeries,
+) -> Option<f64> {
+    series.last_point().map(|point| point.val)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_timeseries_covers(
+    series: toolkit_experimental::TimeSeries,
+    accessor: toolkit_experimental::AccessorCovers,
+) -> Option<tstzrange> {
+    let _ = accessor;
+    timeseries_covers(series)
+}
+
+// the smallest range containing every point of the series
+#[pg_extern(name="covers", schema = "toolkit_experimental", immutable, parallel_safe)]
+fn timeseries_covers(
+    series: toolkit_experimental::TimeSeries,
+) -> Option<tstzrange> {
+    let first = series.first_point()?;
+    let last = series.last_point()?;
+    let range = I64Range {
+        left: Some(first.ts),
+        right: Some(last.ts + 1),
+    };
+    unsafe {
+        Some(crate::range::i64range_to_tstzrange(range) as tstzrange)
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_accessors() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30)",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW sv AS SELECT timeseries(time, value) AS series FROM series",
+                None,
+                None
+            );
+
+            let (num_vals, first_val, last_val) = client.select(
+                "SELECT series -> num_vals(), series -> first_val(), series -> last_val() FROM sv",
+                None,
+                None
+            )
+                .first()
+                .get_three::<i64, f64, f64>();
+            assert_eq!(num_vals, Some(5));
+            assert_eq!(first_val, Some(10.0));
+            assert_eq!(last_val, Some(30.0));
+
+            let (first_time, last_time) = client.select(
+                "SELECT (series -> first_time())::TEXT, last_time(series)::TEXT FROM sv",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(first_time.unwrap(), "2020-01-01 00:00:00+00");
+            assert_eq!(last_time.unwrap(), "2020-01-05 00:00:00+00");
+
+            let covers = client.select(
+                "SELECT (series -> covers())::TEXT FROM sv",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(covers.unwrap(), "[\"2020-01-01 00:00:00+00\",\"2020-01-05 00:00:00.000001+00\")");
+
+            let contained = client.select(
+                "SELECT (series -> covers()) @> '2020-01-05 UTC'::timestamptz FROM sv",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(contained, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_timeseries_timestamp_and_epoch() {
+        Spi::execute(|client| {
+            // TIMESTAMPs are read as UTC whatever the session's timezone
+            client.select("SET timezone TO 'America/New_York'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            let (timestamp, epoch) = client.select(
+                "SELECT \
+                    first_time(timeseries('2020-01-01 00:00'::TIMESTAMP, 1.0)) = '2020-01-01 UTC'::TIMESTAMPTZ, \
+                    first_time(timeseries(1577836800000000::BIGINT, 1.0)) = '2020-01-01 UTC'::TIMESTAMPTZ",
+                None,
+                None
+            )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(timestamp, Some(true));
+            assert_eq!(epoch, Some(true));
+        });
+    }
+
+    #[pg_test(error = "timestamp out of range")]
+    fn test_timeseries_epoch_out_of_range() {
+        Spi::execute(|client| {
+            client.select("SELECT toolkit_experimental.timeseries(9223372036854775807::BIGINT, 1.0)", None, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_large_timeseries_accessors() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // 500,000 points is 8MB, stored compressed and out of line
+            client.select(
+                "CREATE TABLE big AS \
+                    SELECT timeseries(time, value) AS series FROM ( \
+                        SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' seconds')::INTERVAL AS time, \
+                            i::DOUBLE PRECISION AS value \
+                        FROM generate_series(1, 500000) i \
+                    ) points",
+                None,
+                None
+            );
+
+            let (num_vals, first_val, last_val) = client.select(
+                "SELECT series -> num_vals(), series -> first_val(), series -> last_val() FROM big",
+                None,
+                None
+            )
+                .first()
+                .get_three::<i64, f64, f64>();
+            assert_eq!(num_vals, Some(500000));
+            assert_eq!(first_val, Some(1.0));
+            assert_eq!(last_val, Some(500000.0));
+
+            let last_time = client.select("SELECT (series -> last_time())::TEXT FROM big", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(last_time.unwrap(), "2020-01-06 18:53:20+00");
+
+            let deltas = client.select("SELECT (series -> delta()) -> num_vals() FROM big", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(deltas, Some(499999));
+        });
+    }
+}
@@ -0,0 +1,124 @@
+use pgx::*;
+
+use super::*;
+
+// The sample autocorrelation of the series' values at lags 0 through
+// `max_lag`, treating the points as evenly spaced.
+fn autocorrelations(series: &TimeSeries<'_>, max_lag: i32) -> Vec<f64> {
+    let values: Vec<f64> = series.sorted_points().iter().map(|point| point.val).collect();
+    if max_lag < 0 {
+        error!("max_lag must not be negative")
+    }
+    let max_lag = max_lag as usize;
+    if max_lag >= values.len() {
+        error!("max_lag must be less than the number of points")
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let deviations: Vec<f64> = values.iter().map(|v| v - mean).collect();
+    let variance: f64 = deviations.iter().map(|d| d * d).sum();
+    if variance == 0.0 {
+        error!("autocorrelation is undefined for a constant series")
+    }
+    (0..=max_lag)
+        .map(|lag| {
+            let covariance: f64 = deviations.iter()
+                .zip(&deviations[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            covariance / variance
+        })
+        .collect()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn acf(
+    series: toolkit_experimental::TimeSeries<'_>,
+    max_lag: i32,
+) -> impl std::iter::Iterator<Item = (name!(lag,i32),name!(coefficient,f64))> {
+    autocorrelations(&series, max_lag)
+        .into_iter()
+        .enumerate()
+        .map(|(lag, coefficient)| (lag as i32, coefficient))
+}
+
+// The partial autocorrelation at lags 0 through `max_lag`, computed from the
+// autocorrelations with the Durbin-Levinson recursion.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn pacf(
+    series: toolkit_experimental::TimeSeries<'_>,
+    max_lag: i32,
+) -> impl std::iter::Iterator<Item = (name!(lag,i32),name!(coefficient,f64))> {
+    let r = autocorrelations(&series, max_lag);
+    let mut coefficients = vec![1.0];
+    // phi[j - 1] is the coefficient of lag j in the order k - 1 model
+    let mut phi: Vec<f64> = vec![];
+    for k in 1..r.len() {
+        let numerator = r[k] - (1..k).map(|j| phi[j - 1] * r[k - j]).sum::<f64>();
+        let denominator = 1.0 - (1..k).map(|j| phi[j - 1] * r[j]).sum::<f64>();
+        let phi_kk = numerator / denominator;
+        let mut next: Vec<f64> = (1..k).map(|j| phi[j - 1] - phi_kk * phi[k - j - 1]).collect();
+        next.push(phi_kk);
+        phi = next;
+        coefficients.push(phi_kk);
+    }
+    coefficients.into_iter()
+        .enumerate()
+        .map(|(lag, coefficient)| (lag as i32, coefficient))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_autocorrelation() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, i::DOUBLE PRECISION AS value \
+                    FROM generate_series(5, 1, -1) i",
+                None,
+                None
+            );
+
+            let coefficients = |function: &str| -> Vec<(i32, f64)> {
+                client.select(
+                    &format!("SELECT lag, coefficient FROM {}(\
+                        (SELECT timeseries(time, value) FROM series), 3)", function),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                    ))
+                    .collect()
+            };
+            let assert_close = |actual: Vec<(i32, f64)>, expected: &[f64]| {
+                assert_eq!(actual.len(), expected.len());
+                for (i, ((lag, actual), expected)) in actual.iter().zip(expected).enumerate() {
+                    assert_eq!(*lag, i as i32);
+                    assert!((actual - expected).abs() < 1e-12, "lag {}: {} != {}", lag, actual, expected);
+                }
+            };
+
+            assert_close(coefficients("acf"), &[1.0, 0.4, -0.1, -0.4]);
+            assert_close(coefficients("pacf"), &[
+                1.0,
+                0.4,
+                (-0.1 - 0.4 * 0.4) / (1.0 - 0.4 * 0.4),
+                {
+                    let phi22 = (-0.1 - 0.4 * 0.4) / (1.0 - 0.4 * 0.4);
+                    let phi21 = 0.4 - phi22 * 0.4;
+                    (-0.4 - (phi21 * -0.1 + phi22 * 0.4)) / (1.0 - (phi21 * 0.4 + phi22 * -0.1))
+                },
+            ]);
+        });
+    }
+}
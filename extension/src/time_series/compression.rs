@@ -0,0 +1,125 @@
+use pgx::*;
+
+use super::*;
+
+// the encoding written by compress(), see encodings::gorilla
+const CURRENT_ENCODING_VERSION: u64 = 1;
+
+// Stores the points of a timeseries with delta-of-delta timestamps and XORed
+// values, which is typically several times smaller for regularly sampled
+// data. Compressed series are decompressed automatically when they are run
+// through a pipeline. NULL values cannot be compressed.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn compress(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> toolkit_experimental::TimeSeries<'static> {
+    if let SeriesType::CompressedSeries{..} = series.series {
+        return series.clone_owned()
+    }
+    if series.has_nulls() {
+        error!("cannot compress a timeseries containing NULLs, use fill_nulls first")
+    }
+
+    let mut bytes = vec![];
+    gorilla::compress_to_vec(&mut bytes, series.iter().map(|TSPoint{ ts, val }| (ts, val)));
+    build!{
+        TimeSeries {
+            num_label_bytes: series.num_label_bytes,
+            label_bytes: series.label_bytes.as_slice().to_vec().into(),
+            series: SeriesType::CompressedSeries {
+                num_points: series.iter().count() as _,
+                sorted: if series.is_sorted() {1} else {0},
+                encoding_version: CURRENT_ENCODING_VERSION,
+                num_bytes: bytes.len() as _,
+                bytes: bytes.into(),
+            }
+        }
+    }
+}
+
+#[pg_extern(name = "decompress", schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_decompress(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> toolkit_experimental::TimeSeries<'static> {
+    series.decompressed()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_compression() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE VIEW series AS \
+                    SELECT timeseries('2020-01-01 UTC'::TIMESTAMPTZ + step * '1 minute'::INTERVAL, (step / 10)::DOUBLE PRECISION) AS series \
+                    FROM generate_series(0, 999) step",
+                None,
+                None
+            );
+
+            let (original, compressed) = client.select(
+                "SELECT pg_column_size(series), pg_column_size(compress(series)) FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i32, i32>();
+            assert!(compressed.unwrap() * 5 < original.unwrap());
+
+            let same = client.select(
+                "SELECT series::TEXT = compress(series)::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(same, Some(true));
+
+            let same = client.select(
+                "SELECT (series -> mul(2))::TEXT = (compress(series) -> mul(2))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(same, Some(true));
+
+            let (num_vals, last_val) = client.select(
+                "SELECT num_vals(compress(series)), last_val(compress(series)) FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i64, f64>();
+            assert_eq!(num_vals, Some(1000));
+            assert_eq!(last_val, Some(99.0));
+
+            // we use a subselect to guarantee order
+            let val = client.select(
+                "SELECT decompress(compress(series))::TEXT FROM \
+                    (SELECT timeseries(time, value) as series FROM \
+                        (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.5), \
+                            ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                            ('2020-01-03 UTC'::TIMESTAMPTZ, -20.0)) as v(time, value)) s",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:25.5),\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:-20)\
+            ]");
+        });
+    }
+}
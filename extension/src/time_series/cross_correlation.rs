@@ -0,0 +1,141 @@
+use pgx::*;
+
+use super::*;
+
+use crate::utilities::micros_to_interval;
+
+use super::rolling_corr::{correlation, pair_by_time};
+
+type Interval = pg_sys::Datum;
+
+// The correlation of a[i] with b[i + lag] over the pairs for each lag from
+// -max_lag through max_lag, skipping lags where it is undefined.
+fn lagged_correlations(pairs: &[(i64, f64, f64)], max_lag: i32) -> Vec<(i32, f64)> {
+    if max_lag < 0 {
+        error!("max_lag must not be negative")
+    }
+    let n = pairs.len() as i64;
+    (-max_lag..=max_lag)
+        .filter_map(|lag| {
+            let shifted: Vec<(i64, f64, f64)> = (0..n)
+                .filter(|i| (0..n).contains(&(i + lag as i64)))
+                .map(|i| {
+                    let (ts, a, _) = pairs[i as usize];
+                    (ts, a, pairs[(i + lag as i64) as usize].2)
+                })
+                .collect();
+            correlation(&shifted).map(|coefficient| (lag, coefficient))
+        })
+        .collect()
+}
+
+// The Pearson correlation of `a` with `b` shifted by each lag from -max_lag
+// through max_lag points. Points are paired up by timestamp first, points with
+// no partner in the other series are ignored. A strong correlation at a
+// positive lag means `a` leads `b`. Lags without a defined correlation are
+// omitted.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn cross_correlation(
+    a: toolkit_experimental::TimeSeries<'_>,
+    b: toolkit_experimental::TimeSeries<'_>,
+    max_lag: i32,
+) -> impl std::iter::Iterator<Item = (name!(lag,i32),name!(coefficient,f64))> {
+    lagged_correlations(&pair_by_time(&a, &b), max_lag).into_iter()
+}
+
+// The lag of the strongest cross_correlation, positive or negative, as a
+// duration using the average spacing of the paired points; positive when `a`
+// leads `b`. Ties go to the smaller lag. A negative `max_lag` considers up to
+// 10 * log10(n) points in either direction, but no more than n / 4 as the
+// correlations of short overlaps are unreliable. NULL if no lag has a defined
+// correlation.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn best_lag(
+    a: toolkit_experimental::TimeSeries<'_>,
+    b: toolkit_experimental::TimeSeries<'_>,
+    max_lag: default!(i32, -1),
+) -> Option<Interval> {
+    let pairs = pair_by_time(&a, &b);
+    if pairs.len() < 2 {
+        return None
+    }
+    let max_lag = if max_lag < 0 {
+        ((10.0 * (pairs.len() as f64).log10()).floor() as i32).min(pairs.len() as i32 / 4)
+    } else {
+        max_lag
+    };
+
+    let mut correlations = lagged_correlations(&pairs, max_lag);
+    correlations.sort_by_key(|(lag, _)| lag.abs());
+    let mut best: Option<(i32, f64)> = None;
+    for (lag, coefficient) in correlations {
+        match best {
+            Some((_, best_coefficient)) if best_coefficient.abs() >= coefficient.abs() => (),
+            _ => best = Some((lag, coefficient)),
+        }
+    }
+    let (lag, _) = best?;
+
+    let step = (pairs[pairs.len() - 1].0 - pairs[0].0) / (pairs.len() - 1) as i64;
+    Some(micros_to_interval(lag as i64 * step))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_cross_correlation() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // latency follows queue depth two days later
+            client.select(
+                "CREATE TABLE metrics AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        (ARRAY[3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5, 8])[i + 1]::DOUBLE PRECISION AS queue_depth, \
+                        (ARRAY[NULL, NULL, 3, 1, 4, 1, 5, 9, 2, 6, 5, 3])[i + 1]::DOUBLE PRECISION AS latency \
+                    FROM generate_series(0, 11) i",
+                None,
+                None
+            );
+
+            let coefficients: Vec<(i32, f64)> = client.select(
+                "SELECT lag, coefficient FROM cross_correlation(\
+                    (SELECT timeseries(time, queue_depth) FROM metrics), \
+                    (SELECT timeseries(time, latency) FROM metrics WHERE latency IS NOT NULL), \
+                    3)",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                ))
+                .collect();
+            let lags: Vec<i32> = coefficients.iter().map(|(lag, _)| *lag).collect();
+            assert_eq!(lags, vec![-3, -2, -1, 0, 1, 2, 3]);
+            assert!((coefficients[5].1 - 1.0).abs() < 1e-12);
+            assert!(coefficients.iter().all(|(lag, c)| *lag == 2 || c.abs() < 0.99));
+
+            let (best, reversed) = client.select(
+                "SELECT \
+                    best_lag(a, b) = '2 days'::INTERVAL, \
+                    best_lag(b, a, 3) = '-2 days'::INTERVAL \
+                FROM \
+                    (SELECT timeseries(time, queue_depth) AS a FROM metrics) a, \
+                    (SELECT timeseries(time, latency) AS b FROM metrics WHERE latency IS NOT NULL) b",
+                None,
+                None
+            )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(best, Some(true));
+            assert_eq!(reversed, Some(true));
+        });
+    }
+}
@@ -0,0 +1,105 @@
+use pgx::*;
+
+use super::*;
+
+// Tabular CUSUM control chart of the series around `target`. The upper sum
+// accumulates how far values run above `target + k`, the lower sum how far
+// they run below `target - k`, neither dropping below 0. A point where either
+// sum exceeds `h` breaches the control limits, and both sums restart from 0
+// after it so each sustained drift is flagged once. `k` is the slack, usually
+// half the shift to detect, and `h` the decision interval, usually 4 or 5
+// standard deviations.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn cusum(
+    series: toolkit_experimental::TimeSeries<'_>,
+    target: f64,
+    k: f64,
+    h: f64,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(upper_sum,f64),name!(lower_sum,f64),name!(breached,bool))> {
+    if !(k >= 0.0) {
+        error!("cusum k must not be negative")
+    }
+    if !(h > 0.0) {
+        error!("cusum h must be positive")
+    }
+
+    let mut points: Vec<TSPoint> = series.iter().collect();
+    if !series.is_sorted() {
+        points.sort_by_key(|point| point.ts);
+    }
+
+    let (mut upper, mut lower) = (0.0f64, 0.0f64);
+    points.into_iter()
+        .map(move |point| {
+            upper = (upper + point.val - (target + k)).max(0.0);
+            lower = (lower + (target - k) - point.val).max(0.0);
+            let breached = upper > h || lower > h;
+            let row = (point.ts, upper, lower, breached);
+            if breached {
+                upper = 0.0;
+                lower = 0.0;
+            }
+            row
+        })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_cusum() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE readings AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        (ARRAY[10, 10, 12, 12, 12, 10, 8, 8, 8, 8])[i + 1]::DOUBLE PRECISION AS value \
+                    FROM generate_series(0, 9) i",
+                None,
+                None
+            );
+
+            let chart: Vec<(f64, f64, bool)> = client.select(
+                "SELECT upper_sum, lower_sum, breached FROM \
+                    cusum((SELECT timeseries(time, value) FROM readings), 10, 0.5, 4)",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(chart, vec![
+                (0.0, 0.0, false),
+                (0.0, 0.0, false),
+                (1.5, 0.0, false),
+                (3.0, 0.0, false),
+                (4.5, 0.0, true),
+                (0.0, 0.0, false),
+                (0.0, 1.5, false),
+                (0.0, 3.0, false),
+                (0.0, 4.5, true),
+                (0.0, 1.5, false),
+            ]);
+
+            let breaches: Vec<String> = client.select(
+                "SELECT time::TEXT FROM \
+                    cusum((SELECT timeseries(time, value) FROM readings), 10, 0.5, 4) \
+                WHERE breached",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(breaches, vec!["2020-01-05 00:00:00+00", "2020-01-09 00:00:00+00"]);
+        });
+    }
+}
@@ -0,0 +1,113 @@
+use pgx::*;
+
+use super::*;
+
+// the largest decline from a running peak to a later trough
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drawdown {
+    pub drawdown: f64,
+    pub peak: TSPoint,
+    pub trough: TSPoint,
+}
+
+// Finds the maximum drawdown of points given in time order in a single pass,
+// tracking the running maximum. A series that never declines has a drawdown
+// of 0 at its first point. Returns None for an empty series.
+pub fn max_drawdown(points: impl Iterator<Item=TSPoint>) -> Option<Drawdown> {
+    let mut max: Option<Drawdown> = None;
+    let mut peak: Option<TSPoint> = None;
+    for point in points {
+        let current_peak = match peak {
+            Some(peak) if peak.val >= point.val => peak,
+            _ => {
+                peak = Some(point);
+                point
+            }
+        };
+        let drawdown = current_peak.val - point.val;
+        match max {
+            Some(max) if max.drawdown >= drawdown => (),
+            _ => max = Some(Drawdown { drawdown, peak: current_peak, trough: point }),
+        }
+    }
+    max
+}
+
+#[pg_extern(name="max_drawdown", schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_max_drawdown(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> impl std::iter::Iterator<Item = (name!(drawdown,f64),name!(peak_time,pg_sys::TimestampTz),name!(trough_time,pg_sys::TimestampTz))> {
+    let drawdown = if series.is_sorted() {
+        max_drawdown(series.iter())
+    } else {
+        let mut points: Vec<TSPoint> = series.iter().collect();
+        points.sort_by_key(|point| point.ts);
+        max_drawdown(points.into_iter())
+    };
+    drawdown.into_iter()
+        .map(|drawdown| (drawdown.drawdown, drawdown.peak.ts, drawdown.trough.ts))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_max_drawdown() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 12), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 7), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 9), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 20)",
+                None,
+                None
+            );
+
+            let drawdown = |query: &str| -> Vec<(f64, String, String)> {
+                client.select(
+                    &format!("SELECT drawdown, peak_time::TEXT, trough_time::TEXT FROM max_drawdown(({}))", query),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                        row.by_ordinal(3).unwrap().value().unwrap(),
+                    ))
+                    .collect()
+            };
+
+            // the later decline from 12 to 9 is smaller
+            assert_eq!(
+                drawdown("SELECT timeseries(time, value) FROM series"),
+                vec![(8.0, "2020-01-02 00:00:00+00".to_string(), "2020-01-03 00:00:00+00".to_string())],
+            );
+
+            assert_eq!(
+                drawdown("SELECT timeseries(time, value) FROM series WHERE time > '2020-01-04'"),
+                vec![(0.0, "2020-01-05 00:00:00+00".to_string(), "2020-01-05 00:00:00+00".to_string())],
+            );
+
+            assert_eq!(
+                drawdown("SELECT timeseries(time, value) FROM series WHERE value > 100"),
+                vec![],
+            );
+        });
+    }
+}
@@ -0,0 +1,264 @@
+use pgx::*;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Seasonality {
+    Additive,
+    Multiplicative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Band {
+    Forecast,
+    Upper,
+    Lower,
+}
+
+// z-score of the two-sided 95% prediction interval
+const INTERVAL_Z: f64 = 1.96;
+
+// the values each smoothing parameter is tried with when fitting the model
+const PARAMETER_GRID: [f64; 10] = [0.05, 0.15, 0.25, 0.35, 0.45, 0.55, 0.65, 0.75, 0.85, 0.95];
+
+#[derive(Clone, Debug)]
+struct HoltWinters {
+    seasonality: Seasonality,
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    level: f64,
+    trend: f64,
+    // the seasonal components of the last `season_length` points, oldest first
+    season: Vec<f64>,
+    // sum of the squared one step ahead errors
+    sse: f64,
+    num_errors: usize,
+}
+
+impl HoltWinters {
+    // Initializes from the first two seasons: the level is the mean of the
+    // first season, the trend the average change between the two seasons,
+    // and the seasonal components the first season relative to the level.
+    fn fit(values: &[f64], season_length: usize, seasonality: Seasonality, alpha: f64, beta: f64, gamma: f64) -> Self {
+        let m = season_length;
+        let level = values[..m].iter().sum::<f64>() / m as f64;
+        let trend = (0..m).map(|i| values[m + i] - values[i]).sum::<f64>() / (m * m) as f64;
+        let season = values[..m].iter()
+            .map(|&v| match seasonality {
+                Seasonality::Additive => v - level,
+                Seasonality::Multiplicative => v / level,
+            })
+            .collect();
+        let mut model = HoltWinters {
+            seasonality,
+            alpha,
+            beta,
+            gamma,
+            level,
+            trend,
+            season,
+            sse: 0.0,
+            num_errors: 0,
+        };
+        for &value in &values[m..] {
+            model.update(value);
+        }
+        model
+    }
+
+    fn update(&mut self, value: f64) {
+        let (alpha, beta, gamma) = (self.alpha, self.beta, self.gamma);
+        let seasonal = self.season[0];
+        let error = value - self.forecast(1);
+        self.sse += error * error;
+        self.num_errors += 1;
+
+        let prev_level = self.level;
+        let new_seasonal = match self.seasonality {
+            Seasonality::Additive => {
+                self.level = alpha * (value - seasonal) + (1.0 - alpha) * (prev_level + self.trend);
+                self.trend = beta * (self.level - prev_level) + (1.0 - beta) * self.trend;
+                gamma * (value - self.level) + (1.0 - gamma) * seasonal
+            },
+            Seasonality::Multiplicative => {
+                self.level = alpha * (value / seasonal) + (1.0 - alpha) * (prev_level + self.trend);
+                self.trend = beta * (self.level - prev_level) + (1.0 - beta) * self.trend;
+                gamma * (value / self.level) + (1.0 - gamma) * seasonal
+            },
+        };
+        self.season.remove(0);
+        self.season.push(new_seasonal);
+    }
+
+    fn forecast(&self, steps: usize) -> f64 {
+        let seasonal = self.season[(steps - 1) % self.season.len()];
+        let trended = self.level + steps as f64 * self.trend;
+        match self.seasonality {
+            Seasonality::Additive => trended + seasonal,
+            Seasonality::Multiplicative => trended * seasonal,
+        }
+    }
+
+    // standard deviation of the one step ahead errors
+    fn residual_stddev(&self) -> f64 {
+        if self.num_errors == 0 {
+            return 0.0
+        }
+        (self.sse / self.num_errors as f64).sqrt()
+    }
+}
+
+// Forecasts `horizon` points past the end of a regularly spaced series using
+// triple exponential smoothing. The smoothing parameters are chosen from a
+// grid to minimize the squared one step ahead error over the series. The
+// forecast points continue at the series' average spacing. The 'upper' and
+// 'lower' bands are an approximate 95% prediction interval, widening with
+// the square root of the number of steps ahead.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn holt_winters(
+    series: toolkit_experimental::TimeSeries<'_>,
+    season_length: i32,
+    horizon: i32,
+    seasonality: default!(&str, "additive"),
+    band: default!(&str, "forecast"),
+) -> toolkit_experimental::TimeSeries<'static> {
+    let seasonality = match seasonality.to_lowercase().as_str() {
+        "additive" => Seasonality::Additive,
+        "multiplicative" => Seasonality::Multiplicative,
+        _ => panic!("Invalid seasonality, expected 'additive' or 'multiplicative'")
+    };
+    let band = match band.to_lowercase().as_str() {
+        "forecast" => Band::Forecast,
+        "upper" => Band::Upper,
+        "lower" => Band::Lower,
+        _ => panic!("Invalid band, expected 'forecast', 'upper', or 'lower'")
+    };
+    if season_length < 1 {
+        error!("holt_winters season_length must be positive")
+    }
+    if horizon < 0 {
+        error!("holt_winters horizon must not be negative")
+    }
+    let (season_length, horizon) = (season_length as usize, horizon as usize);
+
+    let mut points: Vec<TSPoint> = series.iter().collect();
+    if !series.is_sorted() {
+        points.sort_by_key(|point| point.ts);
+    }
+    if points.len() < 2 * season_length {
+        error!("holt_winters requires at least two seasons of data")
+    }
+    let values: Vec<f64> = points.iter().map(|point| point.val).collect();
+    if seasonality == Seasonality::Multiplicative && values.iter().any(|&v| v <= 0.0) {
+        error!("multiplicative holt_winters requires positive values")
+    }
+
+    let mut best: Option<HoltWinters> = None;
+    for &alpha in &PARAMETER_GRID {
+        for &beta in &PARAMETER_GRID {
+            for &gamma in &PARAMETER_GRID {
+                let model = HoltWinters::fit(&values, season_length, seasonality, alpha, beta, gamma);
+                match &best {
+                    Some(best) if best.sse <= model.sse => (),
+                    _ => best = Some(model),
+                }
+            }
+        }
+    }
+    let model = best.unwrap();
+
+    let last = points[points.len() - 1].ts;
+    let step = (last - points[0].ts) / (points.len() - 1) as i64;
+    let stddev = model.residual_stddev();
+    let forecast: Vec<TSPoint> = (1..=horizon)
+        .map(|h| {
+            let val = model.forecast(h);
+            let width = INTERVAL_Z * stddev * (h as f64).sqrt();
+            let val = match band {
+                Band::Forecast => val,
+                Band::Upper => val + width,
+                Band::Lower => val - width,
+            };
+            TSPoint { ts: last + step * h as i64, val }
+        })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: forecast.len() as u64,
+                points: forecast.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_holt_winters() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // three seasons of a pattern repeating every 4 days
+            client.select(
+                "CREATE TABLE usage AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        (ARRAY[10, 20, 30, 20])[i % 4 + 1]::DOUBLE PRECISION AS value \
+                    FROM generate_series(0, 11) i",
+                None,
+                None
+            );
+
+            let forecast = |args: &str| -> Vec<(String, f64)> {
+                client.select(
+                    &format!("SELECT time::TEXT, value FROM unnest(\
+                        (SELECT holt_winters(timeseries(time, value), {}) FROM usage))", args),
+                    None,
+                    None
+                )
+                    .map(|row| (
+                        row.by_ordinal(1).unwrap().value().unwrap(),
+                        row.by_ordinal(2).unwrap().value().unwrap(),
+                    ))
+                    .collect()
+            };
+
+            // the pattern is fit exactly, so the forecast continues it
+            let expected = [
+                ("2020-01-13 00:00:00+00", 10.0),
+                ("2020-01-14 00:00:00+00", 20.0),
+                ("2020-01-15 00:00:00+00", 30.0),
+                ("2020-01-16 00:00:00+00", 20.0),
+                ("2020-01-17 00:00:00+00", 10.0),
+            ];
+            for args in &["4, 5", "4, 5, 'multiplicative'", "4, 5, 'additive', 'upper'"] {
+                let result = forecast(args);
+                assert_eq!(result.len(), expected.len());
+                for ((time, value), (expected_time, expected_value)) in result.iter().zip(expected.iter()) {
+                    assert_eq!(time, expected_time);
+                    assert!((value - expected_value).abs() < 1e-9, "{} forecast {} instead of {}", args, value, expected_value);
+                }
+            }
+
+            let empty = client.select(
+                "SELECT count(*) FROM unnest(\
+                    (SELECT holt_winters(timeseries(time, value), 4, 0) FROM usage))",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(empty, Some(0));
+        });
+    }
+}
@@ -20,6 +20,16 @@ pub enum Iter<'a> {
         present: &'a [u64],
         vals: flat_serialize::Iter<'a, 'a, f64>,
     },
+    // skips points whose value is NULL
+    Nullable {
+        idx: usize,
+        remaining: usize,
+        nulls: &'a [u64],
+        iter: flat_serialize::Iter<'a, 'a, TSPoint>,
+    },
+    Compressed {
+        iter: encodings::gorilla::Decompressor<'a>,
+    },
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -55,6 +65,21 @@ impl<'a> Iterator for Iter<'a> {
                 *idx += 1;
                 Some(TSPoint{ts, val})
             }
+            Nullable{idx, remaining, nulls, iter} => {
+                loop {
+                    let point = iter.next()?;
+                    let is_null = nulls[*idx / 64] & (1 << (*idx % 64)) != 0;
+                    *idx += 1;
+                    if !is_null {
+                        *remaining -= 1;
+                        return Some(point)
+                    }
+                }
+            }
+            Compressed{iter} => {
+                let (ts, val) = iter.next()?;
+                Some(TSPoint{ts, val})
+            }
         }
     }
 
@@ -68,6 +93,8 @@ impl<'a> Iterator for Iter<'a> {
             Normal { idx: _, start: _, step: _, vals } => (vals.len(), Some(vals.len())),
             GappyNormal { idx: _, count, start: _, step: _, present: _, vals: _ } =>
                 (*count as _, Some(*count as _)),
+            Nullable { remaining, .. } => (*remaining, Some(*remaining)),
+            Compressed { iter } => iter.size_hint(),
         }
     }
 
@@ -0,0 +1,154 @@
+use std::ffi::CStr;
+
+use pgx::*;
+
+use serde_json::{json, Value};
+
+use super::*;
+
+use crate::serialization::{
+    _ts_toolkit_decode_timestamptz, _ts_toolkit_encode_timestamptz,
+};
+
+// Converts a timeseries into a JSON array of `{"ts": ..., "val": ...}` objects,
+// NULL values are represented as JSON nulls.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn to_jsonb(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> JsonB {
+    let points = series.iter_with_nulls()
+        .map(|(ts, val)| json!({
+            "ts": encode_timestamptz(ts),
+            "val": val,
+        }))
+        .collect();
+    JsonB(Value::Array(points))
+}
+
+// inverse of to_jsonb(timeseries)
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_from_jsonb(
+    json: JsonB,
+) -> toolkit_experimental::TimeSeries<'static> {
+    let points = match json.0 {
+        Value::Array(points) => points,
+        _ => error!("timeseries JSON must be an array of {{\"ts\": ..., \"val\": ...}} objects"),
+    };
+
+    let points: Vec<(i64, Option<f64>)> = points.iter()
+        .map(|point| {
+            let ts = match &point["ts"] {
+                Value::String(ts) => unsafe { _ts_toolkit_decode_timestamptz(ts) },
+                _ => error!("timeseries JSON points require a text \"ts\" field"),
+            };
+            let val = match &point["val"] {
+                Value::Null => None,
+                val => match val.as_f64() {
+                    Some(val) => Some(val),
+                    None => error!("timeseries JSON points require a numeric \"val\" field"),
+                },
+            };
+            (ts, val)
+        })
+        .collect();
+
+    series_from_points(points)
+}
+
+// The series of the points, in the order given, NULL values included.
+pub(super) fn series_from_points(points: Vec<(i64, Option<f64>)>) -> toolkit_experimental::TimeSeries<'static> {
+    if points.iter().any(|(_, val)| val.is_none()) {
+        return nullable_series_from(points.into_iter())
+    }
+
+    let points: Vec<TSPoint> = points.into_iter()
+        .map(|(ts, val)| TSPoint{ ts, val: val.unwrap() })
+        .collect();
+    if points.windows(2).all(|w| w[0].ts <= w[1].ts) {
+        build!{
+            TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
+                series: SeriesType::SortedSeries {
+                    num_points: points.len() as _,
+                    points: points.into(),
+                }
+            }
+        }
+    } else {
+        build!{
+            TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
+                series: SeriesType::ExplicitSeries {
+                    num_points: points.len() as _,
+                    points: points.into(),
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn encode_timestamptz(ts: i64) -> String {
+    let mut buf = [0; 128];
+    _ts_toolkit_encode_timestamptz(ts, &mut buf);
+    let text = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    text.to_str().unwrap().to_string()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_jsonb() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries_with_nulls(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.5)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT to_jsonb(series)::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                {\"ts\": \"2020-01-01 00:00:00+00\", \"val\": 10.0}, \
+                {\"ts\": \"2020-01-02 00:00:00+00\", \"val\": null}, \
+                {\"ts\": \"2020-01-03 00:00:00+00\", \"val\": 20.5}\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (timeseries_from_jsonb(to_jsonb(series)) -> fill_nulls('drop'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20.5)\
+            ]");
+
+            let val = client.select(
+                "SELECT timeseries_from_jsonb('[{\"ts\": \"2020-01-02 UTC\", \"val\": 2}, {\"ts\": \"2020-01-01 UTC\", \"val\": 1}]')::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:1)\
+            ]");
+        });
+    }
+}
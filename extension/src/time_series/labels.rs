@@ -0,0 +1,146 @@
+use pgx::*;
+
+use serde_json::{Map, Value};
+
+use super::*;
+
+// Labels are stored in `label_bytes` as `key\0value\0` pairs ordered by key.
+// Postgres text cannot contain NUL bytes, so no escaping is needed.
+impl<'input> TimeSeries<'input> {
+    pub fn labels(&self) -> impl Iterator<Item=(&str, &str)> + '_ {
+        let mut parts = self.label_bytes.as_slice()
+            .split(|b| *b == 0)
+            .map(|part| std::str::from_utf8(part).unwrap());
+        std::iter::from_fn(move || Some((parts.next()?, parts.next()?)))
+    }
+
+    pub fn with_label_bytes(mut self, labels: Vec<u8>) -> Self {
+        self.num_label_bytes = labels.len() as _;
+        self.label_bytes = labels.into();
+        self
+    }
+}
+
+pub(super) fn encode_labels<'a>(labels: impl Iterator<Item=(&'a str, &'a str)>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (key, value) in labels {
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+// the labels of the series as a JSON object of text values
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn labels(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> JsonB {
+    let labels = series.labels()
+        .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+        .collect::<Map<_, _>>();
+    JsonB(Value::Object(labels))
+}
+
+// replaces the labels of the series with those in a JSON object of text values
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn with_labels(
+    series: toolkit_experimental::TimeSeries<'_>,
+    labels: JsonB,
+) -> toolkit_experimental::TimeSeries<'static> {
+    let labels = match &labels.0 {
+        Value::Object(labels) => labels,
+        _ => error!("timeseries labels must be a JSON object"),
+    };
+    // serde_json maps are ordered by key
+    let labels = labels.iter().map(|(key, value)| match value {
+        Value::String(value) => (key.as_str(), value.as_str()),
+        _ => error!("timeseries label values must be strings"),
+    });
+    series.clone_owned().with_label_bytes(encode_labels(labels))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_labels() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT with_labels(timeseries(time, value), '{\"room\": \"1\", \"device\": \"a\"}') as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT labels(series)::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{\"room\": \"1\", \"device\": \"a\"}");
+
+            // labels are preserved by pipelines, even by elements that build a new series
+            let val = client.select(
+                &format!("SELECT labels(series -> sort() -> add(1) -> delta())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{\"room\": \"1\", \"device\": \"a\"}");
+
+            let val = client.select(
+                &format!("SELECT (series -> sort())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(labels:{\"device\":\"a\",\"room\":\"1\"},points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-04 00:00:00+00\",val:25)\
+            ])");
+
+            // the labels survive a round trip through the text format
+            let val = client.select(
+                &format!("SELECT labels(series::TEXT::timeseries)::TEXT, \
+                    (series::TEXT::timeseries)::TEXT = series::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, bool>();
+            assert_eq!(val, (Some("{\"room\": \"1\", \"device\": \"a\"}".to_string()), Some(true)));
+
+            let val = client.select(
+                &format!("SELECT labels(rollup(series))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{\"room\": \"1\", \"device\": \"a\"}");
+
+            let val = client.select(
+                "SELECT labels(timeseries('2020-01-01 UTC'::TIMESTAMPTZ, 1.0))::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{}");
+        });
+    }
+}
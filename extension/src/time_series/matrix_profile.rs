@@ -0,0 +1,229 @@
+use pgx::*;
+
+use super::*;
+
+// matches of a subsequence starting less than this many points away are
+// trivial, as the subsequences mostly overlap
+fn exclusion_zone(subsequence_len: usize) -> usize {
+    (subsequence_len + 3) / 4
+}
+
+// mean and standard deviation of each subsequence of length `m`
+fn rolling_mean_stddev(values: &[f64], m: usize) -> (Vec<f64>, Vec<f64>) {
+    values.windows(m)
+        .map(|window| {
+            let mean = window.iter().sum::<f64>() / m as f64;
+            let variance = window.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / m as f64;
+            (mean, variance.sqrt())
+        })
+        .unzip()
+}
+
+// z-normalized Euclidean distance between two subsequences of length `m`
+// given their dot product. A constant subsequence is as far as possible from
+// any varying one, and identical to any other constant one.
+fn znorm_distance(dot: f64, m: f64, mean_i: f64, stddev_i: f64, mean_j: f64, stddev_j: f64) -> f64 {
+    match (stddev_i == 0.0, stddev_j == 0.0) {
+        (true, true) => 0.0,
+        (true, false) | (false, true) => m.sqrt(),
+        (false, false) => {
+            let correlation = (dot - m * mean_i * mean_j) / (m * stddev_i * stddev_j);
+            (2.0 * m * (1.0 - correlation)).max(0.0).sqrt()
+        },
+    }
+}
+
+// The matrix profile of `values` using STOMP: for each subsequence of length
+// `m` the distance to its nearest non-trivial match. The dot products of each
+// row of the distance matrix are derived from those of the previous row in
+// constant time each, so this is O(n^2) time and O(n) space.
+fn stomp(values: &[f64], m: usize) -> Vec<f64> {
+    let num_subsequences = values.len() - m + 1;
+    let excluded = exclusion_zone(m);
+    let (means, stddevs) = rolling_mean_stddev(values, m);
+    let dot = |i: usize, j: usize| -> f64 {
+        values[i..i + m].iter().zip(&values[j..j + m]).map(|(a, b)| a * b).sum()
+    };
+
+    // the dot products of the first subsequence with every other, which by
+    // symmetry are also the first entry of each row
+    let first_row: Vec<f64> = (0..num_subsequences).map(|j| dot(0, j)).collect();
+    let mut row = first_row.clone();
+    let mut profile = vec![f64::INFINITY; num_subsequences];
+    for i in 0..num_subsequences {
+        if i > 0 {
+            for j in (1..num_subsequences).rev() {
+                row[j] = row[j - 1] - values[i - 1] * values[j - 1] + values[i + m - 1] * values[j + m - 1];
+            }
+            row[0] = first_row[i];
+        }
+        for j in 0..num_subsequences {
+            if (i as isize - j as isize).unsigned_abs() < excluded {
+                continue
+            }
+            let distance = znorm_distance(row[j], m as f64, means[i], stddevs[i], means[j], stddevs[j]);
+            if distance < profile[i] {
+                profile[i] = distance;
+            }
+        }
+    }
+    profile
+}
+
+// The matrix profile of the series: a point at the start of each subsequence
+// of `subsequence_len` points, whose value is the z-normalized Euclidean
+// distance to the most similar subsequence that does not overlap it
+// significantly. The points are treated as evenly spaced. Low values mark
+// repeated patterns (motifs), high values unusual ones (discords).
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn matrix_profile(
+    series: toolkit_experimental::TimeSeries<'_>,
+    subsequence_len: i32,
+) -> toolkit_experimental::TimeSeries<'static> {
+    if subsequence_len < 2 {
+        error!("matrix_profile subsequence_len must be at least 2")
+    }
+    let m = subsequence_len as usize;
+    let points = series.sorted_points();
+    if points.len() < 2 * m {
+        error!("matrix_profile requires at least twice subsequence_len points")
+    }
+    let values: Vec<f64> = points.iter().map(|point| point.val).collect();
+    let profile: Vec<TSPoint> = stomp(&values, m)
+        .into_iter()
+        .zip(&points)
+        .map(|(val, point)| TSPoint { ts: point.ts, val })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: profile.len() as u64,
+                points: profile.into(),
+            }
+        }
+    )
+}
+
+// Picks up to `n` points of the profile in the given order, skipping any
+// whose subsequence would overlap one already picked.
+fn top_subsequences(
+    profile: &TimeSeries<'_>,
+    n: i32,
+    subsequence_len: i32,
+    largest_first: bool,
+) -> Vec<(pg_sys::TimestampTz, f64)> {
+    if n < 0 {
+        error!("the number of subsequences must not be negative")
+    }
+    if subsequence_len < 1 {
+        error!("subsequence_len must be positive")
+    }
+    let points = profile.sorted_points();
+    let mut order: Vec<usize> = (0..points.len()).filter(|&i| !points[i].val.is_nan()).collect();
+    order.sort_by(|&a, &b| {
+        let ordering = points[a].val.partial_cmp(&points[b].val).unwrap();
+        if largest_first { ordering.reverse() } else { ordering }
+    });
+
+    let mut picked: Vec<usize> = vec![];
+    for i in order {
+        if picked.len() >= n as usize {
+            break
+        }
+        if picked.iter().any(|&p| (p as isize - i as isize).unsigned_abs() < subsequence_len as usize) {
+            continue
+        }
+        picked.push(i);
+    }
+    picked.into_iter().map(|i| (points[i].ts, points[i].val)).collect()
+}
+
+// The `n` subsequences of a matrix_profile least like any other, most
+// unusual first. Subsequences overlapping a more unusual one are skipped.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn top_discords(
+    profile: toolkit_experimental::TimeSeries<'_>,
+    n: i32,
+    subsequence_len: i32,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(distance,f64))> {
+    top_subsequences(&profile, n, subsequence_len, true).into_iter()
+}
+
+// The `n` subsequences of a matrix_profile with the closest matches elsewhere
+// in the series, closest first. Subsequences overlapping a closer one are
+// skipped.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn top_motifs(
+    profile: toolkit_experimental::TimeSeries<'_>,
+    n: i32,
+    subsequence_len: i32,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(distance,f64))> {
+    top_subsequences(&profile, n, subsequence_len, false).into_iter()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_matrix_profile() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // a sawtooth repeating every 4 points with one spike at day 21
+            client.select(
+                "CREATE TABLE sensor AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        CASE WHEN i = 21 THEN 10 ELSE i % 4 END::DOUBLE PRECISION AS value \
+                    FROM generate_series(0, 39) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE profile AS SELECT matrix_profile(timeseries(time, value), 4) AS p FROM sensor",
+                None,
+                None
+            );
+
+            let num_points = client.select("SELECT p -> num_vals() FROM profile", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(num_points, Some(37));
+
+            // away from the spike every subsequence matches another tooth exactly
+            let motif_distance = client.select(
+                "SELECT distance FROM profile, top_motifs(p, 1, 4)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert!(motif_distance.unwrap().abs() < 1e-6);
+
+            let discords: Vec<(i32, f64)> = client.select(
+                "SELECT time::DATE - '2020-01-01'::DATE, distance FROM profile, top_discords(p, 2, 4)",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(discords.len(), 2);
+            // the most unusual subsequence contains the spike, the next
+            // doesn't overlap it
+            let (first, second) = (discords[0].0, discords[1].0);
+            assert!(first <= 21 && 21 < first + 4, "{:?}", discords);
+            assert!((second - first).abs() >= 4, "{:?}", discords);
+            assert!(discords[0].1 > discords[1].1);
+        });
+    }
+}
@@ -7,6 +7,25 @@ mod map;
 mod arithmetic;
 mod aggregation;
 mod expansion;
+mod ewma;
+mod normalize;
+mod clamp;
+mod dedupe;
+mod shift;
+mod fill_nulls;
+mod fusion;
+mod slice;
+mod m4;
+mod integral;
+mod counter_correct;
+mod hampel;
+mod bucket;
+mod mad;
+mod bollinger;
+mod indicators;
+mod returns;
+mod anomalies;
+mod quantile_normalize;
 
 use std::convert::TryInto;
 
@@ -46,14 +65,14 @@ pg_type! {
     #[derive(Debug)]
     struct UnstableTimeseriesPipeline<'input> {
         num_elements: u64,
-        elements: [Element; self.num_elements],
+        elements: [Element<'input>; self.num_elements],
     }
 }
 
 flat_serialize_macro::flat_serialize! {
     #[derive(Debug)]
     #[derive(serde::Serialize, serde::Deserialize)]
-    enum Element {
+    enum Element<'input> {
         kind: u64,
         LTTB: 1 {
             resolution: u64,
@@ -81,26 +100,99 @@ flat_serialize_macro::flat_serialize! {
         Arithmetic: 8 {
             function: arithmetic::Function,
             rhs: f64,
+        },
+        Ewma: 9 {
+            alpha: f64,
+        },
+        Normalize: 10 {
+            method: normalize::NormalizeMethod,
+        },
+        Clamp: 11 {
+            min: f64,
+            max: f64,
+            drop_outliers: i64, // padded bool
+        },
+        Dedupe: 12 {
+            method: dedupe::DedupeMethod,
+        },
+        Shift: 13 {
+            offset: i64,
+        },
+        FillNulls: 14 {
+            fill_method: fill_nulls::NullFillMethod,
+            constant: f64,
+        },
+        Slice: 15 {
+            lower: i64,
+            upper: i64,
+            lower_present: u64, // padded bool
+            upper_present: u64, // padded bool
+        },
+        M4: 16 {
+            width: u64,
+        },
+        CounterCorrect: 17 {
+        },
+        Hampel: 18 {
+            window: u64,
+            n_sigmas: f64,
+            drop_outliers: i64, // padded bool
+        },
+        Bucket: 19 {
+            width: i64,
+            aggregate: bucket::BucketAggregate,
+        },
+        // 20 is unused, stored pipelines may still hold it
+        RollingMad: 21 {
+            window: i64,
+        },
+        Bollinger: 22 {
+            window: u64,
+            k: f64,
+            band: bollinger::BollingerBand,
+        },
+        Rsi: 23 {
+            period: u64,
+        },
+        Macd: 24 {
+            fast: u64,
+            slow: u64,
+            signal: u64,
+            output: indicators::MacdOutput,
+        },
+        PctChange: 25 {
+        },
+        LogReturn: 26 {
+        },
+        Anomalies: 27 {
+            window: i64,
+            threshold: f64,
+        },
+        CalendarBucket: 28 {
+            months: i64,
+            width: i64,
+            aggregate: bucket::BucketAggregate,
+            timezone_len: u64,
+            timezone: [u8; self.timezone_len],
         }
     }
 }
 
-impl Element {
+impl<'input> Element<'input> {
     pub fn flatten<'a>(self) -> UnstableTimeseriesPipeline<'a> {
-        let slice = &[self][..];
         unsafe {
             flatten! {
                 UnstableTimeseriesPipeline {
                     num_elements: 1,
-                    elements: slice.into(),
+                    elements: vec![self].into(),
                 }
             }
         }
     }
 }
 
-impl From<Element> for UnstableTimeseriesPipeline<'_> {
-    fn from(element: Element) -> Self {
+impl<'e> From<Element<'e>> for UnstableTimeseriesPipeline<'e> {
+    fn from(element: Element<'e>) -> Self {
         build! {
             UnstableTimeseriesPipeline {
                 num_elements: 1,
@@ -129,13 +221,37 @@ pub fn run_pipeline<'s, 'p>(
         .in_current_context()
 }
 
-pub fn run_pipeline_elements<'s, 'i>(
+pub fn run_pipeline_elements<'s, 'e>(
     mut timeseries: TimeSeries<'s>,
-    pipeline: impl Iterator<Item=Element> + 'i,
+    pipeline: impl Iterator<Item=Element<'e>>,
 ) -> TimeSeries<'s> {
+    // elements may index into or modify the series
+    timeseries = timeseries.decompress();
+
+    // elements that build a new series don't carry the labels over, but the
+    // output still describes the same series as the input
+    let labels = timeseries.label_bytes.as_slice().to_vec();
+
+    // arithmetic elements only change values, so runs of them are deferred
+    // and applied in a single pass instead of once per element
+    let mut deferred = vec![];
     for element in pipeline {
+        if let Element::Arithmetic{ function, rhs } = element {
+            deferred.push((function, rhs));
+            continue
+        }
+        if !deferred.is_empty() {
+            timeseries = arithmetic::apply_all(timeseries, &deferred);
+            deferred.clear();
+        }
         timeseries = execute_pipeline_element(timeseries, &element);
     }
+    if !deferred.is_empty() {
+        timeseries = arithmetic::apply_all(timeseries, &deferred);
+    }
+    if timeseries.label_bytes.as_slice() != &labels[..] {
+        timeseries = timeseries.with_label_bytes(labels);
+    }
     timeseries
 }
 
@@ -160,14 +276,52 @@ pub fn execute_pipeline_element<'s, 'e>(
             return map::apply_to_series(timeseries, function.0),
         Element::Arithmetic{ function, rhs } =>
             return arithmetic::apply(timeseries, *function, *rhs),
+        Element::Ewma{ alpha } =>
+            return ewma::timeseries_ewma_element(timeseries, *alpha),
+        Element::Normalize{ method } =>
+            return method.process(timeseries),
+        Element::Clamp{..} =>
+            return clamp::clamp_timeseries(timeseries, &element),
+        Element::Dedupe{ method } =>
+            return method.process(timeseries),
+        Element::Shift{ offset } =>
+            return shift::shift_timeseries(timeseries, *offset),
+        Element::FillNulls{ fill_method, constant } =>
+            return fill_method.process(timeseries, *constant),
+        Element::Slice{..} =>
+            return slice::slice_timeseries(timeseries, &element),
+        Element::M4{ width } =>
+            return m4::m4_timeseries(timeseries, *width),
+        Element::CounterCorrect{..} =>
+            return counter_correct::counter_correct_timeseries(timeseries),
+        Element::Hampel{..} =>
+            return hampel::hampel_timeseries(timeseries, &element),
+        Element::Bucket{ width, aggregate } =>
+            return bucket::bucket_timeseries(&timeseries, *width, *aggregate),
+        Element::RollingMad{ window } =>
+            return mad::rolling_mad_timeseries(&timeseries, *window),
+        Element::Bollinger{..} =>
+            return bollinger::bollinger_timeseries(&timeseries, &element),
+        Element::Rsi{ period } =>
+            return indicators::rsi_timeseries(&timeseries, *period),
+        Element::Macd{..} =>
+            return indicators::macd_timeseries(timeseries, &element),
+        Element::PctChange{..} =>
+            return returns::timeseries_returns(&timeseries, false),
+        Element::LogReturn{..} =>
+            return returns::timeseries_returns(&timeseries, true),
+        Element::Anomalies{ window, threshold } =>
+            return anomalies::anomalies_timeseries(&timeseries, *window, *threshold),
+        Element::CalendarBucket{ months, width, aggregate, timezone, .. } =>
+            return bucket::calendar_bucket_timeseries(&timeseries, *months, *width, *aggregate, timezone.as_slice()),
     }
 }
 
 // TODO is (immutable, parallel_safe) correct?
 #[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
-pub fn add_unstable_element<'p, 'e>(
+pub fn add_unstable_element<'p>(
     mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'p>,
-    element: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    element: toolkit_experimental::UnstableTimeseriesPipeline<'p>,
 ) -> toolkit_experimental::UnstableTimeseriesPipeline<'p> {
     pipeline.elements.as_owned().extend(element.elements.iter());
     pipeline.num_elements = pipeline.elements.len().try_into().unwrap();
@@ -191,6 +345,32 @@ CREATE OPERATOR -> (
 );
 "#);
 
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// planner support function that folds chains of constant pipelines applied to
+// a series into a single pipeline, see fusion.rs
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn pipeline_support(
+    input: internal,
+) -> internal {
+    unsafe { fusion::simplify(input) }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        ALTER FUNCTION toolkit_experimental.run_pipeline(
+            toolkit_experimental.TimeSeries,
+            toolkit_experimental.UnstableTimeseriesPipeline
+        ) SUPPORT toolkit_experimental.pipeline_support;
+    END IF;
+END
+$$;
+"#);
+
 #[pg_extern(stable, parallel_safe, schema="toolkit_experimental")]
 pub fn run_user_pipeline_element<'s, 'p>(
     timeseries: toolkit_experimental::TimeSeries<'s>,
@@ -282,6 +462,64 @@ pub fn lttb_pipeline_element<'p, 'e>(
 mod tests {
     use pgx::*;
 
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    #[pg_test]
+    fn test_pipeline_fusion() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE fused_pipe (series timeseries)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO fused_pipe \
+                SELECT timeseries(time, val) FROM ( \
+                    SELECT \
+                        '2020-01-01 UTC'::TIMESTAMPTZ + make_interval(days=>foo) as time, \
+                        foo::float8 as val \
+                    FROM generate_series(5,1,-1) foo \
+                ) bar",
+                None,
+                None
+            );
+
+            // all three elements should be folded into the same constant
+            let plan: Vec<String> = client.select(
+                "EXPLAIN (VERBOSE) \
+                SELECT series -> sort() -> add(1.0) -> mul(2.0) FROM fused_pipe",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            let plan = plan.join("\n");
+            assert_eq!(plan.matches("run_pipeline").count(), 1, "{}", plan);
+            assert!(plan.contains("num_elements:3"), "{}", plan);
+
+            let val = client.select(
+                "SELECT (series -> sort() -> add(1.0) -> mul(2.0))::TEXT FROM fused_pipe",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:4),\
+                (ts:\"2020-01-03 00:00:00+00\",val:6),\
+                (ts:\"2020-01-04 00:00:00+00\",val:8),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10),\
+                (ts:\"2020-01-06 00:00:00+00\",val:12)\
+            ]");
+        });
+    }
+
     #[pg_test]
     fn test_pipeline_lttb() {
         Spi::execute(|client| {
@@ -8,24 +8,43 @@ use super::*;
 use crate::{
     ron_inout_funcs, pg_type, build,
     stats_agg::{InternalStatsSummary1D, StatsSummary1D},
+    uddsketch::{
+        UddSketch,
+        PERCENTILE_AGG_DEFAULT_SIZE, PERCENTILE_AGG_DEFAULT_ERROR,
+    },
 };
 
+use ::uddsketch::UDDSketch as UddSketchInternal;
+
 
 pg_type! {
     #[derive(Debug)]
     struct PipelineThenStatsAgg<'input> {
         num_elements: u64,
-        elements: [Element; self.num_elements],
+        elements: [Element<'input>; self.num_elements],
     }
 }
 
 ron_inout_funcs!(PipelineThenStatsAgg);
 
+pg_type! {
+    #[derive(Debug)]
+    struct PipelineThenUddSketch<'input> {
+        max_error: f64,
+        size: u64,
+        num_elements: u64,
+        elements: [Element<'input>; self.num_elements],
+    }
+}
+
+ron_inout_funcs!(PipelineThenUddSketch);
+
 // hack to allow us to qualify names with "toolkit_experimental"
 // so that pgx generates the correct SQL
 pub mod toolkit_experimental {
     pub(crate) use super::*;
     varlena_type!(PipelineThenStatsAgg);
+    varlena_type!(PipelineThenUddSketch);
 }
 
 
@@ -44,8 +63,8 @@ pub fn run_pipeline_then_stats_agg<'s, 'p>(
 }
 
 #[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
-pub fn finalize_with_stats_agg<'p, 'e>(
-    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'p>,
+pub fn finalize_with_stats_agg<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
     then_stats_agg: toolkit_experimental::PipelineThenStatsAgg<'e>,
 ) -> toolkit_experimental::PipelineThenStatsAgg<'e> {
     if then_stats_agg.num_elements == 0 {
@@ -83,6 +102,89 @@ pub fn pipeline_stats_agg<'e>() -> toolkit_experimental::PipelineThenStatsAgg<'e
     }
 }
 
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_then_uddsketch<'s, 'p>(
+    mut timeseries: toolkit_experimental::TimeSeries<'s>,
+    pipeline: toolkit_experimental::PipelineThenUddSketch<'p>,
+) -> UddSketch<'static> {
+    timeseries = run_pipeline_elements(timeseries, pipeline.elements.iter());
+    let mut sketch = UddSketchInternal::new(pipeline.size, pipeline.max_error);
+    for TSPoint{ val, ..} in timeseries.iter() {
+        sketch.add_value(val);
+    }
+    UddSketch::from_internal(&sketch)
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn finalize_with_uddsketch<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    then_uddsketch: toolkit_experimental::PipelineThenUddSketch<'e>,
+) -> toolkit_experimental::PipelineThenUddSketch<'e> {
+    if then_uddsketch.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {flatten! {
+            PipelineThenUddSketch {
+                max_error: then_uddsketch.max_error,
+                size: then_uddsketch.size,
+                num_elements: pipeline.0.num_elements,
+                elements: pipeline.0.elements,
+            }
+        }}
+    }
+
+    let mut elements = replace(pipeline.elements.as_owned(), vec![]);
+    elements.extend(then_uddsketch.elements.iter());
+    build! {
+        PipelineThenUddSketch {
+            max_error: then_uddsketch.max_error,
+            size: then_uddsketch.size,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="uddsketch",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_uddsketch<'e>(
+    size: i32,
+    max_error: f64,
+) -> toolkit_experimental::PipelineThenUddSketch<'e> {
+    if size <= 0 {
+        error!("uddsketch size must be positive")
+    }
+    build! {
+        PipelineThenUddSketch {
+            max_error,
+            size: size as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+// same as uddsketch(), but with the size and error percentile_agg uses
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="percentile_agg",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_percentile_agg<'e>() -> toolkit_experimental::PipelineThenUddSketch<'e> {
+    build! {
+        PipelineThenUddSketch {
+            max_error: PERCENTILE_AGG_DEFAULT_ERROR,
+            size: PERCENTILE_AGG_DEFAULT_SIZE as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
 // using this instead of pg_operator since the latter doesn't support schemas yet
 // FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
 //       need to ensure this works with out unstable warning
@@ -98,6 +200,18 @@ CREATE OPERATOR -> (
     LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
     RIGHTARG=toolkit_experimental.PipelineThenStatsAgg
 );
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."run_pipeline_then_uddsketch",
+    LEFTARG=toolkit_experimental.TimeSeries,
+    RIGHTARG=toolkit_experimental.PipelineThenUddSketch
+);
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."finalize_with_uddsketch",
+    LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
+    RIGHTARG=toolkit_experimental.PipelineThenUddSketch
+);
 "#);
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -132,4 +246,66 @@ mod tests {
             assert_eq!(val.unwrap(), "(version:1,n:5,sx:100,sx2:250,sx3:0,sx4:21250)");
         });
     }
-}
\ No newline at end of file
+
+    #[pg_test]
+    fn test_uddsketch_finalizers() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30)",
+                None,
+                None
+            );
+
+            let (pipeline, aggregate) = client.select(
+                "SELECT \
+                    (timeseries(time, value) -> percentile_agg())::TEXT, \
+                    percentile_agg(value)::TEXT \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(pipeline.unwrap(), aggregate.unwrap());
+
+            let (pipeline, aggregate) = client.select(
+                "SELECT \
+                    (timeseries(time, value) -> (mul(2) -> uddsketch(100, 0.01)))::TEXT, \
+                    uddsketch(100, 0.01, 2 * value)::TEXT \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(pipeline.unwrap(), aggregate.unwrap());
+
+            let count = client.select(
+                "SELECT num_vals(timeseries(time, value) -> uddsketch(100, 0.01)) FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(count, Some(5.0));
+        });
+    }
+}
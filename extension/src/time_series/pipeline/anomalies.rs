@@ -0,0 +1,276 @@
+use std::mem::replace;
+
+use pgx::*;
+
+use super::*;
+
+use crate::{ron_inout_funcs, pg_type, build, utilities::interval_to_micros};
+
+type Interval = pg_sys::Datum;
+
+// scales the deviation from the median so that the robust z-score of normally
+// distributed data matches its standard z-score
+const ROBUST_Z_SCALE: f64 = 0.6745;
+
+pg_type! {
+    #[derive(Debug)]
+    struct PipelineThenNumAnomalies<'input> {
+        window: i64,
+        threshold: f64,
+        num_elements: u64,
+        elements: [Element<'input>; self.num_elements],
+    }
+}
+
+ron_inout_funcs!(PipelineThenNumAnomalies);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(PipelineThenNumAnomalies);
+}
+
+fn window_to_micros(window: Interval, name: &str) -> i64 {
+    let window = interval_to_micros(window, &format!("{} windows", name));
+    if window <= 0 {
+        error!("{} window must be positive", name)
+    }
+    window
+}
+
+fn check_threshold(threshold: f64, name: &str) {
+    if !(threshold >= 0.0) {
+        error!("{} threshold must not be negative", name)
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="anomalies",
+    schema="toolkit_experimental"
+)]
+pub fn anomalies_pipeline_element<'e>(
+    window: Interval,
+    threshold: default!(f64, 3.5),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let window = window_to_micros(window, "anomalies");
+    check_threshold(threshold, "anomalies");
+
+    Element::Anomalies {
+        window,
+        threshold,
+    }.flatten()
+}
+
+// Flags each point whose robust z-score against the trailing window
+// `(ts - window, ts]` exceeds `threshold`. The robust z-score is the distance
+// from the window's median in units of its median absolute deviation; when
+// that deviation is 0 any point that differs from the median is flagged.
+fn flag_anomalies(points: &[TSPoint], window: i64, threshold: f64) -> Vec<bool> {
+    let mut start = 0;
+    let mut scratch = Vec::new();
+    points.iter()
+        .enumerate()
+        .map(|(end, point)| {
+            while points[start].ts <= point.ts - window {
+                start += 1;
+            }
+
+            // NaNs have no place in the order, they're left out of the window
+            // and never flagged
+            scratch.clear();
+            scratch.extend(points[start..=end].iter().map(|p| p.val).filter(|val| !val.is_nan()));
+            if scratch.is_empty() {
+                return false
+            }
+            let center = hampel::median(&mut scratch);
+
+            scratch.iter_mut().for_each(|val| *val = (*val - center).abs());
+            let mad = hampel::median(&mut scratch);
+
+            let deviation = (point.val - center).abs();
+            if mad == 0.0 {
+                return deviation > 0.0
+            }
+            ROBUST_Z_SCALE * deviation / mad > threshold
+        })
+        .collect()
+}
+
+// keeps only the points flagged as anomalous, with their original values
+pub fn anomalies_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    window: i64,
+    threshold: f64,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only find anomalies in sorted timeseries");
+    }
+
+    let points: Vec<TSPoint> = series.iter().collect();
+    let flags = flag_anomalies(&points, window, threshold);
+    let anomalies: Vec<TSPoint> = points.into_iter()
+        .zip(flags)
+        .filter_map(|(point, is_anomaly)| if is_anomaly { Some(point) } else { None })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: anomalies.len() as u64,
+                points: anomalies.into(),
+            }
+        }
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_then_num_anomalies<'s, 'p>(
+    mut timeseries: toolkit_experimental::TimeSeries<'s>,
+    pipeline: toolkit_experimental::PipelineThenNumAnomalies<'p>,
+) -> i64 {
+    timeseries = run_pipeline_elements(timeseries, pipeline.elements.iter());
+    if !timeseries.is_sorted() {
+        panic!("can only find anomalies in sorted timeseries");
+    }
+    let points: Vec<TSPoint> = timeseries.iter().collect();
+    flag_anomalies(&points, pipeline.window, pipeline.threshold)
+        .into_iter()
+        .filter(|&is_anomaly| is_anomaly)
+        .count() as i64
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn finalize_with_num_anomalies<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    then_num_anomalies: toolkit_experimental::PipelineThenNumAnomalies<'e>,
+) -> toolkit_experimental::PipelineThenNumAnomalies<'e> {
+    if then_num_anomalies.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {flatten! {
+            PipelineThenNumAnomalies {
+                window: then_num_anomalies.window,
+                threshold: then_num_anomalies.threshold,
+                num_elements: pipeline.0.num_elements,
+                elements: pipeline.0.elements,
+            }
+        }}
+    }
+
+    let mut elements = replace(pipeline.elements.as_owned(), vec![]);
+    elements.extend(then_num_anomalies.elements.iter());
+    build! {
+        PipelineThenNumAnomalies {
+            window: then_num_anomalies.window,
+            threshold: then_num_anomalies.threshold,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+// counts the points `anomalies(window, threshold)` would keep
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="num_anomalies",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_num_anomalies<'e>(
+    window: Interval,
+    threshold: default!(f64, 3.5),
+) -> toolkit_experimental::PipelineThenNumAnomalies<'e> {
+    let window = window_to_micros(window, "num_anomalies");
+    check_threshold(threshold, "num_anomalies");
+    build! {
+        PipelineThenNumAnomalies {
+            window: window,
+            threshold: threshold,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+// FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
+//       need to ensure this works with out unstable warning
+extension_sql!(r#"
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."run_pipeline_then_num_anomalies",
+    LEFTARG=toolkit_experimental.TimeSeries,
+    RIGHTARG=toolkit_experimental.PipelineThenNumAnomalies
+);
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."finalize_with_num_anomalies",
+    LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
+    RIGHTARG=toolkit_experimental.PipelineThenNumAnomalies
+);
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_anomalies() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        (ARRAY[1, 2, 1, 2, 1, 20, 1, 2, 1, 2])[i + 1]::DOUBLE PRECISION AS value \
+                    FROM generate_series(0, 9) i",
+                None,
+                None
+            );
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> anomalies('5 days'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[(ts:\"2020-01-06 00:00:00+00\",val:20)]");
+
+            let (count, pipeline_count, strict_count) = client.select(
+                "SELECT \
+                    timeseries(time, value) -> sort() -> num_anomalies('5 days'), \
+                    timeseries(time, value) -> (sort() -> mul(2) -> num_anomalies('5 days', 3.5)), \
+                    timeseries(time, value) -> sort() -> num_anomalies('5 days', 20) \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_three::<i64, i64, i64>();
+            assert_eq!(count, Some(1));
+            assert_eq!(pipeline_count, Some(1));
+            assert_eq!(strict_count, Some(0));
+
+            // NaNs are left out of the windows
+            client.select("UPDATE series SET value = 'NaN' WHERE time = '2020-01-03 UTC'", None, None);
+            let count = client.select(
+                "SELECT timeseries(time, value) -> sort() -> num_anomalies('5 days') FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(count, Some(1));
+        });
+    }
+}
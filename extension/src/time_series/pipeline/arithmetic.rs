@@ -30,6 +30,40 @@ pub enum Function {
     Sign,
     Sqrt,
     Trunc,
+    // added after the initial set; kept at the end so existing values don't shift
+    Exp,
+    RoundN,
+}
+
+impl Function {
+    fn as_fn(self) -> fn(f64, f64) -> f64 {
+        match self {
+            Add => |a, b| a + b,
+            Sub => |a, b| a - b,
+            Mul => |a, b| a * b,
+            Div => |a, b| a / b,
+            // TODO is this the right mod?
+            Mod => |a, b| a % b,
+            Power => |a, b| a.powf(b),
+            LogN => |a, b| a.log(b),
+            // unary functions just ignore the second arg
+            Abs => |a, _| a.abs(),
+            Cbrt => |a, _| a.cbrt(),
+            Ceil => |a, _| a.ceil(),
+            Floor => |a, _| a.floor(),
+            Ln => |a, _| a.ln(),
+            Log10 => |a, _| a.log10(),
+            Round => |a, _| a.round(),
+            Sign => |a, _| a.signum(),
+            Sqrt => |a, _| a.sqrt(),
+            Trunc => |a, _| a.trunc(),
+            Exp => |a, _| a.exp(),
+            RoundN => |a, b| {
+                let scale = 10f64.powf(b);
+                (a * scale).round() / scale
+            },
+        }
+    }
 }
 
 pub fn apply(
@@ -37,31 +71,25 @@ pub fn apply(
     function: Function,
     rhs: f64,
 ) -> TimeSeries<'_> {
-    let function: fn(f64, f64) -> f64 = match function {
-        Add => |a, b| a + b,
-        Sub => |a, b| a - b,
-        Mul => |a, b| a * b,
-        Div => |a, b| a / b,
-        // TODO is this the right mod?
-        Mod => |a, b| a % b,
-        Power => |a, b| a.powf(b),
-        LogN => |a, b| a.log(b),
-        // unary functions just ignore the second arg
-        Abs => |a, _| a.abs(),
-        Cbrt => |a, _| a.cbrt(),
-        Ceil => |a, _| a.ceil(),
-        Floor => |a, _| a.floor(),
-        Ln => |a, _| a.ln(),
-        Log10 => |a, _| a.log10(),
-        Round => |a, _| a.round(),
-        Sign => |a, _| a.signum(),
-        Sqrt => |a, _| a.sqrt(),
-        Trunc => |a, _| a.trunc(),
-    };
+    let function = function.as_fn();
     map::map_series(&mut series, |lhs| function(lhs, rhs));
     series
 }
 
+// applies a chain of arithmetic elements in a single pass over the series
+pub fn apply_all(
+    mut series: TimeSeries<'_>,
+    functions: &[(Function, f64)],
+) -> TimeSeries<'_> {
+    let functions: Vec<_> = functions.iter()
+        .map(|(function, rhs)| (function.as_fn(), *rhs))
+        .collect();
+    map::map_series(&mut series, |val| {
+        functions.iter().fold(val, |lhs, (function, rhs)| function(lhs, *rhs))
+    });
+    series
+}
+
 //
 // binary operations
 //
@@ -138,6 +166,19 @@ pub fn pipeline_power<'e>(
     Arithmetic { function: Power, rhs: rhs }.flatten()
 }
 
+// round(double) is unary, this overload rounds to a number of decimal digits
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="round",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_round_n<'e>(
+    digits: i32,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Arithmetic { function: RoundN, rhs: digits as f64 }.flatten()
+}
+
 // log(double) already exists as the log base 10 so we need a new name
 #[pg_extern(
     immutable,
@@ -188,6 +229,17 @@ pub fn pipeline_ceil<'e>()
     Arithmetic { function: Ceil, rhs: 0.0 }.flatten()
 }
 
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="exp",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_exp<'e>()
+-> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Arithmetic { function: Exp, rhs: 0.0 }.flatten()
+}
+
 #[pg_extern(
     immutable,
     parallel_safe,
@@ -393,6 +445,22 @@ mod tests {
                 (ts:\"2020-01-02 00:00:00+00\",val:1.1760912590556811),\
                 (ts:\"2020-01-05 00:00:00+00\",val:1.4771212547196624)\
             ]");
+
+            // runs of arithmetic elements are applied in a single pass, in order
+            let val = client.select(
+                &format!("SELECT (series -> add(1) -> mul(2) -> sub(1))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:51),\
+                (ts:\"2020-01-01 00:00:00+00\",val:21),\
+                (ts:\"2020-01-03 00:00:00+00\",val:41),\
+                (ts:\"2020-01-02 00:00:00+00\",val:31),\
+                (ts:\"2020-01-05 00:00:00+00\",val:61)\
+            ]");
         });
     }
 
@@ -571,4 +639,84 @@ mod tests {
             ]");
         });
     }
-}
\ No newline at end of file
+
+    #[pg_test]
+    fn test_positive_arith_unaryops() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // all values are positive so none of these produce NaNs
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 100.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 16.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 10000.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> sqrt())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:10),\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-05 00:00:00+00\",val:100)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> log10())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1.2041199826559248),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.6020599913279624),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> ln() -> exp() -> round(6))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:100),\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:16),\
+                (ts:\"2020-01-02 00:00:00+00\",val:4),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10000)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> log10() -> round(2))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1.2),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.6),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4)\
+            ]");
+        });
+    }
+}
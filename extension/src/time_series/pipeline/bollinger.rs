@@ -0,0 +1,163 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use crate::build;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum BollingerBand {
+    Upper,
+    Middle,
+    Lower,
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="bollinger",
+    schema="toolkit_experimental"
+)]
+pub fn bollinger_pipeline_element<'e>(
+    window: i32,
+    k: default!(f64, 2.0),
+    band: default!(&str, "middle"),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if window <= 0 {
+        error!("bollinger window must be positive")
+    }
+    if !(k >= 0.0) {
+        error!("bollinger k must not be negative")
+    }
+    let band = match band.to_lowercase().as_str() {
+        "upper" => BollingerBand::Upper,
+        "middle" => BollingerBand::Middle,
+        "lower" => BollingerBand::Lower,
+        _ => panic!("Invalid bollinger band, expected 'upper', 'middle', or 'lower'")
+    };
+
+    Element::Bollinger {
+        window: window as u64,
+        k,
+        band,
+    }.flatten()
+}
+
+// Bollinger bands: the middle band is the mean of the trailing `window`
+// points, the upper and lower bands are `k` (population) standard deviations
+// above and below it. Points before the first full window are dropped.
+pub fn bollinger_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    element: &toolkit_experimental::Element
+) -> toolkit_experimental::TimeSeries<'s> {
+    let (window, k, band) = match element {
+        Element::Bollinger{window, k, band} => (*window as usize, *k, *band),
+        _ => panic!("Bollinger evaluator called on incorrect pipeline element")
+    };
+
+    if !series.is_sorted() {
+        panic!("can only compute bollinger bands for sorted timeseries");
+    }
+
+    let points: Vec<TSPoint> = series.iter().collect();
+    let result: Vec<TSPoint> = points.windows(window)
+        .map(|trailing| {
+            let n = window as f64;
+            let mean = trailing.iter().map(|p| p.val).sum::<f64>() / n;
+            let variance = trailing.iter()
+                .map(|p| (p.val - mean) * (p.val - mean))
+                .sum::<f64>() / n;
+            let val = match band {
+                BollingerBand::Upper => mean + k * variance.sqrt(),
+                BollingerBand::Middle => mean,
+                BollingerBand::Lower => mean - k * variance.sqrt(),
+            };
+            TSPoint{ ts: trailing[window - 1].ts, val }
+        })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: result.len() as u64,
+                points: result.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_bollinger() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 5.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 7.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 9.0)) as v(time, value)";
+
+            let band = |band: &str| {
+                client.select(
+                    &format!("SELECT (series -> sort() -> bollinger(2, 2, '{}'))::TEXT FROM ({}) s", band, create_series),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            assert_eq!(band("middle"), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:3),\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-04 00:00:00+00\",val:4.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:6),\
+                (ts:\"2020-01-06 00:00:00+00\",val:8)\
+            ]");
+            assert_eq!(band("upper"), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:5),\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-04 00:00:00+00\",val:5.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:8),\
+                (ts:\"2020-01-06 00:00:00+00\",val:10)\
+            ]");
+            assert_eq!(band("lower"), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-04 00:00:00+00\",val:3.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4),\
+                (ts:\"2020-01-06 00:00:00+00\",val:6)\
+            ]");
+
+            // a window longer than the series has no full windows
+            let val = client.select(
+                &format!("SELECT (series -> sort() -> bollinger(10))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[]");
+        });
+    }
+}
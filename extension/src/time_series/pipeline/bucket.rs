@@ -0,0 +1,459 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use crate::utilities::USECS_PER_DAY;
+
+type Interval = pg_sys::Datum;
+
+// buckets are aligned the same way time_bucket() aligns them by default:
+// relative to Monday 2000-01-03, which is two days after the postgres epoch
+const BUCKET_ORIGIN: i64 = 2 * USECS_PER_DAY;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum BucketAggregate {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+}
+
+impl BucketAggregate {
+    fn aggregate(&self, vals: &[f64]) -> f64 {
+        match self {
+            BucketAggregate::Avg => vals.iter().sum::<f64>() / vals.len() as f64,
+            BucketAggregate::Sum => vals.iter().sum(),
+            BucketAggregate::Min => vals.iter().cloned().fold(f64::INFINITY, f64::min),
+            BucketAggregate::Max => vals.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            BucketAggregate::Count => vals.len() as f64,
+            BucketAggregate::First => vals[0],
+            BucketAggregate::Last => vals[vals.len() - 1],
+        }
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+// With a `timezone` the buckets follow its wall clock, so that e.g. days start
+// at local midnight on either side of a DST transition, and a month of buckets
+// holds its whole month. A width of months can't be mixed with smaller units.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="bucket",
+    schema="toolkit_experimental"
+)]
+pub fn bucket_pipeline_element<'e>(
+    width: Interval,
+    aggregate: default!(&str, "avg"),
+    timezone: default!(Option<&str>, NULL),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let (months, width) = interval_parts(width);
+    let aggregate = parse_aggregate(aggregate);
+
+    // months vary in length, so they're always bucketed by the calendar
+    if months != 0 || timezone.is_some() {
+        return calendar_bucket_element(months, width, aggregate, timezone.unwrap_or("UTC")).flatten()
+    }
+    if width <= 0 {
+        error!("bucket width must be positive")
+    }
+
+    Element::Bucket {
+        width,
+        aggregate,
+    }.flatten()
+}
+
+// The start of the bucket containing `ts`, the same as the timestamps the
+// timezone-aware bucket() element outputs, for grouping the raw data the same
+// way in regular aggregates.
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn calendar_bucket(
+    width: Interval,
+    ts: pg_sys::TimestampTz,
+    timezone: default!(&str, "UTC"),
+) -> pg_sys::TimestampTz {
+    let (months, width) = interval_parts(width);
+    check_calendar_width(months, width);
+    let local = local_bucket_start(months, width, to_local_time(timezone, ts));
+    from_local_time(timezone, local)
+}
+
+// the months and the rest of the interval in microseconds, days are treated as
+// exactly 24 hours
+fn interval_parts(interval: Interval) -> (i64, i64) {
+    unsafe {
+        let interval = interval as *const pg_sys::Interval;
+        ((*interval).month as i64, (*interval).day as i64 * USECS_PER_DAY + (*interval).time)
+    }
+}
+
+fn parse_aggregate(aggregate: &str) -> BucketAggregate {
+    match aggregate.to_lowercase().as_str() {
+        "avg" | "average" => BucketAggregate::Avg,
+        "sum" => BucketAggregate::Sum,
+        "min" => BucketAggregate::Min,
+        "max" => BucketAggregate::Max,
+        "count" => BucketAggregate::Count,
+        "first" => BucketAggregate::First,
+        "last" => BucketAggregate::Last,
+        _ => error!("unknown bucket aggregate '{}'. Valid aggregates are 'avg', 'sum', 'min', 'max', 'count', 'first' and 'last'", aggregate),
+    }
+}
+
+fn check_calendar_width(months: i64, width: i64) {
+    if months < 0 || width < 0 || months == 0 && width == 0 {
+        error!("bucket width must be positive")
+    }
+    if months != 0 && width != 0 {
+        error!("bucket widths of months can't also have days or smaller units")
+    }
+}
+
+fn calendar_bucket_element(months: i64, width: i64, aggregate: BucketAggregate, timezone: &str) -> Element<'_> {
+    check_calendar_width(months, width);
+    // fail now instead of when the pipeline runs if the timezone doesn't exist
+    to_local_time(timezone, 0);
+
+    Element::CalendarBucket {
+        months,
+        width,
+        aggregate,
+        timezone_len: timezone.len() as u64,
+        timezone: timezone.as_bytes().into(),
+    }
+}
+
+// Groups the points into `width`-sized time buckets and replaces each
+// non-empty bucket with a single point at the bucket's start time.
+pub fn bucket_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    width: i64,
+    aggregate: BucketAggregate,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only bucket sorted timeseries");
+    }
+
+    let bucket_of = |ts: i64| (ts - BUCKET_ORIGIN).div_euclid(width) * width + BUCKET_ORIGIN;
+
+    let mut points = Vec::new();
+    let mut current = None;
+    let mut vals = Vec::new();
+    for TSPoint{ ts, val } in series.iter() {
+        let bucket = bucket_of(ts);
+        if current != Some(bucket) {
+            if let Some(ts) = current {
+                points.push(TSPoint{ ts, val: aggregate.aggregate(&vals) });
+            }
+            current = Some(bucket);
+            vals.clear();
+        }
+        vals.push(val);
+    }
+    if let Some(ts) = current {
+        points.push(TSPoint{ ts, val: aggregate.aggregate(&vals) });
+    }
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: points.len() as u64,
+                points: points.into(),
+            }
+        }
+    )
+}
+
+// the wall clock time in `timezone` at `ts`, as a timestamp without timezone
+fn to_local_time(timezone: &str, ts: i64) -> i64 {
+    // pgx's bindings wrap the functions, so we need the C one to call it
+    extern "C" {
+        fn timestamptz_zone(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe {
+        pg_sys::DirectFunctionCall2Coll(
+            Some(timestamptz_zone),
+            pg_sys::InvalidOid,
+            timezone.into_datum().unwrap(),
+            ts as pg_sys::Datum,
+        ) as i64
+    }
+}
+
+// the time at which the wall clock in `timezone` shows `local`
+fn from_local_time(timezone: &str, local: i64) -> i64 {
+    extern "C" {
+        fn timestamp_zone(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe {
+        pg_sys::DirectFunctionCall2Coll(
+            Some(timestamp_zone),
+            pg_sys::InvalidOid,
+            timezone.into_datum().unwrap(),
+            local as pg_sys::Datum,
+        ) as i64
+    }
+}
+
+// days since 2000-01-01 of a date in the proleptic Gregorian calendar, and
+// back, see http://howardhinnant.github.io/date_algorithms.html
+const DAYS_FROM_CIVIL_EPOCH_TO_2000: i64 = 730_425;
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - DAYS_FROM_CIVIL_EPOCH_TO_2000
+}
+
+fn civil_from_days(days: i64) -> (i64, i64) {
+    let days = days + DAYS_FROM_CIVIL_EPOCH_TO_2000;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+// the start of the bucket containing the local time `local`, months are
+// aligned to 2000-01-01 and fixed widths the same as bucket_timeseries()
+fn local_bucket_start(months: i64, width: i64, local: i64) -> i64 {
+    if months == 0 {
+        return (local - BUCKET_ORIGIN).div_euclid(width) * width + BUCKET_ORIGIN
+    }
+    let (year, month) = civil_from_days(local.div_euclid(USECS_PER_DAY));
+    let index = ((year - 2000) * 12 + month - 1).div_euclid(months) * months;
+    days_from_civil(2000 + index.div_euclid(12), index.rem_euclid(12) + 1, 1) * USECS_PER_DAY
+}
+
+// Like bucket_timeseries(), but the buckets are aligned to the wall clock of
+// `timezone`, and are either a number of calendar months, aligned to
+// 2000-01-01, or of a fixed `width` aligned as time_bucket() does. Buckets are
+// still placed at the time they start.
+pub fn calendar_bucket_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    months: i64,
+    width: i64,
+    aggregate: BucketAggregate,
+    timezone: &[u8],
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only bucket sorted timeseries");
+    }
+    let timezone = std::str::from_utf8(timezone).unwrap();
+
+    // when the clocks go back the same local time occurs twice, so the points
+    // of a bucket aren't necessarily contiguous
+    let mut buckets: std::collections::BTreeMap<i64, Vec<f64>> = Default::default();
+    for TSPoint{ ts, val } in series.iter() {
+        let bucket = local_bucket_start(months, width, to_local_time(timezone, ts));
+        buckets.entry(bucket).or_default().push(val);
+    }
+
+    let mut points: Vec<TSPoint> = buckets.iter()
+        .map(|(&local, vals)| TSPoint{ ts: from_local_time(timezone, local), val: aggregate.aggregate(vals) })
+        .collect();
+    points.sort_by_key(|point| point.ts);
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: points.len() as u64,
+                points: points.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_bucket() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 00:01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-01 00:03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-01 00:05 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-01 00:09 UTC'::TIMESTAMPTZ, 40.0), \
+                    ('2020-01-01 00:21 UTC'::TIMESTAMPTZ, 50.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('5 minutes'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:15),\
+                (ts:\"2020-01-01 00:05:00+00\",val:35),\
+                (ts:\"2020-01-01 00:20:00+00\",val:50)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('5 minutes', 'max') -> bucket('10 minutes', 'sum'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:60),\
+                (ts:\"2020-01-01 00:20:00+00\",val:50)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('1 day', 'count'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[(ts:\"2020-01-01 00:00:00+00\",val:5)]");
+        });
+    }
+
+    #[pg_test]
+    fn test_pipeline_calendar_bucket() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // months of different lengths
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-15 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-31 23:59 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-02-29 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-03-01 UTC'::TIMESTAMPTZ, 40.0), \
+                    ('2020-05-01 UTC'::TIMESTAMPTZ, 50.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('1 month', 'sum'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:30),\
+                (ts:\"2020-02-01 00:00:00+00\",val:30),\
+                (ts:\"2020-03-01 00:00:00+00\",val:40),\
+                (ts:\"2020-05-01 00:00:00+00\",val:50)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('1 year', 'count'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[(ts:\"2020-01-01 00:00:00+00\",val:5)]");
+
+            // in Berlin 2020-03-29 is only 23 hours long, and 2020-10-25 is 25
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-03-28 22:30 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-03-29 21:30 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-03-29 22:30 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-10-25 00:30 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-10-25 01:30 UTC'::TIMESTAMPTZ, 5.0), \
+                    ('2020-10-25 22:30 UTC'::TIMESTAMPTZ, 6.0), \
+                    ('2020-10-25 23:30 UTC'::TIMESTAMPTZ, 7.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> bucket('1 day', aggregate => 'sum', timezone => 'Europe/Berlin'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-03-27 23:00:00+00\",val:1),\
+                (ts:\"2020-03-28 23:00:00+00\",val:2),\
+                (ts:\"2020-03-29 22:00:00+00\",val:3),\
+                (ts:\"2020-10-24 22:00:00+00\",val:15),\
+                (ts:\"2020-10-25 23:00:00+00\",val:7)\
+            ]");
+
+            // both times read 02:30 on the Berlin wall clock
+            let val = client.select(
+                &format!("SELECT (series -> bucket('1 hour', timezone => 'Europe/Berlin'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert!(val.contains("val:4.5)"), "{}", val);
+
+            // timezones aren't limited in length, this POSIX-style one follows
+            // the same rules as New York
+            let (posix, named) = client.select(
+                &format!("SELECT \
+                        (series -> bucket('1 day', timezone => 'EST5EDT,M3.2.0/2:00:00,M11.1.0/2:00:00'))::TEXT, \
+                        (series -> bucket('1 day', timezone => 'America/New_York'))::TEXT \
+                    FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(posix, named);
+
+            let val = client.select(
+                "SELECT calendar_bucket('1 month', '2020-10-31 23:30 UTC', 'Europe/Berlin')::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "2020-10-31 23:00:00+00");
+        });
+    }
+
+    #[pg_test(error = "bucket widths of months can't also have days or smaller units")]
+    fn test_pipeline_calendar_bucket_mixed_width() {
+        Spi::execute(|client| {
+            client.select("SELECT toolkit_experimental.bucket('1 month 1 day', 'avg', 'UTC')", None, None);
+        });
+    }
+
+    #[pg_test(error = "unknown bucket aggregate 'avgg'. Valid aggregates are 'avg', 'sum', 'min', 'max', 'count', 'first' and 'last'")]
+    fn test_pipeline_bucket_unknown_aggregate() {
+        Spi::execute(|client| {
+            client.select("SELECT toolkit_experimental.bucket('1 hour', 'avgg')", None, None);
+        });
+    }
+}
@@ -0,0 +1,149 @@
+use pgx::*;
+
+use super::*;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="clamp",
+    schema="toolkit_experimental"
+)]
+pub fn clamp_pipeline_element<'e>(
+    min: f64,
+    max: f64,
+    drop_outliers: default!(bool, false),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if min > max {
+        error!("clamp min must not be greater than max")
+    }
+
+    Element::Clamp {
+        min,
+        max,
+        drop_outliers: if drop_outliers {1} else {0},
+    }.flatten()
+}
+
+pub fn clamp_timeseries<'s>(
+    mut series: toolkit_experimental::TimeSeries<'s>,
+    element: &toolkit_experimental::Element
+) -> toolkit_experimental::TimeSeries<'s> {
+    let (min, max, drop_outliers) = match element {
+        Element::Clamp{min, max, drop_outliers} => (*min, *max, *drop_outliers == 1),
+        _ => panic!("Clamp evaluator called on incorrect pipeline element")
+    };
+
+    // NaNs are neither in nor out of the range, so they're kept as they are
+    if !drop_outliers {
+        map::map_series(&mut series, |val| if val.is_nan() { val } else { val.max(min).min(max) });
+        return series
+    }
+
+    let points: Vec<_> = series.iter()
+        .filter(|TSPoint{ val, .. }| val.is_nan() || *val >= min && *val <= max)
+        .collect();
+
+    // dropping points preserves ordering, but not normality
+    if series.is_sorted() {
+        build!(
+            TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
+                series: SeriesType::SortedSeries {
+                    num_points: points.len() as u64,
+                    points: points.into(),
+                }
+            }
+        )
+    } else {
+        build!(
+            TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
+                series: SeriesType::ExplicitSeries {
+                    num_points: points.len() as u64,
+                    points: points.into(),
+                }
+            }
+        )
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_clamp() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, -999.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 9999.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> clamp(0, 100))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-05 00:00:00+00\",val:100)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> clamp(0, 100, drop_outliers => true))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15)\
+            ]");
+
+            // NaN is passed through unchanged
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, -999.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 'NaN'), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 50.0)) as v(time, value)";
+            let (clamped, dropped) = client.select(
+                &format!("SELECT \
+                        (series -> clamp(0, 100))::TEXT, \
+                        (series -> clamp(0, 100, drop_outliers => true))::TEXT \
+                    FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(clamped.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-03 00:00:00+00\",val:50)\
+            ]");
+            assert_eq!(dropped.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-03 00:00:00+00\",val:50)\
+            ]");
+        });
+    }
+}
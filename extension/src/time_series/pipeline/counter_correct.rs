@@ -0,0 +1,106 @@
+use pgx::*;
+
+use super::*;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="counter_correct",
+    schema="toolkit_experimental"
+)]
+pub fn counter_correct_pipeline_element<'e>(
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Element::CounterCorrect {}.flatten()
+}
+
+// Treats the series as a raw counter and removes its resets. As in
+// counter_agg, every time the value decreases the counter is assumed to have
+// reset, and the value seen before the reset is added to all later points.
+pub fn counter_correct_timeseries<'s>(
+    mut series: toolkit_experimental::TimeSeries<'s>,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only correct counter resets for sorted timeseries");
+    }
+
+    // sorted series store their values in time order, so we can correct in place
+    let mut prev: Option<f64> = None;
+    let mut reset_sum = 0.0;
+    map::map_series(&mut series, |val| {
+        if let Some(prev) = prev {
+            if val < prev {
+                reset_sum += prev;
+            }
+        }
+        prev = Some(val);
+        val + reset_sum
+    });
+    series
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_counter_correct() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 40), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 5), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 20)",
+                None,
+                None
+            );
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> counter_correct())::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:40),\
+                (ts:\"2020-01-03 00:00:00+00\",val:45),\
+                (ts:\"2020-01-04 00:00:00+00\",val:55),\
+                (ts:\"2020-01-05 00:00:00+00\",val:55),\
+                (ts:\"2020-01-06 00:00:00+00\",val:75)\
+            ]");
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> counter_correct() -> delta())::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:30),\
+                (ts:\"2020-01-03 00:00:00+00\",val:5),\
+                (ts:\"2020-01-04 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0),\
+                (ts:\"2020-01-06 00:00:00+00\",val:20)\
+            ]");
+        });
+    }
+}
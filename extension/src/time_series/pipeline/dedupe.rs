@@ -0,0 +1,168 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum DedupeMethod {
+    First,
+    Last,
+    Mean,
+}
+
+impl DedupeMethod {
+    pub fn process<'s>(&self, series: TimeSeries<'s>) -> TimeSeries<'s> {
+        let points: Vec<TSPoint> = match &series.series {
+            // normal series can't contain duplicate timestamps
+            SeriesType::GappyNormalSeries{..} | SeriesType::NormalSeries{..} => return series,
+            SeriesType::SortedSeries{points, ..} => points.iter().collect(),
+            SeriesType::ExplicitSeries{points, ..} => {
+                let mut points: Vec<_> = points.iter().collect();
+                // sort_by is stable, so duplicates remain in insertion order
+                points.sort_by(|a, b| a.ts.cmp(&b.ts));
+                points
+            },
+            // NULL points are treated as missing
+            SeriesType::NullableSeries{..} | SeriesType::CompressedSeries{..} => {
+                let mut points: Vec<_> = series.iter().collect();
+                points.sort_by(|a, b| a.ts.cmp(&b.ts));
+                points
+            },
+        };
+
+        let mut deduped: Vec<TSPoint> = Vec::with_capacity(points.len());
+        let mut run_len = 0;
+        for point in points {
+            match deduped.last_mut() {
+                Some(last) if last.ts == point.ts => {
+                    run_len += 1;
+                    match self {
+                        DedupeMethod::First => (),
+                        DedupeMethod::Last => last.val = point.val,
+                        // incremental mean so we don't need to store the run
+                        DedupeMethod::Mean =>
+                            last.val += (point.val - last.val) / run_len as f64,
+                    }
+                },
+                _ => {
+                    run_len = 1;
+                    deduped.push(point);
+                },
+            }
+        }
+
+        build!(
+            TimeSeries {
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
+                series: SeriesType::SortedSeries {
+                    num_points: deduped.len() as u64,
+                    points: deduped.into(),
+                }
+            }
+        )
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="dedupe",
+    schema="toolkit_experimental"
+)]
+pub fn dedupe_pipeline_element<'e>(
+    method: default!(&str, "last"),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let method = match method.to_lowercase().as_str() {
+        "first" => DedupeMethod::First,
+        "last" => DedupeMethod::Last,
+        "mean" | "average" => DedupeMethod::Mean,
+        _ => panic!("Invalid dedupe method")
+    };
+
+    Element::Dedupe {
+        method
+    }.flatten()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_dedupe() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 40.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> dedupe('first'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:20),\
+                (ts:\"2020-01-03 00:00:00+00\",val:30)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> dedupe())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:30),\
+                (ts:\"2020-01-03 00:00:00+00\",val:40)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> dedupe('mean'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:25),\
+                (ts:\"2020-01-03 00:00:00+00\",val:35)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> dedupe() -> delta())::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:20),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10)\
+            ]");
+        });
+    }
+}
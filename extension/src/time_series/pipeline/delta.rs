@@ -32,7 +32,7 @@ pub fn timeseries_delta<'s>(
 
     let mut it = series.iter();
     let mut prev = it.next().unwrap().val;
-    let mut delta_points = Vec::new();
+    let mut delta_points = Vec::with_capacity(series.num_points() - 1);
 
     for pt in it {
         delta_points.push(TSPoint{ts: pt.ts, val: pt.val - prev});
@@ -41,6 +41,8 @@ pub fn timeseries_delta<'s>(
 
     build!(
         TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: SeriesType::SortedSeries {
                 num_points: delta_points.len() as u64,
                 points: delta_points.into(),
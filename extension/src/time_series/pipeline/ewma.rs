@@ -0,0 +1,143 @@
+use pgx::*;
+
+use super::*;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="ewma",
+    schema="toolkit_experimental"
+)]
+pub fn ewma_pipeline_element<'e>(
+    alpha: f64,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    check_alpha(alpha);
+    Element::Ewma { alpha }.flatten()
+}
+
+// returns the final smoothed value, i.e. the value the last point of
+// `series -> ewma(alpha)` would have
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="ewma",
+    schema="toolkit_experimental"
+)]
+pub fn timeseries_ewma<'s>(
+    series: toolkit_experimental::TimeSeries<'s>,
+    alpha: f64,
+) -> Option<f64> {
+    check_alpha(alpha);
+    if !series.is_sorted() {
+        panic!("can only compute ewma for sorted timeseries");
+    }
+
+    series.iter()
+        .map(|TSPoint{ val, .. }| val)
+        .fold(None, |prev, val| Some(smooth(prev, val, alpha)))
+}
+
+fn check_alpha(alpha: f64) {
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        error!("ewma alpha must be in the range (0, 1]")
+    }
+}
+
+fn smooth(prev: Option<f64>, val: f64, alpha: f64) -> f64 {
+    match prev {
+        None => val,
+        Some(prev) => alpha * val + (1.0 - alpha) * prev,
+    }
+}
+
+pub fn timeseries_ewma_element<'s>(
+    mut series: toolkit_experimental::TimeSeries<'s>,
+    alpha: f64,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only compute ewma for sorted timeseries");
+    }
+
+    // sorted series store their values in time order, so we can smooth in place
+    let mut prev = None;
+    map::map_series(&mut series, |val| {
+        let smoothed = smooth(prev, val, alpha);
+        prev = Some(smoothed);
+        smoothed
+    });
+    series
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_ewma() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30)",
+                None,
+                None
+            );
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> ewma(0.5))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:12.5),\
+                (ts:\"2020-01-03 00:00:00+00\",val:16.25),\
+                (ts:\"2020-01-04 00:00:00+00\",val:20.625),\
+                (ts:\"2020-01-05 00:00:00+00\",val:25.3125)\
+            ]");
+
+            let val = client.select(
+                "SELECT ewma(timeseries(time, value) -> sort(), 0.5) FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 25.3125);
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> ewma(1.0))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30)\
+            ]");
+        });
+    }
+}
@@ -20,7 +20,7 @@ pg_type! {
     #[derive(Debug)]
     struct PipelineThenUnnest<'input> {
         num_elements: u64,
-        elements: [Element; self.num_elements],
+        elements: [Element<'input>; self.num_elements],
     }
 }
 
@@ -43,8 +43,8 @@ pub fn pipeline_unnest<'e>() -> toolkit_experimental::PipelineThenUnnest<'e> {
 
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
-pub fn arrow_finalize_with_unnest<'p, 'e>(
-    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'p>,
+pub fn arrow_finalize_with_unnest<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
     then_stats_agg: toolkit_experimental::PipelineThenUnnest<'e>,
 ) -> toolkit_experimental::PipelineThenUnnest<'e> {
     if then_stats_agg.num_elements == 0 {
@@ -80,6 +80,38 @@ pub fn arrow_run_pipeline_then_unnest<'s, 'p>(
     crate::time_series::unnest(series.into())
 }
 
+// The number of points of a constant timevector for the planner, see
+// estimate_srf_rows(). Pipelines run before `-> unnest()` may change the
+// number of points, so there it's an estimate.
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn unnest_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |series: TimeSeries| series.iter().count())
+    }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+DECLARE
+    srf regprocedure;
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        FOR srf IN
+            SELECT p.oid::regprocedure FROM pg_catalog.pg_proc p
+            WHERE p.proname IN ('unnest', 'arrow_run_pipeline_then_unnest')
+                AND p.pronargs >= 1
+                AND p.proargtypes[0] = 'toolkit_experimental.TimeSeries'::regtype
+        LOOP
+            EXECUTE format('ALTER FUNCTION %s SUPPORT toolkit_experimental.unnest_rows_support', srf);
+        END LOOP;
+    END IF;
+END
+$$;
+"#);
+
 
 
 #[pg_extern(
@@ -160,4 +192,37 @@ mod tests {
             assert_eq!(val.unwrap(), "[(ts:\"2020-01-04 00:00:00+00\",val:25),(ts:\"2020-01-01 00:00:00+00\",val:11),(ts:\"2020-01-03 00:00:00+00\",val:21),(ts:\"2020-01-02 00:00:00+00\",val:15),(ts:\"2020-01-05 00:00:00+00\",val:31)]");
         });
     }
-}
\ No newline at end of file
+
+    #[pg_test]
+    fn test_unnest_rows_estimate() {
+        Spi::execute(|client| {
+            let version = client.select("SELECT current_setting('server_version_num')::int", None, None)
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            if version < 120000 {
+                return
+            }
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            let series = "'[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:3)\
+            ]'::timeseries";
+            for query in &[
+                format!("EXPLAIN SELECT * FROM unnest({})", series),
+                format!("EXPLAIN SELECT {} -> unnest()", series),
+            ] {
+                let plan = client.select(query, None, None)
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert!(plan.contains("rows=3 "), "{}", plan);
+            }
+        });
+    }
+}
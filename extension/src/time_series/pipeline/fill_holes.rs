@@ -35,6 +35,8 @@ impl FillMethod {
 
                         build!(
                             TimeSeries {
+                                num_label_bytes: 0,
+                                label_bytes: vec![].into(),
                                 series : SeriesType::NormalSeries {
                                     start_ts: *start_ts,
                                     step_interval: *step_interval,
@@ -59,6 +61,8 @@ impl FillMethod {
 
                         build!(
                             TimeSeries {
+                                num_label_bytes: 0,
+                                label_bytes: vec![].into(),
                                 series : SeriesType::NormalSeries {
                                     start_ts: *start_ts,
                                     step_interval: *step_interval,
@@ -0,0 +1,260 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum NullFillMethod {
+    LOCF,
+    Interpolate,
+    Drop,
+    Constant,
+}
+
+impl NullFillMethod {
+    pub fn process<'s>(&self, series: TimeSeries<'s>, constant: f64) -> TimeSeries<'s> {
+        if !series.has_nulls() {
+            return series
+        }
+
+        let sorted = series.is_sorted();
+        let points: Vec<TSPoint> = match self {
+            NullFillMethod::Drop => series.iter().collect(),
+            NullFillMethod::Constant => series.iter_with_nulls()
+                .map(|(ts, val)| TSPoint{ ts, val: val.unwrap_or(constant) })
+                .collect(),
+            NullFillMethod::LOCF => {
+                if !sorted {
+                    panic!("can only fill NULLs with LOCF for sorted timeseries");
+                }
+                // leading NULLs have no prior value to carry forward and are dropped
+                let mut last = None;
+                series.iter_with_nulls()
+                    .filter_map(|(ts, val)| {
+                        if val.is_some() {
+                            last = val;
+                        }
+                        last.map(|val| TSPoint{ ts, val })
+                    })
+                    .collect()
+            },
+            NullFillMethod::Interpolate => {
+                if !sorted {
+                    panic!("can only interpolate NULLs for sorted timeseries");
+                }
+                let values: Vec<_> = series.iter_with_nulls().collect();
+                let mut results = Vec::with_capacity(values.len());
+                let mut prev: Option<TSPoint> = None;
+                for (i, (ts, val)) in values.iter().enumerate() {
+                    match val {
+                        Some(val) => {
+                            let point = TSPoint{ ts: *ts, val: *val };
+                            results.push(point);
+                            prev = Some(point);
+                        },
+                        None => {
+                            // leading and trailing NULLs have nothing to
+                            // interpolate between and are dropped
+                            let next = values[i..].iter()
+                                .find_map(|(ts, val)| val.map(|val| TSPoint{ ts: *ts, val }));
+                            if let (Some(prev), Some(next)) = (prev, next) {
+                                let val = prev.interpolate_linear(&next, *ts)
+                                    .unwrap_or(prev.val);
+                                results.push(TSPoint{ ts: *ts, val });
+                            }
+                        },
+                    }
+                }
+                results
+            },
+        };
+
+        if sorted {
+            build!(
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::SortedSeries {
+                        num_points: points.len() as u64,
+                        points: points.into(),
+                    }
+                }
+            )
+        } else {
+            build!(
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::ExplicitSeries {
+                        num_points: points.len() as u64,
+                        points: points.into(),
+                    }
+                }
+            )
+        }
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="fill_nulls",
+    schema="toolkit_experimental"
+)]
+pub fn fill_nulls_pipeline_element<'e>(
+    fill_method: String,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let fill_method = match fill_method.to_lowercase().as_str() {
+        "locf" => NullFillMethod::LOCF,
+        "interpolate" => NullFillMethod::Interpolate,
+        "linear" => NullFillMethod::Interpolate,
+        "drop" => NullFillMethod::Drop,
+        _ => panic!("Invalid fill_nulls method")
+    };
+
+    Element::FillNulls {
+        fill_method,
+        constant: 0.0,
+    }.flatten()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="fill_nulls",
+    schema="toolkit_experimental"
+)]
+pub fn fill_nulls_constant_pipeline_element<'e>(
+    value: f64,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Element::FillNulls {
+        fill_method: NullFillMethod::Constant,
+        constant: value,
+    }.flatten()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_fill_nulls() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries_with_nulls(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 40.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, NULL)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> fill_nulls('drop'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:40)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> fill_nulls('locf'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10),\
+                (ts:\"2020-01-04 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:40),\
+                (ts:\"2020-01-06 00:00:00+00\",val:40)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> fill_nulls('interpolate'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-04 00:00:00+00\",val:30),\
+                (ts:\"2020-01-05 00:00:00+00\",val:40)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> fill_nulls(0.0))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0),\
+                (ts:\"2020-01-05 00:00:00+00\",val:40),\
+                (ts:\"2020-01-06 00:00:00+00\",val:0)\
+            ]");
+
+            // the NULLs survive a round trip through the text format
+            let val = client.select(
+                &format!("SELECT series::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:None),\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:None),\
+                (ts:\"2020-01-04 00:00:00+00\",val:None),\
+                (ts:\"2020-01-05 00:00:00+00\",val:40),\
+                (ts:\"2020-01-06 00:00:00+00\",val:None)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series::TEXT::timeseries -> fill_nulls(0.0))::TEXT = (series -> fill_nulls(0.0))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
+
+            // timeseries() drops the points with NULL values
+            let val = client.select(
+                "SELECT timeseries(time, value)::TEXT FROM \
+                    (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, NULL), \
+                        ('2020-01-02 UTC'::TIMESTAMPTZ, 10.0)) as v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[(ts:\"2020-01-02 00:00:00+00\",val:10)]");
+        });
+    }
+}
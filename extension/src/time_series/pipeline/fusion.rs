@@ -0,0 +1,114 @@
+use pgx::*;
+
+use super::*;
+
+// Planner support for `run_pipeline`. Since `->` is left associative
+// `series -> a -> b` is planned as `run_pipeline(run_pipeline(series, a), b)`
+// which materializes the intermediate timeseries between `a` and `b`. When
+// both pipelines are constants we can rewrite this into
+// `run_pipeline(series, a -> b)` so that all the elements are run within a
+// single call. Returns a pointer to the replacement expression, or 0 if the
+// expression should be left unchanged.
+//
+// SupportRequestSimplify only exists on PostgreSQL 12 and later, on older
+// versions this is a no-op.
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+pub unsafe fn simplify(input: pg_sys::Datum) -> pg_sys::Datum {
+    let node = input as *mut pg_sys::Node;
+    if !is_a(node, pg_sys::NodeTag_T_SupportRequestSimplify) {
+        return 0
+    }
+
+    let request = node as *mut pg_sys::SupportRequestSimplify;
+    let fcall = (*request).fcall;
+    let args = PgList::<pg_sys::Node>::from_pg((*fcall).args);
+    if args.len() != 2 {
+        return 0
+    }
+
+    // the pipeline being added must be known at plan time ...
+    let outer_pipeline = match as_const(args.get_ptr(1).unwrap()) {
+        Some(pipeline) => pipeline,
+        None => return 0,
+    };
+
+    // ... and it must be applied to the output of another constant pipeline
+    let inner_args = match pipeline_call_args(args.get_ptr(0).unwrap(), (*fcall).funcid) {
+        Some(inner_args) => PgList::<pg_sys::Node>::from_pg(inner_args),
+        None => return 0,
+    };
+    if inner_args.len() != 2 {
+        return 0
+    }
+    let inner_pipeline = match as_const(inner_args.get_ptr(1).unwrap()) {
+        Some(pipeline) => pipeline,
+        None => return 0,
+    };
+
+    let first = UnstableTimeseriesPipeline::from_datum(
+        (*inner_pipeline).constvalue,
+        false,
+        (*inner_pipeline).consttype,
+    ).unwrap();
+    let second = UnstableTimeseriesPipeline::from_datum(
+        (*outer_pipeline).constvalue,
+        false,
+        (*outer_pipeline).consttype,
+    ).unwrap();
+    let fused = add_unstable_element(first, second);
+
+    let fused = pg_sys::makeConst(
+        (*outer_pipeline).consttype,
+        -1,
+        pg_sys::InvalidOid,
+        -1,
+        fused.into_datum().unwrap(),
+        false,
+        false,
+    );
+
+    let mut new_args = PgList::<pg_sys::Node>::new();
+    new_args.push(inner_args.get_ptr(0).unwrap());
+    new_args.push(fused as *mut pg_sys::Node);
+
+    let new_call = pg_sys::copyObjectImpl(fcall as *const _) as *mut pg_sys::FuncExpr;
+    (*new_call).args = new_args.into_pg();
+    new_call as pg_sys::Datum
+}
+
+#[cfg(not(any(feature = "pg12", feature = "pg13")))]
+pub unsafe fn simplify(_input: pg_sys::Datum) -> pg_sys::Datum {
+    0
+}
+
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+unsafe fn as_const(node: *mut pg_sys::Node) -> Option<*mut pg_sys::Const> {
+    if !is_a(node, pg_sys::NodeTag_T_Const) {
+        return None
+    }
+    let node = node as *mut pg_sys::Const;
+    if (*node).constisnull {
+        return None
+    }
+    Some(node)
+}
+
+// If `node` is a call to the function `funcid`, either directly or via an
+// operator, returns its arguments.
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+unsafe fn pipeline_call_args(node: *mut pg_sys::Node, funcid: pg_sys::Oid)
+-> Option<*mut pg_sys::List> {
+    if is_a(node, pg_sys::NodeTag_T_FuncExpr) {
+        let call = node as *mut pg_sys::FuncExpr;
+        if (*call).funcid == funcid {
+            return Some((*call).args)
+        }
+    }
+    if is_a(node, pg_sys::NodeTag_T_OpExpr) {
+        let call = node as *mut pg_sys::OpExpr;
+        if (*call).opfuncid == funcid {
+            return Some((*call).args)
+        }
+    }
+    None
+}
@@ -0,0 +1,194 @@
+use pgx::*;
+
+use super::*;
+
+// scales the median absolute deviation to a consistent estimator of the
+// standard deviation for normally distributed data
+const MAD_SCALE: f64 = 1.4826;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="hampel",
+    schema="toolkit_experimental"
+)]
+pub fn hampel_pipeline_element<'e>(
+    window: i32,
+    n_sigmas: default!(f64, 3.0),
+    drop_outliers: default!(bool, false),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if window <= 0 {
+        error!("hampel window must be positive")
+    }
+    if !(n_sigmas >= 0.0) {
+        error!("hampel n_sigmas must not be negative")
+    }
+
+    Element::Hampel {
+        window: window as u64,
+        n_sigmas,
+        drop_outliers: if drop_outliers {1} else {0},
+    }.flatten()
+}
+
+// Hampel filter: a point is an outlier if it is more than `n_sigmas` scaled
+// MADs away from the median of the `window` points on either side of it.
+// Outliers are replaced by that median, or removed if `drop_outliers` is set.
+pub fn hampel_timeseries<'s>(
+    mut series: toolkit_experimental::TimeSeries<'s>,
+    element: &toolkit_experimental::Element
+) -> toolkit_experimental::TimeSeries<'s> {
+    let (window, n_sigmas, drop_outliers) = match element {
+        Element::Hampel{window, n_sigmas, drop_outliers} =>
+            (*window as usize, *n_sigmas, *drop_outliers == 1),
+        _ => panic!("Hampel evaluator called on incorrect pipeline element")
+    };
+
+    if !series.is_sorted() {
+        panic!("can only apply hampel filter to sorted timeseries");
+    }
+
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    let mut scratch = Vec::with_capacity(2 * window + 1);
+    let filtered: Vec<Option<f64>> = (0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(window);
+            let end = (i + window + 1).min(values.len());
+
+            // NaNs have no place in the order, they're left out of the window
+            // and never counted as outliers
+            scratch.clear();
+            scratch.extend(values[start..end].iter().filter(|val| !val.is_nan()));
+            if scratch.is_empty() {
+                return Some(values[i])
+            }
+            let center = median(&mut scratch);
+
+            scratch.iter_mut().for_each(|val| *val = (*val - center).abs());
+            let mad = median(&mut scratch);
+
+            let is_outlier = (values[i] - center).abs() > n_sigmas * MAD_SCALE * mad;
+            match (is_outlier, drop_outliers) {
+                (false, _) => Some(values[i]),
+                (true, false) => Some(center),
+                (true, true) => None,
+            }
+        })
+        .collect();
+
+    if !drop_outliers {
+        // sorted series store their values in time order, so we can replace in place
+        let mut filtered = filtered.into_iter();
+        map::map_series(&mut series, |_| filtered.next().unwrap().unwrap());
+        return series
+    }
+
+    let points: Vec<_> = series.iter()
+        .zip(filtered)
+        .filter_map(|(point, val)| val.map(|_| point))
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: points.len() as u64,
+                points: points.into(),
+            }
+        }
+    )
+}
+
+// `values` must be non-empty and NaN-free
+pub(super) fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_hampel() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 11.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 100.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 11.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 11.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> hampel(2))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:11),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10),\
+                (ts:\"2020-01-04 00:00:00+00\",val:11),\
+                (ts:\"2020-01-05 00:00:00+00\",val:11),\
+                (ts:\"2020-01-06 00:00:00+00\",val:10),\
+                (ts:\"2020-01-07 00:00:00+00\",val:11)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> hampel(2, 3.0, true))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:11),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:11),\
+                (ts:\"2020-01-06 00:00:00+00\",val:10),\
+                (ts:\"2020-01-07 00:00:00+00\",val:11)\
+            ]");
+
+            // NaNs are left out of the windows and kept as they are
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> hampel(2))::TEXT FROM \
+                    (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                        ('2020-01-02 UTC'::TIMESTAMPTZ, 'NaN'), \
+                        ('2020-01-03 UTC'::TIMESTAMPTZ, 100.0), \
+                        ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0), \
+                        ('2020-01-05 UTC'::TIMESTAMPTZ, 10.0)) as v(time, value)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10),\
+                (ts:\"2020-01-04 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10)\
+            ]");
+        });
+    }
+}
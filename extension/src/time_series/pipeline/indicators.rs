@@ -0,0 +1,225 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use crate::build;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum MacdOutput {
+    Macd,
+    Signal,
+    Histogram,
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="rsi",
+    schema="toolkit_experimental"
+)]
+pub fn rsi_pipeline_element<'e>(
+    period: default!(i32, 14),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if period <= 0 {
+        error!("rsi period must be positive")
+    }
+
+    Element::Rsi {
+        period: period as u64,
+    }.flatten()
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="macd",
+    schema="toolkit_experimental"
+)]
+pub fn macd_pipeline_element<'e>(
+    fast: default!(i32, 12),
+    slow: default!(i32, 26),
+    signal: default!(i32, 9),
+    output: default!(&str, "macd"),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if fast <= 0 || slow <= 0 || signal <= 0 {
+        error!("macd periods must be positive")
+    }
+    if fast >= slow {
+        error!("macd fast period must be shorter than the slow period")
+    }
+    let output = match output.to_lowercase().as_str() {
+        "macd" => MacdOutput::Macd,
+        "signal" => MacdOutput::Signal,
+        "histogram" => MacdOutput::Histogram,
+        _ => panic!("Invalid macd output, expected 'macd', 'signal', or 'histogram'")
+    };
+
+    Element::Macd {
+        fast: fast as u64,
+        slow: slow as u64,
+        signal: signal as u64,
+        output,
+    }.flatten()
+}
+
+// Relative strength index using Wilder's smoothing: the average gain and loss
+// start as the mean of the first `period` changes, and each later change
+// contributes 1/period of the new average. The output starts at the point
+// completing the first `period` changes.
+pub fn rsi_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    period: u64,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only compute rsi for sorted timeseries");
+    }
+
+    let period = period as usize;
+    let points: Vec<TSPoint> = series.iter().collect();
+    let mut result = vec![];
+    let (mut avg_gain, mut avg_loss) = (0.0, 0.0);
+    for (i, pair) in points.windows(2).enumerate() {
+        let change = pair[1].val - pair[0].val;
+        let (gain, loss) = (change.max(0.0), (-change).max(0.0));
+        if i < period {
+            avg_gain += gain / period as f64;
+            avg_loss += loss / period as f64;
+            if i + 1 < period {
+                continue
+            }
+        } else {
+            avg_gain = (avg_gain * (period - 1) as f64 + gain) / period as f64;
+            avg_loss = (avg_loss * (period - 1) as f64 + loss) / period as f64;
+        }
+        let rsi = if avg_loss == 0.0 {
+            100.0
+        } else {
+            100.0 - 100.0 / (1.0 + avg_gain / avg_loss)
+        };
+        result.push(TSPoint{ ts: pair[1].ts, val: rsi });
+    }
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: result.len() as u64,
+                points: result.into(),
+            }
+        }
+    )
+}
+
+// Moving average convergence/divergence: the difference between a fast and a
+// slow exponential moving average, the signal line is an exponential moving
+// average of that difference. A period of `n` smooths with alpha 2/(n+1), and
+// each average is seeded with the first value as in ewma().
+pub fn macd_timeseries<'s>(
+    mut series: toolkit_experimental::TimeSeries<'s>,
+    element: &toolkit_experimental::Element
+) -> toolkit_experimental::TimeSeries<'s> {
+    let (fast, slow, signal, output) = match element {
+        Element::Macd{fast, slow, signal, output} => (*fast, *slow, *signal, *output),
+        _ => panic!("MACD evaluator called on incorrect pipeline element")
+    };
+
+    if !series.is_sorted() {
+        panic!("can only compute macd for sorted timeseries");
+    }
+
+    let alpha = |period: u64| 2.0 / (period as f64 + 1.0);
+    let smooth = |prev: Option<f64>, val: f64, alpha: f64| match prev {
+        None => val,
+        Some(prev) => alpha * val + (1.0 - alpha) * prev,
+    };
+    let (fast, slow, signal) = (alpha(fast), alpha(slow), alpha(signal));
+
+    // sorted series store their values in time order, so we can replace in place
+    let (mut fast_avg, mut slow_avg, mut signal_avg) = (None, None, None);
+    map::map_series(&mut series, |val| {
+        let fast_val = smooth(fast_avg, val, fast);
+        let slow_val = smooth(slow_avg, val, slow);
+        let macd = fast_val - slow_val;
+        let signal_val = smooth(signal_avg, macd, signal);
+        fast_avg = Some(fast_val);
+        slow_avg = Some(slow_val);
+        signal_avg = Some(signal_val);
+        match output {
+            MacdOutput::Macd => macd,
+            MacdOutput::Signal => signal_val,
+            MacdOutput::Histogram => macd - signal_val,
+        }
+    });
+    series
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_indicators() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 5.0)) as v(time, value)";
+            let run = |element: &str| {
+                client.select(
+                    &format!("SELECT (series -> sort() -> {})::TEXT FROM ({}) s", element, create_series),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            assert_eq!(run("rsi(2)"), "[\
+                (ts:\"2020-01-03 00:00:00+00\",val:100),\
+                (ts:\"2020-01-04 00:00:00+00\",val:60),\
+                (ts:\"2020-01-05 00:00:00+00\",val:84.61538461538461)\
+            ]");
+
+            assert_eq!(run("macd(1, 3, 3)"), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.5),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1.25),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0.125),\
+                (ts:\"2020-01-05 00:00:00+00\",val:1.0625)\
+            ]");
+            assert_eq!(run("macd(1, 3, 3, 'signal')"), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.25),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.75),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0.4375),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0.75)\
+            ]");
+            assert_eq!(run("macd(1, 3, 3, 'histogram')"), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.25),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.5),\
+                (ts:\"2020-01-04 00:00:00+00\",val:-0.3125),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0.3125)\
+            ]");
+        });
+    }
+}
@@ -0,0 +1,238 @@
+use std::mem::replace;
+
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use crate::{ron_inout_funcs, pg_type, build};
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum IntegralMethod {
+    Trapezoidal,
+    LOCF,
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct PipelineThenIntegral<'input> {
+        method: IntegralMethod,
+        unit: i64, // microseconds
+        num_elements: u64,
+        elements: [Element<'input>; self.num_elements],
+    }
+}
+
+ron_inout_funcs!(PipelineThenIntegral);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(PipelineThenIntegral);
+}
+
+fn parse_method(method: &str) -> IntegralMethod {
+    match method.to_lowercase().as_str() {
+        "trapezoidal" | "linear" => IntegralMethod::Trapezoidal,
+        "locf" => IntegralMethod::LOCF,
+        _ => panic!("Invalid integral method"),
+    }
+}
+
+fn parse_unit(unit: &str) -> i64 {
+    match unit.to_lowercase().as_str() {
+        "microsecond" | "microseconds" => 1,
+        "millisecond" | "milliseconds" => 1_000,
+        "second" | "seconds" => 1_000_000,
+        "minute" | "minutes" => 60 * 1_000_000,
+        "hour" | "hours" => 60 * 60 * 1_000_000,
+        "day" | "days" => 24 * 60 * 60 * 1_000_000,
+        _ => panic!("Invalid integral unit"),
+    }
+}
+
+// area under the curve of the series, with the time axis measured in `unit`s
+fn integrate(
+    series: &toolkit_experimental::TimeSeries<'_>,
+    method: IntegralMethod,
+    unit: i64,
+) -> Option<f64> {
+    if !series.is_sorted() {
+        panic!("can only compute integral for sorted timeseries");
+    }
+
+    let mut points = series.iter();
+    let mut prev = points.next()?;
+    let mut area = 0.0;
+    for point in points {
+        let width = (point.ts - prev.ts) as f64 / unit as f64;
+        area += match method {
+            IntegralMethod::Trapezoidal => width * (prev.val + point.val) / 2.0,
+            IntegralMethod::LOCF => width * prev.val,
+        };
+        prev = point;
+    }
+    Some(area)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="integral",
+    schema="toolkit_experimental"
+)]
+pub fn timeseries_integral<'s>(
+    series: toolkit_experimental::TimeSeries<'s>,
+    method: default!(&str, "trapezoidal"),
+    unit: default!(&str, "second"),
+) -> Option<f64> {
+    integrate(&series, parse_method(method), parse_unit(unit))
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_then_integral<'s, 'p>(
+    mut timeseries: toolkit_experimental::TimeSeries<'s>,
+    pipeline: toolkit_experimental::PipelineThenIntegral<'p>,
+) -> Option<f64> {
+    timeseries = run_pipeline_elements(timeseries, pipeline.elements.iter());
+    integrate(&timeseries, pipeline.method, pipeline.unit)
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn finalize_with_integral<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    then_integral: toolkit_experimental::PipelineThenIntegral<'e>,
+) -> toolkit_experimental::PipelineThenIntegral<'e> {
+    if then_integral.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {flatten! {
+            PipelineThenIntegral {
+                method: then_integral.method,
+                unit: then_integral.unit,
+                num_elements: pipeline.0.num_elements,
+                elements: pipeline.0.elements,
+            }
+        }}
+    }
+
+    let mut elements = replace(pipeline.elements.as_owned(), vec![]);
+    elements.extend(then_integral.elements.iter());
+    build! {
+        PipelineThenIntegral {
+            method: then_integral.method,
+            unit: then_integral.unit,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="integral",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_integral<'e>(
+    method: default!(&str, "trapezoidal"),
+    unit: default!(&str, "second"),
+) -> toolkit_experimental::PipelineThenIntegral<'e> {
+    build! {
+        PipelineThenIntegral {
+            method: parse_method(method),
+            unit: parse_unit(unit),
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+// FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
+//       need to ensure this works with out unstable warning
+extension_sql!(r#"
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."run_pipeline_then_integral",
+    LEFTARG=toolkit_experimental.TimeSeries,
+    RIGHTARG=toolkit_experimental.PipelineThenIntegral
+);
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."finalize_with_integral",
+    LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
+    RIGHTARG=toolkit_experimental.PipelineThenIntegral
+);
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_integral() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT integral(series, 'trapezoidal', 'day') FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 45.0);
+
+            let val = client.select(
+                &format!("SELECT integral(series, 'locf', 'day') FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 50.0);
+
+            let val = client.select(
+                &format!("SELECT series -> integral('trapezoidal', 'hour') FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 1080.0);
+
+            let val = client.select(
+                &format!("SELECT series -> mul(2) -> integral('locf', 'day') FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 100.0);
+
+            let val = client.select(
+                &format!("SELECT series -> (mul(2) -> integral('locf', 'day')) FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 100.0);
+        });
+    }
+}
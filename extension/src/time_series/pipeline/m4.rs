@@ -0,0 +1,162 @@
+use pgx::*;
+
+use super::*;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="m4",
+    schema="toolkit_experimental"
+)]
+pub fn m4_pipeline_element<'e>(
+    width: i32,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    if width <= 0 {
+        error!("m4 width must be positive")
+    }
+    Element::M4 {
+        width: width as u64,
+    }.flatten()
+}
+
+// M4 downsampling: split the time range of the series into `width` equally
+// sized columns and keep only the first, last, minimum, and maximum point in
+// each column. Rendering the result as a line chart `width` pixels wide is
+// identical to rendering the original series.
+pub fn m4_timeseries<'s>(
+    series: toolkit_experimental::TimeSeries<'s>,
+    width: u64,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("m4 requires sorted timeseries");
+    }
+
+    let points: Vec<TSPoint> = series.iter().collect();
+    if points.len() <= 4 * width as usize {
+        // Nothing to do.
+        return series
+    }
+
+    let start = points.first().unwrap().ts;
+    let span = (points.last().unwrap().ts - start) as i128 + 1;
+    let column_of = |ts: i64| ((ts - start) as i128 * width as i128 / span) as u64;
+
+    let mut sampled = Vec::with_capacity(4 * width as usize);
+    let mut column_start = 0;
+    while column_start < points.len() {
+        let column = column_of(points[column_start].ts);
+        let column_end = column_start + points[column_start..].iter()
+            .position(|p| column_of(p.ts) != column)
+            .unwrap_or(points.len() - column_start);
+
+        let mut min = column_start;
+        let mut max = column_start;
+        for i in column_start..column_end {
+            if points[i].val < points[min].val {
+                min = i;
+            }
+            if points[i].val > points[max].val {
+                max = i;
+            }
+        }
+
+        let mut selected = [column_start, min, max, column_end - 1];
+        selected.sort_unstable();
+        let mut prev = None;
+        for &i in selected.iter() {
+            if prev != Some(i) {
+                sampled.push(points[i]);
+            }
+            prev = Some(i);
+        }
+
+        column_start = column_end;
+    }
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: sampled.len() as u64,
+                points: sampled.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_m4() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 5), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 1), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 2), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 9), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 4), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 8), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 7), \
+                    ('2020-01-08 UTC'::TIMESTAMPTZ, 6), \
+                    ('2020-01-09 UTC'::TIMESTAMPTZ, 5)",
+                None,
+                None
+            );
+
+            // the first column holds 2020-01-01 through 2020-01-05
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> m4(2))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:5),\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-04 00:00:00+00\",val:9),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4),\
+                (ts:\"2020-01-06 00:00:00+00\",val:8),\
+                (ts:\"2020-01-09 00:00:00+00\",val:5)\
+            ]");
+
+            // series that are already small enough are returned unchanged
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> m4(3))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:5),\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:2),\
+                (ts:\"2020-01-04 00:00:00+00\",val:9),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4),\
+                (ts:\"2020-01-06 00:00:00+00\",val:8),\
+                (ts:\"2020-01-07 00:00:00+00\",val:7),\
+                (ts:\"2020-01-08 00:00:00+00\",val:6),\
+                (ts:\"2020-01-09 00:00:00+00\",val:5)\
+            ]");
+        });
+    }
+}
@@ -0,0 +1,240 @@
+use std::mem::replace;
+
+use pgx::*;
+
+use super::*;
+
+use crate::{ron_inout_funcs, pg_type, build, utilities::interval_to_micros};
+
+type Interval = pg_sys::Datum;
+
+pg_type! {
+    #[derive(Debug)]
+    struct PipelineThenMad<'input> {
+        num_elements: u64,
+        elements: [Element<'input>; self.num_elements],
+    }
+}
+
+ron_inout_funcs!(PipelineThenMad);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(PipelineThenMad);
+}
+
+// mean absolute deviation around the mean, NULL if there are no values
+fn mean_absolute_deviation(vals: impl Iterator<Item=f64> + Clone) -> Option<f64> {
+    let (n, sum) = vals.clone().fold((0usize, 0.0), |(n, sum), val| (n + 1, sum + val));
+    if n == 0 {
+        return None
+    }
+    let mean = sum / n as f64;
+    let deviation: f64 = vals.map(|val| (val - mean).abs()).sum();
+    Some(deviation / n as f64)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="mad",
+    schema="toolkit_experimental"
+)]
+pub fn timeseries_mad<'s>(
+    series: toolkit_experimental::TimeSeries<'s>,
+) -> Option<f64> {
+    mean_absolute_deviation(series.iter().map(|p| p.val))
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_then_mad<'s, 'p>(
+    mut timeseries: toolkit_experimental::TimeSeries<'s>,
+    pipeline: toolkit_experimental::PipelineThenMad<'p>,
+) -> Option<f64> {
+    timeseries = run_pipeline_elements(timeseries, pipeline.elements.iter());
+    mean_absolute_deviation(timeseries.iter().map(|p| p.val))
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn finalize_with_mad<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    then_mad: toolkit_experimental::PipelineThenMad<'e>,
+) -> toolkit_experimental::PipelineThenMad<'e> {
+    if then_mad.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {flatten! {
+            PipelineThenMad {
+                num_elements: pipeline.0.num_elements,
+                elements: pipeline.0.elements,
+            }
+        }}
+    }
+
+    let mut elements = replace(pipeline.elements.as_owned(), vec![]);
+    elements.extend(then_mad.elements.iter());
+    build! {
+        PipelineThenMad {
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="mad",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_mad<'e>() -> toolkit_experimental::PipelineThenMad<'e> {
+    build! {
+        PipelineThenMad {
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+// FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
+//       need to ensure this works with out unstable warning
+extension_sql!(r#"
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."run_pipeline_then_mad",
+    LEFTARG=toolkit_experimental.TimeSeries,
+    RIGHTARG=toolkit_experimental.PipelineThenMad
+);
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."finalize_with_mad",
+    LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
+    RIGHTARG=toolkit_experimental.PipelineThenMad
+);
+"#);
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="rolling_mad",
+    schema="toolkit_experimental"
+)]
+pub fn rolling_mad_pipeline_element<'e>(
+    window: Interval,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let window = interval_to_micros(window, "rolling_mad windows");
+    if window <= 0 {
+        error!("rolling_mad window must be positive")
+    }
+
+    Element::RollingMad {
+        window,
+    }.flatten()
+}
+
+// replaces each value with the mean absolute deviation of the values in the
+// trailing window `(ts - window, ts]`
+pub fn rolling_mad_timeseries<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    window: i64,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only compute rolling_mad for sorted timeseries");
+    }
+
+    let points: Vec<TSPoint> = series.iter().collect();
+    let mut start = 0;
+    let result: Vec<TSPoint> = points.iter()
+        .enumerate()
+        .map(|(end, point)| {
+            while points[start].ts <= point.ts - window {
+                start += 1;
+            }
+            let vals = points[start..=end].iter().map(|p| p.val);
+            TSPoint{ ts: point.ts, val: mean_absolute_deviation(vals).unwrap() }
+        })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: result.len() as u64,
+                points: result.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_mad() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 2), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 4), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 4), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 5), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 5), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 7), \
+                    ('2020-01-08 UTC'::TIMESTAMPTZ, 9)",
+                None,
+                None
+            );
+
+            let (accessor, finalizer, pipeline) = client.select(
+                "SELECT \
+                    mad(timeseries(time, value)), \
+                    timeseries(time, value) -> mad(), \
+                    timeseries(time, value) -> (mul(2) -> mad()) \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(accessor, Some(1.5));
+            assert_eq!(finalizer, Some(1.5));
+            assert_eq!(pipeline, Some(3.0));
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> rolling_mad('2 days'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0.5),\
+                (ts:\"2020-01-06 00:00:00+00\",val:0),\
+                (ts:\"2020-01-07 00:00:00+00\",val:1),\
+                (ts:\"2020-01-08 00:00:00+00\",val:1)\
+            ]");
+        });
+    }
+}
@@ -21,7 +21,7 @@ pub fn map_series_pipeline_element<'e>(
     map_series_element(function).flatten()
 }
 
-pub fn map_series_element(function: pg_sys::regproc) -> Element {
+pub fn map_series_element<'e>(function: pg_sys::regproc) -> Element<'e> {
     check_user_function_type(function);
     Element::MapSeries { function: PgProcId(function) }
 }
@@ -182,6 +182,24 @@ pub fn map_series(series: &mut TimeSeries<'_>, mut func: impl FnMut(f64) -> f64)
                 *value = func(*value)
             }
         },
+        NullableSeries { points, nulls, .. } => {
+            let nulls = nulls.as_slice();
+            let points = points.as_owned();
+            //FIXME add setjmp guard around loop
+            for (i, point) in points.iter_mut().enumerate() {
+                if nulls[i / 64] & (1 << (i % 64)) != 0 {
+                    continue
+                }
+                *point = TSPoint {
+                    ts: point.ts,
+                    val: func(point.val),
+                }
+            }
+        },
+        CompressedSeries { .. } => {
+            *series = series.decompressed();
+            map_series(series, func)
+        },
     }
 }
 
@@ -0,0 +1,139 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum NormalizeMethod {
+    ZScore,
+    MinMax,
+}
+
+impl NormalizeMethod {
+    pub fn process<'s>(&self, mut series: TimeSeries<'s>) -> TimeSeries<'s> {
+        // NULL points don't contribute to the statistics
+        let n = series.iter().count();
+        if n == 0 {
+            return series
+        }
+
+        match self {
+            NormalizeMethod::ZScore => {
+                let n = n as f64;
+                let mean = series.iter().map(|p| p.val).sum::<f64>() / n;
+                let variance = series.iter()
+                    .map(|p| (p.val - mean) * (p.val - mean))
+                    .sum::<f64>() / n;
+                let stddev = variance.sqrt();
+                // a constant series has no spread, every point is at the mean
+                if stddev == 0.0 {
+                    map::map_series(&mut series, |_| 0.0);
+                } else {
+                    map::map_series(&mut series, |val| (val - mean) / stddev);
+                }
+            }
+            NormalizeMethod::MinMax => {
+                let (min, max) = series.iter()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| {
+                        (min.min(p.val), max.max(p.val))
+                    });
+                let range = max - min;
+                if range == 0.0 {
+                    map::map_series(&mut series, |_| 0.0);
+                } else {
+                    map::map_series(&mut series, |val| (val - min) / range);
+                }
+            }
+        }
+        series
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="normalize",
+    schema="toolkit_experimental"
+)]
+pub fn normalize_pipeline_element<'e>(
+    method: default!(&str, "zscore"),
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let method = match method.to_lowercase().as_str() {
+        "zscore" | "z_score" => NormalizeMethod::ZScore,
+        "minmax" | "min_max" => NormalizeMethod::MinMax,
+        _ => panic!("Invalid normalize method")
+    };
+
+    Element::Normalize {
+        method
+    }.flatten()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_normalize() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 5.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 5.0), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 7.0), \
+                    ('2020-01-08 UTC'::TIMESTAMPTZ, 9.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> normalize('zscore'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:-1.5),\
+                (ts:\"2020-01-02 00:00:00+00\",val:-0.5),\
+                (ts:\"2020-01-03 00:00:00+00\",val:-0.5),\
+                (ts:\"2020-01-04 00:00:00+00\",val:-0.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0),\
+                (ts:\"2020-01-06 00:00:00+00\",val:0),\
+                (ts:\"2020-01-07 00:00:00+00\",val:1),\
+                (ts:\"2020-01-08 00:00:00+00\",val:2)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> normalize('minmax'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.2857142857142857),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.2857142857142857),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0.2857142857142857),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0.42857142857142855),\
+                (ts:\"2020-01-06 00:00:00+00\",val:0.42857142857142855),\
+                (ts:\"2020-01-07 00:00:00+00\",val:0.7142857142857143),\
+                (ts:\"2020-01-08 00:00:00+00\",val:1)\
+            ]");
+        });
+    }
+}
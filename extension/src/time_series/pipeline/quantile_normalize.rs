@@ -0,0 +1,208 @@
+use std::mem::replace;
+
+use pgx::*;
+
+use super::*;
+
+use crate::{
+    ron_inout_funcs, pg_type, build,
+    uddsketch::UddSketch,
+};
+
+use ::uddsketch::SketchHashKey;
+
+// Maps each value through the CDF of a reference distribution, to the
+// fraction of the reference's values below it, so series from sensors with
+// different scales can be compared against stored baselines. The result is
+// between 0 and 1, as with approx_percentile_rank(). The reference is a
+// varlena so it can't be a regular Element, instead this works like the
+// pipeline finalizers in aggregation.rs, carrying any elements before it.
+pg_type! {
+    #[derive(Debug)]
+    struct PipelineThenQuantileNormalize<'input> {
+        gamma: f64,
+        num_values: u64,
+        num_buckets: u64,
+        num_elements: u64,
+        // the reference sketch's buckets in order, the kind is -1 for negative
+        // buckets, 0 for the zero bucket and 1 for positive ones
+        bucket_kinds: [i64; self.num_buckets],
+        bucket_indexes: [i64; self.num_buckets],
+        bucket_counts: [u64; self.num_buckets],
+        elements: [Element<'input>; self.num_elements],
+    }
+}
+
+ron_inout_funcs!(PipelineThenQuantileNormalize);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(PipelineThenQuantileNormalize);
+}
+
+impl<'input> PipelineThenQuantileNormalize<'input> {
+    fn buckets(&self) -> impl Iterator<Item=(SketchHashKey, u64)> + '_ {
+        self.bucket_kinds.iter()
+            .zip(self.bucket_indexes.iter())
+            .zip(self.bucket_counts.iter())
+            .map(|((kind, index), count)| {
+                let key = match kind {
+                    -1 => SketchHashKey::Negative(index),
+                    0 => SketchHashKey::Zero,
+                    _ => SketchHashKey::Positive(index),
+                };
+                (key, count)
+            })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_then_quantile_normalize<'s, 'p>(
+    mut timeseries: toolkit_experimental::TimeSeries<'s>,
+    pipeline: toolkit_experimental::PipelineThenQuantileNormalize<'p>,
+) -> toolkit_experimental::TimeSeries<'static> {
+    timeseries = run_pipeline_elements(timeseries, pipeline.elements.iter());
+    map::map_series(&mut timeseries, |val| ::uddsketch::estimate_quantile_at_value(
+        val,
+        pipeline.gamma,
+        pipeline.num_values,
+        pipeline.buckets(),
+    ));
+    timeseries.in_current_context()
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn finalize_with_quantile_normalize<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimeseriesPipeline<'e>,
+    then_normalize: toolkit_experimental::PipelineThenQuantileNormalize<'e>,
+) -> toolkit_experimental::PipelineThenQuantileNormalize<'e> {
+    let mut elements = replace(pipeline.elements.as_owned(), vec![]);
+    elements.extend(then_normalize.elements.iter());
+    build! {
+        PipelineThenQuantileNormalize {
+            gamma: then_normalize.gamma,
+            num_values: then_normalize.num_values,
+            num_buckets: then_normalize.num_buckets,
+            num_elements: elements.len().try_into().unwrap(),
+            bucket_kinds: then_normalize.bucket_kinds.iter().collect::<Vec<_>>().into(),
+            bucket_indexes: then_normalize.bucket_indexes.iter().collect::<Vec<_>>().into(),
+            bucket_counts: then_normalize.bucket_counts.iter().collect::<Vec<_>>().into(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="quantile_normalize",
+    schema="toolkit_experimental"
+)]
+pub fn pipeline_quantile_normalize<'e>(
+    reference: UddSketch<'_>,
+) -> toolkit_experimental::PipelineThenQuantileNormalize<'e> {
+    if reference.num_values() == 0 {
+        error!("the reference sketch for quantile_normalize must not be empty")
+    }
+    let mut kinds = vec![];
+    let mut indexes = vec![];
+    let mut counts = vec![];
+    for (key, count) in reference.buckets() {
+        let (kind, index) = match key {
+            SketchHashKey::Negative(index) => (-1, index),
+            SketchHashKey::Zero => (0, 0),
+            SketchHashKey::Positive(index) => (1, index),
+            SketchHashKey::Invalid => unreachable!(),
+        };
+        kinds.push(kind);
+        indexes.push(index);
+        counts.push(count);
+    }
+    build! {
+        PipelineThenQuantileNormalize {
+            gamma: reference.gamma(),
+            num_values: reference.num_values(),
+            num_buckets: counts.len() as u64,
+            num_elements: 0,
+            bucket_kinds: kinds.into(),
+            bucket_indexes: indexes.into(),
+            bucket_counts: counts.into(),
+            elements: vec![].into(),
+        }
+    }
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+// FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
+//       need to ensure this works with out unstable warning
+extension_sql!(r#"
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."run_pipeline_then_quantile_normalize",
+    LEFTARG=toolkit_experimental.TimeSeries,
+    RIGHTARG=toolkit_experimental.PipelineThenQuantileNormalize
+);
+
+CREATE OPERATOR -> (
+    PROCEDURE=toolkit_experimental."finalize_with_quantile_normalize",
+    LEFTARG=toolkit_experimental.UnstableTimeseriesPipeline,
+    RIGHTARG=toolkit_experimental.PipelineThenQuantileNormalize
+);
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_quantile_normalize() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE baseline AS SELECT uddsketch(100, 0.001, v) AS sketch FROM generate_series(1, 100) v",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE series AS SELECT timeseries(time, value) AS series FROM \
+                    (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 1000.0), \
+                        ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
+                        ('2020-01-03 UTC'::TIMESTAMPTZ, 75.0), \
+                        ('2020-01-04 UTC'::TIMESTAMPTZ, -5.0)) as v(time, value)",
+                None,
+                None
+            );
+
+            // the same as approx_percentile_rank() against the reference
+            let (normalized, expected) = client.select(
+                "SELECT \
+                    (SELECT array_agg(value) FROM unnest((SELECT series -> quantile_normalize((SELECT sketch FROM baseline)) FROM series)))::TEXT, \
+                    (SELECT array_agg(approx_percentile_rank(v, (SELECT sketch FROM baseline))) FROM unnest(ARRAY[1000.0, 25.0, 75.0, -5.0]) v)::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(normalized, expected);
+            assert!(normalized.unwrap().starts_with("{1,"));
+
+            // earlier elements run first
+            let val = client.select(
+                "SELECT (series -> (sort() -> quantile_normalize((SELECT sketch FROM baseline))))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert!(val.starts_with("[(ts:\"2020-01-01 00:00:00+00\",val:1)"), "{}", val);
+        });
+    }
+}
@@ -159,6 +159,8 @@ pub fn resample_to_rate<'s>(
     let result = result.unwrap();
     build! {
         TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
             series: SeriesType::GappyNormalSeries {
                 start_ts: result.start_ts,
                 step_interval: result.step_interval,
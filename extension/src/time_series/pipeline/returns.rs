@@ -0,0 +1,124 @@
+use pgx::*;
+
+use super::*;
+
+use crate::build;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="pct_change",
+    schema="toolkit_experimental"
+)]
+pub fn pct_change_pipeline_element<'e>(
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Element::PctChange {}.flatten()
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="log_return",
+    schema="toolkit_experimental"
+)]
+pub fn log_return_pipeline_element<'e>(
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    Element::LogReturn {}.flatten()
+}
+
+// replaces each point after the first with its return relative to the
+// previous point, either as a fraction (val - prev) / prev or as ln(val / prev)
+pub fn timeseries_returns<'s>(
+    series: &toolkit_experimental::TimeSeries<'s>,
+    log: bool,
+) -> toolkit_experimental::TimeSeries<'s> {
+    if !series.is_sorted() {
+        panic!("can only compute returns for sorted timeseries");
+    }
+
+    let mut it = series.iter();
+    let mut returns = Vec::new();
+    if let Some(first) = it.next() {
+        let mut prev = first.val;
+        for pt in it {
+            let val = if log {
+                (pt.val / prev).ln()
+            } else {
+                (pt.val - prev) / prev
+            };
+            returns.push(TSPoint{ts: pt.ts, val});
+            prev = pt.val;
+        }
+    }
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: returns.len() as u64,
+                points: returns.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_returns() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = |values: [f64; 4]| format!("SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, {}::float), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, {}::float), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, {}::float), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, {}::float)) as v(time, value)",
+                values[0], values[1], values[2], values[3]);
+            let run = |element: &str, values: [f64; 4]| {
+                client.select(
+                    &format!("SELECT (series -> {})::TEXT FROM ({}) s", element, create_series(values)),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            assert_eq!(run("pct_change()", [100.0, 110.0, 99.0, 99.0]), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:-0.1),\
+                (ts:\"2020-01-04 00:00:00+00\",val:0)\
+            ]");
+
+            assert_eq!(run("log_return()", [1.0, 2.0, 4.0, 2.0]), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.6931471805599453),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.6931471805599453),\
+                (ts:\"2020-01-04 00:00:00+00\",val:-0.6931471805599453)\
+            ]");
+
+            // returns chain into the statistics finalizers
+            let val = client.select(
+                &format!("SELECT average(series -> pct_change() -> stats_agg()) FROM ({}) s",
+                    create_series([100.0, 110.0, 99.0, 99.0])),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 0.0);
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use pgx::*;
+
+use super::*;
+
+use crate::utilities::interval_to_micros;
+
+type Interval = pg_sys::Datum;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="shift",
+    schema="toolkit_experimental"
+)]
+pub fn shift_pipeline_element<'e>(
+    interval: Interval,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let offset = interval_to_micros(interval, "shift intervals");
+
+    Element::Shift {
+        offset
+    }.flatten()
+}
+
+pub fn shift_timeseries(
+    mut series: toolkit_experimental::TimeSeries<'_>,
+    offset: i64,
+) -> toolkit_experimental::TimeSeries<'_> {
+    use SeriesType::*;
+
+    match &mut series.series {
+        SortedSeries { points, .. } | ExplicitSeries { points, .. } | NullableSeries { points, .. } => {
+            for point in points.as_owned() {
+                point.ts = shift_ts(point.ts, offset);
+            }
+        },
+        NormalSeries { start_ts, .. } | GappyNormalSeries { start_ts, .. } => {
+            *start_ts = shift_ts(*start_ts, offset);
+        },
+        CompressedSeries { .. } =>
+            return shift_timeseries(series.decompress(), offset),
+    }
+    series
+}
+
+fn shift_ts(ts: i64, offset: i64) -> i64 {
+    ts.checked_add(offset)
+        .unwrap_or_else(|| error!("shifted timestamp {} is out of range", ts))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_shift() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT (series -> shift('1 week'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-11 00:00:00+00\",val:25),\
+                (ts:\"2020-01-08 00:00:00+00\",val:10),\
+                (ts:\"2020-01-10 00:00:00+00\",val:20)\
+            ]");
+
+            let val = client.select(
+                &format!("SELECT (series -> shift('-1 day 6 hours'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-03 06:00:00+00\",val:25),\
+                (ts:\"2019-12-31 06:00:00+00\",val:10),\
+                (ts:\"2020-01-02 06:00:00+00\",val:20)\
+            ]");
+        });
+    }
+
+    #[pg_test(error = "shifted timestamp 9223372036854775807 is out of range")]
+    fn test_pipeline_shift_out_of_range() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.run_pipeline(\
+                    toolkit_experimental.timeseries('infinity'::TIMESTAMPTZ, 1.0), \
+                    toolkit_experimental.shift('1 day'))",
+                None,
+                None
+            );
+        });
+    }
+}
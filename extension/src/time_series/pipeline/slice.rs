@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+
+use pgx::*;
+
+use counter_agg::range::I64Range;
+
+use super::*;
+
+#[allow(non_camel_case_types)]
+type tstzrange = pg_sys::Datum;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name="slice",
+    schema="toolkit_experimental"
+)]
+pub fn slice_pipeline_element<'e>(
+    range: tstzrange,
+) -> toolkit_experimental::UnstableTimeseriesPipeline<'e> {
+    let range = unsafe { crate::range::get_range(range as *mut pg_sys::varlena) };
+    let element = match range {
+        // an empty range keeps nothing
+        None => Element::Slice {
+            lower: 0,
+            upper: 0,
+            lower_present: 1,
+            upper_present: 1,
+        },
+        Some(range) => Element::Slice {
+            lower: range.left.unwrap_or(0),
+            upper: range.right.unwrap_or(0),
+            lower_present: range.left.is_some() as u64,
+            upper_present: range.right.is_some() as u64,
+        },
+    };
+    element.flatten()
+}
+
+pub fn slice_timeseries<'s>(
+    series: toolkit_experimental::TimeSeries<'s>,
+    element: &toolkit_experimental::Element
+) -> toolkit_experimental::TimeSeries<'s> {
+    let range = match element {
+        Element::Slice{lower, upper, lower_present, upper_present} => I64Range {
+            left: (*lower_present != 0).then(|| *lower),
+            right: (*upper_present != 0).then(|| *upper),
+        },
+        _ => panic!("Slice evaluator called on incorrect pipeline element")
+    };
+    let in_range = |ts: i64| {
+        range.left.map_or(true, |left| ts >= left)
+            && range.right.map_or(true, |right| ts < right)
+    };
+
+    match &series.series {
+        SeriesType::SortedSeries{points, ..} => {
+            // sorted, so we can binary search for the bounds
+            let points = points.as_slice();
+            let start = match range.left {
+                Some(left) => points
+                    .binary_search_by(|p| if p.ts < left { Ordering::Less } else { Ordering::Greater })
+                    .unwrap_err(),
+                None => 0,
+            };
+            let end = match range.right {
+                Some(right) => points
+                    .binary_search_by(|p| if p.ts < right { Ordering::Less } else { Ordering::Greater })
+                    .unwrap_err(),
+                None => points.len(),
+            };
+            let points = &points[start..end.max(start)];
+            build!(
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::SortedSeries {
+                        num_points: points.len() as u64,
+                        points: points.to_vec().into(),
+                    }
+                }
+            )
+        },
+        SeriesType::NormalSeries{start_ts, step_interval, values, ..} => {
+            // first index whose timestamp is >= ts
+            let index_of = |ts: i64| {
+                if ts <= *start_ts {
+                    return 0
+                }
+                let idx = (ts - start_ts + step_interval - 1) / step_interval;
+                (idx as usize).min(values.len())
+            };
+            let start = range.left.map_or(0, index_of);
+            let end = range.right.map_or(values.len(), index_of).max(start);
+            let values = &values.as_slice()[start..end];
+            build!(
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::NormalSeries {
+                        start_ts: start_ts + start as i64 * step_interval,
+                        step_interval: *step_interval,
+                        num_vals: values.len() as u64,
+                        values: values.to_vec().into(),
+                    }
+                }
+            )
+        },
+        SeriesType::NullableSeries{..} => {
+            nullable_series_from(series.iter_with_nulls().filter(|(ts, _)| in_range(*ts)))
+        },
+        SeriesType::GappyNormalSeries{..}
+        | SeriesType::ExplicitSeries{..}
+        | SeriesType::CompressedSeries{..} => {
+            let points: Vec<_> = series.iter().filter(|p| in_range(p.ts)).collect();
+            if series.is_sorted() {
+                build!(
+                    TimeSeries {
+                        num_label_bytes: 0,
+                        label_bytes: vec![].into(),
+                        series: SeriesType::SortedSeries {
+                            num_points: points.len() as u64,
+                            points: points.into(),
+                        }
+                    }
+                )
+            } else {
+                build!(
+                    TimeSeries {
+                        num_label_bytes: 0,
+                        label_bytes: vec![].into(),
+                        series: SeriesType::ExplicitSeries {
+                            num_points: points.len() as u64,
+                            points: points.into(),
+                        }
+                    }
+                )
+            }
+        },
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_pipeline_slice() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30)",
+                None,
+                None
+            );
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> slice('[2020-01-02 UTC, 2020-01-04 UTC)'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15)\
+            ]");
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> slice('[2020-01-02 UTC, 2020-01-04 UTC]'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-04 00:00:00+00\",val:25)\
+            ]");
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> slice('(2020-01-03 UTC,)'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30)\
+            ]");
+
+            let val = client.select(
+                "SELECT (timeseries(time, value) -> sort() -> slice('empty'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[]");
+        });
+    }
+}
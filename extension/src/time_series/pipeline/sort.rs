@@ -20,6 +20,14 @@ pub fn sort_timeseries(
 ) -> toolkit_experimental::TimeSeries<'_> {
     match &mut series.series {
         SeriesType::GappyNormalSeries{..} | SeriesType::NormalSeries{..} | SeriesType::SortedSeries{..} => series,
+        SeriesType::NullableSeries{sorted, ..} if *sorted != 0 => series,
+        SeriesType::CompressedSeries{sorted, ..} if *sorted != 0 => series,
+        SeriesType::CompressedSeries{..} => sort_timeseries(series.decompress()),
+        SeriesType::NullableSeries{..} => {
+            let mut points: Vec<_> = series.iter_with_nulls().collect();
+            points.sort_by(|a, b| a.0.cmp(&b.0));
+            nullable_series_from(points.into_iter())
+        },
         SeriesType::ExplicitSeries{points, ..} => {
             let points = points.as_owned();
             let mut points = std::mem::replace(points, vec![]);
@@ -28,6 +36,8 @@ pub fn sort_timeseries(
                 header: 0,
                 version: 1,
                 padding: [0; 3],
+                num_label_bytes: 0,
+                label_bytes: vec![].into(),
                 series: SeriesType::SortedSeries {
                     num_points: points.len() as u64,
                     points: points.into(),
@@ -0,0 +1,140 @@
+use pgx::*;
+
+use super::*;
+
+use crate::utilities::interval_to_micros;
+
+type Interval = pg_sys::Datum;
+
+// the (time, a, b) triples of the timestamps present in both series, in time order
+pub(super) fn pair_by_time(a: &TimeSeries<'_>, b: &TimeSeries<'_>) -> Vec<(i64, f64, f64)> {
+    let (a, b) = (a.sorted_points(), b.sorted_points());
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].ts.cmp(&b[j].ts) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                pairs.push((a[i].ts, a[i].val, b[j].val));
+                i += 1;
+                j += 1;
+            },
+        }
+    }
+    pairs
+}
+
+// Pearson correlation of the pairs, NULL if there are fewer than two or
+// either side doesn't vary
+pub(super) fn correlation(pairs: &[(i64, f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None
+    }
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|(_, a, _)| a).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, _, b)| b).sum::<f64>() / n;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (_, a, b) in pairs {
+        cov += (a - mean_a) * (b - mean_b);
+        var_a += (a - mean_a) * (a - mean_a);
+        var_b += (b - mean_b) * (b - mean_b);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return None
+    }
+    Some(cov / (var_a * var_b).sqrt())
+}
+
+// The correlation of `a` and `b` over each trailing window `(ts - window, ts]`.
+// Points are paired up by timestamp, points with no partner in the other
+// series are ignored. There is an output point at each paired timestamp whose
+// window has a defined correlation.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn rolling_corr(
+    a: toolkit_experimental::TimeSeries<'_>,
+    b: toolkit_experimental::TimeSeries<'_>,
+    window: Interval,
+) -> toolkit_experimental::TimeSeries<'static> {
+    let window = interval_to_micros(window, "rolling_corr windows");
+    if window <= 0 {
+        error!("rolling_corr window must be positive")
+    }
+
+    let pairs = pair_by_time(&a, &b);
+
+    let mut start = 0;
+    let points: Vec<TSPoint> = (0..pairs.len())
+        .filter_map(|end| {
+            let ts = pairs[end].0;
+            while pairs[start].0 <= ts - window {
+                start += 1;
+            }
+            correlation(&pairs[start..=end]).map(|val| TSPoint { ts, val })
+        })
+        .collect();
+
+    build!(
+        TimeSeries {
+            num_label_bytes: 0,
+            label_bytes: vec![].into(),
+            series: SeriesType::SortedSeries {
+                num_points: points.len() as u64,
+                points: points.into(),
+            }
+        }
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_rolling_corr() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE metrics(time timestamptz, cpu double precision, latency double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO metrics \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1, 2), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2, 4), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 3, 6), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 4, 8), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 5, 4), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 6, NULL)",
+                None,
+                None
+            );
+
+            // the first window only has one pair, and the last point has no partner
+            let val = client.select(
+                "SELECT rolling_corr(\
+                    timeseries(time, cpu), \
+                    timeseries(time, latency) FILTER (WHERE latency IS NOT NULL), \
+                    '3 days')::TEXT \
+                FROM metrics",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1),\
+                (ts:\"2020-01-04 00:00:00+00\",val:1),\
+                (ts:\"2020-01-05 00:00:00+00\",val:-0.5)\
+            ]");
+        });
+    }
+}
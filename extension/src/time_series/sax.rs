@@ -0,0 +1,128 @@
+use pgx::*;
+
+use stats_agg::distributions::normal_quantile;
+
+use super::*;
+
+const MAX_ALPHABET_SIZE: i32 = 26;
+
+// Piecewise aggregate approximation: the means of `segments` equal length
+// pieces of the values. When the values don't divide evenly a value
+// straddling two pieces contributes to both in proportion.
+fn paa(values: &[f64], segments: usize) -> Vec<f64> {
+    let n = values.len();
+    let mut means = vec![0.0; segments];
+    // stretch the values by a factor of `segments` so each piece covers
+    // exactly `n` of the stretched values
+    for i in 0..n * segments {
+        means[i / n] += values[i / segments];
+    }
+    means.iter_mut().for_each(|mean| *mean /= n as f64);
+    means
+}
+
+// The Symbolic Aggregate approXimation of the series: the values are
+// z-normalized, reduced to `segments` means, and each mean is replaced by a
+// letter from the first `alphabet_size` letters of the alphabet, chosen by
+// breakpoints that split the standard normal distribution into equally likely
+// ranges. A series that doesn't vary maps to the middle letter throughout.
+// Series of similar shape map to the same string regardless of their scale.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn sax(
+    series: toolkit_experimental::TimeSeries<'_>,
+    segments: i32,
+    alphabet_size: i32,
+) -> Option<String> {
+    if !(2..=MAX_ALPHABET_SIZE).contains(&alphabet_size) {
+        error!("sax alphabet_size must be between 2 and {}", MAX_ALPHABET_SIZE)
+    }
+    if segments < 1 {
+        error!("sax segments must be positive")
+    }
+
+    let mut points: Vec<TSPoint> = series.iter().collect();
+    if points.is_empty() {
+        return None
+    }
+    if !series.is_sorted() {
+        points.sort_by_key(|point| point.ts);
+    }
+    if segments as usize > points.len() {
+        error!("sax segments must not exceed the number of points")
+    }
+
+    let n = points.len() as f64;
+    let mean = points.iter().map(|point| point.val).sum::<f64>() / n;
+    let stddev = (points.iter().map(|point| (point.val - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let normalized: Vec<f64> = points.iter()
+        .map(|point| if stddev == 0.0 { 0.0 } else { (point.val - mean) / stddev })
+        .collect();
+
+    let breakpoints: Vec<f64> = (1..alphabet_size)
+        .map(|i| normal_quantile(i as f64 / alphabet_size as f64))
+        .collect();
+    let word = paa(&normalized, segments as usize)
+        .into_iter()
+        .map(|mean| {
+            let letter = breakpoints.iter().filter(|&&breakpoint| breakpoint <= mean).count();
+            (b'a' + letter as u8) as char
+        })
+        .collect();
+    Some(word)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_sax() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE series AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        i::DOUBLE PRECISION AS value \
+                    FROM generate_series(1, 8) i",
+                None,
+                None
+            );
+
+            let (rising, scaled, falling) = client.select(
+                "SELECT \
+                    sax(timeseries(time, value), 4, 4), \
+                    sax(timeseries(time, 100 * value + 7), 4, 4), \
+                    sax(timeseries(time, -value), 4, 4) \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(rising.unwrap(), "abcd");
+            assert_eq!(scaled.unwrap(), "abcd");
+            assert_eq!(falling.unwrap(), "dcba");
+
+            // 8 points don't divide evenly into 3 segments
+            let (uneven, flat, empty) = client.select(
+                "SELECT \
+                    sax(timeseries(time, value), 3, 3), \
+                    sax(timeseries(time, 5), 2, 3), \
+                    (SELECT sax(timeseries(time, value), 2, 3) FROM series WHERE value > 8) \
+                FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(uneven.unwrap(), "abc");
+            assert_eq!(flat.unwrap(), "bb");
+            assert_eq!(empty, None);
+        });
+    }
+}
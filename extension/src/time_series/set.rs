@@ -0,0 +1,317 @@
+use pgx::*;
+
+use super::*;
+
+use super::pipeline::{
+    run_pipeline_elements,
+    toolkit_experimental::UnstableTimeseriesPipeline,
+};
+
+// A collection of labeled timeseries, typically one per device or metric,
+// which lets a pipeline be run over every series at once.
+pg_type! {
+    #[derive(Debug)]
+    struct TimeSeriesSet<'input> {
+        num_series: u64,
+        num_bytes: u64,
+        // the flattened members back to back, each padded to 8 bytes so they
+        // can be read in place
+        series_bytes: [u8; self.num_bytes],
+    }
+}
+
+ron_inout_funcs!(TimeSeriesSet);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+    varlena_type!(TimeSeriesSet);
+}
+
+impl<'input> TimeSeriesSet<'input> {
+    pub fn members(&self) -> impl Iterator<Item=TimeSeries<'_>> + '_ {
+        let mut bytes = self.series_bytes.as_slice();
+        (0..self.num_series).map(move |_| {
+            // the bytes may have come from the text or binary input, so they
+            // can't be assumed to be valid
+            let (data, rest) = match unsafe { TimeSeriesData::try_ref(bytes) } {
+                Ok(member) => member,
+                Err(_) => error!("invalid timeseries_set, member series are corrupt"),
+            };
+            let len = bytes.len() - rest.len();
+            bytes = &bytes[padded_len(len).min(bytes.len())..];
+            TimeSeries(data, None)
+        })
+    }
+}
+
+fn padded_len(len: usize) -> usize {
+    (len + 7) / 8 * 8
+}
+
+fn build_set<'a>(members: impl Iterator<Item=TimeSeries<'a>>) -> TimeSeriesSet<'static> {
+    let mut num_series = 0;
+    let mut bytes = vec![];
+    for member in members {
+        bytes.extend_from_slice(member.0.to_pg_bytes());
+        bytes.resize(padded_len(bytes.len()), 0);
+        num_series += 1;
+    }
+    build!{
+        TimeSeriesSet {
+            num_series,
+            num_bytes: bytes.len() as _,
+            series_bytes: bytes.into(),
+        }
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_set_trans(
+    state: Option<Internal<Vec<TimeSeries<'static>>>>,
+    series: Option<toolkit_experimental::TimeSeries<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<Vec<TimeSeries<'static>>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let series = match series {
+                None => return state,
+                Some(series) => series.clone_owned().decompress(),
+            };
+            let mut state = match state {
+                None => Internal::from(vec![]),
+                Some(state) => state,
+            };
+            // series with the same labels are the same series
+            let existing = state.iter()
+                .position(|member| member.label_bytes.as_slice() == series.label_bytes.as_slice());
+            match existing {
+                Some(i) => {
+                    let member = state.swap_remove(i);
+                    state.push(combine(member, series));
+                },
+                None => state.push(series),
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_set_final(
+    state: Option<Internal<Vec<TimeSeries<'static>>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::TimeSeriesSet<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            let mut members: Vec<_> = state.iter().collect();
+            members.sort_by(|a, b| a.label_bytes.as_slice().cmp(b.label_bytes.as_slice()));
+            Some(build_set(members.into_iter().map(|member| member.clone_owned())))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.timeseries_set(
+    toolkit_experimental.timeseries
+) (
+    sfunc = toolkit_experimental.timeseries_set_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.timeseries_set_final
+);
+"#);
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn num_series(
+    set: toolkit_experimental::TimeSeriesSet<'_>,
+) -> i64 {
+    set.num_series as _
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn unnest_set(
+    set: toolkit_experimental::TimeSeriesSet<'_>,
+) -> impl std::iter::Iterator<Item = (name!(labels,JsonB),name!(series,toolkit_experimental::TimeSeries<'static>))> + '_ {
+    set.members()
+        .map(|member| (super::labels::labels(member.clone_owned()), member.in_current_context()))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+// The points of every series in the set as rows of time, value, and the
+// name of their series, ordered by time then name. This is the long format
+// Grafana's time series panel splits into one series per `metric`. Series
+// are named by their labels as `key=value` pairs separated by commas.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn to_grafana_frames(
+    set: toolkit_experimental::TimeSeriesSet<'_>,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(value,Option<f64>),name!(metric,String))> + '_ {
+    let mut rows: Vec<(pg_sys::TimestampTz, Option<f64>, String)> = vec![];
+    for member in set.members() {
+        let metric = member.labels()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        rows.extend(member.iter_with_nulls().map(|(ts, val)| (ts, val, metric.clone())));
+    }
+    rows.sort_by(|a, b| (a.0, &a.2).cmp(&(b.0, &b.2)));
+    rows.into_iter()
+}
+
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// The rows of the set-returning functions above for the planner, see
+// estimate_srf_rows()
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn unnest_set_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |set: TimeSeriesSet| set.num_series as usize)
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn to_grafana_frames_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |set: TimeSeriesSet|
+            set.members().map(|member| member.iter_with_nulls().count()).sum())
+    }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        ALTER FUNCTION toolkit_experimental.unnest_set(toolkit_experimental.TimeSeriesSet)
+            SUPPORT toolkit_experimental.unnest_set_rows_support;
+        ALTER FUNCTION toolkit_experimental.to_grafana_frames(toolkit_experimental.TimeSeriesSet)
+            SUPPORT toolkit_experimental.to_grafana_frames_rows_support;
+    END IF;
+END
+$$;
+"#);
+
+#[pg_extern(immutable, parallel_safe, schema="toolkit_experimental")]
+pub fn run_pipeline_on_set<'s, 'p>(
+    set: toolkit_experimental::TimeSeriesSet<'s>,
+    pipeline: UnstableTimeseriesPipeline<'p>,
+) -> toolkit_experimental::TimeSeriesSet<'static> {
+    build_set(set.members().map(|member| run_pipeline_elements(member, pipeline.elements.iter())))
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+// FIXME there is no CREATE OR REPLACE OPERATOR need to update post-install.rs
+//       need to ensure this works with out unstable warning
+extension_sql!(r#"
+CREATE OPERATOR ->> (
+    PROCEDURE=toolkit_experimental."run_pipeline_on_set",
+    LEFTARG=toolkit_experimental.TimeSeriesSet,
+    RIGHTARG=toolkit_experimental.UnstableTimeseriesPipeline
+);
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_set() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE readings(device text, time timestamptz, value double precision)",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO readings \
+                    VALUES \
+                    ('b', '2020-01-01 UTC'::TIMESTAMPTZ, 1), \
+                    ('a', '2020-01-02 UTC'::TIMESTAMPTZ, 20), \
+                    ('b', '2020-01-02 UTC'::TIMESTAMPTZ, 3), \
+                    ('a', '2020-01-01 UTC'::TIMESTAMPTZ, 10), \
+                    ('b', '2020-01-03 UTC'::TIMESTAMPTZ, 6)",
+                None,
+                None
+            );
+            client.select(
+                "CREATE VIEW sets AS \
+                    SELECT timeseries_set(series) AS set FROM ( \
+                        SELECT with_labels(timeseries(time, value), jsonb_build_object('device', device)) AS series \
+                        FROM readings GROUP BY device \
+                    ) s",
+                None,
+                None
+            );
+
+            let val = client.select("SELECT num_series(set) FROM sets", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val.unwrap(), 2);
+
+            let rows: Vec<(String, String)> = client.select(
+                "SELECT labels::TEXT, series::TEXT FROM unnest_set((SELECT set ->> (sort() -> delta()) FROM sets))",
+                None,
+                None
+            )
+                .map(|row| (row.by_ordinal(1).unwrap().value().unwrap(), row.by_ordinal(2).unwrap().value().unwrap()))
+                .collect();
+            assert_eq!(rows, vec![
+                (
+                    "{\"device\": \"a\"}".to_string(),
+                    "(labels:{\"device\":\"a\"},points:[(ts:\"2020-01-02 00:00:00+00\",val:10)])".to_string(),
+                ),
+                (
+                    "{\"device\": \"b\"}".to_string(),
+                    "(labels:{\"device\":\"b\"},points:[\
+                        (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                        (ts:\"2020-01-03 00:00:00+00\",val:3)\
+                    ])".to_string(),
+                ),
+            ]);
+
+            let frames: Vec<(String, Option<f64>, String)> = client.select(
+                "SELECT time::TEXT, value, metric FROM to_grafana_frames((SELECT set FROM sets))",
+                None,
+                None
+            )
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(frames, vec![
+                ("2020-01-01 00:00:00+00".to_string(), Some(10.0), "device=a".to_string()),
+                ("2020-01-01 00:00:00+00".to_string(), Some(1.0), "device=b".to_string()),
+                ("2020-01-02 00:00:00+00".to_string(), Some(20.0), "device=a".to_string()),
+                ("2020-01-02 00:00:00+00".to_string(), Some(3.0), "device=b".to_string()),
+                ("2020-01-03 00:00:00+00".to_string(), Some(6.0), "device=b".to_string()),
+            ]);
+        });
+    }
+
+    #[pg_test(error = "invalid timeseries_set, member series are corrupt")]
+    fn test_timeseries_set_invalid_input() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.unnest_set('(version:1,num_series:2,num_bytes:8,series_bytes:[1,0,0,0,0,0,0,0])')",
+                None,
+                None
+            );
+        });
+    }
+}
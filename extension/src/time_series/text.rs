@@ -0,0 +1,117 @@
+use pgx::*;
+
+use super::*;
+
+use super::json::{encode_timestamptz, series_from_points};
+
+use crate::serialization::_ts_toolkit_decode_timestamptz;
+
+// Converts a timeseries into `ts,value` lines, NULL values are left empty.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn to_text(
+    series: toolkit_experimental::TimeSeries<'_>,
+) -> String {
+    series.iter_with_nulls()
+        .map(|(ts, val)| match val {
+            Some(val) => format!("{},{}", encode_timestamptz(ts), val),
+            None => format!("{},", encode_timestamptz(ts)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// inverse of to_text(timeseries): builds a timeseries from `ts,value` lines,
+// splitting each line at its last comma. Blank lines are skipped, and an
+// empty value or `NULL` is a NULL value.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn timeseries_from_text(
+    text: &str,
+) -> toolkit_experimental::TimeSeries<'static> {
+    let points: Vec<(i64, Option<f64>)> = text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (ts, val) = match line.rfind(',') {
+                Some(comma) => (line[..comma].trim(), line[comma + 1..].trim()),
+                None => error!("timeseries text line {} is not of the form `ts,value`", i + 1),
+            };
+            let ts = unsafe { _ts_toolkit_decode_timestamptz(ts) };
+            let val = match val {
+                "" => None,
+                val if val.eq_ignore_ascii_case("null") => None,
+                val => match val.parse() {
+                    Ok(val) => Some(val),
+                    Err(_) => error!("timeseries text line {} has an invalid value \"{}\"", i + 1, val),
+                },
+            };
+            (ts, val)
+        })
+        .collect();
+
+    series_from_points(points)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_timeseries_text() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timeseries_with_nulls(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.5)) as v(time, value)";
+
+            let val = client.select(
+                &format!("SELECT to_text(series) FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "\
+                2020-01-01 00:00:00+00,10\n\
+                2020-01-02 00:00:00+00,\n\
+                2020-01-03 00:00:00+00,20.5");
+
+            let val = client.select(
+                &format!("SELECT (timeseries_from_text(to_text(series)) -> fill_nulls('drop'))::TEXT FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20.5)\
+            ]");
+
+            let val = client.select(
+                "SELECT timeseries_from_text(E'2020-01-02 UTC, 2\\n\\n 2020-01-01 UTC,1.5e1 \\n')::TEXT",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "[\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:15)\
+            ]");
+
+            let val = client.select(
+                "SELECT to_text(timeseries_from_text(E'2020-01-01 UTC,NULL\\n2020-01-02 UTC,3'))",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "2020-01-01 00:00:00+00,\n2020-01-02 00:00:00+00,3");
+        });
+    }
+}
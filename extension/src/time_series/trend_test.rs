@@ -0,0 +1,283 @@
+use std::cmp::Ordering;
+
+use pgx::*;
+
+use stats_agg::distributions::normal_cdf;
+
+use super::*;
+
+fn check_alpha(alpha: f64) {
+    if !(alpha > 0.0 && alpha < 1.0) {
+        error!("alpha must be between 0 and 1")
+    }
+}
+
+// The Mann-Kendall Z score of the values and its two-sided p-value. The
+// variance of S is corrected for tied values.
+fn mann_kendall(values: &[f64]) -> (f64, f64) {
+    let n = values.len();
+    let mut s = 0i64;
+    for i in 0..n {
+        for j in i + 1..n {
+            s += match values[j].partial_cmp(&values[i]) {
+                Some(Ordering::Greater) => 1,
+                Some(Ordering::Less) => -1,
+                _ => 0,
+            };
+        }
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < n {
+        let end = start + sorted[start..].iter().take_while(|&&v| v == sorted[start]).count().max(1);
+        let t = (end - start) as f64;
+        ties += t * (t - 1.0) * (2.0 * t + 5.0);
+        start = end;
+    }
+    let n = n as f64;
+    let variance = (n * (n - 1.0) * (2.0 * n + 5.0) - ties) / 18.0;
+    if variance <= 0.0 {
+        return (0.0, 1.0)
+    }
+    // continuity correction
+    let z = match s.cmp(&0) {
+        Ordering::Greater => (s - 1) as f64 / variance.sqrt(),
+        Ordering::Less => (s + 1) as f64 / variance.sqrt(),
+        Ordering::Equal => 0.0,
+    };
+    (z, 2.0 * (1.0 - normal_cdf(z.abs())))
+}
+
+// Tests the series for a monotonic trend with the Mann-Kendall test. Returns
+// the Z score, its two-sided p-value, and 'increasing' or 'decreasing' when
+// the p-value is below `alpha`, 'no trend' otherwise. Returns no rows for
+// series of fewer than 3 points.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn trend_test(
+    series: toolkit_experimental::TimeSeries<'_>,
+    alpha: default!(f64, 0.05),
+) -> impl std::iter::Iterator<Item = (name!(statistic,f64),name!(p_value,f64),name!(verdict,String))> {
+    check_alpha(alpha);
+    let values: Vec<f64> = series.sorted_points().iter().map(|point| point.val).collect();
+    if values.len() < 3 {
+        return None.into_iter()
+    }
+    let (z, p_value) = mann_kendall(&values);
+    let verdict = match (p_value < alpha, z > 0.0) {
+        (false, _) => "no trend",
+        (true, true) => "increasing",
+        (true, false) => "decreasing",
+    };
+    Some((z, p_value, verdict.to_string())).into_iter()
+}
+
+// Gauss-Jordan inverse of a square matrix, None if it is singular.
+fn invert(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let k = matrix.len();
+    let mut inverse: Vec<Vec<f64>> = (0..k)
+        .map(|i| (0..k).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for col in 0..k {
+        let pivot = (col..k)
+            .max_by(|&a, &b| matrix[a][col].abs().partial_cmp(&matrix[b][col].abs()).unwrap_or(Ordering::Equal))?;
+        if matrix[pivot][col].abs() < 1e-12 {
+            return None
+        }
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let scale = matrix[col][col];
+        for j in 0..k {
+            matrix[col][j] /= scale;
+            inverse[col][j] /= scale;
+        }
+        for row in 0..k {
+            let factor = matrix[row][col];
+            if row == col || factor == 0.0 {
+                continue
+            }
+            for j in 0..k {
+                matrix[row][j] -= factor * matrix[col][j];
+                inverse[row][j] -= factor * inverse[col][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+struct AdfFit {
+    statistic: f64,
+    aic: f64,
+}
+
+// Fits the regression dy[t] = a + g * y[t] + sum(d_i * dy[t - i], i = 1..=lags)
+// by least squares, using the differences from `start` on. Returns the t
+// statistic of g and the fit's Akaike information criterion.
+fn adf_fit(values: &[f64], lags: usize, start: usize) -> Option<AdfFit> {
+    let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+    let k = 2 + lags;
+    let rows: Vec<(Vec<f64>, f64)> = (start..diffs.len())
+        .map(|t| {
+            let mut x = vec![1.0, values[t]];
+            x.extend((1..=lags).map(|i| diffs[t - i]));
+            (x, diffs[t])
+        })
+        .collect();
+    let num_obs = rows.len();
+    if num_obs <= k {
+        return None
+    }
+
+    let mut xtx = vec![vec![0.0; k]; k];
+    let mut xty = vec![0.0; k];
+    for (x, y) in &rows {
+        for i in 0..k {
+            xty[i] += x[i] * y;
+            for j in 0..k {
+                xtx[i][j] += x[i] * x[j];
+            }
+        }
+    }
+    let inverse = invert(xtx)?;
+    let coefficients: Vec<f64> = inverse.iter()
+        .map(|row| row.iter().zip(&xty).map(|(a, b)| a * b).sum())
+        .collect();
+    let residual_sum_squares: f64 = rows.iter()
+        .map(|(x, y)| {
+            let fitted: f64 = x.iter().zip(&coefficients).map(|(a, b)| a * b).sum();
+            (y - fitted).powi(2)
+        })
+        .sum();
+    let residual_variance = residual_sum_squares / (num_obs - k) as f64;
+    let num_obs = num_obs as f64;
+    Some(AdfFit {
+        statistic: coefficients[1] / (residual_variance * inverse[1][1]).sqrt(),
+        aic: num_obs * (residual_sum_squares / num_obs).ln() + 2.0 * k as f64,
+    })
+}
+
+// MacKinnon's (1994) approximate p-value for the Dickey-Fuller statistic of
+// a regression with a constant and no trend.
+fn mackinnon_p_value(statistic: f64) -> f64 {
+    const MAX_STAT: f64 = 2.74;
+    const MIN_STAT: f64 = -18.83;
+    const STAR_STAT: f64 = -1.61;
+    const SMALL_P: [f64; 3] = [2.1659, 1.4412, 0.038269];
+    const LARGE_P: [f64; 4] = [1.7339, 0.93202, -0.12745, -0.010368];
+    if statistic > MAX_STAT {
+        return 1.0
+    }
+    if statistic < MIN_STAT {
+        return 0.0
+    }
+    let coefficients: &[f64] = if statistic <= STAR_STAT { &SMALL_P } else { &LARGE_P };
+    let polynomial = coefficients.iter().rev().fold(0.0, |acc, c| acc * statistic + c);
+    normal_cdf(polynomial)
+}
+
+// Tests the series for a unit root with the augmented Dickey-Fuller test,
+// including a constant in the regression. The number of lagged differences
+// is chosen by AIC from 0 through `max_lag`; a negative `max_lag` uses
+// Schwert's rule of thumb 12 * (n / 100)^(1/4). Returns the test statistic,
+// its p-value, and 'stationary' when the p-value is below `alpha`,
+// 'non-stationary' otherwise. Returns no rows when the series is too short
+// for the regression.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn adf_test(
+    series: toolkit_experimental::TimeSeries<'_>,
+    max_lag: default!(i32, -1),
+    alpha: default!(f64, 0.05),
+) -> impl std::iter::Iterator<Item = (name!(statistic,f64),name!(p_value,f64),name!(verdict,String))> {
+    check_alpha(alpha);
+    let values: Vec<f64> = series.sorted_points().iter().map(|point| point.val).collect();
+    let n = values.len();
+    let max_lag = if max_lag < 0 {
+        let schwert = (12.0 * (n as f64 / 100.0).powf(0.25)).floor() as usize;
+        schwert.min((n / 2).saturating_sub(2))
+    } else {
+        max_lag as usize
+    };
+    if n < max_lag + 4 {
+        return None.into_iter()
+    }
+
+    // compare the lag orders over the same observations
+    let best_lag = (0..=max_lag)
+        .filter_map(|lags| adf_fit(&values, lags, max_lag).map(|fit| (lags, fit.aic)))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .map(|(lags, _)| lags);
+    let fit = best_lag.and_then(|lags| adf_fit(&values, lags, lags));
+    fit.map(|fit| {
+        let p_value = mackinnon_p_value(fit.statistic);
+        let verdict = if p_value < alpha { "stationary" } else { "non-stationary" };
+        (fit.statistic, p_value, verdict.to_string())
+    }).into_iter()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_trend_tests() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // deterministic noise, a random walk built from it, and the noise
+            // on top of a rising trend
+            client.select(
+                "CREATE TABLE metrics AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' days')::INTERVAL AS time, \
+                        sin(i * i) AS noise, \
+                        sum(sin(i * i)) OVER (ORDER BY i) AS walk, \
+                        i / 10.0 + sin(i * i) AS trend \
+                    FROM generate_series(0, 59) i",
+                None,
+                None
+            );
+
+            let test = |function: &str, column: &str| {
+                client.select(
+                    &format!("SELECT statistic, p_value, verdict FROM \
+                        {}((SELECT timeseries(time, {}) FROM metrics))", function, column),
+                    None,
+                    None
+                )
+                    .first()
+                    .get_three::<f64, f64, String>()
+            };
+
+            let (statistic, p_value, verdict) = test("trend_test", "trend");
+            assert!(statistic.unwrap() > 8.0);
+            assert!(p_value.unwrap() < 1e-6);
+            assert_eq!(verdict.unwrap(), "increasing");
+            assert_eq!(test("trend_test", "-trend").2.unwrap(), "decreasing");
+            let (_, p_value, verdict) = test("trend_test", "noise");
+            assert!(p_value.unwrap() > 0.05);
+            assert_eq!(verdict.unwrap(), "no trend");
+
+            let (statistic, p_value, verdict) = test("adf_test", "noise");
+            assert!(statistic.unwrap() < -3.0);
+            assert!(p_value.unwrap() < 0.01);
+            assert_eq!(verdict.unwrap(), "stationary");
+            let (_, p_value, verdict) = test("adf_test", "walk");
+            assert!(p_value.unwrap() > 0.1);
+            assert_eq!(verdict.unwrap(), "non-stationary");
+
+            let rows = client.select(
+                "SELECT count(*) FROM trend_test((SELECT timeseries(time, noise) FROM metrics WHERE time < '2020-01-03 UTC'))",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(rows, Some(0));
+        });
+    }
+}
@@ -4,7 +4,10 @@ use serde::{Deserialize, Serialize};
 use std::slice;
 
 use crate::{
-    aggregate_utils::in_aggregate_context, flatten, ron_inout_funcs, palloc::Internal, pg_type,
+    aggregate_utils::{
+        in_aggregate_context, null_policy, numeric_to_f64, NullPolicy,
+        timestamp_to_timestamptz, epoch_micros_to_timestamptz,
+    }, flatten, ron_inout_funcs, palloc::Internal, pg_type,
     accessors::toolkit_experimental,
 };
 use flat_serialize::*;
@@ -20,6 +23,12 @@ use time_weighted_average::{
 #[allow(non_camel_case_types)]
 type bytea = pg_sys::Datum;
 
+#[allow(non_camel_case_types)]
+type numeric = pg_sys::Datum;
+
+#[allow(non_camel_case_types)]
+type timestamp = pg_sys::Datum;
+
 pg_type! {
     #[derive(Debug)]
     struct TimeWeightSummary {
@@ -50,6 +59,8 @@ pub struct TimeWeightTransState {
     point_buffer: Vec<TSPoint>,
     method: TimeWeightMethod,
     summary_buffer: Vec<TimeWeightSummaryInternal>,
+    // a NULL input was seen with the 'poison' null_policy, the result is NULL
+    poisoned: bool,
 }
 
 impl TimeWeightTransState {
@@ -70,10 +81,7 @@ impl TimeWeightTransState {
     }
 
     fn push_summary(&mut self, other: &TimeWeightTransState) {
-        let cb = other.summary_buffer.clone();
-        for val in cb.into_iter() {
-            self.summary_buffer.push(val);
-        }
+        self.summary_buffer.extend_from_slice(&other.summary_buffer);
     }
 
     fn combine_summaries(&mut self) {
@@ -108,35 +116,133 @@ pub fn time_weight_trans(
     ts: Option<pg_sys::TimestampTz>,
     val: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans_inner(state, method, ts, val, NullPolicy::Ignore, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_null_policy_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    policy: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    let policy = null_policy(policy.as_deref());
+    time_weight_trans_inner(state, method, ts, val, policy, fcinfo)
+}
+
+// The method time_weight() uses when it's called without one.
+pub(crate) static DEFAULT_TIME_WEIGHT_METHOD: GucSetting<Option<&'static str>> =
+    GucSetting::new(Some("linear"));
+
+#[pg_extern(schema = "toolkit_experimental", stable, parallel_safe)]
+pub fn time_weight_default_method_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    // the method is only read when the state is created
+    let method = match &state {
+        Some(_) => String::new(),
+        None => DEFAULT_TIME_WEIGHT_METHOD.get().unwrap_or_else(|| "linear".to_string()),
+    };
+    time_weight_trans(state, method, ts, val, fcinfo)
+}
+
+// REAL and NUMERIC values, see numeric_to_f64()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_real_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans(state, method, ts, val.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_numeric_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans(state, method, ts, val.map(numeric_to_f64), fcinfo)
+}
+
+// TIMESTAMP and epoch BIGINT times, see timestamp_to_timestamptz()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_timestamp_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<timestamp>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans(state, method, ts.map(timestamp_to_timestamptz), val, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_epoch_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<i64>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans(state, method, ts.map(epoch_micros_to_timestamptz), val, fcinfo)
+}
+
+fn time_weight_trans_inner(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    policy: NullPolicy,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<TimeWeightTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
             let p = match (ts, val) {
-                (_, None) => return state,
-                (None, _) => return state,
-                (Some(ts), Some(val)) => TSPoint { ts, val },
+                (Some(ts), Some(val)) => Some(TSPoint { ts, val }),
+                _ => match policy {
+                    NullPolicy::Ignore => return state,
+                    NullPolicy::Error => error!("time_weight input contains NULLs, which null_policy 'error' disallows"),
+                    NullPolicy::Poison => None,
+                },
             };
 
-            match state {
-                None => {
-                    let mut s = TimeWeightTransState {
-                        point_buffer: vec![],
-                        // TODO technically not portable to ASCII-compatible charsets
-                        method: match method.trim().to_lowercase().as_str() {
-                            "linear" => TimeWeightMethod::Linear,
-                            "locf" => TimeWeightMethod::LOCF,
-                            _ => panic!("unknown method"),
-                        },
-                        summary_buffer: vec![],
-                    };
-                    s.push_point(p);
-                    Some(s.into())
+            let mut state: Internal<TimeWeightTransState> = match state {
+                None => TimeWeightTransState {
+                    point_buffer: vec![],
+                    // TODO technically not portable to ASCII-compatible charsets
+                    method: match method.trim().to_lowercase().as_str() {
+                        "linear" => TimeWeightMethod::Linear,
+                        "locf" => TimeWeightMethod::LOCF,
+                        _ => panic!("unknown method"),
+                    },
+                    summary_buffer: vec![],
+                    poisoned: false,
                 }
-                Some(mut s) => {
-                    s.push_point(p);
-                    Some(s)
+                .into(),
+                Some(s) => s,
+            };
+            match p {
+                // the result will be NULL, there's no need to keep anything
+                _ if state.poisoned => (),
+                Some(p) => state.push_point(p),
+                None => {
+                    state.poisoned = true;
+                    state.point_buffer.clear();
+                    state.summary_buffer.clear();
                 }
             }
+            Some(state)
         })
     }
 }
@@ -155,6 +261,7 @@ pub fn time_weight_summary_trans<'b>(
                     summary_buffer: vec![next.to_internal()],
                     point_buffer: vec![],
                     method: next.method.clone(),
+                    poisoned: false,
                 }
                 .into(),
             ),
@@ -164,6 +271,7 @@ pub fn time_weight_summary_trans<'b>(
                     summary_buffer: vec![next.to_internal()],
                     point_buffer: vec![],
                     method: next.method,
+                    poisoned: false,
                 };
                 state.push_summary(&next);
                 Some(state.into())
@@ -182,23 +290,29 @@ pub fn time_weight_combine(
         in_aggregate_context(fcinfo, || {
             match (state1, state2) {
                 (None, None) => None,
+                // state2 may not live in the aggregate context, so it's copied
+                // there if it becomes our state, and otherwise only read
                 (None, Some(state2)) => {
                     let mut s = state2.clone();
                     s.combine_points();
                     Some(s.into())
                 }
-                (Some(state1), None) => {
-                    let mut s = state1.clone();
-                    s.combine_points();
-                    Some(s.into())
+                (Some(mut state1), None) => {
+                    state1.combine_points();
+                    Some(state1)
                 }
-                (Some(state1), Some(state2)) => {
-                    let mut s1 = state1.clone(); // is there a way to avoid if it doesn't need it?
-                    s1.combine_points();
-                    let mut s2 = state2.clone();
-                    s2.combine_points();
-                    s2.push_summary(&s1);
-                    Some(s2.into())
+                (Some(mut state1), Some(state2)) => {
+                    state1.combine_points();
+                    state1.poisoned |= state2.poisoned;
+                    // deserialized states never have points
+                    if state2.point_buffer.is_empty() {
+                        state1.push_summary(&state2);
+                    } else {
+                        let mut s2 = state2.clone();
+                        s2.combine_points();
+                        state1.push_summary(&s2);
+                    }
+                    Some(state1)
                 }
             }
         })
@@ -214,6 +328,7 @@ fn time_weight_final(
         in_aggregate_context(fcinfo, || {
             let mut state = match state {
                 None => return None,
+                Some(state) if state.poisoned => return None,
                 Some(state) => state.clone(),
             };
             state.combine_summaries();
@@ -260,6 +375,184 @@ CREATE AGGREGATE rollup(tws TimeWeightSummary)
 "#
 );
 
+// `a + b` combines two summaries the same way rollup() does, so they can't
+// overlap in time
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn time_weight_summary_add(
+    a: TimeWeightSummary,
+    b: TimeWeightSummary,
+) -> TimeWeightSummary<'static> {
+    let (first, second) = if a.first.ts <= b.first.ts { (a, b) } else { (b, a) };
+    let sum = first.to_internal().combine(&second.to_internal())
+        .unwrap_or_else(|e| match e {
+            TimeWeightError::MethodMismatch =>
+                error!("cannot add TimeWeightSummaries with different methods"),
+            _ => error!("cannot add TimeWeightSummaries that overlap in time"),
+        });
+    unsafe {
+        flatten!(TimeWeightSummary {
+            method: sum.method,
+            first: sum.first,
+            last: sum.last,
+            weighted_sum: sum.w_sum,
+        })
+    }
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+extension_sql!(
+    r#"
+CREATE OPERATOR toolkit_experimental.+ (
+    PROCEDURE=toolkit_experimental.time_weight_summary_add,
+    LEFTARG=TimeWeightSummary,
+    RIGHTARG=TimeWeightSummary
+);
+"#
+);
+
+// `null_policy` is one of 'ignore', 'error' or 'poison', see aggregate_utils.rs.
+extension_sql!(
+    r#"
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value DOUBLE PRECISION, null_policy text)
+(
+    sfunc = toolkit_experimental.time_weight_null_policy_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+"#
+);
+
+// time_weight using the timescaledb_toolkit.default_time_weight_method
+extension_sql!(
+    r#"
+CREATE AGGREGATE toolkit_experimental.time_weight(ts timestamptz, value DOUBLE PRECISION)
+(
+    sfunc = toolkit_experimental.time_weight_default_method_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+"#
+);
+
+// time_weight of REAL and NUMERIC values
+extension_sql!(
+    r#"
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value REAL)
+(
+    sfunc = toolkit_experimental.time_weight_real_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value NUMERIC)
+(
+    sfunc = toolkit_experimental.time_weight_numeric_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+"#
+);
+
+// time_weight of TIMESTAMP and epoch BIGINT times
+extension_sql!(
+    r#"
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts TIMESTAMP, value DOUBLE PRECISION)
+(
+    sfunc = toolkit_experimental.time_weight_timestamp_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts BIGINT, value DOUBLE PRECISION)
+(
+    sfunc = toolkit_experimental.time_weight_epoch_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+"#
+);
+
+// Partial aggregation for merging time_weight states computed elsewhere, e.g.
+// on other nodes or by batch jobs: time_weight_partial() returns the
+// transition state serialized the same way as for parallel workers, and
+// time_weight_finalize() combines any number of those into a
+// TimeWeightSummary.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn time_weight_partial_final(
+    state: Option<Internal<TimeWeightTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<bytea> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state: Internal<TimeWeightTransState> = state?.clone().into();
+            Some(time_weight_trans_serialize(state))
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn time_weight_finalize_trans(
+    state: Option<Internal<TimeWeightTransState>>,
+    partial: Option<bytea>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    let partial = match partial {
+        None => return state,
+        Some(partial) => time_weight_trans_deserialize(partial, None),
+    };
+    time_weight_combine(state, Some(partial), fcinfo)
+}
+
+extension_sql!(
+    r#"
+CREATE AGGREGATE toolkit_experimental.time_weight_partial(method text, ts timestamptz, value DOUBLE PRECISION)
+(
+    sfunc = time_weight_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.time_weight_partial_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.time_weight_finalize(partial bytea)
+(
+    sfunc = toolkit_experimental.time_weight_finalize_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    parallel = restricted
+);
+"#
+);
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_time_weighted_average_average(
@@ -291,6 +584,62 @@ pub fn time_weighted_average_average(
     }
 }
 
+// The TimeWeightSummary as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_to_msgpack(
+    summary: TimeWeightSummary<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&*summary)
+}
+
+// inverse of to_msgpack(TimeWeightSummary)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_from_msgpack(
+    bytes: &[u8],
+) -> TimeWeightSummary<'static> {
+    unsafe { TimeWeightSummary(crate::msgpack::decode("TimeWeightSummary", bytes, 1), None).flatten() }
+}
+
+// The TimeWeightSummary as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_to_jsonb(
+    summary: TimeWeightSummary<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&*summary)
+}
+
+// inverse of to_jsonb(TimeWeightSummary), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_from_jsonb(
+    json: JsonB,
+) -> TimeWeightSummary<'static> {
+    unsafe { TimeWeightSummary(crate::jsonb::decode("TimeWeightSummary", &json.0, 1), None).flatten() }
+}
+
+// The size of the TimeWeightSummary as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_size(
+    summary: TimeWeightSummary<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, summary.0.num_bytes(), None)
+}
+
+// Checks the TimeWeightSummary's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary_validate(
+    summary: TimeWeightSummary<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    if summary.first.ts > summary.last.ts {
+        diagnostics.error("the first point is after the last".to_string())
+    }
+    if !summary.weighted_sum.is_finite() {
+        diagnostics.warning("the weighted sum is not finite".to_string())
+    }
+    diagnostics.into_rows()
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -359,6 +708,96 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_time_weight_null_policy() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', NULL), ('2020-01-01 00:02:00+00', 20.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT average(toolkit_experimental.time_weight('Linear', ts, val, 'ignore')) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 15.0);
+            let stmt = "SELECT toolkit_experimental.time_weight('Linear', ts, val, 'poison') IS NULL FROM test";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_timestamp_and_epoch() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0), ('2020-01-01 00:03:00+00', 10.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT average(toolkit_experimental.time_weight('Linear', (ts AT TIME ZONE 'UTC')::TIMESTAMP, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 15.0);
+            let stmt = "SELECT average(toolkit_experimental.time_weight('LOCF', (extract(epoch FROM ts) * 1000000)::BIGINT, val)) FROM test";
+            assert!((select_one!(client, stmt, f64) - 50.0 / 3.0).abs() < 1e-9);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_summary_add() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0), ('2020-01-01 00:02:00+00', 10.0), ('2020-01-01 00:03:00+00', 20.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT average(b.tws OPERATOR(toolkit_experimental.+) a.tws) FROM \
+                (SELECT time_weight('Linear', ts, val) AS tws FROM test WHERE ts < '2020-01-01 00:02:00+00') a, \
+                (SELECT time_weight('Linear', ts, val) AS tws FROM test WHERE ts >= '2020-01-01 00:02:00+00') b";
+            assert_eq!(select_one!(client, stmt, f64), 15.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_default_method() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT average(toolkit_experimental.time_weight(ts, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 15.0);
+            client.select("SET LOCAL timescaledb_toolkit.default_time_weight_method TO 'locf'", None, None);
+            assert_eq!(select_one!(client, stmt, f64), 10.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_partial_finalize() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO test SELECT '2020-01-01 00:00:00+00'::timestamptz + i * '10 seconds'::interval, (i * 7) % 50 \
+                FROM generate_series(0, 100) i",
+                None,
+                None
+            );
+            client.select(
+                "CREATE TABLE partials AS \
+                SELECT toolkit_experimental.time_weight_partial('Linear', ts, val) AS partial \
+                FROM test GROUP BY date_trunc('minute', ts)",
+                None,
+                None
+            );
+
+            let stmt = "SELECT average(time_weight('Linear', ts, val)) FROM test";
+            let expected = select_one!(client, stmt, f64);
+            let stmt = "SELECT average(toolkit_experimental.time_weight_finalize(partial)) FROM partials";
+            let finalized = select_one!(client, stmt, f64);
+            assert!((expected - finalized).abs() < 1e-9, "{} != {}", expected, finalized);
+        });
+    }
+
     #[pg_test]
     fn test_time_weight_io() {
         Spi::execute(|client| {
@@ -232,6 +232,42 @@ pub fn topn_iter (
     })
 }
 
+// every value tracked by the sketch, most frequent first, with the same
+// bounds on its frequency as topn()
+#[pg_extern(immutable, parallel_safe, name="into_values", schema = "toolkit_experimental")]
+pub fn topn_into_values(
+    agg: toolkit_experimental::TopN<'_>,
+) -> impl std::iter::Iterator<Item = (name!(value,i64),name!(min_freq,f64),name!(max_freq,f64))> + '_ {
+    let num_values = agg.num_values as i32;
+    topn_iter(num_values, agg)
+}
+
+#[allow(non_camel_case_types)]
+type internal = pg_sys::Datum;
+
+// The number of values into_values() returns for the planner, see
+// estimate_srf_rows()
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn topn_into_values_rows_support(
+    input: internal,
+) -> internal {
+    unsafe {
+        crate::utilities::estimate_srf_rows(input, |agg: TopN| agg.num_values as usize)
+    }
+}
+
+// planner support functions were added in PostgreSQL 12
+extension_sql!(r#"
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 120000 THEN
+        ALTER FUNCTION toolkit_experimental.into_values(toolkit_experimental.TopN)
+            SUPPORT toolkit_experimental.topn_into_values_rows_support;
+    END IF;
+END
+$$;
+"#);
+
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
 pub fn guaranteed_topn<'input>(
     n: i32,
@@ -328,6 +364,12 @@ mod tests {
                     .first().get_three::<i64, f64, f64>();
             assert_eq!(test, (Some(99), Some(26./5050.), Some(214./5050.)));
 
+            // into_values() returns every tracked value
+            let test =
+                client.select("SELECT count(*), max(min_freq) FROM into_values((SELECT agg FROM aggs WHERE size=25))", None, None)
+                    .first().get_two::<i64, f64>();
+            assert_eq!(test, (Some(25), Some(26./5050.)));
+
 
             let test =
                 client.select("SELECT num_vals(rollup(agg)) FROM aggs", None, None)
@@ -340,4 +382,31 @@ mod tests {
             assert_eq!(test, (Some(99), Some(253./20200.), Some(545./20200.)));
         });
     }
+
+    #[pg_test]
+    fn test_topn_into_values_rows_estimate() {
+        Spi::execute(|client| {
+            let version = client.select("SELECT current_setting('server_version_num')::int", None, None)
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            if version < 120000 {
+                return
+            }
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            let plan = client.select(
+                "EXPLAIN SELECT * FROM into_values(\
+                    '(version:1,num_values:2,max_values:2,total_inputs:3,values:[1,2],counts:[2,1],overcounts:[0,0])'::topn)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert!(plan.contains("rows=2 "), "{}", plan);
+        });
+    }
 }
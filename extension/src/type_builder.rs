@@ -198,9 +198,19 @@ macro_rules! pg_type_impl {
     }
 }
 
+// Text input and output through RON. Text is tagged with the version of the
+// value it was written from; text of an older `version` is passed to `upgrade`
+// along with that version to be converted to the current format, and text from
+// newer versions of the extension is rejected. Types whose format changes
+// must bump their version, build their values with it, and keep the means to
+// read the old text in `upgrade`.
 #[macro_export]
 macro_rules! ron_inout_funcs {
     ($name:ident) => {
+        $crate::ron_inout_funcs!($name, version: 1, upgrade: |version, _|
+            $crate::serialization::unknown_version(stringify!($name), version));
+    };
+    ($name:ident, version: $version: expr, upgrade: $upgrade: expr) => {
         impl<'input> InOutFuncs for $name<'input> {
             fn output(&self, buffer: &mut StringInfo) {
                 use $crate::serialization::{EncodedStr::*, str_to_db_encoding};
@@ -216,7 +226,7 @@ macro_rules! ron_inout_funcs {
             where
                 Self: Sized,
             {
-                use $crate::serialization::str_from_db_encoding;
+                use $crate::serialization::{read_versioned, str_from_db_encoding};
 
                 // SAFETY our serde shims will allocate and leak copies of all
                 // the data, so the lifetimes of the borrows aren't actually
@@ -226,7 +236,7 @@ macro_rules! ron_inout_funcs {
                         std::mem::transmute(s)
                     }
                     let input = extend_lifetime(str_from_db_encoding(input));
-                    ron::from_str(input).unwrap()
+                    read_versioned(stringify!($name), input, $version, |input| ron::from_str(input).unwrap(), $upgrade)
                 };
                 unsafe { Self(val, None).flatten() }
             }
@@ -237,11 +247,14 @@ macro_rules! ron_inout_funcs {
 #[macro_export]
 macro_rules! flatten {
     ($typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
+        $crate::flatten!(version: 1, $typ { $($field: $value),* })
+    };
+    (version: $version: expr, $typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
         {
             let data = ::paste::paste! {
                 [<$typ Data>] {
                     header: 0,
-                    version: 1,
+                    version: $version,
                     padding: [0; 3],
                     $(
                         $field: $value
@@ -256,11 +269,14 @@ macro_rules! flatten {
 #[macro_export]
 macro_rules! build {
     ($typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
+        $crate::build!(version: 1, $typ { $($field: $value),* })
+    };
+    (version: $version: expr, $typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
         {
             <$typ>::from(::paste::paste! {
                 [<$typ Data>] {
                     header: 0,
-                    version: 1,
+                    version: $version,
                     padding: [0; 3],
                     $(
                         $field: $value
@@ -274,6 +290,47 @@ macro_rules! build {
 #[repr(u8)]
 pub enum SerializationType {
     Default = 1,
+    // bincode, snappy compressed
+    Snappy = 2,
+}
+
+// Whether do_serialize compresses the states it writes, for parallel plans
+// that ship large states between workers.
+pub(crate) static COMPRESS_TRANSITION_STATES: pgx::GucSetting<bool> = pgx::GucSetting::new(false);
+
+// States smaller than this aren't worth compressing.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+// Compresses the bincode of a state serialized by do_serialize, which starts
+// at `start` after the serialization type, when that's enabled and helps.
+pub fn compress_serialized(bytes: &mut Vec<u8>, start: usize) {
+    if !COMPRESS_TRANSITION_STATES.get() || bytes.len() - start < COMPRESSION_THRESHOLD {
+        return
+    }
+    let compressed = snap::raw::Encoder::new()
+        .compress_vec(&bytes[start..])
+        .unwrap_or_else(|e| pgx::error!("serialization error {}", e));
+    if compressed.len() >= bytes.len() - start {
+        return
+    }
+    bytes[start - 1] = SerializationType::Snappy as u8;
+    bytes.truncate(start);
+    bytes.extend_from_slice(&compressed);
+}
+
+// The bincode of a state written by do_serialize, given the bytes following
+// its type version.
+pub fn serialized_bincode(bytes: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+    use std::borrow::Cow;
+    match bytes[0] {
+        t if t == SerializationType::Default as u8 => Cow::Borrowed(&bytes[1..]),
+        t if t == SerializationType::Snappy as u8 => Cow::Owned(
+            snap::raw::Decoder::new()
+                .decompress_vec(&bytes[1..])
+                .unwrap_or_else(|e| pgx::error!("deserialization error {}", e))
+        ),
+        t => pgx::error!("deserialization error, invalid serialization type {}", t),
+    }
 }
 
 #[macro_export]
@@ -296,10 +353,11 @@ macro_rules! do_serialize {
             bytes.extend_from_slice(&varsize);
             // type version
             bytes.push($version);
-            // serialization version; 1 for bincode is currently the only option
+            // serialization version; bincode, possibly compressed below
             bytes.push(SerializationType::Default as u8);
             bincode::serialize_into(&mut bytes, state)
                 .unwrap_or_else(|e| pgx::error!("serialization error {}", e));
+            $crate::type_builder::compress_serialized(&mut bytes, 6);
             unsafe {
                 ::pgx::set_varsize(bytes.as_mut_ptr() as *mut _, bytes.len() as i32);
             }
@@ -307,28 +365,35 @@ macro_rules! do_serialize {
         }
     };
 }
+// Deserializes a value written by `do_serialize`. Values serialized with an
+// older `version` are passed to `upgrade` along with that version and their
+// bincode bytes.
 #[macro_export]
 macro_rules! do_deserialize {
     ($bytes: ident, $t: ty) => {
+        $crate::do_deserialize!($bytes, $t, version: 1, upgrade: |version, _|
+            $crate::serialization::unknown_version(stringify!($t), version))
+    };
+    ($bytes: ident, $t: ty, version: $version: expr, upgrade: $upgrade: expr) => {
         {
-            use $crate::type_builder::SerializationType;
-
             let state: $t = unsafe {
                 let detoasted = pg_sys::pg_detoast_datum_packed($bytes as *mut _);
                 let len = pgx::varsize_any_exhdr(detoasted);
                 let data = pgx::vardata_any(detoasted);
                 let bytes = slice::from_raw_parts(data as *mut u8, len);
-                if bytes.len() < 1 {
+                if bytes.len() < 2 {
                     pgx::error!("deserialization error, no bytes")
                 }
-                if bytes[0] != 1 {
-                    pgx::error!("deserialization error, invalid serialization version {}", bytes[0])
-                }
-                if bytes[1] != SerializationType::Default as u8 {
-                    pgx::error!("deserialization error, invalid serialization type {}", bytes[1])
-                }
-                bincode::deserialize(&bytes[2..]).unwrap_or_else(|e|
-                    pgx::error!("deserialization error {}", e))
+                let bincode_bytes = $crate::type_builder::serialized_bincode(&bytes[1..]);
+                let upgrade: fn(u8, &[u8]) -> $t = $upgrade;
+                $crate::serialization::check_version(
+                    stringify!($t),
+                    bytes[0],
+                    $version,
+                    || bincode::deserialize(&bincode_bytes).unwrap_or_else(|e|
+                        pgx::error!("deserialization error {}", e)),
+                    |version| upgrade(version, &bincode_bytes),
+                )
             };
             state.into()
         }
@@ -9,17 +9,24 @@ use encodings::{delta, prefix_varint};
 
 use uddsketch::{SketchHashKey, UDDSketch as UddSketchInternal};
 
+use time_series::TSPoint;
+
 use crate::{
-    aggregate_utils::in_aggregate_context,
+    aggregate_utils::{in_aggregate_context, numeric_to_f64},
+    build,
     flatten,
     palloc::Internal, pg_type,
     accessors::toolkit_experimental,
+    time_series::{SeriesType, TimeSeries},
 };
 
 
 #[allow(non_camel_case_types)]
 type int = u32;
 
+#[allow(non_camel_case_types)]
+type numeric = pg_sys::Datum;
+
 // PG function for adding values to a sketch.
 // Null values are ignored.
 #[pg_extern(immutable, parallel_safe)]
@@ -46,6 +53,10 @@ pub fn uddsketch_trans(
     }
 }
 
+// the size and error used by percentile_agg
+pub const PERCENTILE_AGG_DEFAULT_SIZE: u32 = 200;
+pub const PERCENTILE_AGG_DEFAULT_ERROR: f64 = 0.001;
+
 // transition function for the simpler percentile_agg aggregate, which doesn't
 // take parameters for the size and error, but uses a default
 #[pg_extern(immutable, parallel_safe)]
@@ -54,11 +65,30 @@ pub fn percentile_agg_trans(
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<UddSketchInternal>> {
-    let default_size = 200;
-    let default_max_error = 0.001;
+    let default_size = PERCENTILE_AGG_DEFAULT_SIZE;
+    let default_max_error = PERCENTILE_AGG_DEFAULT_ERROR;
     uddsketch_trans(state, default_size, default_max_error, value, fcinfo)
 }
 
+// percentile_agg of REAL and NUMERIC values, see numeric_to_f64()
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn percentile_agg_real_trans(
+    state: Option<Internal<UddSketchInternal>>,
+    value: Option<f32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<UddSketchInternal>> {
+    percentile_agg_trans(state, value.map(|v| v as f64), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn percentile_agg_numeric_trans(
+    state: Option<Internal<UddSketchInternal>>,
+    value: Option<numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<UddSketchInternal>> {
+    percentile_agg_trans(state, value.map(numeric_to_f64), fcinfo)
+}
+
 // PG function for merging sketches.
 #[pg_extern(immutable, parallel_safe)]
 pub fn uddsketch_combine(
@@ -71,11 +101,10 @@ pub fn uddsketch_combine(
             match (state1, state2) {
                 (None, None) => None,
                 (None, Some(state2)) => Some(state2.clone().into()),
-                (Some(state1), None) => Some(state1.clone().into()),
-                (Some(state1), Some(state2)) => {
-                    let mut sketch = state1.clone();
-                    sketch.merge_sketch(&state2);
-                    Some(sketch.into())
+                (Some(state1), None) => Some(state1),
+                (Some(mut state1), Some(state2)) => {
+                    state1.merge_sketch(&state2);
+                    Some(state1)
                 }
             }
         })
@@ -265,35 +294,36 @@ impl<'input> UddSketch<'input> {
         decompress_counts(self.negative_counts.as_slice(), self.zero_bucket_count, self.positive_counts.as_slice())
     }
 
-    fn to_uddsketch(&self) -> UddSketchInternal {
-        UddSketchInternal::new_from_data(self.max_buckets as u64, self.alpha, self.compactions, self.count, self.sum, self.keys(), self.counts())
+    // the buckets in order with the number of values in each, along with
+    // gamma() and num_values() enough to estimate from the sketch elsewhere
+    pub(crate) fn buckets(&self) -> impl Iterator<Item=(SketchHashKey, u64)> + '_ {
+        self.keys().zip(self.counts())
     }
-}
 
-// PG function to generate a user-facing UddSketch object from a UddSketchInternal.
-#[pg_extern(immutable, parallel_safe)]
-fn uddsketch_final(
-    state: Option<Internal<UddSketchInternal>>,
-    fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<UddSketch<'static>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let state = match state {
-                None => return None,
-                Some(state) => state,
-            };
+    pub(crate) fn gamma(&self) -> f64 {
+        uddsketch::gamma(self.alpha)
+    }
 
-            let CompressedBuckets {
-                negative_indexes,
-                negative_counts,
-                zero_bucket_count,
-                positive_indexes,
-                positive_counts,
-            } = compress_buckets(state.bucket_iter());
+    pub(crate) fn num_values(&self) -> u64 {
+        self.count
+    }
 
+    fn to_uddsketch(&self) -> UddSketchInternal {
+        UddSketchInternal::new_from_data(self.max_buckets as u64, self.alpha, self.compactions, self.count, self.sum, self.keys(), self.counts())
+    }
+
+    pub fn from_internal(state: &UddSketchInternal) -> UddSketch<'static> {
+        let CompressedBuckets {
+            negative_indexes,
+            negative_counts,
+            zero_bucket_count,
+            positive_indexes,
+            positive_counts,
+        } = compress_buckets(state.bucket_iter());
 
-            // we need to flatten the vector to a single buffer that contains
-            // both the size, the data, and the varlen header
+        // we need to flatten the vector to a single buffer that contains
+        // both the size, the data, and the varlen header
+        unsafe {
             flatten!(
                 UddSketch {
                     alpha: state.max_error(),
@@ -312,21 +342,39 @@ fn uddsketch_final(
                     positive_indexes: positive_indexes.into(),
                     positive_counts: positive_counts.into(),
                 }
-            ).into()
+            )
+        }
+    }
+}
+
+// PG function to generate a user-facing UddSketch object from a UddSketchInternal.
+#[pg_extern(immutable, parallel_safe)]
+fn uddsketch_final(
+    state: Option<Internal<UddSketchInternal>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<UddSketch<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = match state {
+                None => return None,
+                Some(state) => state,
+            };
+
+            UddSketch::from_internal(&state).into()
         })
     }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct CompressedBuckets {
-    negative_indexes: Vec<u8>,
-    negative_counts: Vec<u8>,
-    zero_bucket_count: u64,
-    positive_indexes: Vec<u8>,
-    positive_counts: Vec<u8>,
+pub(crate) struct CompressedBuckets {
+    pub(crate) negative_indexes: Vec<u8>,
+    pub(crate) negative_counts: Vec<u8>,
+    pub(crate) zero_bucket_count: u64,
+    pub(crate) positive_indexes: Vec<u8>,
+    pub(crate) positive_counts: Vec<u8>,
 }
 
-fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> CompressedBuckets {
+pub(crate) fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> CompressedBuckets {
     let mut negative_indexes = prefix_varint::I64Compressor::with(delta::i64_encoder());
     let mut negative_counts = prefix_varint::U64Compressor::with(delta::u64_encoder());
     let mut zero_bucket_count = 0;
@@ -363,7 +411,7 @@ fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> Compre
 }
 
 
-fn decompress_keys<'i>(
+pub(crate) fn decompress_keys<'i>(
     negative_indexes: &'i [u8],
     zero_bucket: bool,
     positive_indexes: &'i [u8]
@@ -381,7 +429,7 @@ fn decompress_keys<'i>(
     negatives.chain(zero).chain(positives)
 }
 
-fn decompress_counts<'b>(
+pub(crate) fn decompress_counts<'b>(
     negative_buckets: &'b [u8],
     zero_bucket: u64,
     positive_buckets: &'b [u8],
@@ -420,6 +468,30 @@ CREATE AGGREGATE percentile_agg(value DOUBLE PRECISION)
 );
 "#);
 
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.percentile_agg(value REAL)
+(
+    sfunc = toolkit_experimental.percentile_agg_real_trans,
+    stype = internal,
+    finalfunc = uddsketch_final,
+    combinefunc = uddsketch_combine,
+    serialfunc = uddsketch_serialize,
+    deserialfunc = uddsketch_deserialize,
+    parallel = safe
+);
+
+CREATE AGGREGATE toolkit_experimental.percentile_agg(value NUMERIC)
+(
+    sfunc = toolkit_experimental.percentile_agg_numeric_trans,
+    stype = internal,
+    finalfunc = uddsketch_final,
+    combinefunc = uddsketch_combine,
+    serialfunc = uddsketch_serialize,
+    deserialfunc = uddsketch_deserialize,
+    parallel = safe
+);
+"#);
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn uddsketch_compound_trans(
     state: Option<Internal<UddSketchInternal>>,
@@ -456,6 +528,261 @@ CREATE AGGREGATE rollup(
 );
 "#);
 
+// `a + b` merges two sketches the same way rollup() does
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn uddsketch_add(
+    a: UddSketch,
+    b: UddSketch,
+) -> UddSketch<'static> {
+    let mut sketch = a.to_uddsketch();
+    sketch.merge_sketch(&b.to_uddsketch());
+    UddSketch::from_internal(&sketch)
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+extension_sql!(r#"
+CREATE OPERATOR toolkit_experimental.+ (
+    PROCEDURE=toolkit_experimental.uddsketch_add,
+    LEFTARG=UddSketch,
+    RIGHTARG=UddSketch
+);
+"#);
+
+// percentile_over_time(bucket, sketch, percentile) gathers one sketch per
+// time bucket, as from a GROUP BY time_bucket() subquery or a continuous
+// aggregate, into a timevector of that percentile per bucket. Sketches of the
+// same bucket, e.g. those of different devices, are merged first.
+#[derive(Clone)]
+pub struct PercentileOverTimeState {
+    percentile: f64,
+    buckets: std::collections::BTreeMap<i64, UddSketchInternal>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedPercentileOverTimeState {
+    percentile: f64,
+    buckets: Vec<(i64, SerializedUddSketch)>,
+}
+
+impl PercentileOverTimeState {
+    fn add_sketch(&mut self, bucket: i64, sketch: &UddSketchInternal) {
+        match self.buckets.get_mut(&bucket) {
+            Some(existing) => existing.merge_sketch(sketch),
+            None => { self.buckets.insert(bucket, sketch.clone()); },
+        }
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn percentile_over_time_trans(
+    state: Option<Internal<PercentileOverTimeState>>,
+    bucket: Option<pg_sys::TimestampTz>,
+    sketch: Option<UddSketch>,
+    percentile: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<PercentileOverTimeState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (bucket, sketch, percentile) = match (bucket, sketch, percentile) {
+                (Some(bucket), Some(sketch), Some(percentile)) => (bucket, sketch, percentile),
+                _ => return state,
+            };
+            if !(0.0..=1.0).contains(&percentile) {
+                error!("percentile must be between 0 and 1")
+            }
+            let mut state = state.unwrap_or_else(|| PercentileOverTimeState {
+                percentile,
+                buckets: Default::default(),
+            }.into());
+            if state.percentile != percentile {
+                error!("percentile_over_time requires the same percentile for every row")
+            }
+            state.add_sketch(bucket, &sketch.to_uddsketch());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn percentile_over_time_combine(
+    state1: Option<Internal<PercentileOverTimeState>>,
+    state2: Option<Internal<PercentileOverTimeState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<PercentileOverTimeState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1),
+                (Some(mut state1), Some(state2)) => {
+                    if state1.percentile != state2.percentile {
+                        error!("percentile_over_time requires the same percentile for every row")
+                    }
+                    for (bucket, sketch) in &state2.buckets {
+                        state1.add_sketch(*bucket, sketch);
+                    }
+                    Some(state1)
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn percentile_over_time_serialize(
+    state: Internal<PercentileOverTimeState>,
+) -> bytea {
+    let serializable = &SerializedPercentileOverTimeState {
+        percentile: state.percentile,
+        buckets: state.buckets.iter()
+            .map(|(bucket, sketch)| (*bucket, SerializedUddSketch::from(sketch)))
+            .collect(),
+    };
+    crate::do_serialize!(serializable)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn percentile_over_time_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<PercentileOverTimeState> {
+    let state: SerializedPercentileOverTimeState = crate::do_deserialize!(bytes, SerializedPercentileOverTimeState);
+    PercentileOverTimeState {
+        percentile: state.percentile,
+        buckets: state.buckets.into_iter()
+            .map(|(bucket, sketch)| (bucket, sketch.into()))
+            .collect(),
+    }.into()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn percentile_over_time_final(
+    state: Option<Internal<PercentileOverTimeState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            let points: Vec<TSPoint> = state.buckets.iter()
+                .map(|(&ts, sketch)| TSPoint{ ts, val: sketch.estimate_quantile(state.percentile) })
+                .collect();
+            Some(build!(
+                TimeSeries {
+                    num_label_bytes: 0,
+                    label_bytes: vec![].into(),
+                    series: SeriesType::SortedSeries {
+                        num_points: points.len() as u64,
+                        points: points.into(),
+                    }
+                }
+            ))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.percentile_over_time(
+    bucket timestamptz, sketch uddsketch, percentile DOUBLE PRECISION
+) (
+    sfunc = toolkit_experimental.percentile_over_time_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.percentile_over_time_final,
+    combinefunc = toolkit_experimental.percentile_over_time_combine,
+    serialfunc = toolkit_experimental.percentile_over_time_serialize,
+    deserialfunc = toolkit_experimental.percentile_over_time_deserialize,
+    parallel = restricted
+);
+"#);
+
+// OTLP exponential histograms of scale s have buckets growing by a factor of
+// 2^(2^-s), and we only convert the scales whose growth factor leaves a
+// representable error
+const OTEL_MIN_SCALE: i32 = -5;
+const OTEL_MAX_SCALE: i32 = 20;
+
+// Converts an OpenTelemetry ExponentialHistogram data point to a sketch. Both
+// use logarithmic buckets, so OTLP bucket `offset + i`, covering
+// (base^(offset + i), base^(offset + i + 1)], becomes sketch bucket
+// `offset + i + 1` with the same bounds, and the error of the sketch is that of
+// the histogram's scale. Negative buckets cover the same ranges of magnitudes.
+// The sum is estimated from the buckets when the data point didn't record one.
+// If there are more than `max_buckets` non-empty buckets they are compacted as
+// in `uddsketch`. Only sketches converted with the same scale and
+// `max_buckets` can be rolled up together.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_otel(
+    scale: i32,
+    zero_count: i64,
+    positive_offset: i32,
+    positive_bucket_counts: Array<i64>,
+    negative_offset: i32,
+    negative_bucket_counts: Array<i64>,
+    sum: Option<f64>,
+    max_buckets: default!(i32, 200),
+) -> UddSketch<'static> {
+    if !(OTEL_MIN_SCALE..=OTEL_MAX_SCALE).contains(&scale) {
+        error!("exponential histogram scale must be between {} and {}", OTEL_MIN_SCALE, OTEL_MAX_SCALE)
+    }
+    if max_buckets < 1 {
+        error!("max_buckets must be positive")
+    }
+    let counts = |counts: Array<i64>| -> Vec<u64> {
+        counts.iter()
+            .map(|count| match count {
+                Some(count) if count >= 0 => count as u64,
+                _ => error!("exponential histogram bucket counts must be non-negative"),
+            })
+            .collect()
+    };
+    let positive_counts = counts(positive_bucket_counts);
+    let negative_counts = counts(negative_bucket_counts);
+    if zero_count < 0 {
+        error!("exponential histogram bucket counts must be non-negative")
+    }
+
+    // base - 1, computed directly to keep the precision of fine scales
+    let growth = (std::f64::consts::LN_2 * 2f64.powi(-scale)).exp_m1();
+    let alpha = growth / (growth + 2.0);
+    let bucket_value = |key: i64| (1.0 + growth).powf(key as f64 - 1.0) * (1.0 + alpha);
+
+    // sketch keys in increasing order of their values
+    let negative = negative_counts.iter().enumerate().rev()
+        .map(|(i, &count)| (SketchHashKey::Negative(negative_offset as i64 + i as i64 + 1), count));
+    let zero = std::iter::once((SketchHashKey::Zero, zero_count as u64));
+    let positive = positive_counts.iter().enumerate()
+        .map(|(i, &count)| (SketchHashKey::Positive(positive_offset as i64 + i as i64 + 1), count));
+    let buckets: Vec<(SketchHashKey, u64)> = negative.chain(zero).chain(positive)
+        .filter(|&(_, count)| count > 0)
+        .collect();
+
+    if buckets.is_empty() {
+        return UddSketch::from_internal(&UddSketchInternal::new(max_buckets as u64, alpha))
+    }
+
+    let count = buckets.iter().map(|&(_, count)| count).sum();
+    let sum = sum.unwrap_or_else(|| buckets.iter()
+        .map(|&(key, count)| count as f64 * match key {
+            SketchHashKey::Positive(key) => bucket_value(key),
+            SketchHashKey::Negative(key) => -bucket_value(key),
+            _ => 0.0,
+        })
+        .sum());
+    let mut sketch = UddSketchInternal::new_from_data(
+        max_buckets as u64,
+        alpha,
+        0,
+        count,
+        sum,
+        buckets.iter().map(|&(key, _)| key),
+        buckets.iter().map(|&(_, count)| count),
+    );
+    while sketch.current_buckets_count() > max_buckets as usize {
+        sketch.compact_buckets();
+    }
+    UddSketch::from_internal(&sketch)
+}
+
 //---- Available PG operations on the sketch
 
 #[pg_operator(immutable, parallel_safe)]
@@ -566,6 +893,75 @@ pub fn uddsketch_error(
     sketch.alpha
 }
 
+// The UddSketch as MessagePack, see msgpack.rs
+#[pg_extern(name="to_msgpack", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_to_msgpack(
+    sketch: UddSketch<'_>,
+) -> Vec<u8> {
+    crate::msgpack::encode(&ReadableUddSketch::from(&sketch))
+}
+
+// inverse of to_msgpack(UddSketch)
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_msgpack(
+    bytes: &[u8],
+) -> UddSketch<'static> {
+    let sketch: ReadableUddSketch = crate::msgpack::decode("UddSketch", bytes, 1);
+    UddSketch::from(&sketch)
+}
+
+// The UddSketch as JSON, in the same form as its MessagePack.
+#[pg_extern(name="to_jsonb", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_to_jsonb(
+    sketch: UddSketch<'_>,
+) -> JsonB {
+    crate::jsonb::encode(&ReadableUddSketch::from(&sketch))
+}
+
+// inverse of to_jsonb(UddSketch), unknown fields are rejected
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_jsonb(
+    json: JsonB,
+) -> UddSketch<'static> {
+    let sketch: ReadableUddSketch = crate::jsonb::decode("UddSketch", &json.0, 1);
+    UddSketch::from(&sketch)
+}
+
+// The size of the UddSketch as stored and in memory, see summary_size.rs.
+#[pg_extern(name="toolkit_summary_size", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_size(
+    sketch: UddSketch<'_>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(on_disk_bytes,i64),name!(in_memory_bytes,i64),name!(entries,Option<i64>))> {
+    crate::summary_size::summary_size(fcinfo, sketch.0.num_bytes(), Some(sketch.num_buckets as usize))
+}
+
+// Checks the UddSketch's internal invariants, see validate.rs.
+#[pg_extern(name="validate", immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_validate(
+    sketch: UddSketch<'_>,
+) -> impl std::iter::Iterator<Item = (name!(severity,String),name!(message,String))> {
+    let mut diagnostics = crate::validate::Diagnostics::default();
+    if !(sketch.alpha > 0.0 && sketch.alpha < 1.0) {
+        diagnostics.error(format!("the relative error {} is not between 0 and 1", sketch.alpha))
+    }
+    if sketch.num_buckets > sketch.max_buckets {
+        diagnostics.error(format!("the sketch has {} buckets but at most {} are allowed", sketch.num_buckets, sketch.max_buckets))
+    }
+    let keys: Vec<SketchHashKey> = sketch.keys().collect();
+    if keys.len() != sketch.num_buckets as usize {
+        diagnostics.error(format!("the sketch stores {} buckets but records {}", keys.len(), sketch.num_buckets))
+    }
+    if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+        diagnostics.error("buckets are not in increasing order".to_string())
+    }
+    let counts: u64 = sketch.counts().sum();
+    if counts != sketch.count {
+        diagnostics.error(format!("bucket counts sum to {} but the count is {}", counts, sketch.count))
+    }
+    diagnostics.into_rows()
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -717,6 +1113,80 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_percentile_agg_real_and_numeric() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select("CREATE TABLE pa_test AS \
+                SELECT v::DOUBLE PRECISION AS d, v::REAL AS r, v::NUMERIC AS n \
+                FROM generate_series(1, 1000) v", None, None);
+
+            let (double, real, numeric) = client
+                .select("SELECT \
+                    approx_percentile(0.5, percentile_agg(d)), \
+                    approx_percentile(0.5, toolkit_experimental.percentile_agg(r)), \
+                    approx_percentile(0.5, toolkit_experimental.percentile_agg(n)) \
+                    FROM pa_test", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(real, double);
+            assert_eq!(numeric, double);
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_add() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select("CREATE TABLE sketches AS \
+                SELECT v > 50 AS upper, uddsketch(100, 0.01, v) \
+                FROM generate_series(1, 100) v \
+                GROUP BY 1", None, None);
+
+            let (sum, rollup) = client
+                .select("SELECT \
+                    (SELECT a.uddsketch OPERATOR(toolkit_experimental.+) b.uddsketch FROM sketches a, sketches b WHERE NOT a.upper AND b.upper)::TEXT, \
+                    (SELECT rollup(uddsketch) FROM sketches)::TEXT", None, None)
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(sum, rollup);
+        });
+    }
+
+    #[pg_test]
+    fn test_percentile_over_time() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            // two devices per hour, each with its own sketch
+            client.select("CREATE TABLE hourly AS \
+                SELECT '2020-01-01 00:00:00+00'::timestamptz + hour * '1 hour'::interval AS bucket, \
+                    device, uddsketch(100, 0.01, hour * 100 + device * 50 + v) AS sketch \
+                FROM generate_series(0, 2) hour, generate_series(0, 1) device, generate_series(1, 50) v \
+                GROUP BY 1, 2", None, None);
+
+            let points: Vec<(String, f64)> = client
+                .select("SELECT time::TEXT, value FROM toolkit_experimental.unnest( \
+                    (SELECT toolkit_experimental.percentile_over_time(bucket, sketch, 0.5) FROM hourly))", None, None)
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                ))
+                .collect();
+            let expected: Vec<(String, f64)> = client
+                .select("SELECT bucket::TEXT, approx_percentile(0.5, rollup(sketch)) \
+                    FROM hourly GROUP BY bucket ORDER BY bucket", None, None)
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(points.len(), 3);
+            assert_eq!(points, expected);
+            assert_eq!(points[0].0, "2020-01-01 00:00:00+00");
+        });
+    }
+
     #[pg_test]
     fn test_percentile_agg() {
         Spi::execute(|client| {
@@ -805,4 +1275,113 @@ mod tests {
             }
         });
     }
+
+    #[pg_test]
+    fn test_uddsketch_from_otel() {
+        Spi::execute(|client| {
+            // base 2: one value in (-4, -2], one zero, and values in (1, 2],
+            // (2, 4], (2, 4], and (4, 8]
+            client.select("CREATE VIEW otel AS \
+                SELECT toolkit_experimental.uddsketch_from_otel(0, 1, 0, '{1, 2, 1}', 1, '{1}', 12.0) AS sketch, \
+                    toolkit_experimental.uddsketch_from_otel(0, 1, 0, '{1, 2, 1}', 1, '{1}', NULL) AS estimated", None, None);
+
+            let (count, error) = client
+                .select("SELECT num_vals(sketch), error(sketch) FROM otel", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            let (mean, estimated_mean) = client
+                .select("SELECT mean(sketch), mean(estimated) FROM otel", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(count.unwrap(), 6.0, 0.000001);
+            apx_eql(error.unwrap(), 1.0 / 3.0, 0.000001);
+            apx_eql(mean.unwrap(), 2.0, 0.000001);
+            // each bucket's value is 4/3 of its lower bound
+            apx_eql(estimated_mean.unwrap(), 28.0 / 18.0, 0.000001);
+
+            let (min, median, max) = client
+                .select("SELECT \
+                    approx_percentile(0.0, sketch), \
+                    approx_percentile(0.5, sketch), \
+                    approx_percentile(1.0, sketch) \
+                    FROM otel", None, None)
+                .first()
+                .get_three::<f64, f64, f64>();
+            apx_eql(min.unwrap(), -8.0 / 3.0, 0.000001);
+            apx_eql(median.unwrap(), 8.0 / 3.0, 0.000001);
+            apx_eql(max.unwrap(), 16.0 / 3.0, 0.000001);
+
+            let (rolled_up, compacted_error) = client
+                .select("SELECT \
+                    (SELECT num_vals(rollup(sketch)) FROM otel, generate_series(1, 2)), \
+                    error(toolkit_experimental.uddsketch_from_otel(0, 1, 0, '{1, 2, 1}', 1, '{1}', NULL, max_buckets => 3))", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(rolled_up.unwrap(), 12.0, 0.000001);
+            assert!(compacted_error.unwrap() > 1.0 / 3.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_compressed_parallel_states() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE sketched AS SELECT v::DOUBLE PRECISION AS v FROM generate_series(1, 100000) v",
+                None,
+                None
+            );
+            client.select("ANALYZE sketched", None, None);
+            let serial = client
+                .select("SELECT num_vals(sketch), approx_percentile(0.5, sketch) FROM \
+                    (SELECT uddsketch(200, 0.001, v) AS sketch FROM sketched) s", None, None)
+                .first()
+                .get_two::<f64, f64>();
+
+            // make the workers ship their states to the leader compressed
+            client.select("SET LOCAL timescaledb_toolkit_compress_transition_states TO true", None, None);
+            client.select("SET LOCAL parallel_setup_cost TO 0", None, None);
+            client.select("SET LOCAL parallel_tuple_cost TO 0", None, None);
+            client.select("SET LOCAL min_parallel_table_scan_size TO 0", None, None);
+            client.select("SET LOCAL max_parallel_workers_per_gather TO 2", None, None);
+            let parallel = client
+                .select("SELECT num_vals(sketch), approx_percentile(0.5, sketch) FROM \
+                    (SELECT uddsketch(200, 0.001, v) AS sketch FROM sketched) s", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(serial, parallel);
+        });
+    }
+
+    #[pg_test]
+    fn test_large_uddsketch() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            // values spread over enough buckets to keep 100,000 of them, so
+            // the sketches are TOASTed
+            client.select(
+                "CREATE TABLE big_sketches AS \
+                    SELECT i % 2 AS part, uddsketch(100000, 0.0001, exp(i / 1000.0)) AS sketch \
+                    FROM generate_series(1, 200000) i \
+                    GROUP BY i % 2",
+                None,
+                None
+            );
+
+            let (entries, on_disk, in_memory) = client
+                .select("SELECT max(entries), max(on_disk_bytes), max(in_memory_bytes) \
+                    FROM big_sketches, toolkit_experimental.toolkit_summary_size(sketch)", None, None)
+                .first()
+                .get_three::<i64, i64, i64>();
+            assert!(entries.unwrap() > 50000 && entries.unwrap() <= 100000);
+            assert!(in_memory.unwrap() > 100000);
+            assert!(on_disk.unwrap() <= in_memory.unwrap());
+
+            let (count, median) = client
+                .select("SELECT num_vals(rollup(sketch)), approx_percentile(0.5, rollup(sketch)) FROM big_sketches", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count, Some(200000.0));
+            pct_eql(median.unwrap(), (100.0f64).exp(), 0.01);
+        });
+    }
 }
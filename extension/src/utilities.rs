@@ -88,6 +88,228 @@ pub fn generate_periodic_normal_series(
     })
 }
 
+// The same as above, but with the durations as intervals instead of
+// microseconds.
+#[pg_extern(name="generate_periodic_normal_series", schema = "toolkit_experimental")]
+pub fn generate_periodic_normal_series_interval(
+    series_start: pg_sys::TimestampTz,
+    series_len: Option<Interval>,
+    sample_interval: Option<Interval>,
+    base_value: Option<f64>,
+    period: Option<Interval>,
+    periodic_magnitude: Option<f64>,
+    standard_deviation: Option<f64>,
+    rng_seed: Option<i64>,
+) -> impl std::iter::Iterator<Item = (name!(time,TimestampTz),name!(value,f64))> + 'static {
+    let sample_interval = sample_interval.map(|i| interval_to_micros(i, "sample_interval"));
+    if matches!(sample_interval, Some(i) if i <= 0) {
+        error!("sample_interval must be positive")
+    }
+    generate_periodic_normal_series(
+        series_start,
+        series_len.map(|i| interval_to_micros(i, "series_len")),
+        sample_interval,
+        base_value,
+        period.map(|i| interval_to_micros(i, "period")),
+        periodic_magnitude,
+        standard_deviation,
+        rng_seed,
+    )
+}
+
+#[allow(non_camel_case_types)]
+type Interval = pg_sys::Datum;
+
+const USECS_PER_SEC: i64 = 1_000_000;
+pub const USECS_PER_DAY: i64 = 24 * 60 * 60 * USECS_PER_SEC;
+
+// The length of an interval in microseconds, for the many arguments that are
+// durations. Days are treated as exactly 24 hours, months vary in length so
+// they're rejected; `name` is the argument as it's named in that error.
+pub fn interval_to_micros(interval: Interval, name: &str) -> i64 {
+    unsafe {
+        let interval = interval as *const pg_sys::Interval;
+        if (*interval).month != 0 {
+            error!("{} can currently only use fixed units (days or smaller)", name)
+        }
+        (*interval).day as i64 * USECS_PER_DAY + (*interval).time
+    }
+}
+
+// An interval of `micros` microseconds, only using the interval's time
+pub fn micros_to_interval(micros: i64) -> Interval {
+    unsafe {
+        let interval = pg_sys::palloc(std::mem::size_of::<pg_sys::Interval>()) as *mut pg_sys::Interval;
+        *interval = pg_sys::Interval {
+            time: micros,
+            day: 0,
+            month: 0,
+        };
+        interval as Interval
+    }
+}
+
+// Planner support for set-returning functions that return a row per element
+// of their first argument: when that argument is a constant the planner is
+// given the `rows` it holds, instead of the 1000 rows it assumes for every
+// set-returning function. Used as the body of the `SUPPORT` functions.
+//
+// SupportRequestRows only exists on PostgreSQL 12 and later, on older
+// versions this is a no-op.
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+pub unsafe fn estimate_srf_rows<T: FromDatum>(input: pg_sys::Datum, rows: impl FnOnce(T) -> usize) -> pg_sys::Datum {
+    let node = input as *mut pg_sys::Node;
+    if !is_a(node, pg_sys::NodeTag_T_SupportRequestRows) {
+        return 0
+    }
+
+    let request = node as *mut pg_sys::SupportRequestRows;
+    let call = (*request).node;
+    let args = if is_a(call, pg_sys::NodeTag_T_FuncExpr) {
+        (*(call as *mut pg_sys::FuncExpr)).args
+    } else if is_a(call, pg_sys::NodeTag_T_OpExpr) {
+        (*(call as *mut pg_sys::OpExpr)).args
+    } else {
+        return 0
+    };
+    let args = PgList::<pg_sys::Node>::from_pg(args);
+    let arg = match args.get_ptr(0) {
+        Some(arg) if is_a(arg, pg_sys::NodeTag_T_Const) => arg as *mut pg_sys::Const,
+        _ => return 0,
+    };
+
+    // the functions are strict, so a NULL argument returns nothing
+    let rows = match T::from_datum((*arg).constvalue, (*arg).constisnull, (*arg).consttype) {
+        Some(arg) => rows(arg),
+        None => 0,
+    };
+    (*request).rows = rows as f64;
+    node as pg_sys::Datum
+}
+
+#[cfg(not(any(feature = "pg12", feature = "pg13")))]
+pub unsafe fn estimate_srf_rows<T: FromDatum>(_input: pg_sys::Datum, _rows: impl FnOnce(T) -> usize) -> pg_sys::Datum {
+    0
+}
+
+#[derive(Clone, Copy)]
+enum SeriesKind {
+    Counter,
+    Gauge,
+    Seasonal,
+}
+
+// The optional parameters of toolkit_generate_series, unknown ones are
+// rejected so a misspelled parameter isn't silently ignored.
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SeriesParams {
+    // seeds the random noise, without one every call differs
+    seed: Option<u64>,
+    // the starting value of a counter, or the level of a gauge or seasonal series
+    base: Option<f64>,
+    // standard deviation of the normally distributed noise added to each point
+    noise: Option<f64>,
+    // counter increase per second
+    rate: Option<f64>,
+    // a counter resets to 0 every `reset_every` points
+    reset_every: Option<u64>,
+    // the seasonal series' swing above and below `base`
+    amplitude: Option<f64>,
+    // the seasonal series' period in seconds
+    period: Option<f64>,
+}
+
+// Synthetic series with controlled resets, noise and seasonality, one point
+// every `step` from `series_start` up to and including `series_end`, for
+// reproducible benchmarks and tests of the aggregates:
+//  - 'counter': starts at `base` (default 0) and grows by `rate` (default 1)
+//    per second plus noise, never decreasing except to reset to 0 every
+//    `reset_every` points
+//  - 'gauge': `base` (default 1000) plus noise
+//  - 'seasonal': `base` (default 1000) plus a sine wave of `amplitude`
+//    (default 100) and `period` seconds (default one day) plus noise
+// Noise defaults to a standard deviation of 0 for counters and 10 otherwise.
+#[pg_extern(name="toolkit_generate_series", schema = "toolkit_experimental")]
+pub fn toolkit_generate_series(
+    kind: &str,
+    series_start: pg_sys::TimestampTz,
+    series_end: pg_sys::TimestampTz,
+    step: Interval,
+    params: default!(Option<JsonB>, NULL),
+) -> impl std::iter::Iterator<Item = (name!(ts,TimestampTz),name!(value,f64))> + 'static {
+    use rand_distr::Distribution;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    let kind = match kind.trim().to_lowercase().as_str() {
+        "counter" => SeriesKind::Counter,
+        "gauge" => SeriesKind::Gauge,
+        "seasonal" => SeriesKind::Seasonal,
+        _ => error!("unknown series kind. Valid kinds are 'counter', 'gauge' and 'seasonal'"),
+    };
+    let params: SeriesParams = match params {
+        Some(params) => serde_json::from_value(params.0)
+            .unwrap_or_else(|e| error!("invalid toolkit_generate_series params: {}", e)),
+        None => SeriesParams::default(),
+    };
+
+    let step = interval_to_micros(step, "step");
+    if step <= 0 {
+        error!("step must be positive")
+    }
+
+    let (default_base, default_noise) = match kind {
+        SeriesKind::Counter => (0.0, 0.0),
+        _ => (1000.0, 10.0),
+    };
+    let base = params.base.unwrap_or(default_base);
+    let noise = params.noise.unwrap_or(default_noise);
+    if !(noise >= 0.0 && noise.is_finite()) {
+        error!("noise must be a finite, non-negative standard deviation")
+    }
+    let rate = params.rate.unwrap_or(1.0);
+    let reset_every = params.reset_every.filter(|&n| n > 0);
+    let amplitude = params.amplitude.unwrap_or(100.0);
+    let period = params.period.unwrap_or((USECS_PER_DAY / USECS_PER_SEC) as f64);
+    if !(period > 0.0) {
+        error!("period must be positive")
+    }
+
+    let mut rng = match params.seed {
+        Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+        None => ChaCha12Rng::from_entropy(),
+    };
+    let distribution = rand_distr::Normal::new(0.0, noise).unwrap();
+    let step_seconds = step as f64 / USECS_PER_SEC as f64;
+
+    let mut counter = base;
+    (0..)
+        .map(move |i: i64| (i, i.checked_mul(step).and_then(|offset| series_start.checked_add(offset))))
+        .take_while(move |&(_, ts)| matches!(ts, Some(ts) if ts <= series_end))
+        .map(move |(i, ts)| {
+            let ts = ts.unwrap();
+            let noise = distribution.sample(&mut rng);
+            let value = match kind {
+                SeriesKind::Counter => {
+                    if i > 0 {
+                        if reset_every.map_or(false, |n| i as u64 % n == 0) {
+                            counter = 0.0;
+                        }
+                        counter += (rate * step_seconds + noise).max(0.0);
+                    }
+                    counter
+                },
+                SeriesKind::Gauge => base + noise,
+                SeriesKind::Seasonal => {
+                    let elapsed = (ts - series_start) as f64 / USECS_PER_SEC as f64;
+                    base + amplitude * f64::sin(2.0 * std::f64::consts::PI * elapsed / period) + noise
+                },
+            };
+            (ts, value)
+        })
+}
+
 // Convert a timestamp to a double precision unix epoch
 extension_sql!(r#"
 CREATE OR REPLACE FUNCTION toolkit_experimental.to_epoch(timestamptz) RETURNS DOUBLE PRECISION LANGUAGE SQL IMMUTABLE PARALLEL SAFE AS $$
@@ -95,6 +317,38 @@ SELECT EXTRACT(EPOCH FROM $1);
 $$;
 "#);
 
+// Integer arithmetic that clamps to the type's range instead of raising an
+// "out of range" error on overflow.
+#[pg_extern(name="saturating_add", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_add_int(a: i32, b: i32) -> i32 {
+    a.saturating_add(b)
+}
+
+#[pg_extern(name="saturating_add", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_add_bigint(a: i64, b: i64) -> i64 {
+    a.saturating_add(b)
+}
+
+#[pg_extern(name="saturating_sub", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_sub_int(a: i32, b: i32) -> i32 {
+    a.saturating_sub(b)
+}
+
+#[pg_extern(name="saturating_sub", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_sub_bigint(a: i64, b: i64) -> i64 {
+    a.saturating_sub(b)
+}
+
+#[pg_extern(name="saturating_mul", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_mul_int(a: i32, b: i32) -> i32 {
+    a.saturating_mul(b)
+}
+
+#[pg_extern(name="saturating_mul", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn saturating_mul_bigint(a: i64, b: i64) -> i64 {
+    a.saturating_mul(b)
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -121,4 +375,126 @@ mod tests {
             assert_eq!(test_val, -42f64);
         });
     }
+
+    #[pg_test]
+    fn test_generate_periodic_normal_series_interval() {
+        Spi::execute(|client| {
+            let (count, first, last) = client
+                .select("SELECT count(*), min(time)::TEXT, max(time)::TEXT \
+                    FROM toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, \
+                        '1 day'::interval, '1 hour'::interval, 1000.0, '12 hours'::interval, 100.0, 10.0, 42)", None, None)
+                .first()
+                .get_three::<i64, String, String>();
+            assert_eq!(count, Some(24));
+
+            // the same series as with the durations in microseconds
+            let (first_micros, last_micros) = client
+                .select("SELECT min(time)::TEXT, max(time)::TEXT \
+                    FROM toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, \
+                        86400000000, 3600000000, 1000.0, 43200000000, 100.0, 10.0, 42)", None, None)
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(first, first_micros);
+            assert_eq!(last, last_micros);
+
+            let same = client
+                .select("SELECT bool_and(a.value = b.value) \
+                    FROM toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, \
+                        '1 day'::interval, '1 hour'::interval, 1000.0, '12 hours'::interval, 100.0, 10.0, 42) a \
+                    JOIN toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, \
+                        86400000000, 3600000000, 1000.0, 43200000000, 100.0, 10.0, 42) b \
+                    USING (time)", None, None)
+                .first()
+                .get_one::<bool>();
+            assert_eq!(same, Some(true));
+        });
+    }
+
+    #[pg_test(error = "period can currently only use fixed units (days or smaller)")]
+    fn test_generate_periodic_normal_series_month_interval() {
+        Spi::execute(|client| {
+            client.select("SELECT count(*) \
+                FROM toolkit_experimental.generate_periodic_normal_series('2020-01-01 UTC'::timestamptz, \
+                    '1 day'::interval, '1 hour'::interval, 1000.0, '1 month'::interval, 100.0, 10.0, 42)", None, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_saturating_arithmetic() {
+        Spi::execute(|client| {
+            let (add, sub, mul) = client
+                .select("SELECT \
+                    toolkit_experimental.saturating_add(2147483647, 1), \
+                    toolkit_experimental.saturating_sub('-2147483648'::int, 1), \
+                    toolkit_experimental.saturating_mul(-65536, 65536)", None, None)
+                .first()
+                .get_three::<i32, i32, i32>();
+            assert_eq!(add, Some(i32::MAX));
+            assert_eq!(sub, Some(i32::MIN));
+            assert_eq!(mul, Some(i32::MIN));
+
+            let (add, sub, mul) = client
+                .select("SELECT \
+                    toolkit_experimental.saturating_add(9223372036854775807, 1), \
+                    toolkit_experimental.saturating_sub(-9223372036854775807, 2), \
+                    toolkit_experimental.saturating_mul(4294967296, 4294967296)", None, None)
+                .first()
+                .get_three::<i64, i64, i64>();
+            assert_eq!(add, Some(i64::MAX));
+            assert_eq!(sub, Some(i64::MIN));
+            assert_eq!(mul, Some(i64::MAX));
+
+            // in range the results are exact, and int mixed with bigint is bigint
+            let (add, mul) = client
+                .select("SELECT \
+                    toolkit_experimental.saturating_add(40, 2::bigint), \
+                    toolkit_experimental.saturating_mul(6, 7)", None, None)
+                .first()
+                .get_two::<i64, i32>();
+            assert_eq!(add, Some(42));
+            assert_eq!(mul, Some(42));
+        });
+    }
+
+    #[pg_test]
+    fn test_toolkit_generate_series() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // 100 points, resetting at the 10th, 20th, ... 90th
+            let (count, resets) = client
+                .select("SELECT count(*), num_resets(counter_agg(ts, value)) \
+                    FROM toolkit_generate_series('counter', '2020-01-01 UTC', '2020-01-01 01:39 UTC', '1 minute', \
+                        '{\"rate\": 2, \"noise\": 5, \"reset_every\": 10, \"seed\": 1}')", None, None)
+                .first()
+                .get_two::<i64, i64>();
+            assert_eq!(count, Some(100));
+            assert_eq!(resets, Some(9));
+
+            // the same seed produces the same series
+            let same = client
+                .select("SELECT bool_and(a.value = b.value) \
+                    FROM toolkit_generate_series('gauge', '2020-01-01 UTC', '2020-01-02 UTC', '1 hour', '{\"seed\": 7}') a \
+                    JOIN toolkit_generate_series('gauge', '2020-01-01 UTC', '2020-01-02 UTC', '1 hour', '{\"seed\": 7}') b \
+                    USING (ts)", None, None)
+                .first()
+                .get_one::<bool>();
+            assert_eq!(same, Some(true));
+
+            let values: Vec<f64> = client
+                .select("SELECT value FROM toolkit_generate_series('seasonal', '2020-01-01 UTC', '2020-01-01 01:00 UTC', '15 minutes', \
+                        '{\"base\": 50, \"amplitude\": 10, \"period\": 3600, \"noise\": 0}') \
+                    ORDER BY ts", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(values.len(), 5);
+            for (value, expected) in values.iter().zip([50.0, 60.0, 50.0, 40.0, 50.0].iter()) {
+                assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+            }
+        });
+    }
 }
@@ -0,0 +1,87 @@
+// The problems `validate()` finds with a summary, returned as
+// (severity, message) rows. Errors are broken invariants, the summary's
+// results can't be trusted; warnings are values that are allowed but likely
+// came from bad input. A summary without problems returns no rows.
+#[derive(Default)]
+pub(crate) struct Diagnostics(Vec<(String, String)>);
+
+impl Diagnostics {
+    pub(crate) fn error(&mut self, message: String) {
+        self.0.push(("error".to_string(), message))
+    }
+
+    pub(crate) fn warning(&mut self, message: String) {
+        self.0.push(("warning".to_string(), message))
+    }
+
+    pub(crate) fn into_rows(self) -> std::vec::IntoIter<(String, String)> {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_validate() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test to make it easier to read
+            let sp = client.select("SELECT format(' %s, toolkit_experimental',current_setting('search_path'))", None, None).first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            client.select(
+                "CREATE TABLE data AS \
+                    SELECT '2020-01-01 UTC'::TIMESTAMPTZ + (i || ' minutes')::INTERVAL AS time, \
+                        (i * i % 17)::DOUBLE PRECISION AS value \
+                    FROM generate_series(1, 100) i",
+                None,
+                None
+            );
+
+            // summaries built by the aggregates are valid
+            let problems = client.select(
+                "SELECT \
+                    (SELECT count(*) FROM (SELECT stats_agg(value) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT stats_agg(value, value * 2) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT tdigest(20, value) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT uddsketch(50, 0.01, value) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT hyperloglog(32, value) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT counter_agg(time, value) s FROM data) d, validate(d.s)) \
+                    + (SELECT count(*) FROM (SELECT time_weight('Linear', time, value) s FROM data) d, validate(d.s))",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(problems, Some(0));
+
+            // summaries written as text are checked
+            let (severity, message) = client.select(
+                "SELECT severity, message FROM \
+                    (SELECT regexp_replace(tdigest(20, value)::TEXT, 'count:\\d+', 'count:1000')::tdigest s FROM data) d, \
+                    validate(d.s)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(severity.unwrap(), "error");
+            assert_eq!(message.unwrap(), "centroid weights sum to 100 but the count is 1000");
+
+            let (severity, message) = client.select(
+                "SELECT severity, message FROM \
+                    (SELECT regexp_replace(uddsketch(50, 0.01, value)::TEXT, 'max_buckets:\\d+', 'max_buckets:2')::uddsketch s FROM data) d, \
+                    validate(d.s)",
+                None,
+                None
+            )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(severity.unwrap(), "error");
+            assert!(message.unwrap().ends_with("buckets but at most 2 are allowed"));
+        });
+    }
+}
@@ -0,0 +1,201 @@
+use std::{ffi::{CStr, CString}, os::raw::c_char, ptr};
+
+use pgx::*;
+
+use crate::serialization::{EncodedStr, str_from_db_encoding, str_to_db_encoding};
+
+// The binary send/receive format of every toolkit type: a format version
+// byte followed by the type's text in UTF-8. The in-memory layout of our
+// types is native-endian and may change between versions, while the text is
+// neither, and is upgraded on read when it's from an older version, so
+// binary COPY between servers of differing architectures or extension
+// versions is as safe as text COPY.
+const BINARY_FORMAT_VERSION: u8 = 1;
+
+// Both functions are declared once per type, see the SQL below, so they find
+// the type to convert through the catalog rather than being generated per
+// type.
+#[no_mangle]
+pub extern "C" fn pg_finfo_toolkit_binary_send() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn toolkit_binary_send(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    unsafe {
+        // the type is the argument of the `<type>_send` function we were
+        // called as
+        let mut argtypes: *mut pg_sys::Oid = ptr::null_mut();
+        let mut nargs = 0;
+        pg_sys::get_func_signature((*(*fcinfo).flinfo).fn_oid, &mut argtypes, &mut nargs);
+        if nargs != 1 {
+            error!("invalid toolkit send function")
+        }
+
+        let mut output_fn = pg_sys::InvalidOid;
+        let mut is_varlena = false;
+        pg_sys::getTypeOutputInfo(*argtypes, &mut output_fn, &mut is_varlena);
+        let text = pg_sys::OidOutputFunctionCall(output_fn, pg_getarg_datum_raw(fcinfo, 0));
+        let text = str_from_db_encoding(CStr::from_ptr(text));
+
+        let mut bytes = Vec::with_capacity(text.len() + 1);
+        bytes.push(BINARY_FORMAT_VERSION);
+        bytes.extend_from_slice(text.as_bytes());
+        bytes.into_datum().unwrap()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_toolkit_binary_recv() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn toolkit_binary_recv(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    unsafe {
+        let buf = pg_getarg_datum_raw(fcinfo, 0) as pg_sys::StringInfo;
+        let typ_io_param = pg_getarg_datum_raw(fcinfo, 1) as pg_sys::Oid;
+        let typmod = pg_getarg_datum_raw(fcinfo, 2) as i32;
+
+        let start = (*buf).cursor as usize;
+        let len = (*buf).len as usize;
+        let bytes = std::slice::from_raw_parts((*buf).data as *const u8, len);
+        let bytes = &bytes[start..];
+        // the message must be consumed entirely
+        (*buf).cursor = (*buf).len;
+
+        let (&version, text) = match bytes.split_first() {
+            Some(split) => split,
+            None => error!("invalid binary toolkit value, no bytes"),
+        };
+        if version != BINARY_FORMAT_VERSION {
+            error!("invalid binary toolkit value, unknown format version {}", version)
+        }
+        let text = std::str::from_utf8(text)
+            .unwrap_or_else(|_| error!("invalid binary toolkit value, text is not UTF-8"));
+        let text = match str_to_db_encoding(text) {
+            EncodedStr::Utf8(text) => CString::new(text),
+            EncodedStr::Other(text) => CString::new(text.to_bytes()),
+        }.unwrap_or_else(|_| error!("invalid binary toolkit value, text contains a NUL"));
+
+        let mut input_fn = pg_sys::InvalidOid;
+        let mut io_param = pg_sys::InvalidOid;
+        pg_sys::getTypeInputInfo(typ_io_param, &mut input_fn, &mut io_param);
+        pg_sys::OidInputFunctionCall(input_fn, text.as_ptr() as *mut c_char, io_param, typmod)
+    }
+}
+
+// Give every base type of the extension that doesn't have them yet send and
+// receive functions, in the type's schema so they're dropped along with it.
+// This runs after all the types are created. Types' send and receive
+// functions can only be altered from PostgreSQL 13 on, before that they don't
+// support binary COPY.
+extension_sql!(r#"
+DO $$
+DECLARE
+    typ record;
+BEGIN
+    IF current_setting('server_version_num')::int < 130000 THEN
+        RETURN;
+    END IF;
+    FOR typ IN
+        SELECT t.oid::regtype AS name, n.nspname AS schema, t.typname AS type_name
+        FROM pg_catalog.pg_type t
+        JOIN pg_catalog.pg_namespace n ON n.oid = t.typnamespace
+        JOIN pg_catalog.pg_depend d
+            ON d.classid = 'pg_catalog.pg_type'::regclass AND d.objid = t.oid AND d.deptype = 'e'
+        JOIN pg_catalog.pg_extension e
+            ON d.refclassid = 'pg_catalog.pg_extension'::regclass AND d.refobjid = e.oid
+        WHERE e.extname = 'timescaledb_toolkit' AND t.typtype = 'b' AND t.typsend = 0
+    LOOP
+        EXECUTE format(
+            'CREATE OR REPLACE FUNCTION %I.%I(%s) RETURNS bytea '
+            'IMMUTABLE STRICT PARALLEL SAFE LANGUAGE C AS %L, %L',
+            typ.schema, typ.type_name || '_send', typ.name, 'MODULE_PATHNAME', 'toolkit_binary_send');
+        EXECUTE format(
+            'CREATE OR REPLACE FUNCTION %I.%I(internal, oid, integer) RETURNS %s '
+            'IMMUTABLE STRICT PARALLEL SAFE LANGUAGE C AS %L, %L',
+            typ.schema, typ.type_name || '_recv', typ.name, 'MODULE_PATHNAME', 'toolkit_binary_recv');
+        EXECUTE format(
+            'ALTER TYPE %s SET (SEND = %I.%I, RECEIVE = %I.%I)',
+            typ.name, typ.schema, typ.type_name || '_send', typ.schema, typ.type_name || '_recv');
+    END LOOP;
+END
+$$;
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_binary_copy() {
+        Spi::execute(|client| {
+            let version = client.select("SELECT current_setting('server_version_num')::int", None, None)
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            if version < 130000 {
+                return
+            }
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+
+            // every base type of the extension can be sent and received
+            let missing = client.select(
+                "SELECT count(*) FROM pg_catalog.pg_type t \
+                    JOIN pg_catalog.pg_depend d ON d.classid = 'pg_catalog.pg_type'::regclass AND d.objid = t.oid AND d.deptype = 'e' \
+                    JOIN pg_catalog.pg_extension e ON d.refobjid = e.oid \
+                WHERE e.extname = 'timescaledb_toolkit' AND t.typtype = 'b' AND (t.typsend = 0 OR t.typreceive = 0)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(missing, Some(0));
+
+            // the binary form is the text behind a format version
+            let sent = client.select(
+                "SELECT tdigest_send(digest) = '\\x01'::BYTEA || convert_to(digest::TEXT, 'UTF8') \
+                FROM (SELECT tdigest(10, v) AS digest FROM generate_series(1, 100) v) d",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(sent, Some(true));
+
+            client.select(
+                "CREATE TABLE summaries AS SELECT \
+                    tdigest(10, v) AS digest, \
+                    uddsketch(20, 0.01, v) AS sketch, \
+                    toolkit_experimental.stats_agg(v) AS stats, \
+                    time_weight('Linear', '2020-01-01 UTC'::TIMESTAMPTZ + v * '1 hour'::INTERVAL, v) AS weights \
+                FROM generate_series(1, 100) v",
+                None,
+                None
+            );
+            client.select("COPY summaries TO '/tmp/toolkit_binary_copy_test' (FORMAT binary)", None, None);
+            client.select("CREATE TABLE copied (LIKE summaries)", None, None);
+            client.select("COPY copied FROM '/tmp/toolkit_binary_copy_test' (FORMAT binary)", None, None);
+
+            let same = client.select(
+                "SELECT \
+                    s.digest::TEXT = c.digest::TEXT \
+                    AND s.sketch::TEXT = c.sketch::TEXT \
+                    AND s.stats::TEXT = c.stats::TEXT \
+                    AND s.weights::TEXT = c.weights::TEXT \
+                FROM summaries s, copied c",
+                None,
+                None
+            )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(same, Some(true));
+        });
+    }
+}
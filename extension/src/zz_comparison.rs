@@ -0,0 +1,175 @@
+use pgx::*;
+
+// Equality, ordering and hashing for the experimental base types of the
+// extension, so summaries can be used in DISTINCT, GROUP BY and UNIQUE
+// constraints. Two values are equal when their stored bytes are, and are
+// ordered by those bytes: the order is arbitrary, but consistent, which is all
+// that sorting and btree indexes need. Equal bytes is stricter than equal
+// summaries, e.g. sums of 0.0 and -0.0 differ. Our types are varlenas, so
+// Postgres' own bytea and varlena functions implement all of this for them,
+// the catalog loop below only declares them once per type. Everything is
+// created in toolkit_experimental; the default operator classes are found by
+// type, so DISTINCT and friends work whatever the search_path. The stable
+// types are left out, as their columns would otherwise depend on objects that
+// are dropped on every update.
+extension_sql!(r#"
+DO $$
+DECLARE
+    typ record;
+    fn record;
+BEGIN
+    FOR typ IN
+        SELECT t.oid::regtype AS name, t.typname AS type_name
+        FROM pg_catalog.pg_type t
+        JOIN pg_catalog.pg_depend d
+            ON d.classid = 'pg_catalog.pg_type'::regclass AND d.objid = t.oid AND d.deptype = 'e'
+        JOIN pg_catalog.pg_extension e
+            ON d.refclassid = 'pg_catalog.pg_extension'::regclass AND d.refobjid = e.oid
+        WHERE e.extname = 'timescaledb_toolkit' AND t.typtype = 'b' AND t.typlen = -1
+            AND t.typnamespace = 'toolkit_experimental'::regnamespace
+    LOOP
+        FOR fn IN
+            SELECT * FROM (VALUES
+                ('eq', 'bool', 'byteaeq', '=', '=', '<>', 'eqsel', 'eqjoinsel'),
+                ('ne', 'bool', 'byteane', '<>', '<>', '=', 'neqsel', 'neqjoinsel'),
+                ('lt', 'bool', 'bytealt', '<', '>', '>=', 'scalarltsel', 'scalarltjoinsel'),
+                ('le', 'bool', 'byteale', '<=', '>=', '>', 'scalarltsel', 'scalarltjoinsel'),
+                ('gt', 'bool', 'byteagt', '>', '<', '<=', 'scalargtsel', 'scalargtjoinsel'),
+                ('ge', 'bool', 'byteage', '>=', '<=', '<', 'scalargtsel', 'scalargtjoinsel')
+            ) f(suffix, return_type, internal_fn, op, commutator, negator, restrict_fn, join_fn)
+        LOOP
+            EXECUTE format(
+                'CREATE FUNCTION toolkit_experimental.%I(%s, %s) RETURNS %s '
+                'IMMUTABLE STRICT PARALLEL SAFE LANGUAGE internal AS %L',
+                typ.type_name || '_' || fn.suffix, typ.name, typ.name, fn.return_type, fn.internal_fn);
+            EXECUTE format(
+                'CREATE OPERATOR toolkit_experimental.%s (PROCEDURE = toolkit_experimental.%I, '
+                'LEFTARG = %s, RIGHTARG = %s, COMMUTATOR = OPERATOR(toolkit_experimental.%s), '
+                'NEGATOR = OPERATOR(toolkit_experimental.%s), RESTRICT = %s, JOIN = %s %s)',
+                fn.op, typ.type_name || '_' || fn.suffix, typ.name, typ.name, fn.commutator,
+                fn.negator, fn.restrict_fn, fn.join_fn, CASE WHEN fn.op = '=' THEN ', HASHES, MERGES' ELSE '' END);
+        END LOOP;
+
+        EXECUTE format(
+            'CREATE FUNCTION toolkit_experimental.%I(%s, %s) RETURNS integer '
+            'IMMUTABLE STRICT PARALLEL SAFE LANGUAGE internal AS %L',
+            typ.type_name || '_cmp', typ.name, typ.name, 'byteacmp');
+        EXECUTE format(
+            'CREATE FUNCTION toolkit_experimental.%I(%s) RETURNS integer '
+            'IMMUTABLE STRICT PARALLEL SAFE LANGUAGE internal AS %L',
+            typ.type_name || '_hash', typ.name, 'hashvarlena');
+
+        EXECUTE format(
+            'CREATE OPERATOR CLASS toolkit_experimental.%I DEFAULT FOR TYPE %s USING btree AS '
+            'OPERATOR 1 toolkit_experimental.<, OPERATOR 2 toolkit_experimental.<=, '
+            'OPERATOR 3 toolkit_experimental.=, OPERATOR 4 toolkit_experimental.>=, '
+            'OPERATOR 5 toolkit_experimental.>, FUNCTION 1 toolkit_experimental.%I(%s, %s)',
+            typ.type_name || '_ops', typ.name, typ.type_name || '_cmp', typ.name, typ.name);
+        EXECUTE format(
+            'CREATE OPERATOR CLASS toolkit_experimental.%I DEFAULT FOR TYPE %s USING hash AS '
+            'OPERATOR 1 toolkit_experimental.=, FUNCTION 1 toolkit_experimental.%I(%s)',
+            typ.type_name || '_ops', typ.name, typ.type_name || '_hash', typ.name);
+    END LOOP;
+END
+$$;
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_summary_equality() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE summaries AS \
+                    SELECT device, toolkit_experimental.hyperloglog(32, v) AS hll, toolkit_experimental.stats_agg(v) AS stats \
+                    FROM generate_series(1, 4) device, generate_series(1, 100) v \
+                    GROUP BY device",
+                None,
+                None
+            );
+            client.select(
+                "INSERT INTO summaries \
+                    SELECT 5, toolkit_experimental.hyperloglog(32, v), toolkit_experimental.stats_agg(v) FROM generate_series(1, 50) v",
+                None,
+                None
+            );
+
+            let (hlls, stats) = client.select(
+                "SELECT count(DISTINCT hll), count(DISTINCT stats) FROM summaries",
+                None,
+                None
+            )
+                .first()
+                .get_two::<i64, i64>();
+            assert_eq!(hlls, Some(2));
+            assert_eq!(stats, Some(2));
+
+            let groups: Vec<i64> = client.select(
+                "SELECT count(*) FROM summaries GROUP BY hll ORDER BY 1",
+                None,
+                None
+            )
+                .map(|row| row.by_ordinal(1).unwrap().value().unwrap())
+                .collect();
+            assert_eq!(groups, vec![1, 4]);
+
+            let (eq, ne, ordered) = client.select(
+                "SELECT \
+                    a.hll OPERATOR(toolkit_experimental.=) b.hll, \
+                    a.hll OPERATOR(toolkit_experimental.<>) c.hll, \
+                    (a.hll OPERATOR(toolkit_experimental.<) c.hll) <> (a.hll OPERATOR(toolkit_experimental.>) c.hll) \
+                FROM summaries a, summaries b, summaries c \
+                WHERE a.device = 1 AND b.device = 2 AND c.device = 5",
+                None,
+                None
+            )
+                .first()
+                .get_three::<bool, bool, bool>();
+            assert_eq!(eq, Some(true));
+            assert_eq!(ne, Some(true));
+            assert_eq!(ordered, Some(true));
+
+            // and without hashing
+            client.select("SET LOCAL enable_hashagg TO off", None, None);
+            let sorted = client.select(
+                "SELECT count(*) FROM (SELECT DISTINCT hll FROM summaries) d",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(sorted, Some(2));
+
+            // the stable types don't get operator classes from the
+            // experimental schema
+            let stable = client.select(
+                "SELECT count(*) FROM pg_catalog.pg_opclass \
+                WHERE opcintype IN ('tdigest'::regtype, 'uddsketch'::regtype, 'timeweightsummary'::regtype)",
+                None,
+                None
+            )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(stable, Some(0));
+        });
+    }
+
+    #[pg_test(error = "could not create unique index \"summaries_hll_idx\"")]
+    fn test_summary_unique() {
+        Spi::execute(|client| {
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select(
+                "CREATE TABLE summaries AS \
+                    SELECT device, toolkit_experimental.hyperloglog(32, v) AS hll \
+                    FROM generate_series(1, 2) device, generate_series(1, 100) v \
+                    GROUP BY device",
+                None,
+                None
+            );
+            client.select("CREATE UNIQUE INDEX summaries_hll_idx ON summaries (hll)", None, None);
+        });
+    }
+}
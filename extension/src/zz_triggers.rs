@@ -102,4 +102,83 @@ CREATE EVENT TRIGGER disallow_experimental_deps ON ddl_command_end
 CREATE EVENT TRIGGER disallow_experimental_dependencies_on_views ON ddl_command_end
   WHEN tag IN ('CREATE MATERIALIZED VIEW', 'CREATE VIEW')
   EXECUTE FUNCTION disallow_experimental_view_dependencies();
-"#);
\ No newline at end of file
+"#);
+// Lists the objects outside the extension that depend on something in
+// toolkit_experimental, and so will be dropped by the next extension update.
+// Views record their dependencies through their rewrite rule, so we report
+// the view the rule belongs to instead. Dependencies only visible at runtime,
+// such as calls in the body of a plpgsql function, can't be found this way.
+extension_sql!(r#"
+CREATE OR REPLACE FUNCTION toolkit_experimental.toolkit_experimental_dependencies()
+RETURNS TABLE(object_type TEXT, object_identity TEXT, depends_on TEXT)
+LANGUAGE SQL STABLE PARALLEL SAFE
+AS $$
+  WITH experimental AS (
+    SELECT d.classid, d.objid
+    FROM pg_catalog.pg_depend d
+    JOIN pg_catalog.pg_namespace n ON n.oid = d.refobjid
+    WHERE d.refclassid = 'pg_catalog.pg_namespace'::pg_catalog.regclass
+    AND n.nspname = 'toolkit_experimental'
+  ),
+  dependents AS (
+    SELECT
+      COALESCE(CASE WHEN r.oid IS NOT NULL THEN 'pg_catalog.pg_class'::pg_catalog.regclass END, d.classid) classid,
+      COALESCE(r.ev_class, d.objid) objid,
+      CASE WHEN r.oid IS NOT NULL THEN 0 ELSE d.objsubid END objsubid,
+      e.classid ref_classid,
+      e.objid ref_objid
+    FROM pg_catalog.pg_depend d
+    JOIN experimental e ON e.classid = d.refclassid AND e.objid = d.refobjid
+    LEFT JOIN pg_catalog.pg_rewrite r
+      ON d.classid = 'pg_catalog.pg_rewrite'::pg_catalog.regclass AND r.oid = d.objid
+    WHERE d.deptype IN ('n', 'a')
+  )
+  SELECT DISTINCT
+    (pg_catalog.pg_identify_object(dep.classid, dep.objid, dep.objsubid)).type,
+    (pg_catalog.pg_identify_object(dep.classid, dep.objid, dep.objsubid)).identity,
+    pg_catalog.pg_describe_object(dep.ref_classid, dep.ref_objid, 0)
+  FROM dependents dep
+  WHERE NOT EXISTS (
+    SELECT 1 FROM experimental e
+    WHERE e.classid = dep.classid AND e.objid = dep.objid)
+  AND NOT EXISTS (
+    SELECT 1 FROM pg_catalog.pg_depend ext
+    WHERE ext.classid = dep.classid AND ext.objid = dep.objid AND ext.deptype = 'e')
+  ORDER BY 2, 3
+$$;
+"#);
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_experimental_dependencies() {
+        Spi::execute(|client| {
+            let none = client
+                .select("SELECT count(*) FROM toolkit_experimental.toolkit_experimental_dependencies()", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(none, Some(0));
+
+            client.select("SET timescaledb_toolkit_acknowledge_auto_drop TO 'true'", None, None);
+            client.select("CREATE VIEW public.epochs AS SELECT toolkit_experimental.to_epoch(now()) AS epoch", None, None);
+            client.select("CREATE TABLE public.series(id int, series toolkit_experimental.TimeSeries)", None, None);
+            client.select("CREATE TABLE public.stable(id int, digest tdigest)", None, None);
+
+            let deps: Vec<(String, String, String)> = client
+                .select("SELECT object_type, object_identity, depends_on \
+                    FROM toolkit_experimental.toolkit_experimental_dependencies()", None, None)
+                .map(|row| (
+                    row.by_ordinal(1).unwrap().value().unwrap(),
+                    row.by_ordinal(2).unwrap().value().unwrap(),
+                    row.by_ordinal(3).unwrap().value().unwrap(),
+                ))
+                .collect();
+            assert_eq!(deps, vec![
+                ("view".to_string(), "public.epochs".to_string(), "function toolkit_experimental.to_epoch(timestamp with time zone)".to_string()),
+                ("table column".to_string(), "public.series.series".to_string(), "type toolkit_experimental.timeseries".to_string()),
+            ]);
+        });
+    }
+}